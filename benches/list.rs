@@ -0,0 +1,204 @@
+//! Compares the `ListStore` backend against the `LinkedList` one it replaced.
+//!
+//! Run with `cargo bench --bench list`. The linked list side is a copy of the
+//! old store's walks, expiry checks included, so both run the same workload.
+
+#![allow(clippy::needless_return)]
+
+use std::collections::{HashMap, LinkedList};
+use std::hint::black_box;
+use std::mem::size_of;
+use std::time::{Duration, Instant};
+
+use granat::store::clock::{system_clock, SharedClock};
+use granat::store::entry::StoreEntry;
+use granat::store::list::ListStore;
+
+const LIST_SIZES: [usize; 3] = [100, 10_000, 100_000];
+
+/// The `LinkedList` backed store as it was, trimmed to the operations benchmarked
+struct LinkedListStore {
+    store: HashMap<String, LinkedList<StoreEntry>>,
+    clock: SharedClock,
+}
+
+impl LinkedListStore {
+    fn new(key: &str, size: usize, expiring: bool) -> Self {
+        let list = (0..size).map(|i| bench_entry(i, expiring)).collect();
+        return Self {
+            store: HashMap::from([(key.to_string(), list)]),
+            clock: system_clock(),
+        };
+    }
+
+    fn push_right(&mut self, key: &str, entry: StoreEntry) {
+        self.store
+            .entry(key.to_string())
+            .or_default()
+            .push_back(entry);
+    }
+
+    fn pop_left(&mut self, key: &str) -> Option<StoreEntry> {
+        let now = self.clock.now_millis();
+        let list = self.store.get_mut(key)?;
+        while let Some(entry) = list.pop_front() {
+            if !entry.is_expired_at(now) {
+                return Some(entry);
+            }
+        }
+
+        return None;
+    }
+
+    fn index(&self, key: &str, idx: usize) -> Option<StoreEntry> {
+        let now = self.clock.now_millis();
+        return self
+            .store
+            .get(key)?
+            .iter()
+            .filter(|e| !e.is_expired_at(now))
+            .nth(idx)
+            .cloned();
+    }
+
+    fn range(&self, key: &str, start: usize, end: usize) -> LinkedList<StoreEntry> {
+        let now = self.clock.now_millis();
+        let mut range = LinkedList::new();
+        if let Some(list) = self.store.get(key) {
+            for (i, item) in list.iter().filter(|e| !e.is_expired_at(now)).enumerate() {
+                if i >= start && i <= end {
+                    range.push_back(item.clone());
+                }
+            }
+        }
+
+        return range;
+    }
+
+    fn insert(&mut self, key: &str, idx: usize, entry: StoreEntry) {
+        if let Some(list) = self.store.get_mut(key) {
+            let mut split = list.split_off(idx);
+            split.push_front(entry);
+            list.append(&mut split);
+        }
+    }
+
+    fn remove_first(&mut self, key: &str, target: &str) {
+        if let Some(list) = self.store.get_mut(key) {
+            if let Some(idx) = list.iter().position(|e| e.value == target) {
+                let mut right = list.split_off(idx);
+                right.pop_front();
+                list.append(&mut right);
+            }
+        }
+    }
+}
+
+/// Runs `op` until it has taken at least a quarter of a second, returning the time per call
+fn time_per_op(mut op: impl FnMut(usize)) -> Duration {
+    let mut iterations = 1;
+    loop {
+        let started = Instant::now();
+        for i in 0..iterations {
+            op(i);
+        }
+
+        let elapsed = started.elapsed();
+        if elapsed >= Duration::from_millis(250) {
+            return elapsed / iterations as u32;
+        }
+
+        iterations *= 2;
+    }
+}
+
+fn report(name: &str, size: usize, old: Duration, new: Duration) {
+    let speedup = old.as_secs_f64() / new.as_secs_f64().max(f64::EPSILON);
+    println!("{name:<12} {size:>8} {old:>14?} {new:>14?} {speedup:>9.1}x");
+}
+
+/// The `i`th entry of a benchmarked list, with `expiring` every tenth one
+/// carries an expiry that won't pass while the benchmark runs
+fn bench_entry(i: usize, expiring: bool) -> StoreEntry {
+    let entry = StoreEntry::new(i.to_string());
+    if expiring && i.is_multiple_of(10) {
+        return entry.expires_in(3600);
+    }
+
+    return entry;
+}
+
+fn list_store(size: usize, expiring: bool) -> ListStore {
+    let mut store = ListStore::new();
+    for i in 0..size {
+        store.push_right(("bench".to_string(), bench_entry(i, expiring)));
+    }
+
+    return store;
+}
+
+fn main() {
+    println!(
+        "{:<12} {:>8} {:>14} {:>14} {:>10}",
+        "operation", "size", "linked list", "deque", "speedup"
+    );
+
+    for size in LIST_SIZES {
+        let mid = size / 2;
+
+        // Lookups with and without some entries carrying an expiry
+        for (suffix, expiring) in [("", false), (" ttl", true)] {
+            let old = LinkedListStore::new("bench", size, expiring);
+            let new = list_store(size, expiring);
+            let old_time = time_per_op(|i| {
+                black_box(old.index("bench", (mid + i) % size));
+            });
+            let new_time = time_per_op(|i| {
+                black_box(new.index("bench", ((mid + i) % size) as isize));
+            });
+            report(&format!("index{suffix}"), size, old_time, new_time);
+
+            let old_time = time_per_op(|_| {
+                black_box(old.range("bench", mid, mid + 99));
+            });
+            let new_time = time_per_op(|_| {
+                black_box(new.range("bench", mid as isize, mid as isize + 99));
+            });
+            report(&format!("range{suffix}"), size, old_time, new_time);
+        }
+
+        // Insert then remove so the list stays the same size between iterations
+        let mut old = LinkedListStore::new("bench", size, false);
+        let mut new = list_store(size, false);
+        let old_time = time_per_op(|_| {
+            old.insert("bench", mid, StoreEntry::new("inserted"));
+            old.remove_first("bench", "inserted");
+        });
+        let new_time = time_per_op(|_| {
+            let _ = new.insert_at(
+                ("bench".to_string(), StoreEntry::new("inserted")),
+                mid as isize,
+            );
+            new.remove("bench", "inserted", 1);
+        });
+        report("insert+rem", size, old_time, new_time);
+
+        let old_time = time_per_op(|i| {
+            old.push_right("bench", StoreEntry::new(i.to_string()));
+            black_box(old.pop_left("bench"));
+        });
+        let new_time = time_per_op(|i| {
+            new.push_right(("bench".to_string(), StoreEntry::new(i.to_string())));
+            black_box(new.pop_left("bench"));
+        });
+        report("push+pop", size, old_time, new_time);
+    }
+
+    // Each linked list node carries the entry plus two pointers and its own allocation
+    let entry = size_of::<StoreEntry>();
+    println!();
+    println!(
+        "bytes per element excluding values: linked list {}, deque {entry}",
+        entry + 2 * size_of::<usize>()
+    );
+}
@@ -1 +1,3 @@
+#![allow(clippy::needless_return)]
+
 pub mod store;
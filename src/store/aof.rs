@@ -0,0 +1,1081 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::store::command::Command;
+use crate::store::keyspace::{ExpireCondition, KeyType};
+use crate::store::sorted_set::AddOptions;
+use crate::store::GranatStore;
+
+/// How often an `EverySecond` log is synced to disk
+const FSYNC_INTERVAL: Duration = Duration::from_secs(1);
+
+/// When the append-only file is synced to disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum FsyncPolicy {
+    /// Sync after every write, the safest and slowest
+    Always,
+    /// Sync at most once a second, a crash can lose about a second of writes
+    EverySecond,
+    /// Leave syncing to the OS
+    Never,
+}
+
+/// Rewrite the log in the background once it has grown by `percentage` since
+/// the last rewrite and is at least `min_size` bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AutoRewrite {
+    pub percentage: u64,
+    pub min_size: u64,
+}
+
+impl AutoRewrite {
+    pub fn new(percentage: u64, min_size: u64) -> Self {
+        return Self {
+            percentage,
+            min_size,
+        };
+    }
+}
+
+/// The log every write is appended to, one JSON encoded `Command` per line
+pub(crate) struct AppendOnlyFile {
+    path: PathBuf,
+    file: File,
+    policy: FsyncPolicy,
+    last_fsync: Instant,
+
+    /// Encoded commands that haven't made it into the file yet, only ever
+    /// non-empty after a failed write. They're retried ahead of the next one.
+    pending: Vec<u8>,
+
+    /// Bytes of whole records in the file, anything past it was left by a
+    /// write that failed part way and is cut off before the next attempt
+    written: u64,
+    torn: bool,
+
+    /// Set when a write or sync fails, cleared once the records that failed
+    /// are on disk
+    last_error: Option<String>,
+
+    /// Set when a write can't be retried, a command that couldn't be encoded
+    /// or a failed rewrite, cleared once a rewrite rebuilds the log
+    rewrite_error: Option<String>,
+
+    /// Bytes in the log, and how many there were after the last rewrite
+    size: u64,
+    base_size: u64,
+
+    auto_rewrite: Option<AutoRewrite>,
+    rewrite: Option<Rewrite>,
+}
+
+/// A compacted log being written on another thread
+struct Rewrite {
+    handle: JoinHandle<Result<()>>,
+    tmp_path: PathBuf,
+
+    /// Writes made since the rewrite took its copy of the store, they're
+    /// appended to the new log before it replaces the old one
+    buffer: Vec<Command>,
+}
+
+fn encode(cmd: &Command) -> Result<Vec<u8>> {
+    let mut line = match serde_json::to_vec(cmd) {
+        Ok(line) => line,
+        Err(e) => return Err(anyhow!("unable to serialize command: {e}")),
+    };
+    line.push(b'\n');
+
+    return Ok(line);
+}
+
+fn write_log(path: &Path, commands: &[Command]) -> Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    for cmd in commands.iter() {
+        writer.write_all(&encode(cmd)?)?;
+    }
+
+    writer.flush()?;
+    writer.get_ref().sync_all()?;
+
+    return Ok(());
+}
+
+impl AppendOnlyFile {
+    pub(crate) fn open(path: impl AsRef<Path>, policy: FsyncPolicy) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path.as_ref())?;
+        let size = file.metadata()?.len();
+
+        return Ok(Self {
+            path: path.as_ref().to_path_buf(),
+            file,
+            policy,
+            last_fsync: Instant::now(),
+            pending: vec![],
+            written: size,
+            torn: false,
+            last_error: None,
+            rewrite_error: None,
+            size,
+            base_size: size,
+            auto_rewrite: None,
+            rewrite: None,
+        });
+    }
+
+    /// Appends `cmd` to the log, erroring if it couldn't be written or, when
+    /// the policy says to, synced. A command that failed to write is kept and
+    /// retried with the next one.
+    pub(crate) fn append(&mut self, cmd: &Command) -> Result<()> {
+        let line = match encode(cmd) {
+            Ok(line) => line,
+            Err(e) => {
+                self.rewrite_error = Some(e.to_string());
+                return Err(e);
+            }
+        };
+
+        if let Some(rewrite) = self.rewrite.as_mut() {
+            rewrite.buffer.push(cmd.clone());
+        }
+
+        self.pending.extend(line.iter());
+        self.size += line.len() as u64;
+
+        return self.sync(false);
+    }
+
+    /// Writes the pending records to the file, cutting off whatever a failed
+    /// write left behind first so the file only ever holds whole records
+    fn write_pending(&mut self) -> Result<()> {
+        if self.torn {
+            self.file.set_len(self.written)?;
+            self.torn = false;
+        }
+
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+
+        if let Err(e) = self.file.write_all(&self.pending) {
+            self.torn = true;
+            return Err(e.into());
+        }
+
+        self.written += self.pending.len() as u64;
+        self.pending.clear();
+
+        return Ok(());
+    }
+
+    /// Whether the log has grown enough since the last rewrite to need another
+    fn rewrite_due(&self) -> bool {
+        let Some(auto) = self.auto_rewrite else {
+            return false;
+        };
+
+        let threshold = self.base_size + self.base_size * auto.percentage / 100;
+        return self.rewrite.is_none() && self.size >= auto.min_size && self.size >= threshold;
+    }
+
+    /// Writes `commands` to a temporary log on a background thread
+    fn start_rewrite(&mut self, commands: Vec<Command>) {
+        let mut tmp_name = self.path.file_name().unwrap_or_default().to_os_string();
+        tmp_name.push(".rewrite");
+        let tmp_path = self.path.with_file_name(tmp_name);
+
+        let thread_path = tmp_path.clone();
+        let handle = thread::spawn(move || write_log(&thread_path, &commands));
+
+        self.rewrite = Some(Rewrite {
+            handle,
+            tmp_path,
+            buffer: vec![],
+        });
+    }
+
+    /// Waits for the background rewrite, tops it up with the writes made in the
+    /// meantime and swaps it in for the current log.
+    ///
+    /// The current log stays complete until the final rename, so a failure at
+    /// any point leaves it in use and loses nothing.
+    fn finish_rewrite(&mut self) -> Result<()> {
+        let Some(rewrite) = self.rewrite.take() else {
+            return Ok(());
+        };
+
+        let result = match rewrite.handle.join() {
+            Ok(result) => result,
+            Err(_) => Err(anyhow!("append-only file rewrite thread panicked")),
+        };
+
+        let swapped = result.and_then(|_| {
+            let mut tail = vec![];
+            for cmd in rewrite.buffer.iter() {
+                tail.extend(encode(cmd)?);
+            }
+
+            let mut file = OpenOptions::new().append(true).open(&rewrite.tmp_path)?;
+            file.write_all(&tail)?;
+            file.sync_all()?;
+
+            fs::rename(&rewrite.tmp_path, &self.path)?;
+            return Ok(file);
+        });
+
+        let file = match swapped {
+            Ok(file) => file,
+            Err(e) => {
+                let _ = fs::remove_file(&rewrite.tmp_path);
+                return Err(e);
+            }
+        };
+
+        // Anything still pending for the old log was captured by the rewrite
+        self.size = file.metadata()?.len();
+        self.base_size = self.size;
+        self.written = self.size;
+        self.file = file;
+        self.pending.clear();
+        self.torn = false;
+        self.last_error = None;
+        self.rewrite_error = None;
+
+        return Ok(());
+    }
+
+    /// Writes pending commands to the file and syncs them according to the
+    /// policy, `force` syncs regardless of it.
+    pub(crate) fn sync(&mut self, force: bool) -> Result<()> {
+        let result = self.write_pending().and_then(|_| {
+            let due = match self.policy {
+                FsyncPolicy::Always => true,
+                FsyncPolicy::EverySecond => self.last_fsync.elapsed() >= FSYNC_INTERVAL,
+                FsyncPolicy::Never => false,
+            };
+
+            if force || due {
+                self.file.sync_data()?;
+                self.last_fsync = Instant::now();
+            }
+
+            return Ok(());
+        });
+
+        self.last_error = result.as_ref().err().map(|e| e.to_string());
+
+        return result;
+    }
+}
+
+/// Reads every command from the log at `path`.
+///
+/// A final record that was only partly written when the process died, so has
+/// no trailing newline, is cut from the file rather than failing the load. Any
+/// complete record that doesn't parse errors, wherever it is.
+pub(crate) fn read_log(path: impl AsRef<Path>) -> Result<Vec<Command>> {
+    let path = path.as_ref();
+    if !path.exists() {
+        return Ok(vec![]);
+    }
+
+    let bytes = std::fs::read(path)?;
+    let mut commands = vec![];
+    let mut offset = 0;
+
+    while offset < bytes.len() {
+        let Some(end) = bytes[offset..].iter().position(|b| *b == b'\n') else {
+            // No trailing newline, the last write never completed
+            truncate(path, offset)?;
+            break;
+        };
+
+        let line = &bytes[offset..offset + end];
+        match serde_json::from_slice::<Command>(line) {
+            Ok(cmd) => commands.push(cmd),
+            Err(e) => {
+                return Err(anyhow!(
+                    "corrupt append-only file at record {}: {e}",
+                    commands.len() + 1
+                ));
+            }
+        }
+
+        offset += end + 1;
+    }
+
+    return Ok(commands);
+}
+
+fn truncate(path: &Path, len: usize) -> Result<()> {
+    let file = OpenOptions::new().write(true).open(path)?;
+    file.set_len(len as u64)?;
+    file.sync_all()?;
+
+    return Ok(());
+}
+
+impl GranatStore {
+    /// Replays the append-only file at `path` onto the store, then logs every
+    /// subsequent write to it. Returns the number of commands replayed.
+    ///
+    /// The log is a full history, so this is meant for an empty store rather
+    /// than one already loaded from a snapshot.
+    pub fn enable_aof(&mut self, path: impl AsRef<Path>, policy: FsyncPolicy) -> Result<usize> {
+        if self.aof.is_some() {
+            return Err(anyhow!("append-only file already enabled"));
+        }
+
+        let commands = read_log(path.as_ref())?;
+        let replayed = commands.len();
+        for cmd in commands.into_iter() {
+            cmd.apply(self)?;
+        }
+
+        // Replayed commands are already on disk
+        self.dirty = 0;
+        self.aof = Some(AppendOnlyFile::open(path, policy)?);
+
+        return Ok(replayed);
+    }
+
+    /// Creates a store from the append-only file at `path`, logging future writes to it
+    pub fn open_aof(path: impl AsRef<Path>, policy: FsyncPolicy) -> Result<Self> {
+        let mut store = Self::new();
+        store.enable_aof(path, policy)?;
+
+        return Ok(store);
+    }
+
+    /// Flushes and syncs the append-only file and stops logging writes to it
+    pub fn disable_aof(&mut self) -> Result<()> {
+        if let Some(mut aof) = self.aof.take() {
+            aof.finish_rewrite()?;
+            if let Err(e) = aof.sync(true) {
+                return Err(anyhow!("unable to sync append-only file: {e}"));
+            }
+        }
+
+        return Ok(());
+    }
+
+    /// Flushes and syncs the append-only file regardless of the fsync policy
+    pub fn sync_aof(&mut self) -> Result<()> {
+        if let Some(aof) = self.aof.as_mut() {
+            let _ = aof.sync(true);
+        }
+
+        return self.aof_status();
+    }
+
+    /// The error keeping the append-only file behind the store, if any. Writes
+    /// that failed are retried until they succeed, a failed rewrite lasts
+    /// until the next one succeeds.
+    pub fn aof_status(&self) -> Result<()> {
+        let Some(aof) = self.aof.as_ref() else {
+            return Ok(());
+        };
+
+        if let Some(e) = aof.last_error.as_ref() {
+            return Err(anyhow!("append-only file write failed: {e}"));
+        }
+
+        match aof.rewrite_error.as_ref() {
+            Some(e) => return Err(anyhow!("append-only file rewrite failed: {e}")),
+            None => return Ok(()),
+        }
+    }
+
+    /// Syncs an `EverySecond` log when due, retries a failed write and
+    /// starts or completes background rewrites.
+    pub(crate) fn aof_cron(&mut self) {
+        let Some(aof) = self.aof.as_mut() else {
+            return;
+        };
+
+        // A failure stays in `last_error` and is retried on the next write
+        let _ = aof.sync(false);
+
+        if aof.rewrite.as_ref().is_some_and(|r| r.handle.is_finished()) {
+            if let Err(e) = aof.finish_rewrite() {
+                aof.rewrite_error = Some(e.to_string());
+            }
+        }
+
+        if aof.rewrite_due() {
+            let commands = self.rewrite_commands();
+            if let Some(aof) = self.aof.as_mut() {
+                aof.start_rewrite(commands);
+            }
+        }
+    }
+
+    /// The smallest set of commands that rebuilds the store as it is now
+    fn rewrite_commands(&self) -> Vec<Command> {
+        return self
+            .keyspace
+            .keys()
+            .flat_map(|key| self.dump_key(key, key))
+            .collect::<Vec<Command>>();
+    }
+
+    /// The commands that recreate `key`, with its expiry, under the name
+    /// `as_key`. Empty if the key doesn't exist or has expired.
+    pub(crate) fn dump_key(&self, key: &str, as_key: &str) -> Vec<Command> {
+        let mut commands = vec![];
+        if self.is_expired_key(key) {
+            return commands;
+        }
+
+        let now = self.clock.now_millis();
+        match self.keyspace.get(key) {
+            Some(KeyType::String) => {
+                if let Some(entry) = self.general.store.get(key) {
+                    commands.push(Command::Set {
+                        key: as_key.to_string(),
+                        entry: entry.clone(),
+                    });
+                }
+            }
+            Some(KeyType::List) => {
+                if let Some(list) = self.list.store.get(key) {
+                    for entry in list.iter().filter(|e| !e.is_expired_at(now)) {
+                        commands.push(Command::PushRight {
+                            key: as_key.to_string(),
+                            entry: entry.clone(),
+                        });
+                    }
+                }
+            }
+            Some(KeyType::Hash) => {
+                let fields = self.hash.get_all(key);
+                if !fields.is_empty() {
+                    commands.push(Command::HashSet {
+                        key: as_key.to_string(),
+                        fields,
+                    });
+                }
+            }
+            Some(KeyType::Set) => {
+                let members = self.set.members(key);
+                if !members.is_empty() {
+                    commands.push(Command::SetAdd {
+                        key: as_key.to_string(),
+                        members,
+                    });
+                }
+            }
+            Some(KeyType::SortedSet) => {
+                let members = self.zset.range_by_rank(key, 0, -1, false);
+                if !members.is_empty() {
+                    commands.push(Command::ZAdd {
+                        key: as_key.to_string(),
+                        members,
+                        opts: AddOptions::default(),
+                    });
+                }
+            }
+            Some(KeyType::Stream) => {
+                if let Some(stream) = self.stream.store.get(key) {
+                    commands.push(Command::StreamRestore {
+                        key: as_key.to_string(),
+                        stream: stream.clone(),
+                    });
+                }
+            }
+            Some(KeyType::HyperLogLog) => {
+                if let Some(hll) = self.hll.store.get(key) {
+                    commands.push(Command::HllRestore {
+                        key: as_key.to_string(),
+                        hll: hll.clone(),
+                    });
+                }
+            }
+            None => return commands,
+        }
+
+        if let Some(timestamp) = self.expires.get(key) {
+            commands.push(Command::PExpireAt {
+                key: as_key.to_string(),
+                timestamp: *timestamp,
+                cond: ExpireCondition::Always,
+            });
+        }
+
+        return commands;
+    }
+
+    /// Compacts the append-only file on a background thread.
+    ///
+    /// The store keeps taking writes while the new log is built, they go to
+    /// the current log as normal and are carried over when the new one is
+    /// swapped in, which happens on a later write, `tick` or `wait_for_aof_rewrite`.
+    pub fn rewrite_aof(&mut self) -> Result<()> {
+        let commands = self.rewrite_commands();
+        let Some(aof) = self.aof.as_mut() else {
+            return Err(anyhow!("append-only file not enabled"));
+        };
+
+        if aof.rewrite.is_some() {
+            return Err(anyhow!("append-only file rewrite already in progress"));
+        }
+
+        aof.start_rewrite(commands);
+
+        return Ok(());
+    }
+
+    /// Whether a background rewrite is still in progress
+    pub fn is_rewriting_aof(&self) -> bool {
+        return self.aof.as_ref().is_some_and(|aof| aof.rewrite.is_some());
+    }
+
+    /// Blocks until any running rewrite finishes and has been swapped in
+    pub fn wait_for_aof_rewrite(&mut self) -> Result<()> {
+        let Some(aof) = self.aof.as_mut() else {
+            return Ok(());
+        };
+
+        let result = aof.finish_rewrite();
+        if let Err(e) = result.as_ref() {
+            aof.rewrite_error = Some(e.to_string());
+        }
+
+        return result;
+    }
+
+    /// Sets up, or with `None` turns off, automatic background rewrites
+    pub fn set_aof_auto_rewrite(&mut self, auto: Option<AutoRewrite>) {
+        if let Some(aof) = self.aof.as_mut() {
+            aof.auto_rewrite = auto;
+        }
+    }
+}
+
+#[cfg(test)]
+mod aof_tests {
+    use super::*;
+    use crate::store::bitmap::{BitFieldOp, BitFieldType, BitOperation, Overflow};
+    use crate::store::entry::StoreEntry;
+    use crate::store::keyspace::ExpireCondition;
+    use crate::store::list::InsertPosition;
+    use crate::store::sorted_set::Aggregate;
+    use crate::store::stream::{NewId, StreamId, TrimStrategy};
+    use crate::store::KVPair;
+    use std::ops::Bound;
+    use std::path::PathBuf;
+
+    fn create_kv(key: &str, value: &str) -> KVPair {
+        return (key.to_string(), StoreEntry::new(value));
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+
+        return std::env::temp_dir()
+            .join(format!("granat-{name}-{}-{nanos}.aof", std::process::id()));
+    }
+
+    #[test]
+    fn replays_writes() {
+        let path = temp_path("replay");
+        let mut gs = GranatStore::open_aof(&path, FsyncPolicy::Always).unwrap();
+        let _ = gs.set(create_kv("string", "value"));
+        let _ = gs.set_multiple(vec![create_kv("a", "1"), create_kv("b", "2")]);
+        let _ = gs.increment("counter", 10);
+        let _ = gs.increment("counter", -3);
+        let _ = gs.increment_float("float", 1.5);
+        let _ = gs.push_right(create_kv("list", "0"));
+        let _ = gs.push_right(create_kv("list", "1"));
+        let _ = gs.push_left(create_kv("list", "2"));
+        let _ = gs.pop_right("list");
+        let _ = gs.list_set(create_kv("list", "3"), 1);
+        let _ = gs.list_remove("list", "2", 0);
+        let _ = gs.trim("list", 0, 0);
+        let _ = gs.rename("b", "c");
+        let _ = gs.del(vec!["a"]);
+        let _ = gs.expire("string", 100, ExpireCondition::Always);
+        assert!(gs.disable_aof().is_ok());
+
+        let mut replayed = GranatStore::new();
+        assert_eq!(replayed.enable_aof(&path, FsyncPolicy::Never).unwrap(), 15);
+        assert_eq!(replayed.changes_since_save(), 0);
+
+        assert_eq!(replayed.keys("*"), gs.keys("*"));
+        assert_eq!(replayed.increment("counter", 0).unwrap(), 7);
+        assert_eq!(
+            replayed.get("float").unwrap().unwrap().value,
+            "1.5".to_string()
+        );
+        assert_eq!(
+            replayed.range("list", 0, -1).unwrap(),
+            gs.range("list", 0, -1).unwrap()
+        );
+        assert_eq!(replayed.ttl("string"), 100);
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn failed_writes_are_not_logged() {
+        let path = temp_path("failed");
+        let mut gs = GranatStore::open_aof(&path, FsyncPolicy::Always).unwrap();
+        let _ = gs.set(create_kv("string", "not a number"));
+        assert!(gs.increment("string", 1).is_err());
+        assert!(gs.push_left(create_kv("string", "value")).is_err());
+        let _ = gs.pop_left("missing");
+        let _ = gs.disable_aof();
+
+        assert_eq!(read_log(&path).unwrap().len(), 1);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn failed_appends_are_retried() {
+        let path = temp_path("retried");
+        let mut gs = GranatStore::open_aof(&path, FsyncPolicy::Always).unwrap();
+        let _ = gs.set(create_kv("before", "value"));
+
+        // Every write to /dev/full fails for lack of space
+        let full = OpenOptions::new().append(true).open("/dev/full").unwrap();
+        let file = std::mem::replace(&mut gs.aof.as_mut().unwrap().file, full);
+        assert!(gs.set(create_kv("failed", "value")).is_err());
+        gs.tick();
+        assert!(gs.aof_status().is_err());
+
+        // What a write that gave out part way would leave behind
+        let mut torn = OpenOptions::new().append(true).open(&path).unwrap();
+        torn.write_all(b"{\"Set\":{\"key\"").unwrap();
+
+        gs.aof.as_mut().unwrap().file = file;
+        assert!(gs.set(create_kv("after", "value")).is_ok());
+        assert!(gs.aof_status().is_ok());
+        assert!(gs.disable_aof().is_ok());
+
+        let replayed = GranatStore::open_aof(&path, FsyncPolicy::Never).unwrap();
+        assert_eq!(replayed.keys("*"), vec!["after", "before", "failed"]);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn failed_rewrite_outlasts_later_writes() {
+        let path = temp_path("failed-rewrite");
+        let mut gs = GranatStore::open_aof(&path, FsyncPolicy::Always).unwrap();
+        let _ = gs.set(create_kv("key", "value"));
+
+        // A directory where the rewrite wants to put its new log
+        let mut tmp_path = path.clone().into_os_string();
+        tmp_path.push(".rewrite");
+        let _ = std::fs::create_dir(&tmp_path);
+
+        assert!(gs.rewrite_aof().is_ok());
+        assert!(gs.wait_for_aof_rewrite().is_err());
+        assert!(gs.set(create_kv("key", "other")).is_ok());
+        gs.tick();
+        assert!(gs.aof_status().is_err());
+
+        let _ = std::fs::remove_dir(&tmp_path);
+        assert!(gs.rewrite_aof().is_ok());
+        assert!(gs.wait_for_aof_rewrite().is_ok());
+        assert!(gs.aof_status().is_ok());
+
+        let _ = gs.disable_aof();
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn tolerates_truncated_final_record() {
+        let path = temp_path("truncated");
+        let mut gs = GranatStore::open_aof(&path, FsyncPolicy::Always).unwrap();
+        let _ = gs.set(create_kv("one", "1"));
+        let _ = gs.set(create_kv("two", "2"));
+        let _ = gs.disable_aof();
+
+        let mut raw = std::fs::read(&path).unwrap();
+        let full_len = raw.len();
+        raw.extend_from_slice(b"{\"Set\":{\"key\":\"thr");
+        let _ = std::fs::write(&path, &raw);
+
+        let mut replayed = GranatStore::open_aof(&path, FsyncPolicy::Always).unwrap();
+        assert_eq!(replayed.keys("*").len(), 2);
+        assert_eq!(std::fs::metadata(&path).unwrap().len() as usize, full_len);
+
+        // Appending after the repair leaves a readable log
+        let _ = replayed.set(create_kv("three", "3"));
+        let _ = replayed.disable_aof();
+        assert_eq!(read_log(&path).unwrap().len(), 3);
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn rejects_corruption_before_the_end() {
+        let path = temp_path("corrupt");
+        let _ = std::fs::write(
+            &path,
+            b"{\"Del\":{\"keys\":[\"a\"]}}\nnot a command\n{\"Del\":{\"keys\":[\"b\"]}}\n",
+        );
+
+        assert!(GranatStore::open_aof(&path, FsyncPolicy::Always).is_err());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn rejects_a_complete_final_record_that_does_not_parse() {
+        let path = temp_path("corrupt-last");
+        let raw = b"{\"Del\":{\"keys\":[\"a\"]}}\nnot a command\n";
+        let _ = std::fs::write(&path, raw);
+
+        // It was fully written, so it's kept for inspection rather than cut
+        assert!(GranatStore::open_aof(&path, FsyncPolicy::Always).is_err());
+        assert_eq!(std::fs::read(&path).unwrap(), raw);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn replays_hash_writes() {
+        let path = temp_path("replay-hash");
+        let mut gs = GranatStore::open_aof(&path, FsyncPolicy::Always).unwrap();
+        let _ = gs.hset("hash", vec![create_kv("a", "1"), create_kv("b", "2")]);
+        let _ = gs.hsetnx("hash", create_kv("a", "ignored"));
+        let _ = gs.hincrby("hash", "a", 10);
+        let _ = gs.hincrbyfloat("hash", "c", 0.5);
+        let _ = gs.hdel("hash", vec!["b"]);
+        assert!(gs.disable_aof().is_ok());
+
+        let mut replayed = GranatStore::new();
+        assert_eq!(replayed.enable_aof(&path, FsyncPolicy::Never).unwrap(), 4);
+
+        let mut fields = replayed.hgetall("hash").unwrap();
+        fields.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(fields, vec![create_kv("a", "11"), create_kv("c", "0.5")]);
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn replays_set_writes() {
+        let path = temp_path("replay-set");
+        let mut gs = GranatStore::open_aof(&path, FsyncPolicy::Always).unwrap();
+        let _ = gs.sadd("a", vec!["1", "2", "3", "4"]);
+        let _ = gs.sadd("b", vec!["3", "4"]);
+        let _ = gs.srem("a", vec!["1"]);
+        let _ = gs.spop("a", 1);
+        let _ = gs.sunionstore("c", vec!["a", "b"]);
+        assert!(gs.disable_aof().is_ok());
+
+        let mut replayed = GranatStore::new();
+        assert_eq!(replayed.enable_aof(&path, FsyncPolicy::Never).unwrap(), 5);
+        for key in ["a", "b", "c"] {
+            let mut expected = gs.smembers(key).unwrap();
+            let mut members = replayed.smembers(key).unwrap();
+            expected.sort();
+            members.sort();
+            assert_eq!(members, expected);
+        }
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn replays_bitmap_writes() {
+        let path = temp_path("replay-bitmap");
+        let mut gs = GranatStore::open_aof(&path, FsyncPolicy::Always).unwrap();
+        let _ = gs.setbit("a", 7, true);
+        let _ = gs.setbit("b", 0, true);
+        let _ = gs.bitop(BitOperation::Or, "c", vec!["a", "b"]);
+
+        let ty = BitFieldType::unsigned(4).unwrap();
+        let incr = BitFieldOp::IncrBy {
+            ty,
+            offset: 0,
+            incr: 9,
+        };
+        let _ = gs.bitfield("c", vec![BitFieldOp::Overflow(Overflow::Sat), incr, incr]);
+        let _ = gs.bitfield("c", vec![BitFieldOp::Get { ty, offset: 0 }]);
+        assert!(gs.disable_aof().is_ok());
+
+        let mut replayed = GranatStore::new();
+        assert_eq!(replayed.enable_aof(&path, FsyncPolicy::Never).unwrap(), 4);
+        for key in ["a", "b", "c"] {
+            assert_eq!(replayed.get(key).unwrap(), gs.get(key).unwrap());
+        }
+        assert_eq!(*replayed.get("c").unwrap().unwrap().value, vec![0xF1]);
+
+        // Rewriting keeps bytes that aren't valid UTF-8
+        assert!(replayed.rewrite_aof().is_ok());
+        assert!(replayed.wait_for_aof_rewrite().is_ok());
+        let _ = replayed.disable_aof();
+        assert_eq!(read_log(&path).unwrap().len(), 3);
+
+        let rewritten = GranatStore::open_aof(&path, FsyncPolicy::Never).unwrap();
+        assert_eq!(rewritten.get("c").unwrap(), gs.get("c").unwrap());
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn replays_binary_values() {
+        let path = temp_path("replay-binary");
+        let mut gs = GranatStore::open_aof(&path, FsyncPolicy::Always).unwrap();
+        let blob: &[u8] = &[0xC3, 0x28];
+        let _ = gs.push_right(("list".to_string(), StoreEntry::new(blob)));
+        let _ = gs.push_right(create_kv("list", "text"));
+        let _ = gs.linsert(
+            "list",
+            InsertPosition::Before,
+            blob,
+            StoreEntry::new([0xFF]),
+        );
+        let _ = gs.list_remove("list", "text", 0);
+        assert!(gs.disable_aof().is_ok());
+
+        let mut replayed = GranatStore::new();
+        assert_eq!(replayed.enable_aof(&path, FsyncPolicy::Never).unwrap(), 4);
+        assert_eq!(
+            replayed.range("list", 0, -1).unwrap(),
+            vec![StoreEntry::new([0xFF]), StoreEntry::new(blob)]
+        );
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn replays_sorted_set_writes() {
+        let path = temp_path("replay-zset");
+        let mut gs = GranatStore::open_aof(&path, FsyncPolicy::Always).unwrap();
+        let members = vec![
+            ("low".to_string(), f64::NEG_INFINITY),
+            ("mid".to_string(), 0.1),
+            ("high".to_string(), 3.),
+        ];
+        let _ = gs.zadd("a", members, AddOptions::default());
+        let _ = gs.zincrby("a", "mid", 0.2);
+        let _ = gs.zpopmax("a", 1);
+        let _ = gs.zadd("b", vec![("mid".to_string(), 1.)], AddOptions::default());
+        let _ = gs.zunionstore("c", vec!["a", "b"], Some(vec![2., 1.]), Aggregate::Max);
+        // JSON has no infinities, the weight has to survive the log all the same
+        let infinite = Some(vec![f64::INFINITY]);
+        let _ = gs.zunionstore("d", vec!["b"], infinite, Aggregate::Sum);
+        assert!(gs.disable_aof().is_ok());
+
+        let mut replayed = GranatStore::new();
+        assert_eq!(replayed.enable_aof(&path, FsyncPolicy::Never).unwrap(), 6);
+        assert_eq!(replayed.zscore("d", "mid").unwrap(), Some(f64::INFINITY));
+        for key in ["a", "b", "c", "d"] {
+            assert_eq!(
+                replayed.zrange(key, 0, -1, false).unwrap(),
+                gs.zrange(key, 0, -1, false).unwrap()
+            );
+        }
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn replays_stream_writes() {
+        let path = temp_path("replay-stream");
+        let mut gs = GranatStore::open_aof(&path, FsyncPolicy::Always).unwrap();
+        for i in 0..4 {
+            let fields = vec![("n".to_string(), i.to_string())];
+            let _ = gs.xadd("events", NewId::Auto, fields, Some(TrimStrategy::MaxLen(3)));
+        }
+        let _ = gs.xgroup_create("events", "workers", Some(StreamId::MIN), false);
+        let read = gs.xreadgroup("events", "workers", "a", None, None, false);
+        let ids = read
+            .unwrap()
+            .iter()
+            .map(|(id, _)| *id)
+            .collect::<Vec<StreamId>>();
+        let _ = gs.xack("events", "workers", vec![ids[0]]);
+        let _ = gs.xclaim("events", "workers", "b", 0, vec![ids[1]]);
+
+        // Reads that hand out nothing new still add or refresh the consumer
+        let _ = gs.xreadgroup("events", "workers", "idle", None, None, false);
+        let _ = gs.xreadgroup("events", "workers", "a", Some(StreamId::MIN), None, false);
+        assert!(gs.disable_aof().is_ok());
+
+        let pending = |s: &GranatStore| {
+            return s
+                .xpending(
+                    "events",
+                    "workers",
+                    Bound::Unbounded,
+                    Bound::Unbounded,
+                    10,
+                    None,
+                )
+                .unwrap()
+                .into_iter()
+                .map(|p| (p.id, p.consumer, p.deliveries))
+                .collect::<Vec<(StreamId, String, u64)>>();
+        };
+
+        let mut replayed = GranatStore::new();
+        assert_eq!(replayed.enable_aof(&path, FsyncPolicy::Never).unwrap(), 10);
+        let all = (Bound::Unbounded, Bound::Unbounded);
+        assert_eq!(
+            replayed.xrange("events", all.0, all.1, None).unwrap(),
+            gs.xrange("events", all.0, all.1, None).unwrap()
+        );
+        assert_eq!(
+            replayed.xinfo_groups("events").unwrap(),
+            gs.xinfo_groups("events").unwrap()
+        );
+        assert_eq!(pending(&replayed), pending(&gs));
+
+        // Consumers are seen at the time the live reads ran
+        let seen = |s: &GranatStore| {
+            return ["a", "b", "idle"].map(|c| s.stream.seen_at("events", "workers", c));
+        };
+        assert!(seen(&gs).iter().all(|at| at.is_some()));
+        assert_eq!(seen(&replayed), seen(&gs));
+
+        // A rewrite recreates the stream, groups and all, in one record
+        assert!(replayed.rewrite_aof().is_ok());
+        assert!(replayed.wait_for_aof_rewrite().is_ok());
+        let _ = replayed.disable_aof();
+        assert_eq!(read_log(&path).unwrap().len(), 1);
+
+        let rewritten = GranatStore::open_aof(&path, FsyncPolicy::Never).unwrap();
+        assert_eq!(
+            rewritten.xinfo_stream("events").unwrap(),
+            gs.xinfo_stream("events").unwrap()
+        );
+        assert_eq!(pending(&rewritten), pending(&gs));
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn replays_hyperloglog_writes() {
+        let path = temp_path("replay-hll");
+        let mut gs = GranatStore::open_aof(&path, FsyncPolicy::Always).unwrap();
+        let _ = gs.pfadd("a", vec!["x", "y"]);
+        let _ = gs.pfadd("a", vec!["x"]);
+        let _ = gs.pfadd(
+            "b",
+            (0..5000).map(|i| i.to_string()).collect::<Vec<String>>(),
+        );
+        let _ = gs.pfmerge("c", vec!["a", "b"]);
+        assert!(gs.disable_aof().is_ok());
+
+        // The add that changed nothing isn't logged
+        let mut replayed = GranatStore::new();
+        assert_eq!(replayed.enable_aof(&path, FsyncPolicy::Never).unwrap(), 3);
+        for key in ["a", "b", "c"] {
+            assert_eq!(
+                replayed.pfcount(vec![key]).unwrap(),
+                gs.pfcount(vec![key]).unwrap()
+            );
+        }
+
+        assert!(replayed.rewrite_aof().is_ok());
+        assert!(replayed.wait_for_aof_rewrite().is_ok());
+        let _ = replayed.disable_aof();
+        assert_eq!(read_log(&path).unwrap().len(), 3);
+
+        let rewritten = GranatStore::open_aof(&path, FsyncPolicy::Never).unwrap();
+        assert_eq!(
+            rewritten.pfcount(vec!["a", "b", "c"]).unwrap(),
+            gs.pfcount(vec!["a", "b", "c"]).unwrap()
+        );
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn rewrite_compacts_the_log() {
+        let path = temp_path("rewrite");
+        let mut gs = GranatStore::open_aof(&path, FsyncPolicy::Never).unwrap();
+        for _ in 0..100 {
+            let _ = gs.increment("counter", 1);
+            let _ = gs.push_right(create_kv("queue", "job"));
+            let _ = gs.pop_left("queue");
+        }
+        let _ = gs.push_right(create_kv("queue", "last"));
+        let _ = gs.expire("queue", 100, ExpireCondition::Always);
+        let _ = gs.sync_aof();
+        assert_eq!(read_log(&path).unwrap().len(), 302);
+
+        assert!(gs.rewrite_aof().is_ok());
+        assert!(gs.wait_for_aof_rewrite().is_ok());
+        let _ = gs.disable_aof();
+
+        let commands = read_log(&path).unwrap();
+        assert_eq!(commands.len(), 3);
+
+        let mut replayed = GranatStore::open_aof(&path, FsyncPolicy::Never).unwrap();
+        assert_eq!(replayed.increment("counter", 0).unwrap(), 100);
+        assert_eq!(
+            replayed.index("queue", 0).unwrap().unwrap().value,
+            "last".to_string()
+        );
+        assert_eq!(replayed.ttl("queue"), 100);
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn writes_during_rewrite_are_kept() {
+        let path = temp_path("rewrite-writes");
+        let mut gs = GranatStore::open_aof(&path, FsyncPolicy::Always).unwrap();
+        for i in 0..50 {
+            let _ = gs.set(create_kv(&format!("before:{i}"), "value"));
+        }
+
+        assert!(gs.rewrite_aof().is_ok());
+        assert!(gs.is_rewriting_aof());
+        assert!(gs.rewrite_aof().is_err());
+
+        let _ = gs.del(vec!["before:0"]);
+        for i in 0..50 {
+            let _ = gs.set(create_kv(&format!("during:{i}"), "value"));
+        }
+
+        assert!(gs.wait_for_aof_rewrite().is_ok());
+        assert!(!gs.is_rewriting_aof());
+        let _ = gs.set(create_kv("after", "value"));
+        let _ = gs.disable_aof();
+
+        let replayed = GranatStore::open_aof(&path, FsyncPolicy::Always).unwrap();
+        assert_eq!(replayed.keys("*"), gs.keys("*"));
+        assert_eq!(replayed.keys("*").len(), 100);
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn automatic_rewrite() {
+        let path = temp_path("auto-rewrite");
+        let mut gs = GranatStore::open_aof(&path, FsyncPolicy::Never).unwrap();
+        gs.set_aof_auto_rewrite(Some(AutoRewrite::new(100, 1024)));
+
+        // The same key over and over grows the log but not the store
+        while !gs.is_rewriting_aof() {
+            let _ = gs.set(create_kv("key", "value"));
+        }
+
+        assert!(gs.wait_for_aof_rewrite().is_ok());
+        let _ = gs.disable_aof();
+
+        // The rewrite starts on the write that crossed the threshold, so that
+        // write follows the compacted state
+        assert_eq!(read_log(&path).unwrap().len(), 2);
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
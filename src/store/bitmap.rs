@@ -0,0 +1,607 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use std::str::FromStr;
+
+use crate::store::error::GranatError;
+
+/// Highest bit offset a bitmap can be addressed at, the same 512MB cap as Redis
+pub const MAX_BIT_OFFSET: u64 = (1 << 32) - 1;
+
+/// Errors with `GranatError::BitOffset` if a field of `bits` at `offset` runs past `MAX_BIT_OFFSET`
+fn check_offset(offset: u64, bits: u8) -> Result<()> {
+    match offset.checked_add(bits as u64 - 1) {
+        Some(last) if last <= MAX_BIT_OFFSET => return Ok(()),
+        _ => return Err(GranatError::BitOffset.into()),
+    }
+}
+
+/// Bits are numbered from the most significant bit of the first byte, as in Redis
+fn bit_at(bytes: &[u8], offset: u64) -> bool {
+    let Some(byte) = bytes.get((offset / 8) as usize) else {
+        return false;
+    };
+
+    return byte & (0x80 >> (offset % 8)) != 0;
+}
+
+/// Writes `bit` at `offset`, growing `bytes` with zeros so the offset exists
+fn write_bit(bytes: &mut Vec<u8>, offset: u64, bit: bool) {
+    let idx = (offset / 8) as usize;
+    if idx >= bytes.len() {
+        bytes.resize(idx + 1, 0);
+    }
+
+    let mask = 0x80 >> (offset % 8);
+    match bit {
+        true => bytes[idx] |= mask,
+        false => bytes[idx] &= !mask,
+    }
+}
+
+pub fn get_bit(bytes: &[u8], offset: u64) -> Result<bool> {
+    check_offset(offset, 1)?;
+    return Ok(bit_at(bytes, offset));
+}
+
+/// Sets the bit at `offset`, returning what it was before
+pub fn set_bit(bytes: &mut Vec<u8>, offset: u64, bit: bool) -> Result<bool> {
+    check_offset(offset, 1)?;
+    let old = bit_at(bytes, offset);
+    write_bit(bytes, offset, bit);
+
+    return Ok(old);
+}
+
+/// Whether a `BitRange` counts in bytes or bits, the Redis `BYTE` and `BIT` options
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub enum BitUnit {
+    #[default]
+    Byte,
+    Bit,
+}
+
+/// An inclusive range over a bitmap, negative indexes count back from the end.
+///
+/// A missing `end` runs to the end of the bitmap.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct BitRange {
+    pub start: isize,
+    pub end: Option<isize>,
+    pub unit: BitUnit,
+}
+
+impl BitRange {
+    /// The first and last bit offsets the range covers, `None` if it's empty
+    fn resolve(&self, bytes: &[u8]) -> Option<(u64, u64)> {
+        let len = match self.unit {
+            BitUnit::Byte => bytes.len() as isize,
+            BitUnit::Bit => bytes.len() as isize * 8,
+        };
+
+        let mut start = self.start;
+        let mut end = self.end.unwrap_or(-1);
+        if start < 0 {
+            start = (start + len).max(0);
+        }
+        if end < 0 {
+            end = (end + len).max(0);
+        }
+        end = end.min(len - 1);
+
+        if len == 0 || start > end {
+            return None;
+        }
+
+        match self.unit {
+            BitUnit::Byte => return Some((start as u64 * 8, end as u64 * 8 + 7)),
+            BitUnit::Bit => return Some((start as u64, end as u64)),
+        }
+    }
+}
+
+/// The bytes holding the `first` to `last` bits, masked to keep only the bits in range
+fn masked_bytes(bytes: &[u8], first: u64, last: u64) -> impl Iterator<Item = u8> + '_ {
+    let (first_byte, last_byte) = (first / 8, last / 8);
+    return (first_byte..=last_byte).map(move |idx| {
+        let lo = if idx == first_byte { first % 8 } else { 0 };
+        let hi = if idx == last_byte { last % 8 } else { 7 };
+        let mask = (0xFF >> lo) & (0xFF << (7 - hi));
+
+        return bytes[idx as usize] & mask;
+    });
+}
+
+/// Number of set bits, in `range` if given or across the whole bitmap
+pub fn count(bytes: &[u8], range: Option<BitRange>) -> usize {
+    let Some((first, last)) = range.unwrap_or_default().resolve(bytes) else {
+        return 0;
+    };
+
+    return masked_bytes(bytes, first, last)
+        .map(|byte| byte.count_ones() as usize)
+        .sum();
+}
+
+/// Offset of the first bit set to `bit`, `-1` if there isn't one.
+///
+/// Like Redis, when looking for a clear bit without an explicit end the bitmap
+/// is treated as padded with zeros, so the first bit past its end is returned.
+pub fn position(bytes: &[u8], bit: bool, range: Option<BitRange>) -> i64 {
+    if bytes.is_empty() {
+        return if bit { -1 } else { 0 };
+    }
+
+    let range = range.unwrap_or_default();
+    let Some((first, last)) = range.resolve(bytes) else {
+        return -1;
+    };
+
+    for idx in first..=last {
+        if bit_at(bytes, idx) == bit {
+            return idx as i64;
+        }
+    }
+
+    if !bit && range.end.is_none() {
+        return last as i64 + 1;
+    }
+
+    return -1;
+}
+
+/// Ways of combining bitmaps into one, the Redis `BITOP` operations
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+pub enum BitOperation {
+    And,
+    Or,
+    Xor,
+    /// Flips every bit of a single bitmap
+    Not,
+}
+
+impl BitOperation {
+    /// Combines `sources` byte by byte, shorter ones are padded with zeros.
+    ///
+    /// Errors with `GranatError::BitOpNot` if `Not` isn't given exactly one source.
+    pub fn combine(&self, sources: Vec<&[u8]>) -> Result<Vec<u8>> {
+        if *self == Self::Not {
+            let [source] = sources.as_slice() else {
+                return Err(GranatError::BitOpNot.into());
+            };
+
+            return Ok(source.iter().map(|b| !b).collect::<Vec<u8>>());
+        }
+
+        let len = sources.iter().map(|s| s.len()).max().unwrap_or(0);
+        let mut result = vec![];
+        for idx in 0..len {
+            let mut bytes = sources.iter().map(|s| s.get(idx).copied().unwrap_or(0));
+            let first = bytes.next().unwrap_or(0);
+            result.push(bytes.fold(first, |acc, b| match self {
+                Self::And => acc & b,
+                Self::Or => acc | b,
+                Self::Xor => acc ^ b,
+                Self::Not => unreachable!("handled above"),
+            }));
+        }
+
+        return Ok(result);
+    }
+}
+
+/// The integer type of a bitfield, a signed field can be up to 64 bits and an unsigned one up to 63
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+pub struct BitFieldType {
+    signed: bool,
+    bits: u8,
+}
+
+impl BitFieldType {
+    pub fn signed(bits: u8) -> Result<Self> {
+        if !(1..=64).contains(&bits) {
+            return Err(GranatError::BitFieldType.into());
+        }
+
+        return Ok(Self { signed: true, bits });
+    }
+
+    pub fn unsigned(bits: u8) -> Result<Self> {
+        if !(1..=63).contains(&bits) {
+            return Err(GranatError::BitFieldType.into());
+        }
+
+        return Ok(Self {
+            signed: false,
+            bits,
+        });
+    }
+
+    /// Bit offset of the `n`th field of this type, the Redis `#n` offsets
+    pub fn nth(&self, n: u64) -> u64 {
+        return n.saturating_mul(self.bits as u64);
+    }
+
+    /// The smallest and largest values the field can hold
+    fn bounds(&self) -> (i128, i128) {
+        match self.signed {
+            true => return (-(1 << (self.bits - 1)), (1 << (self.bits - 1)) - 1),
+            false => return (0, (1 << self.bits) - 1),
+        }
+    }
+
+    /// Brings `value` within the field's bounds under `overflow`, `None` if it fails
+    fn fit(&self, value: i128, overflow: Overflow) -> Option<i64> {
+        let (min, max) = self.bounds();
+        if (min..=max).contains(&value) {
+            return Some(value as i64);
+        }
+
+        match overflow {
+            Overflow::Wrap => return Some(((value - min).rem_euclid(1 << self.bits) + min) as i64),
+            Overflow::Sat => return Some(value.clamp(min, max) as i64),
+            Overflow::Fail => return None,
+        }
+    }
+
+    fn read(&self, bytes: &[u8], offset: u64) -> i64 {
+        let mut raw = 0u64;
+        for bit in 0..self.bits as u64 {
+            raw = raw << 1 | bit_at(bytes, offset + bit) as u64;
+        }
+
+        if self.signed {
+            let shift = 64 - self.bits as u32;
+            return ((raw << shift) as i64) >> shift;
+        }
+
+        return raw as i64;
+    }
+
+    fn write(&self, bytes: &mut Vec<u8>, offset: u64, value: i64) {
+        let raw = value as u64;
+        for bit in 0..self.bits as u64 {
+            let shift = self.bits as u64 - 1 - bit;
+            write_bit(bytes, offset + bit, raw >> shift & 1 == 1);
+        }
+    }
+}
+
+impl FromStr for BitFieldType {
+    type Err = GranatError;
+
+    /// Parses the Redis notation, `i` or `u` followed by the width such as `i16` or `u8`
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let parsed = match s.split_at_checked(1) {
+            Some(("i", bits)) => bits.parse::<u8>().ok().map(Self::signed),
+            Some(("u", bits)) => bits.parse::<u8>().ok().map(Self::unsigned),
+            _ => None,
+        };
+
+        match parsed {
+            Some(Ok(ty)) => return Ok(ty),
+            _ => return Err(GranatError::BitFieldType),
+        }
+    }
+}
+
+/// What happens when a bitfield write goes out of the field's range
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub enum Overflow {
+    /// Wraps around, the same as integer overflow in most languages
+    #[default]
+    Wrap,
+    /// Saturates at the smallest or largest value
+    Sat,
+    /// Leaves the field as it is and returns nothing for the operation
+    Fail,
+}
+
+/// A single `BITFIELD` sub-command, offsets are in bits
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+pub enum BitFieldOp {
+    Get {
+        ty: BitFieldType,
+        offset: u64,
+    },
+    /// Returns the value the field held before
+    Set {
+        ty: BitFieldType,
+        offset: u64,
+        value: i64,
+    },
+    /// Returns the value the field holds after
+    IncrBy {
+        ty: BitFieldType,
+        offset: u64,
+        incr: i64,
+    },
+    /// Sets the overflow policy for the writes after it
+    Overflow(Overflow),
+}
+
+impl BitFieldOp {
+    pub fn is_write(&self) -> bool {
+        return matches!(self, Self::Set { .. } | Self::IncrBy { .. });
+    }
+}
+
+/// Reads the field of type `ty` at `offset`, the `BITFIELD_RO` form of `BitFieldOp::Get`
+pub fn read_field(bytes: &[u8], ty: BitFieldType, offset: u64) -> Result<i64> {
+    check_offset(offset, ty.bits)?;
+    return Ok(ty.read(bytes, offset));
+}
+
+/// Runs the `ops` in order, returning a result for each one other than `Overflow`.
+///
+/// Every offset is checked before anything is written, so a bad one leaves
+/// `bytes` untouched. Writes grow `bytes` with zeros to fit the field.
+pub fn bitfield(bytes: &mut Vec<u8>, ops: &[BitFieldOp]) -> Result<Vec<Option<i64>>> {
+    for op in ops.iter() {
+        match op {
+            BitFieldOp::Get { ty, offset }
+            | BitFieldOp::Set { ty, offset, .. }
+            | BitFieldOp::IncrBy { ty, offset, .. } => check_offset(*offset, ty.bits)?,
+            BitFieldOp::Overflow(_) => {}
+        }
+    }
+
+    let mut overflow = Overflow::default();
+    let mut results = vec![];
+    for op in ops.iter() {
+        match *op {
+            BitFieldOp::Get { ty, offset } => results.push(Some(ty.read(bytes, offset))),
+            BitFieldOp::Set { ty, offset, value } => {
+                let old = ty.read(bytes, offset);
+                let Some(value) = ty.fit(value as i128, overflow) else {
+                    results.push(None);
+                    continue;
+                };
+
+                ty.write(bytes, offset, value);
+                results.push(Some(old));
+            }
+            BitFieldOp::IncrBy { ty, offset, incr } => {
+                let old = ty.read(bytes, offset);
+                let Some(value) = ty.fit(old as i128 + incr as i128, overflow) else {
+                    results.push(None);
+                    continue;
+                };
+
+                ty.write(bytes, offset, value);
+                results.push(Some(value));
+            }
+            BitFieldOp::Overflow(policy) => overflow = policy,
+        }
+    }
+
+    return Ok(results);
+}
+
+#[cfg(test)]
+mod bitmap_tests {
+    use super::*;
+
+    #[test]
+    fn set_and_get_bits() {
+        let mut bytes = vec![];
+        assert!(!set_bit(&mut bytes, 7, true).unwrap());
+        assert_eq!(bytes, vec![0x01]);
+        assert!(set_bit(&mut bytes, 7, true).unwrap());
+
+        assert!(!set_bit(&mut bytes, 17, true).unwrap());
+        assert_eq!(bytes, vec![0x01, 0x00, 0x40]);
+        assert!(get_bit(&bytes, 17).unwrap());
+        assert!(!get_bit(&bytes, 16).unwrap());
+        assert!(!get_bit(&bytes, 1000).unwrap());
+
+        assert!(set_bit(&mut bytes, 7, false).unwrap());
+        assert_eq!(bytes, vec![0x00, 0x00, 0x40]);
+
+        assert_eq!(
+            set_bit(&mut bytes, MAX_BIT_OFFSET + 1, true)
+                .unwrap_err()
+                .downcast_ref::<GranatError>(),
+            Some(&GranatError::BitOffset)
+        );
+    }
+
+    #[test]
+    fn count_and_find_bits() {
+        // "foobar", the example from the Redis docs
+        let bytes = b"foobar";
+        assert_eq!(count(bytes, None), 26);
+        let range = |start, end, unit| {
+            return Some(BitRange {
+                start,
+                end: Some(end),
+                unit,
+            });
+        };
+        assert_eq!(count(bytes, range(0, 0, BitUnit::Byte)), 4);
+        assert_eq!(count(bytes, range(1, 1, BitUnit::Byte)), 6);
+        assert_eq!(count(bytes, range(-2, -1, BitUnit::Byte)), 7);
+        assert_eq!(count(bytes, range(5, 30, BitUnit::Bit)), 17);
+        assert_eq!(count(bytes, range(3, 1, BitUnit::Byte)), 0);
+        assert_eq!(count(&[], None), 0);
+
+        let bytes = [0xFF, 0xF0, 0x00];
+        assert_eq!(position(&bytes, false, None), 12);
+        assert_eq!(position(&bytes, true, range(2, -1, BitUnit::Byte)), -1);
+        assert_eq!(position(&bytes, true, range(7, 15, BitUnit::Bit)), 7);
+        assert_eq!(position(&bytes, false, range(0, 0, BitUnit::Byte)), -1);
+
+        // Clear bits past the end are only found when no end is given
+        let full = [0xFF, 0xFF];
+        assert_eq!(position(&full, false, None), 16);
+        let from_bit = Some(BitRange {
+            start: 3,
+            end: None,
+            unit: BitUnit::Bit,
+        });
+        assert_eq!(position(&full, false, from_bit), 16);
+        assert_eq!(position(&full, false, range(0, -1, BitUnit::Byte)), -1);
+
+        assert_eq!(position(&[], true, None), -1);
+        assert_eq!(position(&[], false, None), 0);
+    }
+
+    #[test]
+    fn combine_bitmaps() {
+        let a: &[u8] = &[0b1100_1100, 0xFF];
+        let b: &[u8] = &[0b1010_1010];
+        assert_eq!(
+            BitOperation::And.combine(vec![a, b]).unwrap(),
+            vec![0b1000_1000, 0x00]
+        );
+        assert_eq!(
+            BitOperation::Or.combine(vec![a, b]).unwrap(),
+            vec![0b1110_1110, 0xFF]
+        );
+        assert_eq!(
+            BitOperation::Xor.combine(vec![a, b]).unwrap(),
+            vec![0b0110_0110, 0xFF]
+        );
+        assert_eq!(
+            BitOperation::Not.combine(vec![b]).unwrap(),
+            vec![0b0101_0101]
+        );
+        assert!(BitOperation::Not.combine(vec![a, b]).is_err());
+        assert!(BitOperation::Or.combine(vec![]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn bitfield_types() {
+        assert_eq!(
+            "i8".parse::<BitFieldType>(),
+            Ok(BitFieldType::signed(8).unwrap())
+        );
+        assert!("i64".parse::<BitFieldType>().is_ok());
+        assert!("u63".parse::<BitFieldType>().is_ok());
+        assert_eq!(
+            "u64".parse::<BitFieldType>(),
+            Err(GranatError::BitFieldType)
+        );
+        assert_eq!("i0".parse::<BitFieldType>(), Err(GranatError::BitFieldType));
+        assert_eq!("x8".parse::<BitFieldType>(), Err(GranatError::BitFieldType));
+        assert_eq!("u".parse::<BitFieldType>(), Err(GranatError::BitFieldType));
+        assert_eq!(BitFieldType::unsigned(4).unwrap().nth(3), 12);
+    }
+
+    #[test]
+    fn bitfield_reads_and_writes() {
+        let u8_ty = BitFieldType::unsigned(8).unwrap();
+        let i8_ty = BitFieldType::signed(8).unwrap();
+        let u4 = BitFieldType::unsigned(4).unwrap();
+
+        let mut bytes = vec![];
+        let results = bitfield(
+            &mut bytes,
+            &[
+                BitFieldOp::Set {
+                    ty: u8_ty,
+                    offset: 0,
+                    value: 200,
+                },
+                BitFieldOp::Get {
+                    ty: u8_ty,
+                    offset: 0,
+                },
+                BitFieldOp::Get {
+                    ty: i8_ty,
+                    offset: 0,
+                },
+                BitFieldOp::Get { ty: u4, offset: 4 },
+                BitFieldOp::IncrBy {
+                    ty: u4,
+                    offset: u4.nth(3),
+                    incr: 5,
+                },
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            results,
+            vec![Some(0), Some(200), Some(-56), Some(8), Some(5)]
+        );
+        assert_eq!(bytes, vec![200, 0x05]);
+
+        // A bad offset anywhere means nothing is written
+        let ops = [
+            BitFieldOp::Set {
+                ty: u8_ty,
+                offset: 0,
+                value: 1,
+            },
+            BitFieldOp::Get {
+                ty: u8_ty,
+                offset: MAX_BIT_OFFSET,
+            },
+        ];
+        assert!(bitfield(&mut bytes, &ops).is_err());
+        assert_eq!(bytes, vec![200, 0x05]);
+
+        // Reads past the end don't grow the bitmap
+        let ops = [BitFieldOp::Get {
+            ty: u8_ty,
+            offset: 64,
+        }];
+        assert_eq!(bitfield(&mut bytes, &ops).unwrap(), vec![Some(0)]);
+        assert_eq!(bytes.len(), 2);
+    }
+
+    #[test]
+    fn bitfield_overflow() {
+        let u2 = BitFieldType::unsigned(2).unwrap();
+        let i8_ty = BitFieldType::signed(8).unwrap();
+        let incr = |ty, incr| BitFieldOp::IncrBy {
+            ty,
+            offset: 0,
+            incr,
+        };
+
+        let mut bytes = vec![];
+        let results = bitfield(
+            &mut bytes,
+            &[
+                incr(u2, 5),
+                BitFieldOp::Overflow(Overflow::Sat),
+                incr(u2, 5),
+                incr(u2, -10),
+                BitFieldOp::Overflow(Overflow::Fail),
+                incr(u2, -1),
+                incr(u2, 2),
+            ],
+        )
+        .unwrap();
+        assert_eq!(results, vec![Some(1), Some(3), Some(0), None, Some(2)]);
+
+        let mut bytes = vec![];
+        let results = bitfield(
+            &mut bytes,
+            &[
+                incr(i8_ty, 127),
+                incr(i8_ty, 1),
+                BitFieldOp::Overflow(Overflow::Sat),
+                incr(i8_ty, -1000),
+                BitFieldOp::Set {
+                    ty: i8_ty,
+                    offset: 0,
+                    value: 300,
+                },
+                BitFieldOp::Overflow(Overflow::Fail),
+                BitFieldOp::Set {
+                    ty: i8_ty,
+                    offset: 0,
+                    value: 128,
+                },
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            results,
+            vec![Some(127), Some(-128), Some(-128), Some(-128), None]
+        );
+        assert_eq!(bytes, vec![127]);
+    }
+}
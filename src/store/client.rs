@@ -0,0 +1,1016 @@
+use anyhow::{anyhow, Result};
+
+use std::ops::Bound;
+use std::sync::Arc;
+
+use crate::store::bitmap::{BitFieldOp, BitFieldType, BitOperation, BitRange};
+use crate::store::command::Reply;
+use crate::store::entry::StoreEntry;
+use crate::store::keyspace::{ExpireCondition, KeyType};
+use crate::store::list::{InsertPosition, ListDirection};
+use crate::store::queue::QueuedStore;
+use crate::store::sorted_set::{AddOptions, Aggregate, ScoredMember};
+use crate::store::stream::{
+    ConsumerInfo, GroupInfo, NewId, PendingMessage, StreamEntry, StreamFields, StreamId,
+    StreamInfo, TrimStrategy,
+};
+use crate::store::{GranatStore, KVPair};
+
+fn unexpected(reply: Reply) -> anyhow::Error {
+    return anyhow!("unexpected reply from the write queue: {reply:?}");
+}
+
+/// An async client for a `GranatStore`, cheap to clone and share between tasks.
+///
+/// Writes go through a `QueuedStore`, so awaiting one never blocks the
+/// executor, it resolves once the worker thread has applied it. Reads go to the
+/// store directly and see every write that has already resolved, they only
+/// wait on the store lock for as long as the worker takes to apply a single write.
+///
+/// Futures are plain `std` futures and work with any executor, tokio included.
+#[derive(Clone)]
+pub struct GranatClient {
+    queue: Arc<QueuedStore>,
+}
+
+impl Default for GranatClient {
+    fn default() -> Self {
+        return Self::new(GranatStore::new());
+    }
+}
+
+impl GranatClient {
+    pub fn new(store: GranatStore) -> Self {
+        return Self {
+            queue: Arc::new(QueuedStore::new(store)),
+        };
+    }
+
+    /// Resolves once every write made before it has been applied
+    pub async fn flush(&self) -> Result<()> {
+        self.queue.flush().await?;
+        return Ok(());
+    }
+
+    /// Runs `f` against the store as it is now
+    pub async fn read<T>(&self, f: impl FnOnce(&GranatStore) -> T) -> T {
+        return self.queue.read(f);
+    }
+
+    // Keyspace
+
+    pub async fn type_of(&self, key: impl AsRef<str>) -> Option<KeyType> {
+        return self.queue.type_of(key);
+    }
+
+    pub async fn exists(&self, keys: Vec<impl AsRef<str>>) -> usize {
+        return self.queue.exists(keys);
+    }
+
+    pub async fn keys(&self, pattern: impl AsRef<str>) -> Vec<String> {
+        return self.queue.keys(pattern);
+    }
+
+    pub async fn del(&self, keys: Vec<impl AsRef<str>>) -> Result<usize> {
+        match self.queue.del(keys).await? {
+            Reply::Count(count) => return Ok(count),
+            other => return Err(unexpected(other)),
+        }
+    }
+
+    pub async fn rename(&self, src: impl AsRef<str>, dst: impl AsRef<str>) -> Result<()> {
+        self.queue.rename(src, dst).await?;
+        return Ok(());
+    }
+
+    // Expiry
+
+    pub async fn expire(
+        &self,
+        key: impl AsRef<str>,
+        seconds: i64,
+        cond: ExpireCondition,
+    ) -> Result<bool> {
+        match self.queue.expire(key, seconds, cond).await? {
+            Reply::Bool(set) => return Ok(set),
+            other => return Err(unexpected(other)),
+        }
+    }
+
+    pub async fn pexpire(
+        &self,
+        key: impl AsRef<str>,
+        millis: i64,
+        cond: ExpireCondition,
+    ) -> Result<bool> {
+        match self.queue.pexpire(key, millis, cond).await? {
+            Reply::Bool(set) => return Ok(set),
+            other => return Err(unexpected(other)),
+        }
+    }
+
+    pub async fn persist(&self, key: impl AsRef<str>) -> Result<bool> {
+        match self.queue.persist(key).await? {
+            Reply::Bool(removed) => return Ok(removed),
+            other => return Err(unexpected(other)),
+        }
+    }
+
+    pub async fn ttl(&self, key: impl AsRef<str>) -> i64 {
+        return self.queue.ttl(key);
+    }
+
+    // General
+
+    pub async fn get(&self, key: impl AsRef<str>) -> Result<Option<StoreEntry>> {
+        return self.queue.get(key);
+    }
+
+    pub async fn get_multiple(&self, keys: Vec<impl AsRef<str>>) -> Vec<Option<StoreEntry>> {
+        return self.queue.get_multiple(keys);
+    }
+
+    pub async fn set(&self, kv: KVPair) -> Result<()> {
+        self.queue.set(kv).await?;
+        return Ok(());
+    }
+
+    pub async fn set_multiple(&self, kvs: Vec<KVPair>) -> Result<()> {
+        self.queue.set_multiple(kvs).await?;
+        return Ok(());
+    }
+
+    pub async fn increment(&self, key: impl AsRef<str>, incr: i64) -> Result<i64> {
+        match self.queue.increment(key, incr).await? {
+            Reply::Integer(value) => return Ok(value),
+            other => return Err(unexpected(other)),
+        }
+    }
+
+    pub async fn increment_float(&self, key: impl AsRef<str>, incr: f64) -> Result<f64> {
+        match self.queue.increment_float(key, incr).await? {
+            Reply::Float(value) => return Ok(value),
+            other => return Err(unexpected(other)),
+        }
+    }
+
+    // Bitmap
+
+    pub async fn setbit(&self, key: impl AsRef<str>, offset: u64, bit: bool) -> Result<bool> {
+        match self.queue.setbit(key, offset, bit).await? {
+            Reply::Bool(old) => return Ok(old),
+            other => return Err(unexpected(other)),
+        }
+    }
+
+    pub async fn getbit(&self, key: impl AsRef<str>, offset: u64) -> Result<bool> {
+        return self.queue.getbit(key, offset);
+    }
+
+    pub async fn bitcount(&self, key: impl AsRef<str>, range: Option<BitRange>) -> Result<usize> {
+        return self.queue.bitcount(key, range);
+    }
+
+    pub async fn bitpos(
+        &self,
+        key: impl AsRef<str>,
+        bit: bool,
+        range: Option<BitRange>,
+    ) -> Result<i64> {
+        return self.queue.bitpos(key, bit, range);
+    }
+
+    pub async fn bitop(
+        &self,
+        op: BitOperation,
+        dst: impl AsRef<str>,
+        keys: Vec<impl AsRef<str>>,
+    ) -> Result<usize> {
+        match self.queue.bitop(op, dst, keys).await? {
+            Reply::Count(len) => return Ok(len),
+            other => return Err(unexpected(other)),
+        }
+    }
+
+    pub async fn bitfield(
+        &self,
+        key: impl AsRef<str>,
+        ops: Vec<BitFieldOp>,
+    ) -> Result<Vec<Option<i64>>> {
+        match self.queue.bitfield(key, ops).await? {
+            Reply::Fields(results) => return Ok(results),
+            other => return Err(unexpected(other)),
+        }
+    }
+
+    pub async fn bitfield_ro(
+        &self,
+        key: impl AsRef<str>,
+        fields: Vec<(BitFieldType, u64)>,
+    ) -> Result<Vec<i64>> {
+        return self.queue.bitfield_ro(key, fields);
+    }
+
+    // List
+
+    pub async fn push_left(&self, kv: KVPair) -> Result<usize> {
+        match self.queue.push_left(kv).await? {
+            Reply::Count(len) => return Ok(len),
+            other => return Err(unexpected(other)),
+        }
+    }
+
+    pub async fn push_right(&self, kv: KVPair) -> Result<usize> {
+        match self.queue.push_right(kv).await? {
+            Reply::Count(len) => return Ok(len),
+            other => return Err(unexpected(other)),
+        }
+    }
+
+    pub async fn push_left_many(
+        &self,
+        key: impl AsRef<str>,
+        values: Vec<StoreEntry>,
+    ) -> Result<usize> {
+        match self.queue.push_left_many(key, values).await? {
+            Reply::Count(len) => return Ok(len),
+            other => return Err(unexpected(other)),
+        }
+    }
+
+    pub async fn push_right_many(
+        &self,
+        key: impl AsRef<str>,
+        values: Vec<StoreEntry>,
+    ) -> Result<usize> {
+        match self.queue.push_right_many(key, values).await? {
+            Reply::Count(len) => return Ok(len),
+            other => return Err(unexpected(other)),
+        }
+    }
+
+    pub async fn push_left_if_exists(
+        &self,
+        key: impl AsRef<str>,
+        values: Vec<StoreEntry>,
+    ) -> Result<usize> {
+        match self.queue.push_left_if_exists(key, values).await? {
+            Reply::Count(len) => return Ok(len),
+            other => return Err(unexpected(other)),
+        }
+    }
+
+    pub async fn push_right_if_exists(
+        &self,
+        key: impl AsRef<str>,
+        values: Vec<StoreEntry>,
+    ) -> Result<usize> {
+        match self.queue.push_right_if_exists(key, values).await? {
+            Reply::Count(len) => return Ok(len),
+            other => return Err(unexpected(other)),
+        }
+    }
+
+    pub async fn pop_left(&self, key: impl AsRef<str>) -> Result<Option<StoreEntry>> {
+        match self.queue.pop_left(key).await? {
+            Reply::Entry(entry) => return Ok(entry),
+            other => return Err(unexpected(other)),
+        }
+    }
+
+    pub async fn pop_right(&self, key: impl AsRef<str>) -> Result<Option<StoreEntry>> {
+        match self.queue.pop_right(key).await? {
+            Reply::Entry(entry) => return Ok(entry),
+            other => return Err(unexpected(other)),
+        }
+    }
+
+    pub async fn pop_left_n(&self, key: impl AsRef<str>, count: usize) -> Result<Vec<StoreEntry>> {
+        match self.queue.pop_left_n(key, count).await? {
+            Reply::Entries(entries) => return Ok(entries),
+            other => return Err(unexpected(other)),
+        }
+    }
+
+    pub async fn pop_right_n(&self, key: impl AsRef<str>, count: usize) -> Result<Vec<StoreEntry>> {
+        match self.queue.pop_right_n(key, count).await? {
+            Reply::Entries(entries) => return Ok(entries),
+            other => return Err(unexpected(other)),
+        }
+    }
+
+    pub async fn lmove(
+        &self,
+        src: impl AsRef<str>,
+        dst: impl AsRef<str>,
+        from: ListDirection,
+        to: ListDirection,
+    ) -> Result<Option<StoreEntry>> {
+        match self.queue.lmove(src, dst, from, to).await? {
+            Reply::Entry(entry) => return Ok(entry),
+            other => return Err(unexpected(other)),
+        }
+    }
+
+    pub async fn index(&self, key: impl AsRef<str>, idx: isize) -> Result<Option<StoreEntry>> {
+        return self.queue.index(key, idx);
+    }
+
+    pub async fn position(
+        &self,
+        key: impl AsRef<str>,
+        value: impl AsRef<[u8]>,
+        rank: isize,
+        count: usize,
+        maxlen: usize,
+    ) -> Result<Vec<usize>> {
+        return self.queue.position(key, value, rank, count, maxlen);
+    }
+
+    pub async fn list_len(&self, key: impl AsRef<str>) -> Result<usize> {
+        return self.queue.list_len(key);
+    }
+
+    pub async fn range(
+        &self,
+        key: impl AsRef<str>,
+        start: isize,
+        end: isize,
+    ) -> Result<Vec<StoreEntry>> {
+        return self.queue.range(key, start, end);
+    }
+
+    pub async fn list_set(&self, kv: KVPair, idx: isize) -> Result<()> {
+        self.queue.list_set(kv, idx).await?;
+        return Ok(());
+    }
+
+    pub async fn insert_at(&self, kv: KVPair, idx: isize) -> Result<usize> {
+        match self.queue.insert_at(kv, idx).await? {
+            Reply::Count(len) => return Ok(len),
+            other => return Err(unexpected(other)),
+        }
+    }
+
+    pub async fn linsert(
+        &self,
+        key: impl AsRef<str>,
+        pos: InsertPosition,
+        pivot: impl AsRef<[u8]>,
+        value: StoreEntry,
+    ) -> Result<i64> {
+        match self.queue.linsert(key, pos, pivot, value).await? {
+            Reply::Integer(len) => return Ok(len),
+            other => return Err(unexpected(other)),
+        }
+    }
+
+    pub async fn trim(&self, key: impl AsRef<str>, start: isize, end: isize) -> Result<()> {
+        self.queue.trim(key, start, end).await?;
+        return Ok(());
+    }
+
+    pub async fn list_remove(
+        &self,
+        key: impl AsRef<str>,
+        value: impl AsRef<[u8]>,
+        count: isize,
+    ) -> Result<usize> {
+        match self.queue.list_remove(key, value, count).await? {
+            Reply::Count(removed) => return Ok(removed),
+            other => return Err(unexpected(other)),
+        }
+    }
+
+    // Hash
+
+    pub async fn hset(&self, key: impl AsRef<str>, fields: Vec<KVPair>) -> Result<usize> {
+        match self.queue.hset(key, fields).await? {
+            Reply::Count(added) => return Ok(added),
+            other => return Err(unexpected(other)),
+        }
+    }
+
+    pub async fn hsetnx(&self, key: impl AsRef<str>, field: KVPair) -> Result<bool> {
+        match self.queue.hsetnx(key, field).await? {
+            Reply::Bool(set) => return Ok(set),
+            other => return Err(unexpected(other)),
+        }
+    }
+
+    pub async fn hget(
+        &self,
+        key: impl AsRef<str>,
+        field: impl AsRef<str>,
+    ) -> Result<Option<StoreEntry>> {
+        return self.queue.hget(key, field);
+    }
+
+    pub async fn hdel(&self, key: impl AsRef<str>, fields: Vec<impl AsRef<str>>) -> Result<usize> {
+        match self.queue.hdel(key, fields).await? {
+            Reply::Count(removed) => return Ok(removed),
+            other => return Err(unexpected(other)),
+        }
+    }
+
+    pub async fn hexists(&self, key: impl AsRef<str>, field: impl AsRef<str>) -> Result<bool> {
+        return self.queue.hexists(key, field);
+    }
+
+    pub async fn hlen(&self, key: impl AsRef<str>) -> Result<usize> {
+        return self.queue.hlen(key);
+    }
+
+    pub async fn hkeys(&self, key: impl AsRef<str>) -> Result<Vec<String>> {
+        return self.queue.hkeys(key);
+    }
+
+    pub async fn hvals(&self, key: impl AsRef<str>) -> Result<Vec<StoreEntry>> {
+        return self.queue.hvals(key);
+    }
+
+    pub async fn hgetall(&self, key: impl AsRef<str>) -> Result<Vec<KVPair>> {
+        return self.queue.hgetall(key);
+    }
+
+    pub async fn hincrby(
+        &self,
+        key: impl AsRef<str>,
+        field: impl AsRef<str>,
+        incr: i64,
+    ) -> Result<i64> {
+        match self.queue.hincrby(key, field, incr).await? {
+            Reply::Integer(value) => return Ok(value),
+            other => return Err(unexpected(other)),
+        }
+    }
+
+    pub async fn hincrbyfloat(
+        &self,
+        key: impl AsRef<str>,
+        field: impl AsRef<str>,
+        incr: f64,
+    ) -> Result<f64> {
+        match self.queue.hincrbyfloat(key, field, incr).await? {
+            Reply::Float(value) => return Ok(value),
+            other => return Err(unexpected(other)),
+        }
+    }
+
+    // Set
+
+    pub async fn sadd(&self, key: impl AsRef<str>, members: Vec<impl AsRef<str>>) -> Result<usize> {
+        match self.queue.sadd(key, members).await? {
+            Reply::Count(added) => return Ok(added),
+            other => return Err(unexpected(other)),
+        }
+    }
+
+    pub async fn srem(&self, key: impl AsRef<str>, members: Vec<impl AsRef<str>>) -> Result<usize> {
+        match self.queue.srem(key, members).await? {
+            Reply::Count(removed) => return Ok(removed),
+            other => return Err(unexpected(other)),
+        }
+    }
+
+    pub async fn sismember(&self, key: impl AsRef<str>, member: impl AsRef<str>) -> Result<bool> {
+        return self.queue.sismember(key, member);
+    }
+
+    pub async fn smembers(&self, key: impl AsRef<str>) -> Result<Vec<String>> {
+        return self.queue.smembers(key);
+    }
+
+    pub async fn scard(&self, key: impl AsRef<str>) -> Result<usize> {
+        return self.queue.scard(key);
+    }
+
+    pub async fn spop(&self, key: impl AsRef<str>, count: usize) -> Result<Vec<String>> {
+        match self.queue.spop(key, count).await? {
+            Reply::Members(popped) => return Ok(popped),
+            other => return Err(unexpected(other)),
+        }
+    }
+
+    pub async fn srandmember(&self, key: impl AsRef<str>, count: isize) -> Result<Vec<String>> {
+        return self.queue.srandmember(key, count);
+    }
+
+    pub async fn sunion(&self, keys: Vec<impl AsRef<str>>) -> Result<Vec<String>> {
+        return self.queue.sunion(keys);
+    }
+
+    pub async fn sinter(&self, keys: Vec<impl AsRef<str>>) -> Result<Vec<String>> {
+        return self.queue.sinter(keys);
+    }
+
+    pub async fn sdiff(&self, keys: Vec<impl AsRef<str>>) -> Result<Vec<String>> {
+        return self.queue.sdiff(keys);
+    }
+
+    pub async fn sunionstore(
+        &self,
+        dst: impl AsRef<str>,
+        keys: Vec<impl AsRef<str>>,
+    ) -> Result<usize> {
+        match self.queue.sunionstore(dst, keys).await? {
+            Reply::Count(size) => return Ok(size),
+            other => return Err(unexpected(other)),
+        }
+    }
+
+    pub async fn sinterstore(
+        &self,
+        dst: impl AsRef<str>,
+        keys: Vec<impl AsRef<str>>,
+    ) -> Result<usize> {
+        match self.queue.sinterstore(dst, keys).await? {
+            Reply::Count(size) => return Ok(size),
+            other => return Err(unexpected(other)),
+        }
+    }
+
+    pub async fn sdiffstore(
+        &self,
+        dst: impl AsRef<str>,
+        keys: Vec<impl AsRef<str>>,
+    ) -> Result<usize> {
+        match self.queue.sdiffstore(dst, keys).await? {
+            Reply::Count(size) => return Ok(size),
+            other => return Err(unexpected(other)),
+        }
+    }
+
+    // Sorted set
+
+    pub async fn zadd(
+        &self,
+        key: impl AsRef<str>,
+        members: Vec<ScoredMember>,
+        opts: AddOptions,
+    ) -> Result<usize> {
+        match self.queue.zadd(key, members, opts).await? {
+            Reply::Count(changed) => return Ok(changed),
+            other => return Err(unexpected(other)),
+        }
+    }
+
+    pub async fn zadd_incr(
+        &self,
+        key: impl AsRef<str>,
+        member: impl AsRef<str>,
+        incr: f64,
+        opts: AddOptions,
+    ) -> Result<Option<f64>> {
+        match self.queue.zadd_incr(key, member, incr, opts).await? {
+            Reply::Score(score) => return Ok(score),
+            other => return Err(unexpected(other)),
+        }
+    }
+
+    pub async fn zincrby(
+        &self,
+        key: impl AsRef<str>,
+        member: impl AsRef<str>,
+        incr: f64,
+    ) -> Result<f64> {
+        match self.queue.zincrby(key, member, incr).await? {
+            Reply::Score(score) => return Ok(score.unwrap_or_default()),
+            other => return Err(unexpected(other)),
+        }
+    }
+
+    pub async fn zrem(&self, key: impl AsRef<str>, members: Vec<impl AsRef<str>>) -> Result<usize> {
+        match self.queue.zrem(key, members).await? {
+            Reply::Count(removed) => return Ok(removed),
+            other => return Err(unexpected(other)),
+        }
+    }
+
+    pub async fn zcard(&self, key: impl AsRef<str>) -> Result<usize> {
+        return self.queue.zcard(key);
+    }
+
+    pub async fn zscore(
+        &self,
+        key: impl AsRef<str>,
+        member: impl AsRef<str>,
+    ) -> Result<Option<f64>> {
+        return self.queue.zscore(key, member);
+    }
+
+    pub async fn zrank(
+        &self,
+        key: impl AsRef<str>,
+        member: impl AsRef<str>,
+    ) -> Result<Option<usize>> {
+        return self.queue.zrank(key, member);
+    }
+
+    pub async fn zrevrank(
+        &self,
+        key: impl AsRef<str>,
+        member: impl AsRef<str>,
+    ) -> Result<Option<usize>> {
+        return self.queue.zrevrank(key, member);
+    }
+
+    pub async fn zrange(
+        &self,
+        key: impl AsRef<str>,
+        start: isize,
+        stop: isize,
+        rev: bool,
+    ) -> Result<Vec<ScoredMember>> {
+        return self.queue.zrange(key, start, stop, rev);
+    }
+
+    pub async fn zrange_by_score(
+        &self,
+        key: impl AsRef<str>,
+        min: Bound<f64>,
+        max: Bound<f64>,
+        rev: bool,
+        limit: Option<(usize, usize)>,
+    ) -> Result<Vec<ScoredMember>> {
+        return self.queue.zrange_by_score(key, min, max, rev, limit);
+    }
+
+    pub async fn zrange_by_lex(
+        &self,
+        key: impl AsRef<str>,
+        min: Bound<String>,
+        max: Bound<String>,
+        rev: bool,
+        limit: Option<(usize, usize)>,
+    ) -> Result<Vec<String>> {
+        return self.queue.zrange_by_lex(key, min, max, rev, limit);
+    }
+
+    pub async fn zcount(
+        &self,
+        key: impl AsRef<str>,
+        min: Bound<f64>,
+        max: Bound<f64>,
+    ) -> Result<usize> {
+        return self.queue.zcount(key, min, max);
+    }
+
+    pub async fn zpopmin(&self, key: impl AsRef<str>, count: usize) -> Result<Vec<ScoredMember>> {
+        match self.queue.zpopmin(key, count).await? {
+            Reply::Scored(popped) => return Ok(popped),
+            other => return Err(unexpected(other)),
+        }
+    }
+
+    pub async fn zpopmax(&self, key: impl AsRef<str>, count: usize) -> Result<Vec<ScoredMember>> {
+        match self.queue.zpopmax(key, count).await? {
+            Reply::Scored(popped) => return Ok(popped),
+            other => return Err(unexpected(other)),
+        }
+    }
+
+    pub async fn zunion(
+        &self,
+        keys: Vec<impl AsRef<str>>,
+        weights: Option<Vec<f64>>,
+        aggregate: Aggregate,
+    ) -> Result<Vec<ScoredMember>> {
+        return self.queue.zunion(keys, weights, aggregate);
+    }
+
+    pub async fn zinter(
+        &self,
+        keys: Vec<impl AsRef<str>>,
+        weights: Option<Vec<f64>>,
+        aggregate: Aggregate,
+    ) -> Result<Vec<ScoredMember>> {
+        return self.queue.zinter(keys, weights, aggregate);
+    }
+
+    pub async fn zunionstore(
+        &self,
+        dst: impl AsRef<str>,
+        keys: Vec<impl AsRef<str>>,
+        weights: Option<Vec<f64>>,
+        aggregate: Aggregate,
+    ) -> Result<usize> {
+        match self
+            .queue
+            .zunionstore(dst, keys, weights, aggregate)
+            .await?
+        {
+            Reply::Count(size) => return Ok(size),
+            other => return Err(unexpected(other)),
+        }
+    }
+
+    pub async fn zinterstore(
+        &self,
+        dst: impl AsRef<str>,
+        keys: Vec<impl AsRef<str>>,
+        weights: Option<Vec<f64>>,
+        aggregate: Aggregate,
+    ) -> Result<usize> {
+        match self
+            .queue
+            .zinterstore(dst, keys, weights, aggregate)
+            .await?
+        {
+            Reply::Count(size) => return Ok(size),
+            other => return Err(unexpected(other)),
+        }
+    }
+
+    // Stream
+
+    pub async fn xadd(
+        &self,
+        key: impl AsRef<str>,
+        id: NewId,
+        fields: StreamFields,
+        trim: Option<TrimStrategy>,
+    ) -> Result<StreamId> {
+        match self.queue.xadd(key, id, fields, trim).await? {
+            Reply::StreamId(id) => return Ok(id),
+            other => return Err(unexpected(other)),
+        }
+    }
+
+    pub async fn xtrim(&self, key: impl AsRef<str>, strategy: TrimStrategy) -> Result<usize> {
+        match self.queue.xtrim(key, strategy).await? {
+            Reply::Count(trimmed) => return Ok(trimmed),
+            other => return Err(unexpected(other)),
+        }
+    }
+
+    pub async fn xlen(&self, key: impl AsRef<str>) -> Result<usize> {
+        return self.queue.xlen(key);
+    }
+
+    pub async fn xrange(
+        &self,
+        key: impl AsRef<str>,
+        start: Bound<StreamId>,
+        end: Bound<StreamId>,
+        count: Option<usize>,
+    ) -> Result<Vec<StreamEntry>> {
+        return self.queue.xrange(key, start, end, count);
+    }
+
+    pub async fn xrevrange(
+        &self,
+        key: impl AsRef<str>,
+        end: Bound<StreamId>,
+        start: Bound<StreamId>,
+        count: Option<usize>,
+    ) -> Result<Vec<StreamEntry>> {
+        return self.queue.xrevrange(key, end, start, count);
+    }
+
+    pub async fn xread(
+        &self,
+        streams: Vec<(impl AsRef<str>, StreamId)>,
+        count: Option<usize>,
+    ) -> Result<Vec<(String, Vec<StreamEntry>)>> {
+        return self.queue.xread(streams, count);
+    }
+
+    pub async fn xgroup_create(
+        &self,
+        key: impl AsRef<str>,
+        group: impl AsRef<str>,
+        start: Option<StreamId>,
+        mkstream: bool,
+    ) -> Result<()> {
+        match self
+            .queue
+            .xgroup_create(key, group, start, mkstream)
+            .await?
+        {
+            Reply::Ok => return Ok(()),
+            other => return Err(unexpected(other)),
+        }
+    }
+
+    pub async fn xgroup_destroy(
+        &self,
+        key: impl AsRef<str>,
+        group: impl AsRef<str>,
+    ) -> Result<bool> {
+        match self.queue.xgroup_destroy(key, group).await? {
+            Reply::Bool(destroyed) => return Ok(destroyed),
+            other => return Err(unexpected(other)),
+        }
+    }
+
+    pub async fn xreadgroup(
+        &self,
+        key: impl AsRef<str>,
+        group: impl AsRef<str>,
+        consumer: impl AsRef<str>,
+        after: Option<StreamId>,
+        count: Option<usize>,
+        noack: bool,
+    ) -> Result<Vec<StreamEntry>> {
+        match self
+            .queue
+            .xreadgroup(key, group, consumer, after, count, noack)
+            .await?
+        {
+            Reply::StreamEntries(entries) => return Ok(entries),
+            other => return Err(unexpected(other)),
+        }
+    }
+
+    pub async fn xack(
+        &self,
+        key: impl AsRef<str>,
+        group: impl AsRef<str>,
+        ids: Vec<StreamId>,
+    ) -> Result<usize> {
+        match self.queue.xack(key, group, ids).await? {
+            Reply::Count(acked) => return Ok(acked),
+            other => return Err(unexpected(other)),
+        }
+    }
+
+    pub async fn xpending(
+        &self,
+        key: impl AsRef<str>,
+        group: impl AsRef<str>,
+        start: Bound<StreamId>,
+        end: Bound<StreamId>,
+        count: usize,
+        consumer: Option<&str>,
+    ) -> Result<Vec<PendingMessage>> {
+        return self.queue.xpending(key, group, start, end, count, consumer);
+    }
+
+    pub async fn xclaim(
+        &self,
+        key: impl AsRef<str>,
+        group: impl AsRef<str>,
+        consumer: impl AsRef<str>,
+        min_idle: u64,
+        ids: Vec<StreamId>,
+    ) -> Result<Vec<StreamEntry>> {
+        match self
+            .queue
+            .xclaim(key, group, consumer, min_idle, ids)
+            .await?
+        {
+            Reply::StreamEntries(entries) => return Ok(entries),
+            other => return Err(unexpected(other)),
+        }
+    }
+
+    pub async fn xinfo_stream(&self, key: impl AsRef<str>) -> Result<Option<StreamInfo>> {
+        return self.queue.xinfo_stream(key);
+    }
+
+    pub async fn xinfo_groups(&self, key: impl AsRef<str>) -> Result<Vec<GroupInfo>> {
+        return self.queue.xinfo_groups(key);
+    }
+
+    pub async fn xinfo_consumers(
+        &self,
+        key: impl AsRef<str>,
+        group: impl AsRef<str>,
+    ) -> Result<Vec<ConsumerInfo>> {
+        return self.queue.xinfo_consumers(key, group);
+    }
+
+    // HyperLogLog
+
+    pub async fn pfadd(
+        &self,
+        key: impl AsRef<str>,
+        elements: Vec<impl AsRef<[u8]>>,
+    ) -> Result<bool> {
+        match self.queue.pfadd(key, elements).await? {
+            Reply::Bool(changed) => return Ok(changed),
+            other => return Err(unexpected(other)),
+        }
+    }
+
+    pub async fn pfcount(&self, keys: Vec<impl AsRef<str>>) -> Result<u64> {
+        return self.queue.pfcount(keys);
+    }
+
+    pub async fn pfmerge(&self, dst: impl AsRef<str>, keys: Vec<impl AsRef<str>>) -> Result<()> {
+        match self.queue.pfmerge(dst, keys).await? {
+            Reply::Ok => return Ok(()),
+            other => return Err(unexpected(other)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod client_tests {
+    use super::*;
+    use crate::store::error::GranatError;
+    use std::future::Future;
+    use std::pin::pin;
+    use std::task::{Context, Poll, Wake, Waker};
+    use std::thread::{self, Thread};
+
+    struct ThreadWaker(Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    /// Just enough of an executor to drive a future on the test thread
+    fn block_on<F: Future>(fut: F) -> F::Output {
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        let mut fut = pin!(fut);
+
+        loop {
+            match fut.as_mut().poll(&mut cx) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    fn create_kv(key: &str, value: &str) -> KVPair {
+        return (key.to_string(), StoreEntry::new(value));
+    }
+
+    fn assert_send<T: Send>(_: &T) {}
+
+    #[test]
+    fn futures_are_send() {
+        let client = GranatClient::default();
+        assert_send(&client.set(create_kv("key", "value")));
+        assert_send(&client.get("key"));
+    }
+
+    #[test]
+    fn writes_are_visible_once_resolved() {
+        let client = GranatClient::default();
+        block_on(async {
+            client.set(create_kv("string", "value")).await.unwrap();
+            assert_eq!(
+                client.get("string").await.unwrap().unwrap().value,
+                "value".to_string()
+            );
+
+            assert_eq!(client.increment("counter", 2).await.unwrap(), 2);
+            assert_eq!(client.push_right(create_kv("list", "a")).await.unwrap(), 1);
+            assert_eq!(client.push_right(create_kv("list", "b")).await.unwrap(), 2);
+            assert_eq!(
+                client.pop_left("list").await.unwrap(),
+                Some(StoreEntry::new("a"))
+            );
+            assert!(client
+                .expire("list", 100, ExpireCondition::Always)
+                .await
+                .unwrap());
+            assert!(!client
+                .expire("list", 200, ExpireCondition::Nx)
+                .await
+                .unwrap());
+            assert_eq!(client.ttl("list").await, 100);
+
+            let err = client
+                .push_left(create_kv("string", "x"))
+                .await
+                .unwrap_err();
+            assert_eq!(
+                err.downcast_ref::<GranatError>(),
+                Some(&GranatError::WrongType)
+            );
+        });
+    }
+
+    #[test]
+    fn clients_share_a_store() {
+        let client = GranatClient::default();
+        let tasks = (0..4)
+            .map(|_| {
+                let client = client.clone();
+                return thread::spawn(move || {
+                    block_on(async {
+                        for _ in 0..50 {
+                            client.increment("counter", 1).await.unwrap();
+                        }
+                    })
+                });
+            })
+            .collect::<Vec<thread::JoinHandle<()>>>();
+
+        for task in tasks.into_iter() {
+            task.join().unwrap();
+        }
+
+        block_on(async {
+            client.flush().await.unwrap();
+            assert_eq!(client.increment("counter", 0).await.unwrap(), 200);
+        });
+    }
+}
@@ -0,0 +1,89 @@
+use chrono::Utc;
+
+use std::fmt;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Source of the current time for expiry checks
+pub trait Clock: fmt::Debug + Send + Sync {
+    /// Current Unix timestamp in milliseconds
+    fn now_millis(&self) -> i64;
+}
+
+pub type SharedClock = Arc<dyn Clock>;
+
+/// Shared handle to the system clock, the default for every store
+pub fn system_clock() -> SharedClock {
+    return Arc::new(SystemClock);
+}
+
+/// Wall clock time from the OS
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_millis(&self) -> i64 {
+        return Utc::now().timestamp_millis();
+    }
+}
+
+/// A clock that only moves when told to, for testing expiry without waiting on it
+#[derive(Debug, Default)]
+pub struct MockClock {
+    now: AtomicI64,
+}
+
+impl MockClock {
+    /// Creates a clock stopped at the Unix timestamp `now` in milliseconds
+    pub fn new(now: i64) -> Self {
+        return Self {
+            now: AtomicI64::new(now),
+        };
+    }
+
+    /// Creates a clock stopped at the current system time
+    pub fn starting_now() -> Self {
+        return Self::new(Utc::now().timestamp_millis());
+    }
+
+    pub fn set(&self, now: i64) {
+        self.now.store(now, Ordering::SeqCst);
+    }
+
+    pub fn advance(&self, by: Duration) {
+        self.now.fetch_add(by.as_millis() as i64, Ordering::SeqCst);
+    }
+}
+
+impl Clock for MockClock {
+    fn now_millis(&self) -> i64 {
+        return self.now.load(Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod clock_tests {
+    use super::*;
+
+    #[test]
+    fn mock_clock_only_moves_when_told() {
+        let clock = MockClock::new(1_000);
+        assert_eq!(clock.now_millis(), 1_000);
+
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(clock.now_millis(), 6_000);
+
+        clock.set(42);
+        assert_eq!(clock.now_millis(), 42);
+    }
+
+    #[test]
+    fn shared_mock_clock() {
+        let clock = Arc::new(MockClock::new(0));
+        let shared: SharedClock = clock.clone();
+
+        clock.advance(Duration::from_millis(250));
+        assert_eq!(shared.now_millis(), 250);
+    }
+}
@@ -0,0 +1,475 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::store::bitmap::{BitFieldOp, BitOperation};
+use crate::store::entry::{StoreEntry, Value};
+use crate::store::hyperloglog::HyperLogLog;
+use crate::store::keyspace::ExpireCondition;
+use crate::store::list::{InsertPosition, ListDirection};
+use crate::store::set::SetOperation;
+use crate::store::sorted_set::{
+    scored_members, weights, AddOptions, Aggregate, ScoredMember, SortedSetOperation,
+};
+use crate::store::stream::{NewId, Stream, StreamEntry, StreamFields, StreamId, TrimStrategy};
+use crate::store::{GranatStore, KVPair};
+
+/// A write against a `GranatStore`, as queued by `QueuedStore` and recorded in
+/// the append-only file.
+///
+/// Commands are logged once they've been applied, with anything that depends
+/// on when they ran (expiry deadlines, float increments) resolved to absolute
+/// values so replaying them later rebuilds the same state. That means some are
+/// only ever queued and are logged as what they did instead, while a few are
+/// only ever logged, each of those says so.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub enum Command {
+    Set {
+        key: String,
+        entry: StoreEntry,
+    },
+    SetMultiple {
+        pairs: Vec<KVPair>,
+    },
+    Increment {
+        key: String,
+        incr: i64,
+    },
+    /// Never logged, the result is recorded as a `Set` instead
+    IncrementFloat {
+        key: String,
+        incr: f64,
+    },
+    SetBit {
+        key: String,
+        offset: u64,
+        bit: bool,
+    },
+    BitOp {
+        op: BitOperation,
+        dst: String,
+        keys: Vec<String>,
+    },
+    /// Only logged when at least one of the `ops` writes
+    BitField {
+        key: String,
+        ops: Vec<BitFieldOp>,
+    },
+    PushLeft {
+        key: String,
+        entry: StoreEntry,
+    },
+    HashSet {
+        key: String,
+        fields: Vec<KVPair>,
+    },
+    HashSetNx {
+        key: String,
+        field: KVPair,
+    },
+    HashDelete {
+        key: String,
+        fields: Vec<String>,
+    },
+    HashIncrement {
+        key: String,
+        field: String,
+        incr: i64,
+    },
+    /// Never logged, the result is recorded as a `HashSet` instead
+    HashIncrementFloat {
+        key: String,
+        field: String,
+        incr: f64,
+    },
+    SetAdd {
+        key: String,
+        members: Vec<String>,
+    },
+    SetRemove {
+        key: String,
+        members: Vec<String>,
+    },
+    /// Never logged, the members popped are recorded as a `SetRemove` instead
+    SetPop {
+        key: String,
+        count: usize,
+    },
+    SetCombineStore {
+        op: SetOperation,
+        dst: String,
+        keys: Vec<String>,
+    },
+    ZAdd {
+        key: String,
+        #[serde(with = "scored_members")]
+        members: Vec<ScoredMember>,
+        opts: AddOptions,
+    },
+    /// Never logged, the new score is recorded as a `ZAdd` instead
+    ZAddIncr {
+        key: String,
+        member: String,
+        incr: f64,
+        opts: AddOptions,
+    },
+    ZRemove {
+        key: String,
+        members: Vec<String>,
+    },
+    /// Never logged, the members popped are recorded as a `ZRemove` instead
+    ZPop {
+        key: String,
+        count: usize,
+        rev: bool,
+    },
+    ZCombineStore {
+        op: SortedSetOperation,
+        dst: String,
+        keys: Vec<String>,
+        #[serde(with = "weights")]
+        weights: Option<Vec<f64>>,
+        aggregate: Aggregate,
+    },
+    /// Logged with the ID the entry was given
+    StreamAdd {
+        key: String,
+        id: NewId,
+        fields: StreamFields,
+        trim: Option<TrimStrategy>,
+    },
+    StreamTrim {
+        key: String,
+        strategy: TrimStrategy,
+    },
+    /// Logged with the ID the group starts from
+    StreamGroupCreate {
+        key: String,
+        group: String,
+        start: Option<StreamId>,
+        mkstream: bool,
+    },
+    StreamGroupDestroy {
+        key: String,
+        group: String,
+    },
+    /// Never logged, recorded as a `StreamDeliver` of what it handed out and when instead
+    StreamReadGroup {
+        key: String,
+        group: String,
+        consumer: String,
+        after: Option<StreamId>,
+        count: Option<usize>,
+        noack: bool,
+    },
+    StreamAck {
+        key: String,
+        group: String,
+        ids: Vec<StreamId>,
+    },
+    /// Never logged, recorded as a `StreamDeliver` of what it claimed and when instead
+    StreamClaim {
+        key: String,
+        group: String,
+        consumer: String,
+        min_idle: u64,
+        ids: Vec<StreamId>,
+    },
+    /// Only logged, what group reads and claims delivered, see `StreamStore::deliver`
+    StreamDeliver {
+        key: String,
+        group: String,
+        consumer: String,
+        ids: Vec<StreamId>,
+        delivered_at: i64,
+        last_delivered: StreamId,
+    },
+    /// Only logged by rewrites, recreates a stream along with its groups
+    StreamRestore {
+        key: String,
+        stream: Stream,
+    },
+    HllAdd {
+        key: String,
+        elements: Vec<Value>,
+    },
+    HllMerge {
+        dst: String,
+        keys: Vec<String>,
+    },
+    /// Only logged by rewrites, recreates a HyperLogLog from its registers
+    HllRestore {
+        key: String,
+        hll: HyperLogLog,
+    },
+    PushRight {
+        key: String,
+        entry: StoreEntry,
+    },
+    PopLeft {
+        key: String,
+    },
+    PopRight {
+        key: String,
+    },
+    PushMany {
+        key: String,
+        entries: Vec<StoreEntry>,
+        dir: ListDirection,
+    },
+    PushExisting {
+        key: String,
+        entries: Vec<StoreEntry>,
+        dir: ListDirection,
+    },
+    PopLeftCount {
+        key: String,
+        count: usize,
+    },
+    PopRightCount {
+        key: String,
+        count: usize,
+    },
+    ListSet {
+        key: String,
+        entry: StoreEntry,
+        idx: isize,
+    },
+    Trim {
+        key: String,
+        start: isize,
+        end: isize,
+    },
+    ListRemove {
+        key: String,
+        value: Value,
+        count: isize,
+    },
+    ListInsertAt {
+        key: String,
+        entry: StoreEntry,
+        idx: isize,
+    },
+    ListInsert {
+        key: String,
+        pos: InsertPosition,
+        pivot: Value,
+        entry: StoreEntry,
+    },
+    ListMove {
+        src: String,
+        dst: String,
+        from: ListDirection,
+        to: ListDirection,
+    },
+    Del {
+        keys: Vec<String>,
+    },
+    Rename {
+        src: String,
+        dst: String,
+    },
+    /// Logged with `Always`, the condition was met when it was applied
+    PExpireAt {
+        key: String,
+        timestamp: i64,
+        #[serde(default)]
+        cond: ExpireCondition,
+    },
+    Persist {
+        key: String,
+    },
+}
+
+/// What running a `Command` returned
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reply {
+    Ok,
+    Bool(bool),
+    Integer(i64),
+    Float(f64),
+    Count(usize),
+    Entry(Option<StoreEntry>),
+    Entries(Vec<StoreEntry>),
+    Members(Vec<String>),
+    /// Results of a `BitField`, `None` where a write failed on overflow
+    Fields(Vec<Option<i64>>),
+    Score(Option<f64>),
+    Scored(Vec<ScoredMember>),
+    StreamId(StreamId),
+    StreamEntries(Vec<StreamEntry>),
+}
+
+impl Command {
+    /// Runs the command against `store`
+    pub fn apply(self, store: &mut GranatStore) -> Result<()> {
+        self.execute(store)?;
+
+        return Ok(());
+    }
+
+    /// Runs the command against `store`, returning what the matching store method did
+    pub fn execute(self, store: &mut GranatStore) -> Result<Reply> {
+        let reply = match self {
+            Self::Set { key, entry } => {
+                store.set((key, entry))?;
+                Reply::Ok
+            }
+            Self::SetMultiple { pairs } => {
+                store.set_multiple(pairs)?;
+                Reply::Ok
+            }
+            Self::Increment { key, incr } => Reply::Integer(store.increment(key, incr)?),
+            Self::IncrementFloat { key, incr } => Reply::Float(store.increment_float(key, incr)?),
+            Self::SetBit { key, offset, bit } => Reply::Bool(store.setbit(key, offset, bit)?),
+            Self::BitOp { op, dst, keys } => Reply::Count(store.bitop(op, dst, keys)?),
+            Self::BitField { key, ops } => Reply::Fields(store.bitfield(key, ops)?),
+            Self::PushLeft { key, entry } => Reply::Count(store.push_left((key, entry))?),
+            Self::HashSet { key, fields } => Reply::Count(store.hset(key, fields)?),
+            Self::HashSetNx { key, field } => Reply::Bool(store.hsetnx(key, field)?),
+            Self::HashDelete { key, fields } => Reply::Count(store.hdel(key, fields)?),
+            Self::HashIncrement { key, field, incr } => {
+                Reply::Integer(store.hincrby(key, field, incr)?)
+            }
+            Self::HashIncrementFloat { key, field, incr } => {
+                Reply::Float(store.hincrbyfloat(key, field, incr)?)
+            }
+            Self::SetAdd { key, members } => Reply::Count(store.sadd(key, members)?),
+            Self::SetRemove { key, members } => Reply::Count(store.srem(key, members)?),
+            Self::SetPop { key, count } => Reply::Members(store.spop(key, count)?),
+            Self::SetCombineStore { op, dst, keys } => {
+                Reply::Count(store.set_combine_store(op, &dst, &keys)?)
+            }
+            Self::ZAdd { key, members, opts } => Reply::Count(store.zadd(key, members, opts)?),
+            Self::ZAddIncr {
+                key,
+                member,
+                incr,
+                opts,
+            } => Reply::Score(store.zadd_incr(key, member, incr, opts)?),
+            Self::ZRemove { key, members } => Reply::Count(store.zrem(key, members)?),
+            Self::ZPop { key, count, rev } => match rev {
+                true => Reply::Scored(store.zpopmax(key, count)?),
+                false => Reply::Scored(store.zpopmin(key, count)?),
+            },
+            Self::ZCombineStore {
+                op,
+                dst,
+                keys,
+                weights,
+                aggregate,
+            } => Reply::Count(store.zset_combine_store(
+                op,
+                &dst,
+                &keys,
+                weights.as_deref(),
+                aggregate,
+            )?),
+            Self::StreamAdd {
+                key,
+                id,
+                fields,
+                trim,
+            } => Reply::StreamId(store.xadd(key, id, fields, trim)?),
+            Self::StreamTrim { key, strategy } => Reply::Count(store.xtrim(key, strategy)?),
+            Self::StreamGroupCreate {
+                key,
+                group,
+                start,
+                mkstream,
+            } => {
+                store.xgroup_create(key, group, start, mkstream)?;
+                Reply::Ok
+            }
+            Self::StreamGroupDestroy { key, group } => {
+                Reply::Bool(store.xgroup_destroy(key, group)?)
+            }
+            Self::StreamReadGroup {
+                key,
+                group,
+                consumer,
+                after,
+                count,
+                noack,
+            } => Reply::StreamEntries(store.xreadgroup(key, group, consumer, after, count, noack)?),
+            Self::StreamAck { key, group, ids } => Reply::Count(store.xack(key, group, ids)?),
+            Self::StreamClaim {
+                key,
+                group,
+                consumer,
+                min_idle,
+                ids,
+            } => Reply::StreamEntries(store.xclaim(key, group, consumer, min_idle, ids)?),
+            Self::StreamDeliver {
+                key,
+                group,
+                consumer,
+                ids,
+                delivered_at,
+                last_delivered,
+            } => {
+                store.stream_deliver(&key, &group, &consumer, ids, delivered_at, last_delivered)?;
+                Reply::Ok
+            }
+            Self::StreamRestore { key, stream } => {
+                store.stream_restore(&key, stream)?;
+                Reply::Ok
+            }
+            Self::HllAdd { key, elements } => Reply::Bool(store.pfadd(key, elements)?),
+            Self::HllMerge { dst, keys } => {
+                store.pfmerge(dst, keys)?;
+                Reply::Ok
+            }
+            Self::HllRestore { key, hll } => {
+                store.hll_restore(&key, hll)?;
+                Reply::Ok
+            }
+            Self::PushRight { key, entry } => Reply::Count(store.push_right((key, entry))?),
+            Self::PopLeft { key } => Reply::Entry(store.pop_left(key)?),
+            Self::PopRight { key } => Reply::Entry(store.pop_right(key)?),
+            Self::PushMany { key, entries, dir } => match dir {
+                ListDirection::Left => Reply::Count(store.push_left_many(key, entries)?),
+                ListDirection::Right => Reply::Count(store.push_right_many(key, entries)?),
+            },
+            Self::PushExisting { key, entries, dir } => match dir {
+                ListDirection::Left => Reply::Count(store.push_left_if_exists(key, entries)?),
+                ListDirection::Right => Reply::Count(store.push_right_if_exists(key, entries)?),
+            },
+            Self::PopLeftCount { key, count } => Reply::Entries(store.pop_left_n(key, count)?),
+            Self::PopRightCount { key, count } => Reply::Entries(store.pop_right_n(key, count)?),
+            Self::ListSet { key, entry, idx } => {
+                store.list_set((key, entry), idx)?;
+                Reply::Ok
+            }
+            Self::Trim { key, start, end } => {
+                store.trim(key, start, end)?;
+                Reply::Ok
+            }
+            Self::ListRemove { key, value, count } => {
+                Reply::Count(store.list_remove(key, value, count)?)
+            }
+            Self::ListInsertAt { key, entry, idx } => {
+                Reply::Count(store.insert_at((key, entry), idx)?)
+            }
+            Self::ListInsert {
+                key,
+                pos,
+                pivot,
+                entry,
+            } => Reply::Integer(store.linsert(key, pos, pivot, entry)?),
+            Self::ListMove { src, dst, from, to } => Reply::Entry(store.lmove(src, dst, from, to)?),
+            Self::Del { keys } => Reply::Count(store.del(keys)),
+            Self::Rename { src, dst } => {
+                store.rename(src, dst)?;
+                Reply::Ok
+            }
+            Self::PExpireAt {
+                key,
+                timestamp,
+                cond,
+            } => Reply::Bool(store.pexpire_at(key, timestamp, cond)),
+            Self::Persist { key } => Reply::Bool(store.persist(key)),
+        };
+
+        return Ok(reply);
+    }
+}
@@ -1,67 +1,321 @@
 use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::string::FromUtf8Error;
+use std::time::Duration;
 
 use anyhow::{anyhow, Result};
-use chrono::Utc;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
-#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Hash, Eq)]
+use crate::store::clock::{Clock, SystemClock};
+use crate::store::error::GranatError;
+
+/// `Active` holds the deadline as a Unix timestamp in milliseconds
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq, Hash, Eq)]
 pub enum ExpiryState {
     Expired,
     Active(i64),
+    #[default]
     NoExpiry,
 }
 
-#[derive(Debug, Hash, Deserialize, Serialize, PartialEq, Eq)]
+/// The raw bytes held by an entry, compares equal to text holding the same bytes
+#[derive(Debug, Clone, Default, Hash, PartialEq, Eq)]
+pub struct Value(Vec<u8>);
+
+/// Text is persisted as a string, so files written before values were bytes
+/// still load, anything else as an array of bytes
+impl Serialize for Value {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        match std::str::from_utf8(&self.0) {
+            Ok(text) => return serializer.serialize_str(text),
+            Err(_) => return serializer.serialize_bytes(&self.0),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Value {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Persisted {
+            Text(String),
+            Bytes(Vec<u8>),
+        }
+
+        match Persisted::deserialize(deserializer)? {
+            Persisted::Text(text) => return Ok(Self(text.into_bytes())),
+            Persisted::Bytes(bytes) => return Ok(Self(bytes)),
+        }
+    }
+}
+
+impl Deref for Value {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Self::Target {
+        return &self.0;
+    }
+}
+
+impl DerefMut for Value {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        return &mut self.0;
+    }
+}
+
+impl Value {
+    /// The value as text, `None` if it isn't valid UTF-8
+    pub fn as_str(&self) -> Option<&str> {
+        return std::str::from_utf8(&self.0).ok();
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        return self.0;
+    }
+}
+
+impl AsRef<[u8]> for Value {
+    fn as_ref(&self) -> &[u8] {
+        return &self.0;
+    }
+}
+
+impl From<Vec<u8>> for Value {
+    fn from(bytes: Vec<u8>) -> Self {
+        return Self(bytes);
+    }
+}
+
+impl From<&[u8]> for Value {
+    fn from(bytes: &[u8]) -> Self {
+        return Self(bytes.to_vec());
+    }
+}
+
+impl From<String> for Value {
+    fn from(text: String) -> Self {
+        return Self(text.into_bytes());
+    }
+}
+
+impl From<&str> for Value {
+    fn from(text: &str) -> Self {
+        return Self(text.as_bytes().to_vec());
+    }
+}
+
+/// Takes the bytes back without copying, failing if they aren't valid UTF-8
+impl TryFrom<Value> for String {
+    type Error = FromUtf8Error;
+
+    fn try_from(value: Value) -> std::result::Result<Self, Self::Error> {
+        return String::from_utf8(value.0);
+    }
+}
+
+impl PartialEq<[u8]> for Value {
+    fn eq(&self, other: &[u8]) -> bool {
+        return self.0 == other;
+    }
+}
+
+impl PartialEq<&[u8]> for Value {
+    fn eq(&self, other: &&[u8]) -> bool {
+        return self.0 == *other;
+    }
+}
+
+impl PartialEq<Vec<u8>> for Value {
+    fn eq(&self, other: &Vec<u8>) -> bool {
+        return self.0 == *other;
+    }
+}
+
+impl PartialEq<str> for Value {
+    fn eq(&self, other: &str) -> bool {
+        return self.0 == other.as_bytes();
+    }
+}
+
+impl PartialEq<&str> for Value {
+    fn eq(&self, other: &&str) -> bool {
+        return self.0 == other.as_bytes();
+    }
+}
+
+impl PartialEq<String> for Value {
+    fn eq(&self, other: &String) -> bool {
+        return self.0 == other.as_bytes();
+    }
+}
+
+/// Text is shown as is, anything that isn't valid UTF-8 is shown lossily
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", String::from_utf8_lossy(&self.0))
+    }
+}
+
+#[derive(Debug, Default, Hash, Deserialize, Serialize, PartialEq, Eq)]
 pub struct StoreEntry {
-    pub value: String,
+    pub value: Value,
     pub expiry: ExpiryState,
 }
 
 impl StoreEntry {
-    pub fn new(value: impl AsRef<str>) -> Self {
+    /// Takes anything that can be viewed as bytes, text or binary alike
+    pub fn new(value: impl AsRef<[u8]>) -> Self {
+        return Self::from_bytes(value.as_ref());
+    }
+
+    /// Like `new` but takes ownership of the bytes rather than copying them
+    pub fn from_bytes(value: impl Into<Vec<u8>>) -> Self {
         return Self {
-            value: value.as_ref().to_string(),
+            value: Value(value.into()),
             expiry: ExpiryState::NoExpiry,
         };
     }
 
     pub fn from_obj<T: Serialize>(obj: &T) -> Result<Self> {
-        match serde_json::to_string::<T>(obj) {
-            Ok(obj_str) => {
-                return Ok(Self {
-                    value: obj_str,
-                    expiry: ExpiryState::NoExpiry,
-                })
-            }
+        match serde_json::to_vec::<T>(obj) {
+            Ok(obj_bytes) => return Ok(Self::from_bytes(obj_bytes)),
             Err(e) => return Err(anyhow!("unable to serialize object: {e}")),
         }
     }
 
-    pub fn expires_in(mut self, expiry_time: i64) -> Self {
-        let now = Utc::now().timestamp();
-        self.expiry = ExpiryState::Active(now + expiry_time);
+    /// Sets the entry to expire `expiry_time` seconds from now
+    pub fn expires_in(self, expiry_time: i64) -> Self {
+        return self.expires_in_with(expiry_time, &SystemClock);
+    }
+
+    /// Sets the entry to expire `expiry_time` seconds from now according to `clock`
+    pub fn expires_in_with(self, expiry_time: i64, clock: &dyn Clock) -> Self {
+        return self.expires_in_millis_with(expiry_time.saturating_mul(1000), clock);
+    }
+
+    /// Sets the entry to expire `expiry_time` milliseconds from now
+    pub fn expires_in_millis(self, expiry_time: i64) -> Self {
+        return self.expires_in_millis_with(expiry_time, &SystemClock);
+    }
+
+    /// Sets the entry to expire `expiry_time` milliseconds from now according to `clock`
+    pub fn expires_in_millis_with(self, expiry_time: i64, clock: &dyn Clock) -> Self {
+        return self.expires_at(clock.now_millis().saturating_add(expiry_time));
+    }
+
+    /// Sets the entry to expire at the Unix `timestamp` in milliseconds
+    pub fn expires_at(mut self, timestamp: i64) -> Self {
+        self.expiry = ExpiryState::Active(timestamp);
         return self;
     }
 
+    pub fn as_bytes(&self) -> &[u8] {
+        return &self.value;
+    }
+
+    /// The value as text, `None` if it isn't valid UTF-8
+    pub fn as_str(&self) -> Option<&str> {
+        return self.value.as_str();
+    }
+
+    /// Adds `incr` to the value, which has to hold an integer, returning the new value.
+    ///
+    /// Errors with `GranatError::Overflow`, leaving the value as it was, if the result doesn't fit.
+    pub fn increment(&mut self, incr: i64) -> Result<i64> {
+        let Some(text) = self.as_str() else {
+            return Err(anyhow!(
+                "unable to convert to integer: value isn't valid UTF-8"
+            ));
+        };
+
+        match text.parse::<i64>() {
+            Ok(val) => {
+                let Some(val) = val.checked_add(incr) else {
+                    return Err(GranatError::Overflow.into());
+                };
+                self.value = val.to_string().into();
+
+                return Ok(val);
+            }
+            Err(e) => return Err(anyhow!("unable to convert to integer: {e}")),
+        }
+    }
+
+    /// Adds `incr` to the value, which has to hold a float, returning the new value.
+    ///
+    /// Errors with `GranatError::NotFinite`, leaving the value as it was, if the result would be
+    /// infinite or NaN.
+    pub fn increment_float(&mut self, incr: f64) -> Result<f64> {
+        let Some(text) = self.as_str() else {
+            return Err(anyhow!(
+                "unable to convert to float: value isn't valid UTF-8"
+            ));
+        };
+
+        match text.parse::<f64>() {
+            Ok(val) => {
+                let val = val + incr;
+                if !val.is_finite() {
+                    return Err(GranatError::NotFinite.into());
+                }
+                self.value = val.to_string().into();
+
+                return Ok(val);
+            }
+            Err(e) => return Err(anyhow!("unable to convert to float: {e}")),
+        }
+    }
+
     pub fn to_obj<T: serde::de::DeserializeOwned>(&self) -> Result<T> {
-        match serde_json::from_str::<T>(&self.value) {
+        match serde_json::from_slice::<T>(&self.value) {
             Ok(obj) => return Ok(obj),
             Err(e) => return Err(anyhow!("unable to deserialize object: {e}")),
         };
     }
 
     pub fn ttl(&mut self) -> ExpiryState {
+        return self.ttl_at(SystemClock.now_millis());
+    }
+
+    /// `ttl` as of `now`, a Unix timestamp in milliseconds
+    pub fn ttl_at(&mut self, now: i64) -> ExpiryState {
+        if let ExpiryState::Active(exp) = self.expiry {
+            if exp < now {
+                self.expiry = ExpiryState::Expired;
+            }
+        }
+
+        return self.expiry.clone();
+    }
+
+    /// Time left before the entry expires, `None` if it never does
+    pub fn remaining(&self) -> Option<Duration> {
+        return self.remaining_at(SystemClock.now_millis());
+    }
+
+    /// `remaining` as of `now`, a Unix timestamp in milliseconds
+    pub fn remaining_at(&self, now: i64) -> Option<Duration> {
         match self.expiry {
+            ExpiryState::Expired => return Some(Duration::ZERO),
             ExpiryState::Active(exp) => {
-                let now = Utc::now().timestamp();
-                if exp < now {
-                    self.expiry = ExpiryState::Expired;
-                }
+                return Some(Duration::from_millis(exp.saturating_sub(now).max(0) as u64));
             }
-            _ => {}
+            ExpiryState::NoExpiry => return None,
         }
+    }
 
-        return self.expiry.clone();
+    /// Whether the entry's deadline has passed, unlike `ttl` this doesn't update the stored state
+    pub fn is_expired(&self) -> bool {
+        return self.is_expired_at(SystemClock.now_millis());
+    }
+
+    /// `is_expired` as of `now`, a Unix timestamp in milliseconds
+    pub fn is_expired_at(&self, now: i64) -> bool {
+        match self.expiry {
+            ExpiryState::Expired => return true,
+            ExpiryState::Active(exp) => return exp < now,
+            ExpiryState::NoExpiry => return false,
+        }
     }
 }
 
@@ -76,13 +330,15 @@ impl Clone for StoreEntry {
 
 impl fmt::Display for StoreEntry {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.value.to_string())
+        write!(f, "{}", self.value)
     }
 }
 
 #[cfg(test)]
 mod entry_tests {
     use super::*;
+    use crate::store::clock::MockClock;
+    use chrono::Utc;
     use serde::{Deserialize, Serialize};
 
     #[derive(Serialize, Deserialize)]
@@ -107,17 +363,60 @@ mod entry_tests {
     }
 
     #[test]
-    #[ignore]
-    /// Takes ages to run
     fn create_entry_with_expiry() {
-        let mut entry = StoreEntry::new("five hundred").expires_in(5);
-        assert_eq!(entry.ttl(), ExpiryState::Active(Utc::now().timestamp() + 5));
+        let now = Utc::now().timestamp_millis();
+        let mut entry = StoreEntry::new("five hundred").expires_at(now + 5000);
+        assert_eq!(entry.ttl_at(now), ExpiryState::Active(now + 5000));
+        assert_eq!(entry.remaining_at(now), Some(Duration::from_millis(5000)));
 
-        while entry.ttl() != ExpiryState::Expired {
-            std::thread::sleep(std::time::Duration::from_secs(1));
-        }
+        assert_eq!(entry.ttl_at(now + 4999), ExpiryState::Active(now + 5000));
+        assert_eq!(
+            entry.remaining_at(now + 4999),
+            Some(Duration::from_millis(1))
+        );
+        assert!(!entry.is_expired_at(now + 5000));
+
+        assert_eq!(entry.ttl_at(now + 5001), ExpiryState::Expired);
+        assert_eq!(entry.remaining_at(now + 5001), Some(Duration::ZERO));
+        assert!(entry.is_expired());
+    }
+
+    #[test]
+    fn expiry_against_a_mock_clock() {
+        let clock = MockClock::new(0);
+        let mut entry = StoreEntry::new("value").expires_in_with(5, &clock);
+        assert_eq!(entry.ttl_at(clock.now_millis()), ExpiryState::Active(5000));
+
+        clock.advance(Duration::from_millis(5001));
+        assert_eq!(entry.ttl_at(clock.now_millis()), ExpiryState::Expired);
 
-        assert_eq!(entry.ttl(), ExpiryState::Expired);
+        let entry = StoreEntry::new("value").expires_in_millis_with(250, &clock);
+        assert_eq!(entry.expiry, ExpiryState::Active(5251));
+    }
+
+    #[test]
+    fn expiry_in_milliseconds() {
+        let entry = StoreEntry::new("value").expires_in_millis(1500);
+        let remaining = entry.remaining().unwrap();
+        assert!(remaining > Duration::from_millis(1000));
+        assert!(remaining <= Duration::from_millis(1500));
+
+        assert!(StoreEntry::new("value").remaining().is_none());
+    }
+
+    #[test]
+    fn expired_entries() {
+        let mut entry = StoreEntry::new("value");
+        assert!(!entry.is_expired());
+
+        entry.expiry = ExpiryState::Active(Utc::now().timestamp_millis() - 10_000);
+        assert!(entry.is_expired());
+
+        entry.expiry = ExpiryState::Active(Utc::now().timestamp_millis() + 10_000);
+        assert!(!entry.is_expired());
+
+        entry.expiry = ExpiryState::Expired;
+        assert!(entry.is_expired());
     }
 
     #[test]
@@ -156,6 +455,67 @@ mod entry_tests {
         assert_eq!(inner_from_output.message, "some message".to_string());
     }
 
+    #[test]
+    fn persist_text_and_bytes() {
+        let text = serde_json::to_string(&StoreEntry::new("text")).unwrap();
+        assert_eq!(text, "{\"value\":\"text\",\"expiry\":\"NoExpiry\"}");
+        assert_eq!(
+            serde_json::from_str::<StoreEntry>(&text).unwrap(),
+            StoreEntry::new("text")
+        );
+
+        let bytes = StoreEntry::from_bytes(vec![0xFF, 0x00]);
+        let raw = serde_json::to_string(&bytes).unwrap();
+        assert_eq!(serde_json::from_str::<StoreEntry>(&raw).unwrap(), bytes);
+        assert_eq!(bytes.to_string(), "\u{FFFD}\0");
+    }
+
+    #[test]
+    fn increments_stay_in_range() {
+        let mut int = StoreEntry::new(i64::MAX.to_string());
+        let err = int.increment(1).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<GranatError>(),
+            Some(&GranatError::Overflow)
+        );
+        assert_eq!(int.value, i64::MAX.to_string());
+        assert_eq!(int.increment(-1).unwrap(), i64::MAX - 1);
+
+        let mut int = StoreEntry::new(i64::MIN.to_string());
+        assert!(int.increment(-1).is_err());
+
+        let mut float = StoreEntry::new("1e308");
+        let err = float.increment_float(1e308).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<GranatError>(),
+            Some(&GranatError::NotFinite)
+        );
+        assert_eq!(float.value, "1e308".to_string());
+        assert!(float.increment_float(f64::NAN).is_err());
+        assert!(StoreEntry::new("inf").increment_float(1.).is_err());
+    }
+
+    #[test]
+    fn binary_values() {
+        let text = StoreEntry::new("text");
+        assert_eq!(text.as_str(), Some("text"));
+        assert_eq!(text.as_bytes(), b"text");
+        assert_eq!(StoreEntry::new(b"text"), text);
+
+        let mut binary = StoreEntry::new([0x00, 0xFF, b'1']);
+        assert_eq!(binary.as_str(), None);
+        assert_eq!(binary.value, vec![0x00, 0xFF, b'1']);
+        assert_eq!(binary.value.len(), 3);
+        assert!(String::try_from(binary.value.clone()).is_err());
+        assert!(binary.increment(1).is_err());
+        assert!(binary.increment_float(1.).is_err());
+
+        let value = Value::from("caf\u{e9}");
+        assert_eq!(value, "caf\u{e9}");
+        assert_eq!(String::try_from(value.clone()).unwrap(), "caf\u{e9}");
+        assert_eq!(value.into_bytes(), vec![b'c', b'a', b'f', 0xC3, 0xA9]);
+    }
+
     #[test]
     fn ensure_none_when_converting_none_object() {
         let entry = StoreEntry::new("5");
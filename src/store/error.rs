@@ -0,0 +1,76 @@
+use std::fmt;
+
+/// Errors raised by the `GranatStore` command surface.
+///
+/// These are returned wrapped in an `anyhow::Error` so they sit alongside the
+/// rest of the crate's errors, callers who care about the specific failure can
+/// `downcast_ref::<GranatError>()` to match on it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GranatError {
+    /// The key exists but holds a different type to the one the operation expects
+    WrongType,
+    /// The key the operation needs to exist is missing
+    NoSuchKey,
+    /// The index is outside the list
+    OutOfRange,
+    /// A rank of zero was given to a search, ranks count matches from `1` or `-1`
+    ZeroRank,
+    /// Options were given together that can't be, such as `NX` with `XX`
+    IncompatibleOptions,
+    /// The operation would have produced a score that isn't a number
+    NotANumber,
+    /// The number of weights doesn't match the number of keys being combined
+    WeightCount,
+    /// A stream ID that couldn't be parsed
+    InvalidStreamId,
+    /// A stream entry was added with an ID that isn't above the stream's last one
+    StreamIdTooSmall,
+    /// The stream or the consumer group on it doesn't exist
+    NoSuchGroup,
+    /// A consumer group was created with the name of one that already exists
+    GroupExists,
+    /// A bit offset past the largest a bitmap can hold
+    BitOffset,
+    /// A bitfield type other than `i1` to `i64` or `u1` to `u63`
+    BitFieldType,
+    /// `BITOP NOT` was given more or less than one source
+    BitOpNot,
+    /// An integer increment whose result doesn't fit in an `i64`
+    Overflow,
+    /// A float increment whose result would be infinite or NaN
+    NotFinite,
+}
+
+impl fmt::Display for GranatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::WrongType => write!(
+                f,
+                "WRONGTYPE operation against a key holding the wrong kind of value"
+            ),
+            Self::NoSuchKey => write!(f, "ERR no such key"),
+            Self::OutOfRange => write!(f, "ERR index out of range"),
+            Self::ZeroRank => write!(f, "ERR RANK can't be zero"),
+            Self::IncompatibleOptions => write!(f, "ERR options are not compatible"),
+            Self::NotANumber => write!(f, "ERR resulting score is not a number (NaN)"),
+            Self::WeightCount => write!(f, "ERR number of weights doesn't match number of keys"),
+            Self::InvalidStreamId => write!(f, "ERR invalid stream ID"),
+            Self::StreamIdTooSmall => write!(
+                f,
+                "ERR the ID specified is equal or smaller than the stream's last ID"
+            ),
+            Self::NoSuchGroup => write!(f, "NOGROUP no such key or consumer group"),
+            Self::GroupExists => write!(f, "BUSYGROUP consumer group name already exists"),
+            Self::BitOffset => write!(f, "ERR bit offset is not an integer or out of range"),
+            Self::BitFieldType => write!(
+                f,
+                "ERR Invalid bitfield type. Use something like i16 u8. Note that u64 is not supported but i64 is."
+            ),
+            Self::BitOpNot => write!(f, "ERR BITOP NOT must be called with a single source key."),
+            Self::Overflow => write!(f, "ERR increment or decrement would overflow"),
+            Self::NotFinite => write!(f, "ERR increment would produce NaN or Infinity"),
+        }
+    }
+}
+
+impl std::error::Error for GranatError {}
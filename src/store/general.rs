@@ -1,21 +1,38 @@
-use anyhow::{anyhow, Result};
+use anyhow::Result;
 use serde::{Deserialize, Serialize};
 
 use std::collections::HashMap;
 
+use crate::store::bitmap::{self, BitFieldOp, BitFieldType, BitOperation, BitRange};
+use crate::store::clock::{system_clock, SharedClock};
 use crate::store::entry::StoreEntry;
 use crate::store::KVPair;
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct GeneralStore {
     pub store: HashMap<String, StoreEntry>,
+
+    #[serde(skip, default = "system_clock")]
+    clock: SharedClock,
+}
+
+impl Default for GeneralStore {
+    fn default() -> Self {
+        return Self::new();
+    }
 }
 
 impl GeneralStore {
     pub fn new() -> Self {
-        Self {
+        return Self::with_clock(system_clock());
+    }
+
+    /// Creates a store that checks expiry against `clock`
+    pub fn with_clock(clock: SharedClock) -> Self {
+        return Self {
             store: HashMap::new(),
-        }
+            clock,
+        };
     }
 
     pub fn set(&mut self, kv: KVPair) -> Result<()> {
@@ -35,6 +52,10 @@ impl GeneralStore {
 
     pub fn get(&self, key: impl AsRef<str>) -> Option<StoreEntry> {
         if let Some(raw) = self.store.get(key.as_ref()) {
+            if raw.is_expired_at(self.clock.now_millis()) {
+                return None;
+            }
+
             return Some(raw.clone());
         }
 
@@ -42,11 +63,14 @@ impl GeneralStore {
     }
 
     pub fn get_multiple(&self, keys: Vec<impl AsRef<str>>) -> Vec<Option<StoreEntry>> {
+        let now = self.clock.now_millis();
         return keys
             .into_iter()
             .map(|k| {
                 if let Some(raw) = self.store.get(k.as_ref()) {
-                    return Some(raw.clone());
+                    if !raw.is_expired_at(now) {
+                        return Some(raw.clone());
+                    }
                 }
 
                 return None;
@@ -55,21 +79,12 @@ impl GeneralStore {
     }
 
     pub fn increment(&mut self, key: impl AsRef<str>, incr: i64) -> Result<i64> {
+        self.purge_expired(key.as_ref());
         if let Some(raw) = self.store.get_mut(key.as_ref()) {
-            match raw.value.parse::<i64>() {
-                Ok(mut val) => {
-                    val += incr;
-                    raw.value = val.to_string();
-
-                    return Ok(val);
-                }
-                Err(e) => {
-                    return Err(anyhow!("unable to convert to integer: {e}"));
-                }
-            }
+            return raw.increment(incr);
         }
 
-        let initial_value = 0 + incr;
+        let initial_value = incr;
         let key_value = key.as_ref().to_string();
 
         self.store
@@ -79,18 +94,9 @@ impl GeneralStore {
     }
 
     pub fn increment_float(&mut self, key: impl AsRef<str>, incr: f64) -> Result<f64> {
+        self.purge_expired(key.as_ref());
         if let Some(raw) = self.store.get_mut(key.as_ref()) {
-            match raw.value.parse::<f64>() {
-                Ok(mut val) => {
-                    val += incr;
-                    raw.value = val.to_string();
-
-                    return Ok(val);
-                }
-                Err(e) => {
-                    return Err(anyhow!("unable to convert to float: {e}"));
-                }
-            }
+            return raw.increment_float(incr);
         }
 
         let initial_value = 0. + incr;
@@ -100,16 +106,144 @@ impl GeneralStore {
 
         return Ok(initial_value);
     }
+
+    /// Whether the key is present but past its deadline
+    pub fn is_expired(&self, key: impl AsRef<str>) -> bool {
+        if let Some(raw) = self.store.get(key.as_ref()) {
+            return raw.is_expired_at(self.clock.now_millis());
+        }
+
+        return false;
+    }
+
+    /// Removes the key if it has expired, returning whether it was removed
+    pub fn purge_expired(&mut self, key: impl AsRef<str>) -> bool {
+        if self.is_expired(key.as_ref()) {
+            self.store.remove(key.as_ref());
+            return true;
+        }
+
+        return false;
+    }
+
+    /// The bytes held at the key, empty if it's missing or expired
+    fn bytes(&self, key: &str) -> &[u8] {
+        match self.store.get(key) {
+            Some(raw) if !raw.is_expired_at(self.clock.now_millis()) => return &raw.value,
+            _ => return &[],
+        }
+    }
+
+    /// Runs `f` over the bytes held at the key, a missing key is only created
+    /// if `f` leaves something in it
+    fn modify_bytes<T>(
+        &mut self,
+        key: &str,
+        f: impl FnOnce(&mut Vec<u8>) -> Result<T>,
+    ) -> Result<T> {
+        self.purge_expired(key);
+        if let Some(raw) = self.store.get_mut(key) {
+            return f(&mut raw.value);
+        }
+
+        let mut bytes = vec![];
+        let result = f(&mut bytes)?;
+        if !bytes.is_empty() {
+            self.store
+                .insert(key.to_string(), StoreEntry::from_bytes(bytes));
+        }
+
+        return Ok(result);
+    }
+
+    /// Sets the bit at `offset`, returning what it was before
+    pub fn setbit(&mut self, key: impl AsRef<str>, offset: u64, bit: bool) -> Result<bool> {
+        return self.modify_bytes(key.as_ref(), |bytes| bitmap::set_bit(bytes, offset, bit));
+    }
+
+    pub fn getbit(&self, key: impl AsRef<str>, offset: u64) -> Result<bool> {
+        return bitmap::get_bit(self.bytes(key.as_ref()), offset);
+    }
+
+    pub fn bitcount(&self, key: impl AsRef<str>, range: Option<BitRange>) -> usize {
+        return bitmap::count(self.bytes(key.as_ref()), range);
+    }
+
+    /// See `bitmap::position`
+    pub fn bitpos(&self, key: impl AsRef<str>, bit: bool, range: Option<BitRange>) -> i64 {
+        return bitmap::position(self.bytes(key.as_ref()), bit, range);
+    }
+
+    /// Runs the bitfield `ops` against the key, see `bitmap::bitfield`
+    pub fn bitfield(
+        &mut self,
+        key: impl AsRef<str>,
+        ops: &[BitFieldOp],
+    ) -> Result<Vec<Option<i64>>> {
+        return self.modify_bytes(key.as_ref(), |bytes| bitmap::bitfield(bytes, ops));
+    }
+
+    /// Reads each field of the given type at its offset
+    pub fn bitfield_ro(
+        &self,
+        key: impl AsRef<str>,
+        fields: &[(BitFieldType, u64)],
+    ) -> Result<Vec<i64>> {
+        let bytes = self.bytes(key.as_ref());
+        return fields
+            .iter()
+            .map(|(ty, offset)| bitmap::read_field(bytes, *ty, *offset))
+            .collect::<Result<Vec<i64>>>();
+    }
+
+    /// Combines the bitmaps at `keys` into `dst`, replacing whatever it held.
+    ///
+    /// Returns the length of the result in bytes, `dst` is removed if it's empty.
+    pub fn bitop(
+        &mut self,
+        op: BitOperation,
+        dst: impl AsRef<str>,
+        keys: &[impl AsRef<str>],
+    ) -> Result<usize> {
+        let sources = keys
+            .iter()
+            .map(|k| self.bytes(k.as_ref()))
+            .collect::<Vec<&[u8]>>();
+        let result = op.combine(sources)?;
+
+        let len = result.len();
+        if result.is_empty() {
+            self.store.remove(dst.as_ref());
+        } else {
+            self.store
+                .insert(dst.as_ref().to_string(), StoreEntry::from_bytes(result));
+        }
+
+        return Ok(len);
+    }
 }
 
 #[cfg(test)]
+// The older tests pass owned keys from before keys took `impl AsRef<str>`
+#[allow(clippy::unnecessary_to_owned)]
 mod general_store_tests {
     use super::*;
+    use crate::store::clock::MockClock;
+    use crate::store::entry::ExpiryState;
+    use std::sync::Arc;
+    use std::time::Duration;
 
     fn create_kv(key: &str, value: &str) -> KVPair {
         return (key.to_string(), StoreEntry::new(value));
     }
 
+    fn create_expired_kv(key: &str, value: &str) -> KVPair {
+        let mut entry = StoreEntry::new(value);
+        entry.expiry = ExpiryState::Expired;
+
+        return (key.to_string(), entry);
+    }
+
     #[test]
     fn get_set_single() {
         let mut gs = GeneralStore::new();
@@ -208,4 +342,85 @@ mod general_store_tests {
         result = gs.increment_float("string".to_string(), 10.0);
         assert!(result.is_err());
     }
+
+    #[test]
+    fn expired_entries_are_absent() {
+        let mut gs = GeneralStore::new();
+        let inserts = vec![create_kv("live", "1"), create_expired_kv("expired", "2")];
+        let _ = gs.set_multiple(inserts);
+
+        assert!(gs.get("live").is_some());
+        assert!(gs.get("expired").is_none());
+
+        let results = gs.get_multiple(vec!["live", "expired"]);
+        assert!(results[0].is_some());
+        assert!(results[1].is_none());
+
+        // Incrementing an expired key starts it afresh
+        let _ = gs.set(create_expired_kv("counter", "10"));
+        assert_eq!(gs.increment("counter", 1).unwrap(), 1);
+
+        let _ = gs.set(create_expired_kv("expired", "2"));
+        assert!(gs.purge_expired("expired"));
+        assert!(!gs.purge_expired("live"));
+        assert!(!gs.store.contains_key("expired"));
+    }
+
+    #[test]
+    fn expiry_follows_the_store_clock() {
+        let clock = Arc::new(MockClock::new(0));
+        let mut gs = GeneralStore::with_clock(clock.clone());
+
+        let entry = StoreEntry::new("value").expires_in_with(10, clock.as_ref());
+        let _ = gs.set(("session".to_string(), entry));
+        assert!(gs.get("session").is_some());
+
+        clock.advance(Duration::from_secs(10));
+        assert!(gs.get("session").is_some());
+
+        clock.advance(Duration::from_millis(1));
+        assert!(gs.get("session").is_none());
+        assert!(gs.purge_expired("session"));
+    }
+
+    #[test]
+    fn bitmaps_on_string_values() {
+        let mut gs = GeneralStore::new();
+        assert!(!gs.setbit("visits", 0, true).unwrap());
+        assert!(!gs.setbit("visits", 9, true).unwrap());
+        assert_eq!(*gs.get("visits").unwrap().value, vec![0x80u8, 0x40]);
+        assert!(gs.getbit("visits", 9).unwrap());
+        assert_eq!(gs.bitcount("visits", None), 2);
+        assert_eq!(gs.bitpos("visits", false, None), 1);
+
+        // Bits can be set on text, which may leave it as invalid UTF-8
+        let _ = gs.set(create_kv("text", "a"));
+        assert!(!gs.setbit("text", 0, true).unwrap());
+        assert_eq!(*gs.get("text").unwrap().value, vec![0xE1u8]);
+
+        // Reads and failed writes don't create the key
+        assert!(!gs.getbit("missing", 3).unwrap());
+        let ty = BitFieldType::unsigned(8).unwrap();
+        assert_eq!(
+            gs.bitfield("missing", &[BitFieldOp::Get { ty, offset: 0 }])
+                .unwrap(),
+            vec![Some(0)]
+        );
+        assert!(gs.setbit("missing", u64::MAX, true).is_err());
+        assert!(!gs.store.contains_key("missing"));
+
+        assert_eq!(
+            gs.bitfield_ro("visits", &[(ty, 0), (ty, 8)]).unwrap(),
+            vec![128, 64]
+        );
+
+        assert_eq!(
+            gs.bitop(BitOperation::Or, "dst", &["visits", "text", "missing"])
+                .unwrap(),
+            2
+        );
+        assert_eq!(*gs.get("dst").unwrap().value, vec![0xE1u8, 0x40]);
+        assert_eq!(gs.bitop(BitOperation::And, "dst", &["missing"]).unwrap(), 0);
+        assert!(!gs.store.contains_key("dst"));
+    }
 }
@@ -0,0 +1,1970 @@
+use anyhow::Result;
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::ops::{Bound, Deref};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::{Duration, Instant};
+
+use crate::store::bitmap::{BitFieldOp, BitFieldType, BitOperation, BitRange};
+use crate::store::clock::{system_clock, SharedClock};
+use crate::store::entry::StoreEntry;
+use crate::store::error::GranatError;
+use crate::store::hyperloglog::HyperLogLog;
+use crate::store::keyspace::{ExpireCondition, KeyType};
+use crate::store::list::{InsertPosition, ListDirection};
+use crate::store::set::SetOperation;
+use crate::store::sorted_set::{
+    AddOptions, Aggregate, ScoredMember, SortedSet, SortedSetOperation,
+};
+use crate::store::stream::{
+    ConsumerInfo, GroupInfo, NewId, PendingMessage, StreamEntry, StreamFields, StreamId,
+    StreamInfo, TrimStrategy,
+};
+use crate::store::{GranatStore, KVPair};
+
+/// Number of shards a handle splits the keyspace over by default
+pub const DEFAULT_SHARDS: usize = 16;
+
+/// Where a `GranatHandle::scan` carries on from
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScanCursor {
+    shard: usize,
+    /// Last key examined in `shard`
+    after: Option<String>,
+}
+
+/// Clients blocked on list keys, queued per key in the order they arrived
+#[derive(Default)]
+struct BlockedClients {
+    next_id: u64,
+    queues: HashMap<String, VecDeque<u64>>,
+
+    /// Bumped whenever blocked clients should look at their keys again, so a
+    /// client that checked without the lock held can tell if it missed a wake up
+    generation: u64,
+}
+
+impl BlockedClients {
+    fn register(&mut self, keys: &[String]) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        for key in keys.iter() {
+            self.queues.entry(key.clone()).or_default().push_back(id);
+        }
+
+        return id;
+    }
+
+    fn unregister(&mut self, id: u64, keys: &[String]) {
+        for key in keys.iter() {
+            if let Some(queue) = self.queues.get_mut(key) {
+                queue.retain(|waiting| *waiting != id);
+                if queue.is_empty() {
+                    self.queues.remove(key);
+                }
+            }
+        }
+    }
+
+    /// Whether the client is next in line for `key`
+    fn is_next(&self, id: u64, key: &str) -> bool {
+        return self
+            .queues
+            .get(key)
+            .is_some_and(|queue| queue.front() == Some(&id));
+    }
+}
+
+#[derive(Default)]
+struct Blocking {
+    clients: Mutex<BlockedClients>,
+    ready: Condvar,
+}
+
+/// A cloneable, thread safe handle to a store.
+///
+/// The keyspace is split over a number of shards, each its own `GranatStore`
+/// behind a `RwLock`, so any number of threads can read a shard at once and
+/// writes only block the keys that hash to the same shard. Operations over
+/// several keys lock every shard involved, in shard order, so they stay atomic
+/// and can't deadlock against each other.
+#[derive(Clone)]
+pub struct GranatHandle {
+    shards: Arc<Vec<RwLock<GranatStore>>>,
+    blocking: Arc<Blocking>,
+}
+
+impl Default for GranatHandle {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+impl GranatHandle {
+    pub fn new() -> Self {
+        return Self::with_shards(DEFAULT_SHARDS, system_clock());
+    }
+
+    pub fn with_clock(clock: SharedClock) -> Self {
+        return Self::with_shards(DEFAULT_SHARDS, clock);
+    }
+
+    /// Creates a handle over `shards` stores, all sharing `clock`
+    pub fn with_shards(shards: usize, clock: SharedClock) -> Self {
+        let shards = (0..shards.max(1))
+            .map(|_| RwLock::new(GranatStore::with_clock(clock.clone())))
+            .collect::<Vec<RwLock<GranatStore>>>();
+
+        return Self {
+            shards: Arc::new(shards),
+            blocking: Arc::new(Blocking::default()),
+        };
+    }
+
+    pub fn shard_count(&self) -> usize {
+        return self.shards.len();
+    }
+
+    fn shard_for(&self, key: &str) -> usize {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+
+        return (hasher.finish() % self.shards.len() as u64) as usize;
+    }
+
+    // A panic while a shard is locked poisons it, carry on using the store
+    // rather than failing every later call that lands on that shard.
+
+    fn read_shard(&self, idx: usize) -> RwLockReadGuard<'_, GranatStore> {
+        return self.shards[idx].read().unwrap_or_else(|e| e.into_inner());
+    }
+
+    fn write_shard(&self, idx: usize) -> RwLockWriteGuard<'_, GranatStore> {
+        return self.shards[idx].write().unwrap_or_else(|e| e.into_inner());
+    }
+
+    /// Write locks two different shards, in shard order so two threads locking
+    /// the same pair can't deadlock. The guards come back in the order asked for.
+    fn write_two(
+        &self,
+        first: usize,
+        second: usize,
+    ) -> (
+        RwLockWriteGuard<'_, GranatStore>,
+        RwLockWriteGuard<'_, GranatStore>,
+    ) {
+        if first < second {
+            let first = self.write_shard(first);
+            return (first, self.write_shard(second));
+        }
+
+        let second = self.write_shard(second);
+        return (self.write_shard(first), second);
+    }
+
+    /// Groups `keys` by the shard they live on, keeping each key's position
+    fn group_by_shard<'a>(
+        &self,
+        keys: &'a [impl AsRef<str>],
+    ) -> BTreeMap<usize, Vec<(usize, &'a str)>> {
+        let mut groups: BTreeMap<usize, Vec<(usize, &str)>> = BTreeMap::new();
+        for (pos, key) in keys.iter().enumerate() {
+            groups
+                .entry(self.shard_for(key.as_ref()))
+                .or_default()
+                .push((pos, key.as_ref()));
+        }
+
+        return groups;
+    }
+
+    /// Runs `f` against the shard holding `key` with a shared lock
+    pub fn read<T>(&self, key: impl AsRef<str>, f: impl FnOnce(&GranatStore) -> T) -> T {
+        let shard = self.read_shard(self.shard_for(key.as_ref()));
+        return f(&shard);
+    }
+
+    /// Runs `f` against the shard holding `key` with an exclusive lock
+    pub fn write<T>(&self, key: impl AsRef<str>, f: impl FnOnce(&mut GranatStore) -> T) -> T {
+        let result = f(&mut self.write_shard(self.shard_for(key.as_ref())));
+        self.signal_blocked();
+
+        return result;
+    }
+
+    fn blocked_clients(&self) -> MutexGuard<'_, BlockedClients> {
+        return self
+            .blocking
+            .clients
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+    }
+
+    /// Wakes blocked clients to check their keys again after a write that
+    /// could have added to a list. Called once the shard lock is released.
+    fn signal_blocked(&self) {
+        let mut clients = self.blocked_clients();
+        if !clients.queues.is_empty() {
+            clients.generation += 1;
+            self.blocking.ready.notify_all();
+        }
+    }
+
+    /// Number of clients currently blocked on a list
+    pub fn blocked_count(&self) -> usize {
+        let clients = self.blocked_clients();
+        let mut ids = clients.queues.values().flatten().collect::<Vec<&u64>>();
+        ids.sort();
+        ids.dedup();
+
+        return ids.len();
+    }
+
+    /// Blocks until `serve` returns something for one of `keys`, or `timeout` passes.
+    ///
+    /// `serve` is only tried on a key once every client that blocked on it
+    /// earlier has been served or given up, so clients are served in the
+    /// order they arrived. `None` for `timeout` waits forever.
+    ///
+    /// The blocked clients lock is never held while `serve` runs, so clients
+    /// taking shard locks don't hold up writers waking everyone else.
+    fn block_on<T>(
+        &self,
+        keys: &[String],
+        timeout: Option<Duration>,
+        mut serve: impl FnMut(&str) -> Result<Option<T>>,
+    ) -> Result<Option<T>> {
+        let deadline = timeout.map(|t| Instant::now() + t);
+        let id = self.blocked_clients().register(keys);
+
+        let result = 'wait: loop {
+            let (turns, generation) = {
+                let clients = self.blocked_clients();
+                let turns = keys
+                    .iter()
+                    .filter(|key| clients.is_next(id, key))
+                    .collect::<Vec<&String>>();
+
+                (turns, clients.generation)
+            };
+
+            for key in turns.into_iter() {
+                match serve(key) {
+                    Ok(None) => {}
+                    served => break 'wait served,
+                }
+            }
+
+            let clients = self.blocked_clients();
+            // Something changed while serving, look again before sleeping
+            if clients.generation != generation {
+                continue;
+            }
+
+            match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        break Ok(None);
+                    }
+
+                    drop(self.blocking.ready.wait_timeout(clients, deadline - now));
+                }
+                None => {
+                    drop(self.blocking.ready.wait(clients));
+                }
+            }
+        };
+
+        // Whoever was queued behind this client may be able to go now
+        let mut clients = self.blocked_clients();
+        clients.unregister(id, keys);
+        clients.generation += 1;
+        self.blocking.ready.notify_all();
+
+        return result;
+    }
+
+    /// Runs housekeeping on every shard, see `GranatStore::tick`
+    pub fn tick(&self) {
+        for idx in 0..self.shards.len() {
+            self.write_shard(idx).tick();
+        }
+    }
+
+    // Keyspace
+
+    pub fn type_of(&self, key: impl AsRef<str>) -> Option<KeyType> {
+        return self.read(key.as_ref(), |s| s.type_of(key.as_ref()));
+    }
+
+    pub fn exists(&self, keys: Vec<impl AsRef<str>>) -> usize {
+        let groups = self.group_by_shard(&keys);
+        let shards = groups
+            .keys()
+            .map(|idx| self.read_shard(*idx))
+            .collect::<Vec<RwLockReadGuard<'_, GranatStore>>>();
+
+        return groups
+            .values()
+            .zip(shards.iter())
+            .map(|(keys, shard)| shard.exists(keys.iter().map(|(_, k)| *k).collect()))
+            .sum();
+    }
+
+    pub fn del(&self, keys: Vec<impl AsRef<str>>) -> usize {
+        let groups = self.group_by_shard(&keys);
+        let mut shards = groups
+            .keys()
+            .map(|idx| self.write_shard(*idx))
+            .collect::<Vec<RwLockWriteGuard<'_, GranatStore>>>();
+
+        return groups
+            .values()
+            .zip(shards.iter_mut())
+            .map(|(keys, shard)| shard.del(keys.iter().map(|(_, k)| *k).collect()))
+            .sum();
+    }
+
+    /// Renames `src` to `dst`, moving the value between shards if needed
+    pub fn rename(&self, src: impl AsRef<str>, dst: impl AsRef<str>) -> Result<()> {
+        let (src, dst) = (src.as_ref(), dst.as_ref());
+        let (src_idx, dst_idx) = (self.shard_for(src), self.shard_for(dst));
+        if src_idx == dst_idx {
+            return self.write(src, |s| s.rename(src, dst));
+        }
+
+        let (mut src_shard, mut dst_shard) = self.write_two(src_idx, dst_idx);
+        Self::move_between(&mut src_shard, &mut dst_shard, src, dst)?;
+
+        drop((src_shard, dst_shard));
+        self.signal_blocked();
+
+        return Ok(());
+    }
+
+    /// Renames `src` to `dst` only if `dst` doesn't exist, checking it under the
+    /// same locks as the move so another writer can't create it in between
+    pub fn renamenx(&self, src: impl AsRef<str>, dst: impl AsRef<str>) -> Result<bool> {
+        let (src, dst) = (src.as_ref(), dst.as_ref());
+        let (src_idx, dst_idx) = (self.shard_for(src), self.shard_for(dst));
+        if src_idx == dst_idx {
+            return self.write(src, |s| s.renamenx(src, dst));
+        }
+
+        let (mut src_shard, mut dst_shard) = self.write_two(src_idx, dst_idx);
+        if src_shard.type_of(src).is_none() {
+            return Err(GranatError::NoSuchKey.into());
+        }
+
+        if dst_shard.type_of(dst).is_some() {
+            return Ok(false);
+        }
+
+        Self::move_between(&mut src_shard, &mut dst_shard, src, dst)?;
+
+        drop((src_shard, dst_shard));
+        self.signal_blocked();
+
+        return Ok(true);
+    }
+
+    /// Moves `src` on one shard to `dst` on another, replacing whatever `dst` held.
+    ///
+    /// Fails without touching either shard if the value can't be rebuilt.
+    fn move_between(
+        src_shard: &mut GranatStore,
+        dst_shard: &mut GranatStore,
+        src: &str,
+        dst: &str,
+    ) -> Result<()> {
+        let commands = src_shard.dump_key(src, dst);
+        if commands.is_empty() {
+            return Err(GranatError::NoSuchKey.into());
+        }
+
+        // Rebuild the value on a scratch store first, so a command that fails
+        // leaves `dst` as it was rather than half written
+        let mut scratch = GranatStore::with_clock(dst_shard.clock().clone());
+        for cmd in commands.iter().cloned() {
+            cmd.apply(&mut scratch)?;
+        }
+
+        dst_shard.del(vec![dst]);
+        for cmd in commands.into_iter() {
+            cmd.apply(dst_shard)?;
+        }
+        src_shard.del(vec![src]);
+
+        return Ok(());
+    }
+
+    /// Returns every key matching the glob `pattern` across all shards, sorted
+    pub fn keys(&self, pattern: impl AsRef<str>) -> Vec<String> {
+        let mut keys = (0..self.shards.len())
+            .flat_map(|idx| self.read_shard(idx).keys(pattern.as_ref()))
+            .collect::<Vec<String>>();
+        keys.sort();
+
+        return keys;
+    }
+
+    /// Incrementally iterates every shard in turn, see `GranatStore::scan`.
+    ///
+    /// The cursor holds the shard being scanned, so it's only meaningful to a
+    /// handle with the same number of shards.
+    pub fn scan(
+        &self,
+        cursor: Option<ScanCursor>,
+        pattern: Option<&str>,
+        count: usize,
+    ) -> (Option<ScanCursor>, Vec<String>) {
+        let cursor = cursor.unwrap_or_default();
+        let (after, keys) =
+            self.read_shard(cursor.shard)
+                .scan(cursor.after.as_deref(), pattern, count);
+
+        if after.is_some() {
+            let next = ScanCursor {
+                shard: cursor.shard,
+                after,
+            };
+            return (Some(next), keys);
+        }
+
+        if cursor.shard + 1 < self.shards.len() {
+            let next = ScanCursor {
+                shard: cursor.shard + 1,
+                after: None,
+            };
+            return (Some(next), keys);
+        }
+
+        return (None, keys);
+    }
+
+    // Expiry
+
+    pub fn expire(&self, key: impl AsRef<str>, seconds: i64, cond: ExpireCondition) -> bool {
+        return self.write(key.as_ref(), |s| s.expire(key.as_ref(), seconds, cond));
+    }
+
+    pub fn pexpire(&self, key: impl AsRef<str>, millis: i64, cond: ExpireCondition) -> bool {
+        return self.write(key.as_ref(), |s| s.pexpire(key.as_ref(), millis, cond));
+    }
+
+    pub fn expire_at(&self, key: impl AsRef<str>, timestamp: i64, cond: ExpireCondition) -> bool {
+        return self.write(key.as_ref(), |s| s.expire_at(key.as_ref(), timestamp, cond));
+    }
+
+    pub fn pexpire_at(&self, key: impl AsRef<str>, timestamp: i64, cond: ExpireCondition) -> bool {
+        return self.write(key.as_ref(), |s| {
+            s.pexpire_at(key.as_ref(), timestamp, cond)
+        });
+    }
+
+    pub fn persist(&self, key: impl AsRef<str>) -> bool {
+        return self.write(key.as_ref(), |s| s.persist(key.as_ref()));
+    }
+
+    pub fn ttl(&self, key: impl AsRef<str>) -> i64 {
+        return self.read(key.as_ref(), |s| s.ttl(key.as_ref()));
+    }
+
+    pub fn pttl(&self, key: impl AsRef<str>) -> i64 {
+        return self.read(key.as_ref(), |s| s.pttl(key.as_ref()));
+    }
+
+    // General
+
+    pub fn get(&self, key: impl AsRef<str>) -> Result<Option<StoreEntry>> {
+        return self.read(key.as_ref(), |s| s.get(key.as_ref()));
+    }
+
+    /// Reads every key from a single consistent view of the shards involved
+    pub fn get_multiple(&self, keys: Vec<impl AsRef<str>>) -> Vec<Option<StoreEntry>> {
+        let groups = self.group_by_shard(&keys);
+        let shards = groups
+            .keys()
+            .map(|idx| self.read_shard(*idx))
+            .collect::<Vec<RwLockReadGuard<'_, GranatStore>>>();
+
+        let mut entries = vec![None; keys.len()];
+        for (keys, shard) in groups.values().zip(shards.iter()) {
+            let found = shard.get_multiple(keys.iter().map(|(_, k)| *k).collect());
+            for ((pos, _), entry) in keys.iter().zip(found) {
+                entries[*pos] = entry;
+            }
+        }
+
+        return entries;
+    }
+
+    pub fn set(&self, kv: KVPair) -> Result<()> {
+        let idx = self.shard_for(&kv.0);
+        return self.write_shard(idx).set(kv);
+    }
+
+    /// Sets every pair atomically, no reader sees some of them set and not others
+    pub fn set_multiple(&self, kvs: Vec<KVPair>) -> Result<()> {
+        let mut groups: BTreeMap<usize, Vec<KVPair>> = BTreeMap::new();
+        for kv in kvs.into_iter() {
+            groups.entry(self.shard_for(&kv.0)).or_default().push(kv);
+        }
+
+        let mut shards = groups
+            .keys()
+            .map(|idx| self.write_shard(*idx))
+            .collect::<Vec<RwLockWriteGuard<'_, GranatStore>>>();
+
+        for (kvs, shard) in groups.into_values().zip(shards.iter_mut()) {
+            shard.set_multiple(kvs)?;
+        }
+
+        return Ok(());
+    }
+
+    pub fn increment(&self, key: impl AsRef<str>, incr: i64) -> Result<i64> {
+        return self.write(key.as_ref(), |s| s.increment(key.as_ref(), incr));
+    }
+
+    pub fn increment_float(&self, key: impl AsRef<str>, incr: f64) -> Result<f64> {
+        return self.write(key.as_ref(), |s| s.increment_float(key.as_ref(), incr));
+    }
+
+    // Bitmap
+
+    pub fn setbit(&self, key: impl AsRef<str>, offset: u64, bit: bool) -> Result<bool> {
+        return self.write(key.as_ref(), |s| s.setbit(key.as_ref(), offset, bit));
+    }
+
+    pub fn getbit(&self, key: impl AsRef<str>, offset: u64) -> Result<bool> {
+        return self.read(key.as_ref(), |s| s.getbit(key.as_ref(), offset));
+    }
+
+    pub fn bitcount(&self, key: impl AsRef<str>, range: Option<BitRange>) -> Result<usize> {
+        return self.read(key.as_ref(), |s| s.bitcount(key.as_ref(), range));
+    }
+
+    pub fn bitpos(&self, key: impl AsRef<str>, bit: bool, range: Option<BitRange>) -> Result<i64> {
+        return self.read(key.as_ref(), |s| s.bitpos(key.as_ref(), bit, range));
+    }
+
+    /// Combines the bitmaps into `dst` with every shard involved locked, so the
+    /// result is built from one view of the sources
+    pub fn bitop(
+        &self,
+        op: BitOperation,
+        dst: impl AsRef<str>,
+        keys: Vec<impl AsRef<str>>,
+    ) -> Result<usize> {
+        let dst = dst.as_ref();
+        let mut all = keys.iter().map(|k| k.as_ref()).collect::<Vec<&str>>();
+        all.push(dst);
+
+        let groups = self.group_by_shard(&all);
+        if groups.len() == 1 {
+            return self.write(dst, |s| s.bitop(op, dst, keys));
+        }
+
+        let mut shards = groups
+            .keys()
+            .map(|idx| self.write_shard(*idx))
+            .collect::<Vec<RwLockWriteGuard<'_, GranatStore>>>();
+
+        let mut sources = vec![vec![]; keys.len()];
+        for (group, shard) in groups.values().zip(shards.iter()) {
+            for (pos, key) in group.iter().filter(|(pos, _)| *pos < keys.len()) {
+                if let Some(entry) = shard.get(key)? {
+                    sources[*pos] = entry.value.to_vec();
+                }
+            }
+        }
+
+        let result = op.combine(sources.iter().map(|s| s.as_slice()).collect())?;
+        // Guards are in shard order, `dst`'s comes after every shard before it
+        let shard = &mut shards[groups.range(..self.shard_for(dst)).count()];
+        shard.del(vec![dst]);
+        let len = result.len();
+        if len > 0 {
+            shard.set((dst.to_string(), StoreEntry::from_bytes(result)))?;
+        }
+
+        return Ok(len);
+    }
+
+    pub fn bitfield(&self, key: impl AsRef<str>, ops: Vec<BitFieldOp>) -> Result<Vec<Option<i64>>> {
+        return self.write(key.as_ref(), |s| s.bitfield(key.as_ref(), ops));
+    }
+
+    pub fn bitfield_ro(
+        &self,
+        key: impl AsRef<str>,
+        fields: Vec<(BitFieldType, u64)>,
+    ) -> Result<Vec<i64>> {
+        return self.read(key.as_ref(), |s| s.bitfield_ro(key.as_ref(), fields));
+    }
+
+    // List
+
+    pub fn push_left(&self, kv: KVPair) -> Result<usize> {
+        let idx = self.shard_for(&kv.0);
+        let len = self.write_shard(idx).push_left(kv);
+        self.signal_blocked();
+
+        return len;
+    }
+
+    pub fn push_right(&self, kv: KVPair) -> Result<usize> {
+        let idx = self.shard_for(&kv.0);
+        let len = self.write_shard(idx).push_right(kv);
+        self.signal_blocked();
+
+        return len;
+    }
+
+    pub fn push_left_many(&self, key: impl AsRef<str>, values: Vec<StoreEntry>) -> Result<usize> {
+        let len = self
+            .write_shard(self.shard_for(key.as_ref()))
+            .push_left_many(key, values);
+        self.signal_blocked();
+
+        return len;
+    }
+
+    pub fn push_right_many(&self, key: impl AsRef<str>, values: Vec<StoreEntry>) -> Result<usize> {
+        let len = self
+            .write_shard(self.shard_for(key.as_ref()))
+            .push_right_many(key, values);
+        self.signal_blocked();
+
+        return len;
+    }
+
+    pub fn push_left_if_exists(
+        &self,
+        key: impl AsRef<str>,
+        values: Vec<StoreEntry>,
+    ) -> Result<usize> {
+        let len = self
+            .write_shard(self.shard_for(key.as_ref()))
+            .push_left_if_exists(key, values);
+        self.signal_blocked();
+
+        return len;
+    }
+
+    pub fn push_right_if_exists(
+        &self,
+        key: impl AsRef<str>,
+        values: Vec<StoreEntry>,
+    ) -> Result<usize> {
+        let len = self
+            .write_shard(self.shard_for(key.as_ref()))
+            .push_right_if_exists(key, values);
+        self.signal_blocked();
+
+        return len;
+    }
+
+    fn pop(&self, key: &str, dir: ListDirection) -> Result<Option<StoreEntry>> {
+        let mut shard = self.write_shard(self.shard_for(key));
+        match dir {
+            ListDirection::Left => return shard.pop_left(key),
+            ListDirection::Right => return shard.pop_right(key),
+        }
+    }
+
+    /// Pops from `src` and pushes onto `dst` with both locked, so the entry is
+    /// never missing from both
+    fn move_entry(
+        &self,
+        src: &str,
+        dst: &str,
+        from: ListDirection,
+        to: ListDirection,
+    ) -> Result<Option<StoreEntry>> {
+        let (src_idx, dst_idx) = (self.shard_for(src), self.shard_for(dst));
+        if src_idx == dst_idx {
+            return self.write_shard(src_idx).lmove(src, dst, from, to);
+        }
+
+        let (mut src_shard, mut dst_shard) = self.write_two(src_idx, dst_idx);
+        if dst_shard.type_of(dst).is_some_and(|kt| kt != KeyType::List) {
+            return Err(GranatError::WrongType.into());
+        }
+
+        let entry = match from {
+            ListDirection::Left => src_shard.pop_left(src)?,
+            ListDirection::Right => src_shard.pop_right(src)?,
+        };
+        let Some(entry) = entry else {
+            return Ok(None);
+        };
+
+        let kv = (dst.to_string(), entry.clone());
+        match to {
+            ListDirection::Left => dst_shard.push_left(kv)?,
+            ListDirection::Right => dst_shard.push_right(kv)?,
+        };
+
+        return Ok(Some(entry));
+    }
+
+    /// Atomically pops from the `from` end of `src` and pushes onto the `to` end
+    /// of `dst`, see `GranatStore::lmove`
+    pub fn lmove(
+        &self,
+        src: impl AsRef<str>,
+        dst: impl AsRef<str>,
+        from: ListDirection,
+        to: ListDirection,
+    ) -> Result<Option<StoreEntry>> {
+        let moved = self.move_entry(src.as_ref(), dst.as_ref(), from, to);
+        self.signal_blocked();
+
+        return moved;
+    }
+
+    pub fn rpoplpush(
+        &self,
+        src: impl AsRef<str>,
+        dst: impl AsRef<str>,
+    ) -> Result<Option<StoreEntry>> {
+        return self.lmove(src, dst, ListDirection::Right, ListDirection::Left);
+    }
+
+    /// Pops from the left of the first non-empty list in `keys`, waiting up to
+    /// `timeout` for one to be pushed to if they're all empty. `None` waits forever.
+    ///
+    /// Returns the key popped from along with the entry, or `None` on timeout.
+    pub fn blocking_pop_left(
+        &self,
+        keys: Vec<impl AsRef<str>>,
+        timeout: Option<Duration>,
+    ) -> Result<Option<KVPair>> {
+        let keys = keys
+            .iter()
+            .map(|k| k.as_ref().to_string())
+            .collect::<Vec<String>>();
+        return self.block_on(&keys, timeout, |key| {
+            let entry = self.pop(key, ListDirection::Left)?;
+            return Ok(entry.map(|e| (key.to_string(), e)));
+        });
+    }
+
+    /// Like `blocking_pop_left`, popping from the right
+    pub fn blocking_pop_right(
+        &self,
+        keys: Vec<impl AsRef<str>>,
+        timeout: Option<Duration>,
+    ) -> Result<Option<KVPair>> {
+        let keys = keys
+            .iter()
+            .map(|k| k.as_ref().to_string())
+            .collect::<Vec<String>>();
+        return self.block_on(&keys, timeout, |key| {
+            let entry = self.pop(key, ListDirection::Right)?;
+            return Ok(entry.map(|e| (key.to_string(), e)));
+        });
+    }
+
+    /// Atomically pops from the `from` end of `src` and pushes onto the `to` end
+    /// of `dst`, waiting up to `timeout` for `src` to be pushed to if it's empty.
+    ///
+    /// Returns the entry moved, or `None` on timeout.
+    pub fn blocking_move(
+        &self,
+        src: impl AsRef<str>,
+        dst: impl AsRef<str>,
+        from: ListDirection,
+        to: ListDirection,
+        timeout: Option<Duration>,
+    ) -> Result<Option<StoreEntry>> {
+        let keys = vec![src.as_ref().to_string()];
+        let moved = self.block_on(&keys, timeout, |key| {
+            return self.move_entry(key, dst.as_ref(), from, to);
+        });
+
+        // The move can feed a client blocked on `dst`
+        if matches!(moved, Ok(Some(_))) {
+            self.signal_blocked();
+        }
+
+        return moved;
+    }
+
+    pub fn pop_left(&self, key: impl AsRef<str>) -> Result<Option<StoreEntry>> {
+        return self.write(key.as_ref(), |s| s.pop_left(key.as_ref()));
+    }
+
+    pub fn pop_right(&self, key: impl AsRef<str>) -> Result<Option<StoreEntry>> {
+        return self.write(key.as_ref(), |s| s.pop_right(key.as_ref()));
+    }
+
+    pub fn pop_left_n(&self, key: impl AsRef<str>, count: usize) -> Result<Vec<StoreEntry>> {
+        return self.write(key.as_ref(), |s| s.pop_left_n(key.as_ref(), count));
+    }
+
+    pub fn pop_right_n(&self, key: impl AsRef<str>, count: usize) -> Result<Vec<StoreEntry>> {
+        return self.write(key.as_ref(), |s| s.pop_right_n(key.as_ref(), count));
+    }
+
+    pub fn position(
+        &self,
+        key: impl AsRef<str>,
+        value: impl AsRef<[u8]>,
+        rank: isize,
+        count: usize,
+        maxlen: usize,
+    ) -> Result<Vec<usize>> {
+        return self.read(key.as_ref(), |s| {
+            s.position(key.as_ref(), value, rank, count, maxlen)
+        });
+    }
+
+    pub fn index(&self, key: impl AsRef<str>, idx: isize) -> Result<Option<StoreEntry>> {
+        return self.read(key.as_ref(), |s| s.index(key.as_ref(), idx));
+    }
+
+    pub fn list_len(&self, key: impl AsRef<str>) -> Result<usize> {
+        return self.read(key.as_ref(), |s| s.list_len(key.as_ref()));
+    }
+
+    pub fn range(&self, key: impl AsRef<str>, start: isize, end: isize) -> Result<Vec<StoreEntry>> {
+        return self.read(key.as_ref(), |s| s.range(key.as_ref(), start, end));
+    }
+
+    pub fn list_set(&self, kv: KVPair, idx: isize) -> Result<()> {
+        let shard = self.shard_for(&kv.0);
+        return self.write_shard(shard).list_set(kv, idx);
+    }
+
+    pub fn insert_at(&self, kv: KVPair, idx: isize) -> Result<usize> {
+        let shard = self.shard_for(&kv.0);
+        let len = self.write_shard(shard).insert_at(kv, idx);
+        self.signal_blocked();
+
+        return len;
+    }
+
+    pub fn linsert(
+        &self,
+        key: impl AsRef<str>,
+        pos: InsertPosition,
+        pivot: impl AsRef<[u8]>,
+        value: StoreEntry,
+    ) -> Result<i64> {
+        return self.write(key.as_ref(), |s| s.linsert(key.as_ref(), pos, pivot, value));
+    }
+
+    pub fn trim(&self, key: impl AsRef<str>, start: isize, end: isize) -> Result<()> {
+        return self.write(key.as_ref(), |s| s.trim(key.as_ref(), start, end));
+    }
+
+    pub fn list_remove(
+        &self,
+        key: impl AsRef<str>,
+        value: impl AsRef<[u8]>,
+        count: isize,
+    ) -> Result<usize> {
+        return self.write(key.as_ref(), |s| {
+            s.list_remove(key.as_ref(), value.as_ref(), count)
+        });
+    }
+
+    // Hash
+
+    pub fn hset(&self, key: impl AsRef<str>, fields: Vec<KVPair>) -> Result<usize> {
+        return self.write(key.as_ref(), |s| s.hset(key.as_ref(), fields));
+    }
+
+    pub fn hsetnx(&self, key: impl AsRef<str>, field: KVPair) -> Result<bool> {
+        return self.write(key.as_ref(), |s| s.hsetnx(key.as_ref(), field));
+    }
+
+    pub fn hget(&self, key: impl AsRef<str>, field: impl AsRef<str>) -> Result<Option<StoreEntry>> {
+        return self.read(key.as_ref(), |s| s.hget(key.as_ref(), field));
+    }
+
+    pub fn hdel(&self, key: impl AsRef<str>, fields: Vec<impl AsRef<str>>) -> Result<usize> {
+        return self.write(key.as_ref(), |s| s.hdel(key.as_ref(), fields));
+    }
+
+    pub fn hexists(&self, key: impl AsRef<str>, field: impl AsRef<str>) -> Result<bool> {
+        return self.read(key.as_ref(), |s| s.hexists(key.as_ref(), field));
+    }
+
+    pub fn hlen(&self, key: impl AsRef<str>) -> Result<usize> {
+        return self.read(key.as_ref(), |s| s.hlen(key.as_ref()));
+    }
+
+    pub fn hkeys(&self, key: impl AsRef<str>) -> Result<Vec<String>> {
+        return self.read(key.as_ref(), |s| s.hkeys(key.as_ref()));
+    }
+
+    pub fn hvals(&self, key: impl AsRef<str>) -> Result<Vec<StoreEntry>> {
+        return self.read(key.as_ref(), |s| s.hvals(key.as_ref()));
+    }
+
+    pub fn hgetall(&self, key: impl AsRef<str>) -> Result<Vec<KVPair>> {
+        return self.read(key.as_ref(), |s| s.hgetall(key.as_ref()));
+    }
+
+    pub fn hincrby(&self, key: impl AsRef<str>, field: impl AsRef<str>, incr: i64) -> Result<i64> {
+        return self.write(key.as_ref(), |s| s.hincrby(key.as_ref(), field, incr));
+    }
+
+    pub fn hincrbyfloat(
+        &self,
+        key: impl AsRef<str>,
+        field: impl AsRef<str>,
+        incr: f64,
+    ) -> Result<f64> {
+        return self.write(key.as_ref(), |s| s.hincrbyfloat(key.as_ref(), field, incr));
+    }
+
+    // Set
+
+    pub fn sadd(&self, key: impl AsRef<str>, members: Vec<impl AsRef<str>>) -> Result<usize> {
+        return self.write(key.as_ref(), |s| s.sadd(key.as_ref(), members));
+    }
+
+    pub fn srem(&self, key: impl AsRef<str>, members: Vec<impl AsRef<str>>) -> Result<usize> {
+        return self.write(key.as_ref(), |s| s.srem(key.as_ref(), members));
+    }
+
+    pub fn sismember(&self, key: impl AsRef<str>, member: impl AsRef<str>) -> Result<bool> {
+        return self.read(key.as_ref(), |s| s.sismember(key.as_ref(), member));
+    }
+
+    pub fn smembers(&self, key: impl AsRef<str>) -> Result<Vec<String>> {
+        return self.read(key.as_ref(), |s| s.smembers(key.as_ref()));
+    }
+
+    pub fn scard(&self, key: impl AsRef<str>) -> Result<usize> {
+        return self.read(key.as_ref(), |s| s.scard(key.as_ref()));
+    }
+
+    pub fn spop(&self, key: impl AsRef<str>, count: usize) -> Result<Vec<String>> {
+        return self.write(key.as_ref(), |s| s.spop(key.as_ref(), count));
+    }
+
+    pub fn srandmember(&self, key: impl AsRef<str>, count: isize) -> Result<Vec<String>> {
+        return self.read(key.as_ref(), |s| s.srandmember(key.as_ref(), count));
+    }
+
+    pub fn sunion(&self, keys: Vec<impl AsRef<str>>) -> Result<Vec<String>> {
+        return self.set_combine(SetOperation::Union, &keys);
+    }
+
+    pub fn sinter(&self, keys: Vec<impl AsRef<str>>) -> Result<Vec<String>> {
+        return self.set_combine(SetOperation::Intersection, &keys);
+    }
+
+    pub fn sdiff(&self, keys: Vec<impl AsRef<str>>) -> Result<Vec<String>> {
+        return self.set_combine(SetOperation::Difference, &keys);
+    }
+
+    pub fn sunionstore(&self, dst: impl AsRef<str>, keys: Vec<impl AsRef<str>>) -> Result<usize> {
+        return self.set_combine_store(SetOperation::Union, dst.as_ref(), &keys);
+    }
+
+    pub fn sinterstore(&self, dst: impl AsRef<str>, keys: Vec<impl AsRef<str>>) -> Result<usize> {
+        return self.set_combine_store(SetOperation::Intersection, dst.as_ref(), &keys);
+    }
+
+    pub fn sdiffstore(&self, dst: impl AsRef<str>, keys: Vec<impl AsRef<str>>) -> Result<usize> {
+        return self.set_combine_store(SetOperation::Difference, dst.as_ref(), &keys);
+    }
+
+    /// Reads the first `count` keys of `groups` as sets from the locked `shards`
+    fn read_sets(
+        &self,
+        shards: &[impl Deref<Target = GranatStore>],
+        groups: &BTreeMap<usize, Vec<(usize, &str)>>,
+        count: usize,
+    ) -> Result<Vec<HashSet<String>>> {
+        let mut sets = vec![HashSet::new(); count];
+        for (keys, shard) in groups.values().zip(shards.iter()) {
+            for (pos, key) in keys.iter().filter(|(pos, _)| *pos < count) {
+                sets[*pos] = shard.smembers(key)?.into_iter().collect();
+            }
+        }
+
+        return Ok(sets);
+    }
+
+    fn set_combine(&self, op: SetOperation, keys: &[impl AsRef<str>]) -> Result<Vec<String>> {
+        let groups = self.group_by_shard(keys);
+        let shards = groups
+            .keys()
+            .map(|idx| self.read_shard(*idx))
+            .collect::<Vec<RwLockReadGuard<'_, GranatStore>>>();
+
+        let sets = self.read_sets(&shards, &groups, keys.len())?;
+
+        return Ok(op.combine(sets).into_iter().collect::<Vec<String>>());
+    }
+
+    /// Combines the sets into `dst` with every shard involved locked, so the
+    /// result is built from one view of the sources
+    fn set_combine_store(
+        &self,
+        op: SetOperation,
+        dst: &str,
+        keys: &[impl AsRef<str>],
+    ) -> Result<usize> {
+        let mut all = keys.iter().map(|k| k.as_ref()).collect::<Vec<&str>>();
+        all.push(dst);
+
+        let groups = self.group_by_shard(&all);
+        if groups.len() == 1 {
+            return self.write(dst, |s| s.set_combine_store(op, dst, keys));
+        }
+
+        let mut shards = groups
+            .keys()
+            .map(|idx| self.write_shard(*idx))
+            .collect::<Vec<RwLockWriteGuard<'_, GranatStore>>>();
+
+        let members = op.combine(self.read_sets(&shards, &groups, keys.len())?);
+        // Guards are in shard order, `dst`'s comes after every shard before it
+        let shard = &mut shards[groups.range(..self.shard_for(dst)).count()];
+        shard.del(vec![dst]);
+        shard.sadd(dst, members.into_iter().collect::<Vec<String>>())?;
+
+        return shard.scard(dst);
+    }
+
+    // Sorted set
+
+    pub fn zadd(
+        &self,
+        key: impl AsRef<str>,
+        members: Vec<ScoredMember>,
+        opts: AddOptions,
+    ) -> Result<usize> {
+        return self.write(key.as_ref(), |s| s.zadd(key.as_ref(), members, opts));
+    }
+
+    pub fn zadd_incr(
+        &self,
+        key: impl AsRef<str>,
+        member: impl AsRef<str>,
+        incr: f64,
+        opts: AddOptions,
+    ) -> Result<Option<f64>> {
+        return self.write(key.as_ref(), |s| {
+            s.zadd_incr(key.as_ref(), member, incr, opts)
+        });
+    }
+
+    pub fn zincrby(&self, key: impl AsRef<str>, member: impl AsRef<str>, incr: f64) -> Result<f64> {
+        return self.write(key.as_ref(), |s| s.zincrby(key.as_ref(), member, incr));
+    }
+
+    pub fn zrem(&self, key: impl AsRef<str>, members: Vec<impl AsRef<str>>) -> Result<usize> {
+        return self.write(key.as_ref(), |s| s.zrem(key.as_ref(), members));
+    }
+
+    pub fn zcard(&self, key: impl AsRef<str>) -> Result<usize> {
+        return self.read(key.as_ref(), |s| s.zcard(key.as_ref()));
+    }
+
+    pub fn zscore(&self, key: impl AsRef<str>, member: impl AsRef<str>) -> Result<Option<f64>> {
+        return self.read(key.as_ref(), |s| s.zscore(key.as_ref(), member));
+    }
+
+    pub fn zrank(&self, key: impl AsRef<str>, member: impl AsRef<str>) -> Result<Option<usize>> {
+        return self.read(key.as_ref(), |s| s.zrank(key.as_ref(), member));
+    }
+
+    pub fn zrevrank(&self, key: impl AsRef<str>, member: impl AsRef<str>) -> Result<Option<usize>> {
+        return self.read(key.as_ref(), |s| s.zrevrank(key.as_ref(), member));
+    }
+
+    pub fn zrange(
+        &self,
+        key: impl AsRef<str>,
+        start: isize,
+        stop: isize,
+        rev: bool,
+    ) -> Result<Vec<ScoredMember>> {
+        return self.read(key.as_ref(), |s| s.zrange(key.as_ref(), start, stop, rev));
+    }
+
+    pub fn zrange_by_score(
+        &self,
+        key: impl AsRef<str>,
+        min: Bound<f64>,
+        max: Bound<f64>,
+        rev: bool,
+        limit: Option<(usize, usize)>,
+    ) -> Result<Vec<ScoredMember>> {
+        return self.read(key.as_ref(), |s| {
+            s.zrange_by_score(key.as_ref(), min, max, rev, limit)
+        });
+    }
+
+    pub fn zrange_by_lex(
+        &self,
+        key: impl AsRef<str>,
+        min: Bound<String>,
+        max: Bound<String>,
+        rev: bool,
+        limit: Option<(usize, usize)>,
+    ) -> Result<Vec<String>> {
+        return self.read(key.as_ref(), |s| {
+            s.zrange_by_lex(key.as_ref(), min, max, rev, limit)
+        });
+    }
+
+    pub fn zcount(&self, key: impl AsRef<str>, min: Bound<f64>, max: Bound<f64>) -> Result<usize> {
+        return self.read(key.as_ref(), |s| s.zcount(key.as_ref(), min, max));
+    }
+
+    pub fn zpopmin(&self, key: impl AsRef<str>, count: usize) -> Result<Vec<ScoredMember>> {
+        return self.write(key.as_ref(), |s| s.zpopmin(key.as_ref(), count));
+    }
+
+    pub fn zpopmax(&self, key: impl AsRef<str>, count: usize) -> Result<Vec<ScoredMember>> {
+        return self.write(key.as_ref(), |s| s.zpopmax(key.as_ref(), count));
+    }
+
+    pub fn zunion(
+        &self,
+        keys: Vec<impl AsRef<str>>,
+        weights: Option<Vec<f64>>,
+        aggregate: Aggregate,
+    ) -> Result<Vec<ScoredMember>> {
+        let combined = self.zset_combine(
+            SortedSetOperation::Union,
+            &keys,
+            weights.as_deref(),
+            aggregate,
+        )?;
+
+        return Ok(combined.iter().collect::<Vec<ScoredMember>>());
+    }
+
+    pub fn zinter(
+        &self,
+        keys: Vec<impl AsRef<str>>,
+        weights: Option<Vec<f64>>,
+        aggregate: Aggregate,
+    ) -> Result<Vec<ScoredMember>> {
+        let combined = self.zset_combine(
+            SortedSetOperation::Intersection,
+            &keys,
+            weights.as_deref(),
+            aggregate,
+        )?;
+
+        return Ok(combined.iter().collect::<Vec<ScoredMember>>());
+    }
+
+    pub fn zunionstore(
+        &self,
+        dst: impl AsRef<str>,
+        keys: Vec<impl AsRef<str>>,
+        weights: Option<Vec<f64>>,
+        aggregate: Aggregate,
+    ) -> Result<usize> {
+        return self.zset_combine_store(
+            SortedSetOperation::Union,
+            dst.as_ref(),
+            &keys,
+            weights.as_deref(),
+            aggregate,
+        );
+    }
+
+    pub fn zinterstore(
+        &self,
+        dst: impl AsRef<str>,
+        keys: Vec<impl AsRef<str>>,
+        weights: Option<Vec<f64>>,
+        aggregate: Aggregate,
+    ) -> Result<usize> {
+        return self.zset_combine_store(
+            SortedSetOperation::Intersection,
+            dst.as_ref(),
+            &keys,
+            weights.as_deref(),
+            aggregate,
+        );
+    }
+
+    /// Combines the first `count` keys of `groups` as sorted sets from the locked `shards`
+    fn combine_sorted_sets(
+        &self,
+        shards: &[impl Deref<Target = GranatStore>],
+        groups: &BTreeMap<usize, Vec<(usize, &str)>>,
+        count: usize,
+        op: SortedSetOperation,
+        weights: Option<&[f64]>,
+        aggregate: Aggregate,
+    ) -> Result<SortedSet> {
+        let mut sets = vec![SortedSet::new(); count];
+        for (keys, shard) in groups.values().zip(shards.iter()) {
+            for (pos, key) in keys.iter().filter(|(pos, _)| *pos < count) {
+                sets[*pos] = shard.zrange(key, 0, -1, false)?.into_iter().collect();
+            }
+        }
+
+        return op.combine(sets.iter().collect::<Vec<&SortedSet>>(), weights, aggregate);
+    }
+
+    fn zset_combine(
+        &self,
+        op: SortedSetOperation,
+        keys: &[impl AsRef<str>],
+        weights: Option<&[f64]>,
+        aggregate: Aggregate,
+    ) -> Result<SortedSet> {
+        let groups = self.group_by_shard(keys);
+        let shards = groups
+            .keys()
+            .map(|idx| self.read_shard(*idx))
+            .collect::<Vec<RwLockReadGuard<'_, GranatStore>>>();
+
+        return self.combine_sorted_sets(&shards, &groups, keys.len(), op, weights, aggregate);
+    }
+
+    /// Combines the sorted sets into `dst` with every shard involved locked, so
+    /// the result is built from one view of the sources
+    fn zset_combine_store(
+        &self,
+        op: SortedSetOperation,
+        dst: &str,
+        keys: &[impl AsRef<str>],
+        weights: Option<&[f64]>,
+        aggregate: Aggregate,
+    ) -> Result<usize> {
+        let mut all = keys.iter().map(|k| k.as_ref()).collect::<Vec<&str>>();
+        all.push(dst);
+
+        let groups = self.group_by_shard(&all);
+        if groups.len() == 1 {
+            return self.write(dst, |s| {
+                s.zset_combine_store(op, dst, keys, weights, aggregate)
+            });
+        }
+
+        let mut shards = groups
+            .keys()
+            .map(|idx| self.write_shard(*idx))
+            .collect::<Vec<RwLockWriteGuard<'_, GranatStore>>>();
+
+        let combined =
+            self.combine_sorted_sets(&shards, &groups, keys.len(), op, weights, aggregate)?;
+        // Guards are in shard order, `dst`'s comes after every shard before it
+        let shard = &mut shards[groups.range(..self.shard_for(dst)).count()];
+        shard.del(vec![dst]);
+        shard.zadd(dst, combined.iter().collect(), AddOptions::default())?;
+
+        return shard.zcard(dst);
+    }
+
+    // Stream
+
+    pub fn xadd(
+        &self,
+        key: impl AsRef<str>,
+        id: NewId,
+        fields: StreamFields,
+        trim: Option<TrimStrategy>,
+    ) -> Result<StreamId> {
+        return self.write(key.as_ref(), |s| s.xadd(key.as_ref(), id, fields, trim));
+    }
+
+    pub fn xtrim(&self, key: impl AsRef<str>, strategy: TrimStrategy) -> Result<usize> {
+        return self.write(key.as_ref(), |s| s.xtrim(key.as_ref(), strategy));
+    }
+
+    pub fn xlen(&self, key: impl AsRef<str>) -> Result<usize> {
+        return self.read(key.as_ref(), |s| s.xlen(key.as_ref()));
+    }
+
+    pub fn xrange(
+        &self,
+        key: impl AsRef<str>,
+        start: Bound<StreamId>,
+        end: Bound<StreamId>,
+        count: Option<usize>,
+    ) -> Result<Vec<StreamEntry>> {
+        return self.read(key.as_ref(), |s| s.xrange(key.as_ref(), start, end, count));
+    }
+
+    pub fn xrevrange(
+        &self,
+        key: impl AsRef<str>,
+        end: Bound<StreamId>,
+        start: Bound<StreamId>,
+        count: Option<usize>,
+    ) -> Result<Vec<StreamEntry>> {
+        return self.read(key.as_ref(), |s| {
+            s.xrevrange(key.as_ref(), end, start, count)
+        });
+    }
+
+    /// Reads each stream from its own shard, so the result isn't one view of them all
+    pub fn xread(
+        &self,
+        streams: Vec<(impl AsRef<str>, StreamId)>,
+        count: Option<usize>,
+    ) -> Result<Vec<(String, Vec<StreamEntry>)>> {
+        let mut read = vec![];
+        for (key, after) in streams.iter() {
+            read.extend(self.read(key.as_ref(), |s| s.xread(vec![(key, *after)], count))?);
+        }
+
+        return Ok(read);
+    }
+
+    pub fn xgroup_create(
+        &self,
+        key: impl AsRef<str>,
+        group: impl AsRef<str>,
+        start: Option<StreamId>,
+        mkstream: bool,
+    ) -> Result<()> {
+        return self.write(key.as_ref(), |s| {
+            s.xgroup_create(key.as_ref(), group, start, mkstream)
+        });
+    }
+
+    pub fn xgroup_destroy(&self, key: impl AsRef<str>, group: impl AsRef<str>) -> Result<bool> {
+        return self.write(key.as_ref(), |s| s.xgroup_destroy(key.as_ref(), group));
+    }
+
+    pub fn xreadgroup(
+        &self,
+        key: impl AsRef<str>,
+        group: impl AsRef<str>,
+        consumer: impl AsRef<str>,
+        after: Option<StreamId>,
+        count: Option<usize>,
+        noack: bool,
+    ) -> Result<Vec<StreamEntry>> {
+        return self.write(key.as_ref(), |s| {
+            s.xreadgroup(key.as_ref(), group, consumer, after, count, noack)
+        });
+    }
+
+    pub fn xack(
+        &self,
+        key: impl AsRef<str>,
+        group: impl AsRef<str>,
+        ids: Vec<StreamId>,
+    ) -> Result<usize> {
+        return self.write(key.as_ref(), |s| s.xack(key.as_ref(), group, ids));
+    }
+
+    pub fn xpending(
+        &self,
+        key: impl AsRef<str>,
+        group: impl AsRef<str>,
+        start: Bound<StreamId>,
+        end: Bound<StreamId>,
+        count: usize,
+        consumer: Option<&str>,
+    ) -> Result<Vec<PendingMessage>> {
+        return self.read(key.as_ref(), |s| {
+            s.xpending(key.as_ref(), group, start, end, count, consumer)
+        });
+    }
+
+    pub fn xclaim(
+        &self,
+        key: impl AsRef<str>,
+        group: impl AsRef<str>,
+        consumer: impl AsRef<str>,
+        min_idle: u64,
+        ids: Vec<StreamId>,
+    ) -> Result<Vec<StreamEntry>> {
+        return self.write(key.as_ref(), |s| {
+            s.xclaim(key.as_ref(), group, consumer, min_idle, ids)
+        });
+    }
+
+    pub fn xinfo_stream(&self, key: impl AsRef<str>) -> Result<Option<StreamInfo>> {
+        return self.read(key.as_ref(), |s| s.xinfo_stream(key.as_ref()));
+    }
+
+    pub fn xinfo_groups(&self, key: impl AsRef<str>) -> Result<Vec<GroupInfo>> {
+        return self.read(key.as_ref(), |s| s.xinfo_groups(key.as_ref()));
+    }
+
+    pub fn xinfo_consumers(
+        &self,
+        key: impl AsRef<str>,
+        group: impl AsRef<str>,
+    ) -> Result<Vec<ConsumerInfo>> {
+        return self.read(key.as_ref(), |s| s.xinfo_consumers(key.as_ref(), group));
+    }
+
+    // HyperLogLog
+
+    pub fn pfadd(&self, key: impl AsRef<str>, elements: Vec<impl AsRef<[u8]>>) -> Result<bool> {
+        return self.write(key.as_ref(), |s| s.pfadd(key.as_ref(), elements));
+    }
+
+    pub fn pfcount(&self, keys: Vec<impl AsRef<str>>) -> Result<u64> {
+        let groups = self.group_by_shard(&keys);
+        if groups.len() == 1 {
+            let shard = self.read_shard(self.shard_for(keys[0].as_ref()));
+            return shard.pfcount(keys);
+        }
+
+        let shards = groups
+            .keys()
+            .map(|idx| self.read_shard(*idx))
+            .collect::<Vec<RwLockReadGuard<'_, GranatStore>>>();
+
+        let mut union = HyperLogLog::new();
+        for (group, shard) in groups.values().zip(shards.iter()) {
+            for (_, key) in group.iter() {
+                if let Some(hll) = shard.hll_get(key)? {
+                    union.merge(&hll);
+                }
+            }
+        }
+
+        return Ok(union.count());
+    }
+
+    /// Merges into `dst` with every shard involved locked, so the result is
+    /// built from one view of the sources
+    pub fn pfmerge(&self, dst: impl AsRef<str>, keys: Vec<impl AsRef<str>>) -> Result<()> {
+        let dst = dst.as_ref();
+        let mut all = keys.iter().map(|k| k.as_ref()).collect::<Vec<&str>>();
+        all.push(dst);
+
+        let groups = self.group_by_shard(&all);
+        if groups.len() == 1 {
+            return self.write(dst, |s| s.pfmerge(dst, keys));
+        }
+
+        let mut shards = groups
+            .keys()
+            .map(|idx| self.write_shard(*idx))
+            .collect::<Vec<RwLockWriteGuard<'_, GranatStore>>>();
+
+        // `dst` is one of the sources, so it keeps what it already counted
+        let mut merged = HyperLogLog::new();
+        for (group, shard) in groups.values().zip(shards.iter()) {
+            for (_, key) in group.iter() {
+                if let Some(hll) = shard.hll_get(key)? {
+                    merged.merge(&hll);
+                }
+            }
+        }
+
+        // Guards are in shard order, `dst`'s comes after every shard before it
+        let shard = &mut shards[groups.range(..self.shard_for(dst)).count()];
+        shard.hll_restore(dst, merged)?;
+
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod handle_tests {
+    use super::*;
+    use std::thread;
+
+    fn create_kv(key: &str, value: &str) -> KVPair {
+        return (key.to_string(), StoreEntry::new(value));
+    }
+
+    #[test]
+    fn handle_is_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync + Clone>() {}
+        assert_send_sync::<GranatHandle>();
+    }
+
+    #[test]
+    fn concurrent_writers() {
+        let handle = GranatHandle::new();
+        let workers = (0..8)
+            .map(|worker| {
+                let handle = handle.clone();
+                return thread::spawn(move || {
+                    for i in 0..100 {
+                        let _ = handle.increment("shared", 1);
+                        let _ =
+                            handle.push_right(create_kv(&format!("list:{worker}"), &i.to_string()));
+                    }
+                });
+            })
+            .collect::<Vec<thread::JoinHandle<()>>>();
+
+        for worker in workers.into_iter() {
+            worker.join().unwrap();
+        }
+
+        assert_eq!(handle.increment("shared", 0).unwrap(), 800);
+        for worker in 0..8 {
+            assert_eq!(handle.list_len(format!("list:{worker}")).unwrap(), 100);
+        }
+        assert_eq!(handle.keys("*").len(), 9);
+    }
+
+    #[test]
+    fn readers_see_whole_multi_key_writes() {
+        let handle = GranatHandle::new();
+        let keys = (0..8).map(|i| format!("key:{i}")).collect::<Vec<String>>();
+        let _ = handle.set_multiple(keys.iter().map(|k| create_kv(k, "0")).collect());
+
+        let writer = {
+            let (handle, keys) = (handle.clone(), keys.clone());
+            thread::spawn(move || {
+                for round in 1..=200 {
+                    let kvs = keys.iter().map(|k| create_kv(k, &round.to_string()));
+                    let _ = handle.set_multiple(kvs.collect());
+                }
+            })
+        };
+
+        for _ in 0..200 {
+            let values = handle
+                .get_multiple(keys.clone())
+                .into_iter()
+                .map(|e| e.unwrap().value.to_string())
+                .collect::<Vec<String>>();
+            assert!(values.iter().all(|v| *v == values[0]));
+        }
+
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn multi_key_operations_span_shards() {
+        let handle = GranatHandle::with_shards(4, system_clock());
+        for i in 0..20 {
+            let _ = handle.set(create_kv(&format!("key:{i}"), "value"));
+        }
+
+        assert_eq!(
+            handle.exists(vec!["key:0", "key:7", "key:19", "missing"]),
+            3
+        );
+        assert_eq!(
+            handle.get_multiple(vec!["missing", "key:3"]),
+            vec![None, Some(StoreEntry::new("value"))]
+        );
+
+        let mut cursor = None;
+        let mut scanned = vec![];
+        loop {
+            let (next, keys) = handle.scan(cursor, Some("key:*"), 3);
+            scanned.extend(keys);
+            cursor = next;
+            if cursor.is_none() {
+                break;
+            }
+        }
+        scanned.sort();
+        assert_eq!(scanned, handle.keys("*"));
+
+        assert_eq!(handle.del(vec!["key:0", "key:7", "missing"]), 2);
+        assert_eq!(handle.keys("*").len(), 18);
+    }
+
+    #[test]
+    fn set_algebra_across_shards() {
+        let handle = GranatHandle::new();
+        let keys = (0..8).map(|i| format!("set-{i}")).collect::<Vec<String>>();
+        for (i, key) in keys.iter().enumerate() {
+            let _ = handle.sadd(key, vec!["shared".to_string(), i.to_string()]);
+        }
+
+        assert_eq!(handle.sunion(keys.clone()).unwrap().len(), 9);
+        assert_eq!(handle.sinter(keys.clone()).unwrap(), vec!["shared"]);
+        assert_eq!(handle.sdiff(keys.clone()).unwrap(), vec!["0"]);
+
+        let _ = handle.set(("dst".to_string(), StoreEntry::new("value")));
+        assert_eq!(handle.sunionstore("dst", keys.clone()).unwrap(), 9);
+        assert_eq!(handle.type_of("dst"), Some(KeyType::Set));
+        assert_eq!(handle.sinterstore("dst", vec!["dst", "set-3"]).unwrap(), 2);
+        assert_eq!(handle.sdiffstore("dst", vec!["set-0", "dst"]).unwrap(), 1);
+
+        let _ = handle.set(("string".to_string(), StoreEntry::new("value")));
+        assert!(handle.sunionstore("dst", vec!["set-0", "string"]).is_err());
+        assert_eq!(handle.smembers("dst").unwrap(), vec!["0"]);
+    }
+
+    #[test]
+    fn bitmaps_across_shards() {
+        let handle = GranatHandle::new();
+        let days = (0..8).map(|i| format!("day-{i}")).collect::<Vec<String>>();
+        for (i, day) in days.iter().enumerate() {
+            // User 0 is active every day, user `i + 1` only on day `i`
+            let _ = handle.setbit(day, 0, true);
+            let _ = handle.setbit(day, i as u64 + 1, true);
+        }
+
+        assert_eq!(
+            handle.bitop(BitOperation::Or, "any", days.clone()).unwrap(),
+            2
+        );
+        assert_eq!(handle.bitcount("any", None).unwrap(), 9);
+        assert_eq!(
+            handle
+                .bitop(BitOperation::And, "all", days.clone())
+                .unwrap(),
+            2
+        );
+        assert_eq!(handle.bitcount("all", None).unwrap(), 1);
+        assert_eq!(handle.bitpos("all", true, None).unwrap(), 0);
+
+        let _ = handle.sadd("set", vec!["a"]);
+        assert!(handle
+            .bitop(BitOperation::Or, "all", vec!["day-0", "set"])
+            .is_err());
+        assert!(handle
+            .bitop(BitOperation::Not, "all", days.clone())
+            .is_err());
+        assert_eq!(handle.bitcount("all", None).unwrap(), 1);
+
+        // Missing keys are empty strings, so only pad the other sources with zeros
+        let _ = handle.bitop(BitOperation::And, "all", vec!["all", "missing"]);
+        assert_eq!(handle.bitcount("all", None).unwrap(), 0);
+        assert_eq!(
+            handle
+                .bitop(BitOperation::Or, "all", vec!["missing"])
+                .unwrap(),
+            0
+        );
+        assert_eq!(handle.type_of("all"), None);
+    }
+
+    #[test]
+    fn hyperloglogs_across_shards() {
+        let handle = GranatHandle::new();
+        // Every page sees visitor 0, then 100 of its own
+        let mut all = HyperLogLog::new();
+        let pages = (0..8).map(|i| format!("page-{i}")).collect::<Vec<String>>();
+        for (i, page) in pages.iter().enumerate() {
+            let mut visitors = vec!["0".to_string()];
+            visitors.extend((0..100).map(|v| format!("{i}-{v}")));
+            for visitor in visitors.iter() {
+                all.add(visitor);
+            }
+            assert!(handle.pfadd(page, visitors).unwrap());
+        }
+
+        assert_eq!(handle.pfcount(vec![&pages[0]]).unwrap(), 101);
+        assert_eq!(handle.pfcount(pages.clone()).unwrap(), all.count());
+
+        let _ = handle.pfadd("site", vec!["extra"]);
+        all.add("extra");
+        assert!(handle.pfmerge("site", pages.clone()).is_ok());
+        assert_eq!(handle.pfcount(vec!["site"]).unwrap(), all.count());
+        assert_eq!(handle.type_of("site"), Some(KeyType::HyperLogLog));
+
+        let _ = handle.sadd("set", vec!["a"]);
+        assert!(handle.pfcount(vec!["site", "set"]).is_err());
+        assert!(handle.pfmerge("set", pages).is_err());
+        assert_eq!(handle.type_of("set"), Some(KeyType::Set));
+    }
+
+    #[test]
+    fn sorted_set_algebra_across_shards() {
+        let handle = GranatHandle::new();
+        let keys = (0..8).map(|i| format!("zset-{i}")).collect::<Vec<String>>();
+        for (i, key) in keys.iter().enumerate() {
+            let members = vec![("shared".to_string(), 1.), (i.to_string(), i as f64)];
+            let _ = handle.zadd(key, members, AddOptions::default());
+        }
+
+        let union = handle.zunion(keys.clone(), None, Aggregate::Sum).unwrap();
+        assert_eq!(union.len(), 9);
+        assert_eq!(union.last().unwrap(), &("shared".to_string(), 8.));
+        let inter = handle.zinter(keys.clone(), None, Aggregate::Max).unwrap();
+        assert_eq!(inter, vec![("shared".to_string(), 1.)]);
+
+        let _ = handle.set(("dst".to_string(), StoreEntry::new("value")));
+        let weights = Some(vec![2.; keys.len()]);
+        let stored = handle.zunionstore("dst", keys.clone(), weights, Aggregate::Min);
+        assert_eq!(stored.unwrap(), 9);
+        assert_eq!(handle.type_of("dst"), Some(KeyType::SortedSet));
+        assert_eq!(handle.zscore("dst", "7").unwrap(), Some(14.));
+
+        let _ = handle.set(("string".to_string(), StoreEntry::new("value")));
+        let stored = handle.zinterstore("dst", vec!["zset-0", "string"], None, Aggregate::Sum);
+        assert!(stored.is_err());
+        assert_eq!(handle.zcard("dst").unwrap(), 9);
+    }
+
+    #[test]
+    fn consumers_share_a_stream() {
+        let handle = GranatHandle::new();
+        for i in 0..100 {
+            let fields = vec![("job".to_string(), i.to_string())];
+            let _ = handle.xadd("jobs", NewId::Auto, fields, None);
+        }
+        let _ = handle.xgroup_create("jobs", "workers", Some(StreamId::MIN), false);
+
+        let workers = (0..4)
+            .map(|worker| {
+                let handle = handle.clone();
+                return thread::spawn(move || {
+                    let consumer = format!("worker-{worker}");
+                    let mut done = vec![];
+                    loop {
+                        let jobs = handle
+                            .xreadgroup("jobs", "workers", &consumer, None, Some(3), false)
+                            .unwrap();
+                        if jobs.is_empty() {
+                            return done;
+                        }
+
+                        let ids = jobs.iter().map(|(id, _)| *id).collect::<Vec<StreamId>>();
+                        let _ = handle.xack("jobs", "workers", ids.clone());
+                        done.extend(ids);
+                    }
+                });
+            })
+            .collect::<Vec<thread::JoinHandle<Vec<StreamId>>>>();
+
+        let mut done = workers
+            .into_iter()
+            .flat_map(|worker| worker.join().unwrap())
+            .collect::<Vec<StreamId>>();
+        done.sort();
+        done.dedup();
+        assert_eq!(done.len(), 100);
+
+        let groups = handle.xinfo_groups("jobs").unwrap();
+        assert_eq!(groups[0].pending, 0);
+        assert_eq!(groups[0].consumers, 4);
+    }
+
+    #[test]
+    fn rename_across_shards() {
+        let handle = GranatHandle::with_shards(4, system_clock());
+        let src = "list";
+        let dst = (0..)
+            .map(|i| format!("dst:{i}"))
+            .find(|k| handle.shard_for(k) != handle.shard_for(src))
+            .unwrap();
+
+        let _ = handle.push_right(create_kv(src, "a"));
+        let _ = handle.push_right(create_kv(src, "b"));
+        let _ = handle.expire(src, 100, ExpireCondition::Always);
+        let _ = handle.set(create_kv(&dst, "overwritten"));
+
+        assert!(handle.rename(src, &dst).is_ok());
+        assert_eq!(handle.type_of(src), None);
+        assert_eq!(handle.type_of(&dst), Some(KeyType::List));
+        assert_eq!(handle.list_len(&dst).unwrap(), 2);
+        assert_eq!(
+            handle.index(&dst, 0).unwrap().unwrap().value,
+            "a".to_string()
+        );
+        assert_eq!(handle.ttl(&dst), 100);
+
+        assert!(handle.rename(src, &dst).is_err());
+        assert!(!handle.renamenx(&dst, &dst).unwrap());
+    }
+
+    #[test]
+    fn failed_rename_across_shards_leaves_both_keys() {
+        let handle = GranatHandle::with_shards(4, system_clock());
+        let src = "zset";
+        let dst = (0..)
+            .map(|i| format!("dst:{i}"))
+            .find(|k| handle.shard_for(k) != handle.shard_for(src))
+            .unwrap();
+
+        let members = vec![("a".to_string(), 1.), ("b".to_string(), 2.)];
+        let _ = handle.zadd(src, members, AddOptions::default());
+        let _ = handle.set(create_kv(&dst, "kept"));
+
+        // A score the store would never accept makes the rebuilt ZADD fail part way
+        handle.write(src, |s| {
+            s.zset.store.get_mut(src).unwrap().insert("c", f64::NAN)
+        });
+
+        assert!(handle.rename(src, &dst).is_err());
+        assert_eq!(handle.get(&dst).unwrap().unwrap().value, "kept".to_string());
+        assert_eq!(handle.type_of(src), Some(KeyType::SortedSet));
+    }
+
+    #[test]
+    fn renamenx_never_overwrites_a_racing_dst() {
+        let handle = GranatHandle::with_shards(4, system_clock());
+        let dst = "dst";
+        let same = (0..)
+            .map(|i| format!("same:{i}"))
+            .find(|k| handle.shard_for(k) == handle.shard_for(dst))
+            .unwrap();
+        let other = (0..)
+            .map(|i| format!("other:{i}"))
+            .find(|k| handle.shard_for(k) != handle.shard_for(dst))
+            .unwrap();
+
+        // Two renames race onto the same `dst`, only one of them can win
+        for _ in 0..200 {
+            let _ = handle.del(vec![dst]);
+            let _ = handle.set(create_kv(&same, &same));
+            let _ = handle.set(create_kv(&other, &other));
+
+            let barrier = Arc::new(std::sync::Barrier::new(2));
+            let racers = [same.clone(), other.clone()]
+                .into_iter()
+                .map(|src| {
+                    let (handle, barrier) = (handle.clone(), barrier.clone());
+                    return thread::spawn(move || {
+                        barrier.wait();
+                        let won = handle.renamenx(&src, dst).unwrap();
+                        return (src, won);
+                    });
+                })
+                .collect::<Vec<thread::JoinHandle<(String, bool)>>>();
+
+            let results = racers
+                .into_iter()
+                .map(|racer| racer.join().unwrap())
+                .collect::<Vec<(String, bool)>>();
+            let winners = results
+                .iter()
+                .filter(|(_, won)| *won)
+                .collect::<Vec<&(String, bool)>>();
+
+            assert_eq!(winners.len(), 1);
+            assert_eq!(handle.get(dst).unwrap().unwrap().value, winners[0].0);
+        }
+    }
+
+    fn wait_until_blocked(handle: &GranatHandle, count: usize) {
+        while handle.blocked_count() < count {
+            thread::yield_now();
+        }
+    }
+
+    #[test]
+    fn blocking_pop_waits_for_a_push() {
+        let handle = GranatHandle::new();
+        let timeout = Some(Duration::from_millis(20));
+        assert_eq!(
+            handle.blocking_pop_left(vec!["queue"], timeout).unwrap(),
+            None
+        );
+        assert_eq!(handle.blocked_count(), 0);
+
+        let _ = handle.push_right(create_kv("ready", "now"));
+        assert_eq!(
+            handle
+                .blocking_pop_right(vec!["queue", "ready"], timeout)
+                .unwrap(),
+            Some(create_kv("ready", "now"))
+        );
+
+        let waiter = {
+            let handle = handle.clone();
+            thread::spawn(move || handle.blocking_pop_left(vec!["a", "b"], None))
+        };
+        wait_until_blocked(&handle, 1);
+
+        let _ = handle.push_left(create_kv("b", "job"));
+        assert_eq!(waiter.join().unwrap().unwrap(), Some(create_kv("b", "job")));
+        assert_eq!(handle.type_of("b"), None);
+    }
+
+    #[test]
+    fn blocked_clients_are_served_in_order() {
+        let handle = GranatHandle::new();
+        let waiters = (0..3)
+            .map(|i| {
+                let waiter = {
+                    let handle = handle.clone();
+                    thread::spawn(move || handle.blocking_pop_left(vec!["queue"], None))
+                };
+                wait_until_blocked(&handle, i + 1);
+
+                return waiter;
+            })
+            .collect::<Vec<thread::JoinHandle<Result<Option<KVPair>>>>>();
+
+        for i in 0..3 {
+            let _ = handle.push_right(create_kv("queue", &i.to_string()));
+        }
+
+        for (i, waiter) in waiters.into_iter().enumerate() {
+            assert_eq!(
+                waiter.join().unwrap().unwrap(),
+                Some(create_kv("queue", &i.to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn blocking_move() {
+        let handle = GranatHandle::new();
+        let waiter = {
+            let handle = handle.clone();
+            thread::spawn(move || {
+                handle.blocking_move(
+                    "pending",
+                    "processing",
+                    ListDirection::Right,
+                    ListDirection::Left,
+                    None,
+                )
+            })
+        };
+        wait_until_blocked(&handle, 1);
+
+        let _ = handle.push_left(create_kv("pending", "job"));
+        assert_eq!(
+            waiter.join().unwrap().unwrap(),
+            Some(StoreEntry::new("job"))
+        );
+        assert_eq!(handle.type_of("pending"), None);
+        assert_eq!(handle.list_len("processing").unwrap(), 1);
+
+        let _ = handle.set(create_kv("string", "value"));
+        let _ = handle.push_left(create_kv("pending", "job"));
+        let moved = handle.blocking_move(
+            "pending",
+            "string",
+            ListDirection::Right,
+            ListDirection::Left,
+            None,
+        );
+        assert!(moved.is_err());
+        assert_eq!(handle.list_len("pending").unwrap(), 1);
+        assert!(handle.blocking_pop_left(vec!["string"], None).is_err());
+    }
+
+    #[test]
+    fn lmove_across_shards() {
+        let handle = GranatHandle::with_shards(4, system_clock());
+        let src = "pending";
+        let dst = (0..)
+            .map(|i| format!("processing:{i}"))
+            .find(|k| handle.shard_for(k) != handle.shard_for(src))
+            .unwrap();
+
+        let _ = handle.push_right(create_kv(src, "a"));
+        let _ = handle.push_right(create_kv(src, "b"));
+        assert_eq!(
+            handle.rpoplpush(src, &dst).unwrap(),
+            Some(StoreEntry::new("b"))
+        );
+        assert_eq!(
+            handle
+                .lmove(src, src, ListDirection::Left, ListDirection::Right)
+                .unwrap(),
+            Some(StoreEntry::new("a"))
+        );
+        assert_eq!(handle.list_len(src).unwrap(), 1);
+        assert_eq!(handle.list_len(&dst).unwrap(), 1);
+    }
+}
@@ -0,0 +1,363 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+
+use crate::store::clock::{system_clock, SharedClock};
+use crate::store::entry::StoreEntry;
+use crate::store::KVPair;
+
+/// Hashes of fields to entries, fields past their own expiry are skipped on
+/// reads and dropped on the next write to the hash.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct HashStore {
+    pub store: HashMap<String, HashMap<String, StoreEntry>>,
+
+    #[serde(skip, default = "system_clock")]
+    clock: SharedClock,
+}
+
+impl Default for HashStore {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+impl HashStore {
+    pub fn new() -> Self {
+        return Self::with_clock(system_clock());
+    }
+
+    /// Creates a store that checks expiry against `clock`
+    pub fn with_clock(clock: SharedClock) -> Self {
+        return Self {
+            store: HashMap::new(),
+            clock,
+        };
+    }
+
+    /// Sets each field to its entry, returning how many fields were newly added
+    pub fn set(&mut self, key: impl AsRef<str>, fields: Vec<KVPair>) -> usize {
+        self.purge_expired(key.as_ref());
+        if fields.is_empty() {
+            return 0;
+        }
+
+        let hash = self.store.entry(key.as_ref().to_string()).or_default();
+        let mut added = 0;
+        for (field, value) in fields.into_iter() {
+            if hash.insert(field, value).is_none() {
+                added += 1;
+            }
+        }
+
+        return added;
+    }
+
+    /// Sets the field only if it doesn't already exist, returning whether it was set
+    pub fn set_nx(&mut self, key: impl AsRef<str>, field: KVPair) -> bool {
+        self.purge_expired(key.as_ref());
+        let hash = self.store.entry(key.as_ref().to_string()).or_default();
+        if hash.contains_key(&field.0) {
+            return false;
+        }
+
+        hash.insert(field.0, field.1);
+
+        return true;
+    }
+
+    pub fn get(&self, key: impl AsRef<str>, field: impl AsRef<str>) -> Option<StoreEntry> {
+        let entry = self.store.get(key.as_ref())?.get(field.as_ref())?;
+        if entry.is_expired_at(self.clock.now_millis()) {
+            return None;
+        }
+
+        return Some(entry.clone());
+    }
+
+    /// Removes the fields, dropping the hash once it's empty.
+    ///
+    /// Returns the number of fields removed.
+    pub fn delete(&mut self, key: impl AsRef<str>, fields: Vec<impl AsRef<str>>) -> usize {
+        self.purge_expired(key.as_ref());
+        let Some(hash) = self.store.get_mut(key.as_ref()) else {
+            return 0;
+        };
+
+        let removed = fields
+            .iter()
+            .filter(|f| hash.remove(f.as_ref()).is_some())
+            .count();
+
+        if hash.is_empty() {
+            self.store.remove(key.as_ref());
+        }
+
+        return removed;
+    }
+
+    pub fn exists(&self, key: impl AsRef<str>, field: impl AsRef<str>) -> bool {
+        return self.get(key, field).is_some();
+    }
+
+    pub fn len(&self, key: impl AsRef<str>) -> usize {
+        return self.live_fields(key.as_ref()).count();
+    }
+
+    pub fn keys(&self, key: impl AsRef<str>) -> Vec<String> {
+        return self
+            .live_fields(key.as_ref())
+            .map(|(field, _)| field.clone())
+            .collect::<Vec<String>>();
+    }
+
+    pub fn values(&self, key: impl AsRef<str>) -> Vec<StoreEntry> {
+        return self
+            .live_fields(key.as_ref())
+            .map(|(_, entry)| entry.clone())
+            .collect::<Vec<StoreEntry>>();
+    }
+
+    /// Every field with its entry, in no particular order
+    pub fn get_all(&self, key: impl AsRef<str>) -> Vec<KVPair> {
+        return self
+            .live_fields(key.as_ref())
+            .map(|(field, entry)| (field.clone(), entry.clone()))
+            .collect::<Vec<KVPair>>();
+    }
+
+    /// Adds `incr` to the field, starting from `0` if it doesn't exist
+    pub fn increment(
+        &mut self,
+        key: impl AsRef<str>,
+        field: impl AsRef<str>,
+        incr: i64,
+    ) -> Result<i64> {
+        let mut entry = self.field_or_zero(key.as_ref(), field.as_ref());
+        let val = entry.increment(incr)?;
+        self.put_field(key.as_ref(), field.as_ref(), entry);
+
+        return Ok(val);
+    }
+
+    /// Adds `incr` to the field, starting from `0` if it doesn't exist
+    pub fn increment_float(
+        &mut self,
+        key: impl AsRef<str>,
+        field: impl AsRef<str>,
+        incr: f64,
+    ) -> Result<f64> {
+        let mut entry = self.field_or_zero(key.as_ref(), field.as_ref());
+        let val = entry.increment_float(incr)?;
+        self.put_field(key.as_ref(), field.as_ref(), entry);
+
+        return Ok(val);
+    }
+
+    /// A copy of the field to increment, so a failed increment leaves the hash untouched
+    fn field_or_zero(&mut self, key: &str, field: &str) -> StoreEntry {
+        self.purge_expired(key);
+        return self
+            .store
+            .get(key)
+            .and_then(|hash| hash.get(field))
+            .cloned()
+            .unwrap_or_else(|| StoreEntry::new("0"));
+    }
+
+    fn put_field(&mut self, key: &str, field: &str, entry: StoreEntry) {
+        self.store
+            .entry(key.to_string())
+            .or_default()
+            .insert(field.to_string(), entry);
+    }
+
+    /// Drops any expired fields from the hash, removing the key if nothing is left.
+    ///
+    /// Returns the number of fields dropped.
+    pub fn purge_expired(&mut self, key: impl AsRef<str>) -> usize {
+        let now = self.clock.now_millis();
+        if let Some(hash) = self.store.get_mut(key.as_ref()) {
+            let before = hash.len();
+            hash.retain(|_, entry| !entry.is_expired_at(now));
+
+            let purged = before - hash.len();
+            if hash.is_empty() {
+                self.store.remove(key.as_ref());
+            }
+
+            return purged;
+        }
+
+        return 0;
+    }
+
+    fn live_fields(&self, key: &str) -> impl Iterator<Item = (&String, &StoreEntry)> {
+        let now = self.clock.now_millis();
+        return self
+            .store
+            .get(key)
+            .into_iter()
+            .flatten()
+            .filter(move |(_, entry)| !entry.is_expired_at(now));
+    }
+}
+
+#[cfg(test)]
+mod hash_store_tests {
+    use super::*;
+    use crate::store::clock::MockClock;
+    use crate::store::entry::ExpiryState;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn create_field(field: &str, value: &str) -> KVPair {
+        return (field.to_string(), StoreEntry::new(value));
+    }
+
+    fn create_expired_field(field: &str, value: &str) -> KVPair {
+        let mut entry = StoreEntry::new(value);
+        entry.expiry = ExpiryState::Expired;
+
+        return (field.to_string(), entry);
+    }
+
+    fn create_basic_hash_store() -> HashStore {
+        let mut hs = HashStore::new();
+        hs.set(
+            "user",
+            vec![
+                create_field("name", "granat"),
+                create_field("age", "3"),
+                create_field("score", "1.5"),
+            ],
+        );
+
+        return hs;
+    }
+
+    #[test]
+    fn set_and_get_fields() {
+        let mut hs = create_basic_hash_store();
+        assert_eq!(hs.len("user"), 3);
+        assert_eq!(hs.get("user", "name").unwrap().value, "granat".to_string());
+        assert!(hs.get("user", "missing").is_none());
+        assert!(hs.get("missing", "name").is_none());
+
+        // Only new fields are counted
+        let added = hs.set(
+            "user",
+            vec![create_field("name", "store"), create_field("lang", "rust")],
+        );
+        assert_eq!(added, 1);
+        assert_eq!(hs.get("user", "name").unwrap().value, "store".to_string());
+        assert!(hs.exists("user", "lang"));
+        assert!(!hs.exists("user", "missing"));
+
+        assert_eq!(hs.set("empty", vec![]), 0);
+        assert!(!hs.store.contains_key("empty"));
+    }
+
+    #[test]
+    fn set_nx() {
+        let mut hs = create_basic_hash_store();
+        assert!(!hs.set_nx("user", create_field("name", "other")));
+        assert_eq!(hs.get("user", "name").unwrap().value, "granat".to_string());
+
+        assert!(hs.set_nx("user", create_field("lang", "rust")));
+        assert!(hs.set_nx("new", create_field("field", "value")));
+        assert_eq!(hs.len("new"), 1);
+    }
+
+    #[test]
+    fn delete_fields() {
+        let mut hs = create_basic_hash_store();
+        assert_eq!(hs.delete("user", vec!["name", "missing"]), 1);
+        assert_eq!(hs.len("user"), 2);
+
+        assert_eq!(hs.delete("user", vec!["age", "score"]), 2);
+        assert!(!hs.store.contains_key("user"));
+        assert_eq!(hs.delete("user", vec!["age"]), 0);
+    }
+
+    #[test]
+    fn keys_values_and_get_all() {
+        let hs = create_basic_hash_store();
+
+        let mut keys = hs.keys("user");
+        keys.sort();
+        assert_eq!(keys, vec!["age", "name", "score"]);
+
+        let mut values = hs
+            .values("user")
+            .into_iter()
+            .map(|e| e.value.to_string())
+            .collect::<Vec<String>>();
+        values.sort();
+        assert_eq!(values, vec!["1.5", "3", "granat"]);
+
+        let mut all = hs.get_all("user");
+        all.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(all[0], create_field("age", "3"));
+        assert_eq!(all.len(), 3);
+
+        assert!(hs.get_all("missing").is_empty());
+    }
+
+    #[test]
+    fn increment_fields() {
+        let mut hs = create_basic_hash_store();
+        assert_eq!(hs.increment("user", "age", 2).unwrap(), 5);
+        assert_eq!(hs.increment("user", "visits", -1).unwrap(), -1);
+        assert!(hs.increment("user", "name", 1).is_err());
+        assert!(hs.increment("user", "score", 1).is_err());
+
+        assert_eq!(hs.increment_float("user", "score", 1.25).unwrap(), 2.75);
+        assert_eq!(hs.increment_float("user", "ratio", 0.5).unwrap(), 0.5);
+        assert!(hs.increment_float("user", "name", 1.0).is_err());
+        assert_eq!(hs.get("user", "score").unwrap().value, "2.75".to_string());
+
+        // Results out of range error without creating anything
+        hs.set("user", vec![create_field("max", &i64::MAX.to_string())]);
+        assert!(hs.increment("user", "max", 1).is_err());
+        assert_eq!(hs.increment("user", "max", -1).unwrap(), i64::MAX - 1);
+        assert!(hs.increment_float("user", "huge", f64::INFINITY).is_err());
+        assert!(hs.get("user", "huge").is_none());
+        assert!(hs.increment_float("missing", "f", f64::NAN).is_err());
+        assert!(!hs.store.contains_key("missing"));
+    }
+
+    #[test]
+    fn expired_fields_are_skipped() {
+        let mut hs = create_basic_hash_store();
+        hs.set("user", vec![create_expired_field("session", "abc")]);
+
+        assert!(hs.get("user", "session").is_none());
+        assert!(!hs.exists("user", "session"));
+        assert_eq!(hs.len("user"), 3);
+        assert!(!hs.keys("user").contains(&"session".to_string()));
+
+        // Writing over an expired field counts as adding it
+        assert!(hs.set_nx("user", create_field("session", "def")));
+        assert_eq!(hs.get("user", "session").unwrap().value, "def".to_string());
+
+        hs.set("cache", vec![create_expired_field("field", "value")]);
+        assert_eq!(hs.purge_expired("cache"), 1);
+        assert!(!hs.store.contains_key("cache"));
+    }
+
+    #[test]
+    fn expiry_follows_the_store_clock() {
+        let clock = Arc::new(MockClock::new(0));
+        let mut hs = HashStore::with_clock(clock.clone());
+
+        let entry = StoreEntry::new("value").expires_in_with(10, clock.as_ref());
+        hs.set("hash", vec![("field".to_string(), entry)]);
+        assert!(hs.exists("hash", "field"));
+
+        clock.advance(Duration::from_secs(11));
+        assert!(!hs.exists("hash", "field"));
+        assert_eq!(hs.len("hash"), 0);
+    }
+}
@@ -0,0 +1,442 @@
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+
+/// Bits of the hash used to pick a register
+const P: u32 = 14;
+/// Number of registers, giving a standard error of `1.04 / sqrt(M)`, about 0.81%
+const M: usize = 1 << P;
+/// Bits of the hash left over to count zeros in, a register never holds more than `Q + 1`
+const Q: u32 = 64 - P;
+/// Most registers the sparse form holds before switching to dense, past this
+/// it takes more memory than the dense form
+const SPARSE_MAX: usize = 3000;
+
+/// MurmurHash64A, the hash Redis uses for HyperLogLogs.
+///
+/// The std hashers aren't guaranteed to be stable between releases, this has
+/// to be as the registers it fills are persisted.
+fn murmur64a(data: &[u8], seed: u64) -> u64 {
+    const MUL: u64 = 0xc6a4a7935bd1e995;
+    const R: u32 = 47;
+
+    let mut h = seed ^ (data.len() as u64).wrapping_mul(MUL);
+    let mut chunks = data.chunks_exact(8);
+    for chunk in chunks.by_ref() {
+        let mut k = u64::from_le_bytes(chunk.try_into().unwrap());
+        k = k.wrapping_mul(MUL);
+        k ^= k >> R;
+        k = k.wrapping_mul(MUL);
+
+        h ^= k;
+        h = h.wrapping_mul(MUL);
+    }
+
+    let tail = chunks.remainder();
+    if !tail.is_empty() {
+        for (i, byte) in tail.iter().enumerate() {
+            h ^= (*byte as u64) << (8 * i);
+        }
+        h = h.wrapping_mul(MUL);
+    }
+
+    h ^= h >> R;
+    h = h.wrapping_mul(MUL);
+    h ^= h >> R;
+
+    return h;
+}
+
+/// The register `element` falls in and the run of zeros it counts there
+fn register_for(element: &[u8]) -> (usize, u8) {
+    let hash = murmur64a(element, 0xadc83b19);
+    let idx = (hash & (M as u64 - 1)) as usize;
+    let rest = (hash >> P) | (1 << Q);
+
+    return (idx, rest.trailing_zeros() as u8 + 1);
+}
+
+/// `sigma` from Ertl's "New cardinality estimation algorithms for HyperLogLog sketches"
+fn sigma(mut x: f64) -> f64 {
+    if x == 1. {
+        return f64::INFINITY;
+    }
+
+    let mut y = 1.;
+    let mut z = x;
+    loop {
+        x *= x;
+        let prev = z;
+        z += x * y;
+        y += y;
+        if prev == z {
+            return z;
+        }
+    }
+}
+
+/// `tau` from the same paper
+fn tau(mut x: f64) -> f64 {
+    if x == 0. || x == 1. {
+        return 0.;
+    }
+
+    let mut y = 1.;
+    let mut z = 1. - x;
+    loop {
+        x = x.sqrt();
+        let prev = z;
+        y *= 0.5;
+        z -= (1. - x).powi(2) * y;
+        if prev == z {
+            return z / 3.;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(try_from = "UncheckedRegisters")]
+enum Registers {
+    /// Only the registers that have been set, sorted by index
+    Sparse(Vec<(u16, u8)>),
+    /// Every register, one byte each
+    Dense(Vec<u8>),
+}
+
+/// `Registers` as read from a snapshot or the append-only file, checked before
+/// use since a register out of range would panic the first time it's touched
+#[derive(Deserialize)]
+enum UncheckedRegisters {
+    Sparse(Vec<(u16, u8)>),
+    Dense(Vec<u8>),
+}
+
+impl TryFrom<UncheckedRegisters> for Registers {
+    type Error = String;
+
+    fn try_from(registers: UncheckedRegisters) -> Result<Self, Self::Error> {
+        let max_rank = Q as u8 + 1;
+        match registers {
+            UncheckedRegisters::Sparse(set) => {
+                if let Some((idx, rank)) = set
+                    .iter()
+                    .find(|(idx, rank)| *idx as usize >= M || *rank == 0 || *rank > max_rank)
+                {
+                    return Err(format!("invalid HyperLogLog register {idx} of rank {rank}"));
+                }
+
+                if set.windows(2).any(|pair| pair[0].0 >= pair[1].0) {
+                    return Err("HyperLogLog registers out of order".to_string());
+                }
+
+                return Ok(Self::Sparse(set));
+            }
+            UncheckedRegisters::Dense(registers) => {
+                if registers.len() != M {
+                    return Err(format!(
+                        "expected {M} HyperLogLog registers, found {}",
+                        registers.len()
+                    ));
+                }
+
+                if let Some(rank) = registers.iter().find(|rank| **rank > max_rank) {
+                    return Err(format!("invalid HyperLogLog register rank {rank}"));
+                }
+
+                return Ok(Self::Dense(registers));
+            }
+        }
+    }
+}
+
+/// A probabilistic count of distinct elements in a fixed 16KB at most.
+///
+/// Starts out sparse, holding only the registers that have been set, and
+/// switches to a dense array of every register once that would be smaller.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct HyperLogLog {
+    registers: Registers,
+}
+
+impl Default for HyperLogLog {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+impl HyperLogLog {
+    pub fn new() -> Self {
+        return Self {
+            registers: Registers::Sparse(vec![]),
+        };
+    }
+
+    pub fn is_sparse(&self) -> bool {
+        return matches!(self.registers, Registers::Sparse(_));
+    }
+
+    /// Raises the register at `idx` to `rank`, returning whether it changed
+    fn raise(&mut self, idx: usize, rank: u8) -> bool {
+        match &mut self.registers {
+            Registers::Sparse(set) => match set.binary_search_by_key(&(idx as u16), |(i, _)| *i) {
+                Ok(pos) if set[pos].1 >= rank => return false,
+                Ok(pos) => set[pos].1 = rank,
+                Err(pos) => set.insert(pos, (idx as u16, rank)),
+            },
+            Registers::Dense(registers) => {
+                if registers[idx] >= rank {
+                    return false;
+                }
+                registers[idx] = rank;
+            }
+        }
+
+        if let Registers::Sparse(set) = &self.registers {
+            if set.len() > SPARSE_MAX {
+                let mut registers = vec![0; M];
+                for (idx, rank) in set.iter() {
+                    registers[*idx as usize] = *rank;
+                }
+                self.registers = Registers::Dense(registers);
+            }
+        }
+
+        return true;
+    }
+
+    /// Adds the element, returning whether the estimate may have changed
+    pub fn add(&mut self, element: impl AsRef<[u8]>) -> bool {
+        let (idx, rank) = register_for(element.as_ref());
+        return self.raise(idx, rank);
+    }
+
+    /// Folds `other` in, so this counts the union of both
+    pub fn merge(&mut self, other: &HyperLogLog) {
+        match &other.registers {
+            Registers::Sparse(set) => {
+                for (idx, rank) in set.iter() {
+                    self.raise(*idx as usize, *rank);
+                }
+            }
+            Registers::Dense(registers) => {
+                for (idx, rank) in registers.iter().enumerate().filter(|(_, r)| **r > 0) {
+                    self.raise(idx, *rank);
+                }
+            }
+        }
+    }
+
+    /// The estimated number of distinct elements added
+    pub fn count(&self) -> u64 {
+        // How many registers hold each value, unset registers are all zeros
+        let mut histogram = [0u32; Q as usize + 2];
+        match &self.registers {
+            Registers::Sparse(set) => {
+                histogram[0] = (M - set.len()) as u32;
+                for (_, rank) in set.iter() {
+                    histogram[*rank as usize] += 1;
+                }
+            }
+            Registers::Dense(registers) => {
+                for rank in registers.iter() {
+                    histogram[*rank as usize] += 1;
+                }
+            }
+        }
+
+        let m = M as f64;
+        let mut z = m * tau((m - histogram[Q as usize + 1] as f64) / m);
+        for count in histogram[1..=Q as usize].iter().rev() {
+            z += *count as f64;
+            z *= 0.5;
+        }
+        z += m * sigma(histogram[0] as f64 / m);
+
+        let alpha = 0.5 / std::f64::consts::LN_2;
+        return (alpha * m * m / z).round() as u64;
+    }
+}
+
+/// HyperLogLogs, estimating how many distinct elements were added to each key
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct HyperLogLogStore {
+    pub store: HashMap<String, HyperLogLog>,
+}
+
+impl Default for HyperLogLogStore {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+impl HyperLogLogStore {
+    pub fn new() -> Self {
+        return Self {
+            store: HashMap::new(),
+        };
+    }
+
+    /// Adds the elements, creating the key if needed.
+    ///
+    /// Returns whether the estimate may have changed, which includes creating the key.
+    pub fn add(&mut self, key: impl AsRef<str>, elements: &[impl AsRef<[u8]>]) -> bool {
+        let mut changed = !self.store.contains_key(key.as_ref());
+        let hll = self.store.entry(key.as_ref().to_string()).or_default();
+        for element in elements.iter() {
+            changed |= hll.add(element);
+        }
+
+        return changed;
+    }
+
+    /// The estimated number of distinct elements across all of `keys`, missing keys add nothing
+    pub fn count(&self, keys: &[impl AsRef<str>]) -> u64 {
+        if let [key] = keys {
+            return self.store.get(key.as_ref()).map_or(0, |hll| hll.count());
+        }
+
+        let mut union = HyperLogLog::new();
+        for hll in keys.iter().filter_map(|k| self.store.get(k.as_ref())) {
+            union.merge(hll);
+        }
+
+        return union.count();
+    }
+
+    /// Merges the HyperLogLogs at `keys` into `dst`, keeping what `dst` already counted
+    pub fn merge(&mut self, dst: impl AsRef<str>, keys: &[impl AsRef<str>]) {
+        let mut merged = self.store.get(dst.as_ref()).cloned().unwrap_or_default();
+        for hll in keys.iter().filter_map(|k| self.store.get(k.as_ref())) {
+            merged.merge(hll);
+        }
+
+        self.store.insert(dst.as_ref().to_string(), merged);
+    }
+}
+
+#[cfg(test)]
+mod hyperloglog_store_tests {
+    use super::*;
+
+    /// Whether `estimate` is within `error` of `actual`, as a fraction
+    fn within(estimate: u64, actual: u64, error: f64) -> bool {
+        return (estimate as f64 - actual as f64).abs() <= actual as f64 * error;
+    }
+
+    #[test]
+    fn hash_is_stable() {
+        // Pinned, persisted registers depend on these never changing
+        assert_eq!(murmur64a(b"", 0), 0);
+        assert_eq!(murmur64a(b"hello", 0), 0x1e68d17c457bf117);
+        assert_eq!(murmur64a(b"hello, world", 0xadc83b19), 0xf97a3f73969996ca);
+    }
+
+    #[test]
+    fn small_counts_are_exact() {
+        let mut hll = HyperLogLog::new();
+        assert_eq!(hll.count(), 0);
+        assert!(hll.add("a"));
+        assert!(!hll.add("a"));
+        assert!(hll.add("b"));
+        assert!(hll.add([0xFF, 0x00]));
+        assert_eq!(hll.count(), 3);
+        assert!(hll.is_sparse());
+    }
+
+    #[test]
+    fn estimates_large_counts() {
+        let mut hll = HyperLogLog::new();
+        for i in 0..100_000 {
+            hll.add(format!("visitor-{i}"));
+        }
+
+        assert!(!hll.is_sparse());
+        // Several standard errors of slack, the estimate is deterministic so this can't flake
+        assert!(within(hll.count(), 100_000, 0.03));
+
+        // Adding the same elements again changes nothing
+        let before = hll.clone();
+        for i in 0..1000 {
+            hll.add(format!("visitor-{i}"));
+        }
+        assert_eq!(hll, before);
+    }
+
+    #[test]
+    fn merging_counts_the_union() {
+        let mut hs = HyperLogLogStore::new();
+        let evens = (0..20_000)
+            .step_by(2)
+            .map(|i| i.to_string())
+            .collect::<Vec<String>>();
+        let thirds = (0..20_000)
+            .step_by(3)
+            .map(|i| i.to_string())
+            .collect::<Vec<String>>();
+        assert!(hs.add("evens", &evens));
+        assert!(hs.add("thirds", &thirds));
+        assert!(!hs.add("evens", &evens[..10]));
+
+        // 10000 + 6667 - 3334 shared
+        let union = hs.count(&["evens", "thirds", "missing"]);
+        assert!(within(union, 13_333, 0.03));
+        assert!(within(hs.count(&["evens"]), 10_000, 0.03));
+        assert_eq!(hs.count(&["missing"]), 0);
+
+        hs.merge("all", &["evens", "thirds"]);
+        assert_eq!(hs.count(&["all"]), union);
+        hs.merge("all", &["missing"]);
+        assert_eq!(hs.count(&["all"]), union);
+
+        // Creating a key counts as a change even without elements
+        let none: [&str; 0] = [];
+        assert!(hs.add("empty", &none));
+        assert!(!hs.add("empty", &none));
+        assert_eq!(hs.count(&["empty"]), 0);
+    }
+
+    #[test]
+    fn sparse_and_dense_agree() {
+        let mut sparse = HyperLogLog::new();
+        for i in 0..2000 {
+            sparse.add(i.to_string());
+        }
+        assert!(sparse.is_sparse());
+
+        let mut dense = HyperLogLog {
+            registers: Registers::Dense(vec![0; M]),
+        };
+        dense.merge(&sparse);
+        assert!(!dense.is_sparse());
+        assert_eq!(dense.count(), sparse.count());
+
+        let json = serde_json::to_string(&sparse).unwrap();
+        assert_eq!(serde_json::from_str::<HyperLogLog>(&json).unwrap(), sparse);
+        let json = serde_json::to_string(&dense).unwrap();
+        assert_eq!(serde_json::from_str::<HyperLogLog>(&json).unwrap(), dense);
+    }
+
+    #[test]
+    fn rejects_invalid_registers() {
+        for json in [
+            r#"{"registers":{"Sparse":[[16384,1]]}}"#,
+            r#"{"registers":{"Sparse":[[1,52]]}}"#,
+            r#"{"registers":{"Sparse":[[1,0]]}}"#,
+            r#"{"registers":{"Sparse":[[2,1],[1,1]]}}"#,
+            r#"{"registers":{"Dense":[1,2,3]}}"#,
+        ] {
+            assert!(serde_json::from_str::<HyperLogLog>(json).is_err(), "{json}");
+        }
+
+        let mut dense = vec![0; M];
+        dense[0] = 52;
+        let json = serde_json::to_string(&serde_json::json!({ "registers": { "Dense": dense } }));
+        assert!(serde_json::from_str::<HyperLogLog>(&json.unwrap()).is_err());
+
+        let json = r#"{"registers":{"Sparse":[[1,1],[16383,51]]}}"#;
+        assert_eq!(
+            serde_json::from_str::<HyperLogLog>(json).unwrap().count(),
+            2
+        );
+    }
+}
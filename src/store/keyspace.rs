@@ -0,0 +1,191 @@
+use serde::{Deserialize, Serialize};
+
+use std::fmt;
+
+/// The type of value a key in the keyspace holds
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq, Hash)]
+pub enum KeyType {
+    String,
+    List,
+    Hash,
+    Set,
+    SortedSet,
+    Stream,
+    HyperLogLog,
+}
+
+impl fmt::Display for KeyType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::String => "string",
+            Self::List => "list",
+            Self::Hash => "hash",
+            Self::Set => "set",
+            Self::SortedSet => "zset",
+            Self::Stream => "stream",
+            Self::HyperLogLog => "hyperloglog",
+        };
+
+        write!(f, "{name}")
+    }
+}
+
+/// Conditions for setting a key's expiry, mirroring the Redis `EXPIRE` flags
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum ExpireCondition {
+    /// Always set the expiry
+    #[default]
+    Always,
+    /// Only set the expiry if the key has none
+    Nx,
+    /// Only set the expiry if the key already has one
+    Xx,
+    /// Only set the expiry if it's later than the current one, keys without one count as infinite
+    Gt,
+    /// Only set the expiry if it's earlier than the current one, keys without one count as infinite
+    Lt,
+}
+
+/// Redis style glob matching used by `KEYS` and `SCAN`.
+///
+/// Supports `*`, `?`, character classes (`[abc]`, `[a-z]`, `[^a]`) and `\` to
+/// escape any of the special characters.
+pub fn glob_match(pattern: impl AsRef<str>, key: impl AsRef<str>) -> bool {
+    let pattern = pattern.as_ref().chars().collect::<Vec<char>>();
+    let key = key.as_ref().chars().collect::<Vec<char>>();
+
+    return match_from(&pattern, &key);
+}
+
+fn match_from(pattern: &[char], key: &[char]) -> bool {
+    let (mut p, mut k) = (0, 0);
+
+    // Position to resume from when the most recent `*` needs to swallow another char
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while k < key.len() {
+        if p < pattern.len() {
+            match pattern[p] {
+                '*' => {
+                    backtrack = Some((p, k));
+                    p += 1;
+                    continue;
+                }
+                '?' => {
+                    p += 1;
+                    k += 1;
+                    continue;
+                }
+                '[' => {
+                    if let Some((matched, next)) = match_class(pattern, p, key[k]) {
+                        if matched {
+                            p = next;
+                            k += 1;
+                            continue;
+                        }
+                    }
+                }
+                '\\' if p + 1 < pattern.len() => {
+                    if pattern[p + 1] == key[k] {
+                        p += 2;
+                        k += 1;
+                        continue;
+                    }
+                }
+                c => {
+                    if c == key[k] {
+                        p += 1;
+                        k += 1;
+                        continue;
+                    }
+                }
+            }
+        }
+
+        match backtrack {
+            Some((star, consumed)) => {
+                p = star + 1;
+                k = consumed + 1;
+                backtrack = Some((star, consumed + 1));
+            }
+            None => return false,
+        }
+    }
+
+    return pattern[p..].iter().all(|c| *c == '*');
+}
+
+/// Matches `c` against the class starting at `pattern[start]` (the `[`).
+///
+/// Returns whether it matched and the index after the closing `]`, or `None`
+/// if the class is unterminated.
+fn match_class(pattern: &[char], start: usize, c: char) -> Option<(bool, usize)> {
+    let mut idx = start + 1;
+    let negate = idx < pattern.len() && (pattern[idx] == '^' || pattern[idx] == '!');
+    if negate {
+        idx += 1;
+    }
+
+    let mut matched = false;
+    let mut first = true;
+    while idx < pattern.len() && (first || pattern[idx] != ']') {
+        first = false;
+        let mut lo = pattern[idx];
+        if lo == '\\' && idx + 1 < pattern.len() {
+            idx += 1;
+            lo = pattern[idx];
+        }
+
+        if idx + 2 < pattern.len() && pattern[idx + 1] == '-' && pattern[idx + 2] != ']' {
+            let hi = pattern[idx + 2];
+            let (lo, hi) = if lo <= hi { (lo, hi) } else { (hi, lo) };
+            if c >= lo && c <= hi {
+                matched = true;
+            }
+            idx += 3;
+        } else {
+            if c == lo {
+                matched = true;
+            }
+            idx += 1;
+        }
+    }
+
+    if idx >= pattern.len() {
+        return None;
+    }
+
+    return Some((matched != negate, idx + 1));
+}
+
+#[cfg(test)]
+mod keyspace_tests {
+    use super::*;
+
+    #[test]
+    fn glob_literals_and_wildcards() {
+        assert!(glob_match("foo", "foo"));
+        assert!(!glob_match("foo", "foobar"));
+        assert!(glob_match("*", "anything"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("foo*", "foobar"));
+        assert!(glob_match("*bar", "foobar"));
+        assert!(glob_match("f*o*r", "foobar"));
+        assert!(!glob_match("f*z", "foobar"));
+        assert!(glob_match("h?llo", "hello"));
+        assert!(!glob_match("h?llo", "hllo"));
+    }
+
+    #[test]
+    fn glob_classes_and_escapes() {
+        assert!(glob_match("h[ae]llo", "hallo"));
+        assert!(!glob_match("h[ae]llo", "hillo"));
+        assert!(glob_match("h[^e]llo", "hallo"));
+        assert!(!glob_match("h[^e]llo", "hello"));
+        assert!(glob_match("key:[0-9]", "key:7"));
+        assert!(!glob_match("key:[0-9]", "key:a"));
+        assert!(glob_match("what\\?", "what?"));
+        assert!(!glob_match("what\\?", "whats"));
+        assert!(glob_match("star\\*", "star*"));
+    }
+}
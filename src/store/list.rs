@@ -1,8 +1,12 @@
-use anyhow::{anyhow, Result};
-use serde::{Deserialize, Serialize};
+use anyhow::Result;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
-use std::collections::{HashMap, LinkedList};
+use std::collections::vec_deque::{self, VecDeque};
+use std::collections::HashMap;
 
+use crate::store::clock::{system_clock, SharedClock};
+use crate::store::entry::ExpiryState;
+use crate::store::error::GranatError;
 use crate::store::{entry::StoreEntry, KVPair};
 
 fn idx_from_offset(list_size: usize, idx: isize) -> isize {
@@ -13,21 +17,251 @@ fn idx_from_offset(list_size: usize, idx: isize) -> isize {
     return list_size as isize + idx;
 }
 
-#[derive(Debug, PartialEq)]
-enum ListDirection {
+/// Number of entries in the list that haven't expired as of `now`
+fn live_len(list: &List, now: i64) -> usize {
+    if !list.may_have_expired(now) {
+        return list.len();
+    }
+
+    return list.iter().filter(|e| !e.is_expired_at(now)).count();
+}
+
+/// When the entry expires, `i64::MIN` if it already has and `None` if it never does
+fn deadline(entry: &StoreEntry) -> Option<i64> {
+    match entry.expiry {
+        ExpiryState::Expired => return Some(i64::MIN),
+        ExpiryState::Active(exp) => return Some(exp),
+        ExpiryState::NoExpiry => return None,
+    }
+}
+
+/// Which end of a list to push onto or pop from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum ListDirection {
     Left,
     Right,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+/// Which side of the pivot `linsert` puts the new entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum InsertPosition {
+    Before,
+    After,
+}
+
+/// The entries of a single list.
+///
+/// Entries sit in one contiguous ring buffer so indexing is O(1) and there's
+/// no per-entry allocation beyond the value itself. It also tracks the
+/// earliest deadline of the entries that carry an expiry, until that passes
+/// nothing has expired and lookups index straight into the buffer.
+#[derive(Debug, Clone, Default)]
+pub struct List {
+    entries: VecDeque<StoreEntry>,
+    expiring: usize,
+
+    /// No entry expires before this. It can fall behind as entries leave the
+    /// list but never runs ahead, purging brings it back up to date.
+    earliest_expiry: Option<i64>,
+}
+
+impl PartialEq for List {
+    fn eq(&self, other: &Self) -> bool {
+        return self.entries == other.entries;
+    }
+}
+
+impl Eq for List {}
+
+impl List {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    pub fn len(&self) -> usize {
+        return self.entries.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.entries.is_empty();
+    }
+
+    /// Whether any entry carries an expiry, expired or not
+    pub fn has_expiring(&self) -> bool {
+        return self.expiring > 0;
+    }
+
+    /// Whether an entry could have expired by `now`, if not every entry is live
+    pub fn may_have_expired(&self, now: i64) -> bool {
+        return self.earliest_expiry.is_some_and(|earliest| earliest < now);
+    }
+
+    pub fn iter(&self) -> vec_deque::Iter<'_, StoreEntry> {
+        return self.entries.iter();
+    }
+
+    pub fn get(&self, idx: usize) -> Option<&StoreEntry> {
+        return self.entries.get(idx);
+    }
+
+    pub fn front(&self) -> Option<&StoreEntry> {
+        return self.entries.front();
+    }
+
+    pub fn back(&self) -> Option<&StoreEntry> {
+        return self.entries.back();
+    }
+
+    fn added(&mut self, entry: &StoreEntry) {
+        if let Some(deadline) = deadline(entry) {
+            self.expiring += 1;
+            self.earliest_expiry = Some(self.earliest_expiry.map_or(deadline, |e| e.min(deadline)));
+        }
+    }
+
+    fn taken(&mut self, entry: Option<StoreEntry>) -> Option<StoreEntry> {
+        if entry.as_ref().and_then(deadline).is_some() {
+            self.expiring -= 1;
+            if self.expiring == 0 {
+                self.earliest_expiry = None;
+            }
+        }
+
+        return entry;
+    }
+
+    /// Recounts the expiring entries and their earliest deadline from scratch
+    fn recount(&mut self) {
+        let deadlines = self.entries.iter().filter_map(deadline);
+        self.expiring = deadlines.clone().count();
+        self.earliest_expiry = deadlines.min();
+    }
+
+    pub fn push_front(&mut self, entry: StoreEntry) {
+        self.added(&entry);
+        self.entries.push_front(entry);
+    }
+
+    pub fn push_back(&mut self, entry: StoreEntry) {
+        self.added(&entry);
+        self.entries.push_back(entry);
+    }
+
+    pub fn pop_front(&mut self) -> Option<StoreEntry> {
+        let entry = self.entries.pop_front();
+        return self.taken(entry);
+    }
+
+    pub fn pop_back(&mut self) -> Option<StoreEntry> {
+        let entry = self.entries.pop_back();
+        return self.taken(entry);
+    }
+
+    /// Replaces the entry at `idx`, returning the old one
+    pub fn set(&mut self, idx: usize, entry: StoreEntry) -> Option<StoreEntry> {
+        if idx >= self.entries.len() {
+            return None;
+        }
+
+        self.added(&entry);
+        let old = std::mem::replace(&mut self.entries[idx], entry);
+
+        return self.taken(Some(old));
+    }
+
+    /// Inserts `entry` at `idx`, shifting everything after it along
+    pub fn insert(&mut self, idx: usize, entry: StoreEntry) {
+        self.added(&entry);
+        self.entries.insert(idx, entry);
+    }
+
+    pub fn remove(&mut self, idx: usize) -> Option<StoreEntry> {
+        let entry = self.entries.remove(idx);
+        return self.taken(entry);
+    }
+
+    /// Keeps only the entries `keep` returns true for, front to back
+    pub fn retain(&mut self, keep: impl FnMut(&StoreEntry) -> bool) {
+        self.entries.retain(keep);
+        self.recount();
+    }
+
+    /// Keeps only the entries between `start` and `end` inclusive
+    fn keep_range(&mut self, start: usize, end: usize) {
+        self.entries.truncate(end + 1);
+        self.entries.drain(..start);
+        self.recount();
+    }
+}
+
+impl FromIterator<StoreEntry> for List {
+    fn from_iter<I: IntoIterator<Item = StoreEntry>>(iter: I) -> Self {
+        let mut list = Self::new();
+        for entry in iter {
+            list.push_back(entry);
+        }
+
+        return list;
+    }
+}
+
+impl<'a> IntoIterator for &'a List {
+    type Item = &'a StoreEntry;
+    type IntoIter = vec_deque::Iter<'a, StoreEntry>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        return self.entries.iter();
+    }
+}
+
+impl IntoIterator for List {
+    type Item = StoreEntry;
+    type IntoIter = vec_deque::IntoIter<StoreEntry>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        return self.entries.into_iter();
+    }
+}
+
+// Persisted as a plain sequence of entries, the expiry count is rebuilt on load
+
+impl Serialize for List {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        return self.entries.serialize(serializer);
+    }
+}
+
+impl<'de> Deserialize<'de> for List {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let entries = VecDeque::<StoreEntry>::deserialize(deserializer)?;
+        return Ok(entries.into_iter().collect::<List>());
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ListStore {
-    pub store: HashMap<String, LinkedList<StoreEntry>>,
+    pub store: HashMap<String, List>,
+
+    #[serde(skip, default = "system_clock")]
+    clock: SharedClock,
+}
+
+impl Default for ListStore {
+    fn default() -> Self {
+        return Self::new();
+    }
 }
 
 impl ListStore {
     pub fn new() -> Self {
+        return Self::with_clock(system_clock());
+    }
+
+    /// Creates a store that checks expiry against `clock`
+    pub fn with_clock(clock: SharedClock) -> Self {
         return Self {
             store: HashMap::new(),
+            clock,
         };
     }
 
@@ -41,21 +275,59 @@ impl ListStore {
 
     fn push(&mut self, kv: KVPair, dir: ListDirection) {
         let (key, value) = kv;
+        let list = self.store.entry(key).or_default();
+        match dir {
+            ListDirection::Left => list.push_front(value),
+            ListDirection::Right => list.push_back(value),
+        }
+    }
 
-        if let Some(list) = self.store.get_mut(&key) {
-            match dir {
-                ListDirection::Left => list.push_front(value),
-                ListDirection::Right => list.push_back(value),
-            }
-        } else {
-            let mut list = LinkedList::new();
+    /// Pushes every entry onto the head in turn, so the last one ends up first.
+    ///
+    /// Returns the length of the list after the push.
+    pub fn push_left_many(&mut self, key: impl AsRef<str>, values: Vec<StoreEntry>) -> usize {
+        return self.push_many(key.as_ref(), values, ListDirection::Left, true);
+    }
+
+    /// Pushes every entry onto the tail in turn.
+    ///
+    /// Returns the length of the list after the push.
+    pub fn push_right_many(&mut self, key: impl AsRef<str>, values: Vec<StoreEntry>) -> usize {
+        return self.push_many(key.as_ref(), values, ListDirection::Right, true);
+    }
+
+    /// Like `push_left_many` but leaves a missing list missing, returning `0`
+    pub fn push_left_if_exists(&mut self, key: impl AsRef<str>, values: Vec<StoreEntry>) -> usize {
+        return self.push_many(key.as_ref(), values, ListDirection::Left, false);
+    }
+
+    /// Like `push_right_many` but leaves a missing list missing, returning `0`
+    pub fn push_right_if_exists(&mut self, key: impl AsRef<str>, values: Vec<StoreEntry>) -> usize {
+        return self.push_many(key.as_ref(), values, ListDirection::Right, false);
+    }
+
+    fn push_many(
+        &mut self,
+        key: &str,
+        values: Vec<StoreEntry>,
+        dir: ListDirection,
+        create: bool,
+    ) -> usize {
+        // A list of nothing but expired entries counts as missing
+        self.purge_expired(key);
+        if values.is_empty() || (!create && !self.store.contains_key(key)) {
+            return self.store.get(key).map_or(0, |list| list.len());
+        }
+
+        let list = self.store.entry(key.to_string()).or_default();
+        for value in values.into_iter() {
             match dir {
                 ListDirection::Left => list.push_front(value),
                 ListDirection::Right => list.push_back(value),
             }
-
-            self.store.insert(key.to_string(), list);
         }
+
+        return list.len();
     }
 
     pub fn pop_left(&mut self, key: impl AsRef<str>) -> Option<StoreEntry> {
@@ -67,230 +339,349 @@ impl ListStore {
     }
 
     fn pop(&mut self, key: impl AsRef<str>, dir: ListDirection) -> Option<StoreEntry> {
+        return self.pop_n(key, 1, dir).pop();
+    }
+
+    /// Pops up to `count` entries from the head, in the order they were popped
+    pub fn pop_left_n(&mut self, key: impl AsRef<str>, count: usize) -> Vec<StoreEntry> {
+        return self.pop_n(key, count, ListDirection::Left);
+    }
+
+    /// Pops up to `count` entries from the tail, in the order they were popped
+    pub fn pop_right_n(&mut self, key: impl AsRef<str>, count: usize) -> Vec<StoreEntry> {
+        return self.pop_n(key, count, ListDirection::Right);
+    }
+
+    fn pop_n(&mut self, key: impl AsRef<str>, count: usize, dir: ListDirection) -> Vec<StoreEntry> {
+        let now = self.clock.now_millis();
+        let mut items = vec![];
         if let Some(list) = self.store.get_mut(key.as_ref()) {
-            let item = match dir {
-                ListDirection::Left => list.pop_front(),
-                ListDirection::Right => list.pop_back(),
-            };
+            while items.len() < count {
+                let Some(entry) = (match dir {
+                    ListDirection::Left => list.pop_front(),
+                    ListDirection::Right => list.pop_back(),
+                }) else {
+                    break;
+                };
+
+                if !entry.is_expired_at(now) {
+                    items.push(entry);
+                }
+            }
 
-            if list.len() == 0 {
+            if list.is_empty() {
                 self.store.remove(key.as_ref());
             }
-
-            return item;
         }
 
-        return None;
+        return items;
+    }
+
+    /// Pops from the `from` end of `src` and pushes onto the `to` end of `dst`,
+    /// returning the entry moved. With `src` and `dst` the same this rotates the list.
+    pub fn lmove(
+        &mut self,
+        src: impl AsRef<str>,
+        dst: impl AsRef<str>,
+        from: ListDirection,
+        to: ListDirection,
+    ) -> Option<StoreEntry> {
+        let entry = self.pop(src, from)?;
+        self.push((dst.as_ref().to_string(), entry.clone()), to);
+
+        return Some(entry);
     }
 
     pub fn index(&self, key: impl AsRef<str>, idx: isize) -> Option<StoreEntry> {
+        let now = self.clock.now_millis();
         if let Some(list) = self.store.get(key.as_ref()) {
-            let target_idx = idx_from_offset(list.len(), idx);
+            let target_idx = idx_from_offset(live_len(list, now), idx);
             if target_idx < 0 {
                 return None;
             }
 
-            for (i, item) in list.iter().enumerate() {
-                if target_idx as usize == i {
-                    return Some(item.clone());
-                }
+            if !list.may_have_expired(now) {
+                return list.get(target_idx as usize).cloned();
             }
+
+            return list
+                .iter()
+                .filter(|e| !e.is_expired_at(now))
+                .nth(target_idx as usize)
+                .cloned();
         }
 
         return None;
     }
 
+    /// Indexes of the entries holding `value`, like Redis `LPOS`.
+    ///
+    /// `rank` picks which match to start from, `1` is the first from the head and
+    /// `-1` the first from the tail. A `count` of `0` returns every match and a
+    /// `maxlen` of `0` scans the whole list, otherwise only the first `maxlen`
+    /// entries from the searched end are compared.
+    pub fn position(
+        &self,
+        key: impl AsRef<str>,
+        value: impl AsRef<[u8]>,
+        rank: isize,
+        count: usize,
+        maxlen: usize,
+    ) -> Result<Vec<usize>> {
+        if rank == 0 {
+            return Err(GranatError::ZeroRank.into());
+        }
+
+        let now = self.clock.now_millis();
+        let Some(list) = self.store.get(key.as_ref()) else {
+            return Ok(vec![]);
+        };
+
+        let size = live_len(list, now);
+        let live = list.iter().filter(|e| !e.is_expired_at(now));
+        let scanned: Box<dyn Iterator<Item = (usize, &StoreEntry)>> = match rank > 0 {
+            true => Box::new(live.enumerate()),
+            false => Box::new(live.rev().enumerate().map(|(i, e)| (size - 1 - i, e))),
+        };
+
+        let maxlen = if maxlen == 0 { usize::MAX } else { maxlen };
+        let count = if count == 0 { usize::MAX } else { count };
+        let target = value.as_ref();
+
+        return Ok(scanned
+            .take(maxlen)
+            .filter(|(_, e)| e.value == target)
+            .skip(rank.unsigned_abs() - 1)
+            .take(count)
+            .map(|(i, _)| i)
+            .collect::<Vec<usize>>());
+    }
+
     pub fn len(&self, key: impl AsRef<str>) -> usize {
         if let Some(list) = self.store.get(key.as_ref()) {
-            return list.len();
+            return live_len(list, self.clock.now_millis());
         }
 
         return 0;
     }
 
-    pub fn range(
-        &self,
-        key: impl AsRef<str>,
-        mut start: isize,
-        mut end: isize,
-    ) -> LinkedList<StoreEntry> {
-        let mut ll = LinkedList::new();
-
+    pub fn range(&self, key: impl AsRef<str>, mut start: isize, mut end: isize) -> Vec<StoreEntry> {
+        let now = self.clock.now_millis();
         if let Some(list) = self.store.get(key.as_ref()) {
-            start = idx_from_offset(list.len(), start);
-            end = idx_from_offset(list.len(), end);
-            let size = list.len() as isize;
+            let size = live_len(list, now) as isize;
+            start = idx_from_offset(size as usize, start).max(0);
+            end = idx_from_offset(size as usize, end);
 
             if start >= size || start > end {
-                return ll;
+                return vec![];
             }
 
             if end >= size {
                 end = size - 1;
             }
 
-            for (i, item) in list.iter().enumerate() {
-                if i as isize >= start && i as isize <= end {
-                    ll.push_back(item.clone());
-                }
+            let count = (end - start + 1) as usize;
+            if !list.may_have_expired(now) {
+                return list
+                    .entries
+                    .range(start as usize..=end as usize)
+                    .cloned()
+                    .collect::<Vec<StoreEntry>>();
             }
+
+            return list
+                .iter()
+                .filter(|e| !e.is_expired_at(now))
+                .skip(start as usize)
+                .take(count)
+                .cloned()
+                .collect::<Vec<StoreEntry>>();
         }
 
-        return ll;
+        return vec![];
     }
 
+    /// Replaces the entry at `idx`, like Redis `LSET`.
+    ///
+    /// Errors with `GranatError::NoSuchKey` if the list doesn't exist and
+    /// `GranatError::OutOfRange` if `idx` is past either end.
     pub fn set(&mut self, kv: KVPair, idx: isize) -> Result<()> {
         let (key, value) = kv;
+        self.purge_expired(&key);
 
-        if let Some(list) = self.store.get_mut(&key) {
-            let size = list.len() as isize;
-            let target_idx = idx_from_offset(list.len(), idx);
-
-            if target_idx < 0 || target_idx > size {
-                return Err(anyhow!("index out of range"));
-            }
+        let Some(list) = self.store.get_mut(&key) else {
+            return Err(GranatError::NoSuchKey.into());
+        };
 
-            if target_idx == 0 {
-                list.push_front(value);
-            } else if target_idx == size - 1 {
-                list.push_back(value);
-            } else {
-                let mut split = list.split_off(target_idx as usize);
-                split.push_front(value);
-                list.append(&mut split);
-            }
+        let target_idx = idx_from_offset(list.len(), idx);
+        if target_idx < 0 || list.set(target_idx as usize, value).is_none() {
+            return Err(GranatError::OutOfRange.into());
         }
 
         return Ok(());
     }
 
-    // Hoachin'
-    pub fn trim(&mut self, key: impl AsRef<str>, mut start: isize, mut end: isize) {
-        if let Some(list) = self.store.get_mut(key.as_ref()) {
-            let size = list.len();
+    /// Inserts the entry so it ends up at `idx`, shifting the rest along, so `0`
+    /// prepends and `-1` appends. A missing key is treated as an empty list.
+    ///
+    /// Returns the length of the list after the insert.
+    pub fn insert_at(&mut self, kv: KVPair, idx: isize) -> Result<usize> {
+        let (key, value) = kv;
+        self.purge_expired(&key);
 
-            // Calculate start and end to establish limits
-            start = idx_from_offset(size, start);
-            end = idx_from_offset(size, end);
-            if start as usize >= size || start > end {
-                self.store.remove(key.as_ref());
-                return;
-            }
+        let size = self.store.get(&key).map_or(0, |list| list.len());
+        let target_idx = idx_from_offset(size + 1, idx);
+        if target_idx < 0 || target_idx as usize > size {
+            return Err(GranatError::OutOfRange.into());
+        }
 
-            let mut split = list.split_off(start as usize);
+        let list = self.store.entry(key).or_default();
+        list.insert(target_idx as usize, value);
 
-            // recalculate end index and ensure it's in bounds
-            end = idx_from_offset(split.len(), end - start);
-            end = if end + 1 >= split.len() as isize {
-                (split.len()) as isize
-            } else {
-                end + 1
-            };
+        return Ok(list.len());
+    }
+
+    /// Inserts the entry before or after the first entry holding `pivot`, like
+    /// Redis `LINSERT`.
+    ///
+    /// Returns the length of the list after the insert, `-1` if `pivot` wasn't
+    /// found and `0` if the list doesn't exist.
+    pub fn linsert(
+        &mut self,
+        key: impl AsRef<str>,
+        pos: InsertPosition,
+        pivot: impl AsRef<[u8]>,
+        value: StoreEntry,
+    ) -> i64 {
+        self.purge_expired(key.as_ref());
+        let Some(list) = self.store.get_mut(key.as_ref()) else {
+            return 0;
+        };
 
-            let _ = split.split_off(end as usize);
+        let Some(idx) = find_entry(list, pivot.as_ref(), ListDirection::Left) else {
+            return -1;
+        };
 
-            if split.len() == 0 {
-                self.store.remove("test");
+        match pos {
+            InsertPosition::Before => list.insert(idx, value),
+            InsertPosition::After => list.insert(idx + 1, value),
+        }
+
+        return list.len() as i64;
+    }
+
+    pub fn trim(&mut self, key: impl AsRef<str>, mut start: isize, mut end: isize) {
+        self.purge_expired(key.as_ref());
+        if let Some(list) = self.store.get_mut(key.as_ref()) {
+            let size = list.len() as isize;
+            start = idx_from_offset(size as usize, start);
+            end = idx_from_offset(size as usize, end).min(size - 1);
+
+            if start < 0 || start >= size || start > end {
+                self.store.remove(key.as_ref());
                 return;
-            } else {
-                self.store.insert(key.as_ref().to_string(), split);
             }
+
+            list.keep_range(start as usize, end as usize);
         }
     }
 
-    pub fn remove(
-        &mut self,
-        key: impl AsRef<str>,
-        value: impl AsRef<str>,
-        mut count: isize,
-    ) -> usize {
+    /// Removes `count` entries matching `value`, from the head for a positive
+    /// count and from the tail for a negative one, `0` removes every match.
+    pub fn remove(&mut self, key: impl AsRef<str>, value: impl AsRef<[u8]>, count: isize) -> usize {
+        self.purge_expired(key.as_ref());
         let mut total_removed = 0;
         if let Some(list) = self.store.get_mut(key.as_ref()) {
-            let target = value.as_ref().to_string();
+            let target = value.as_ref();
 
             if count == 0 {
-                while let Some(idx) = find_entry(list, &target, ListDirection::Left) {
-                    let mut right = list.split_off(idx);
-                    right.pop_front();
-                    list.append(&mut right);
-                    total_removed += 1;
-                }
-            } else if count > 0 {
-                while let Some(idx) = find_entry(list, &target, ListDirection::Left) {
-                    let mut right = list.split_off(idx);
-                    right.pop_front();
-                    list.append(&mut right);
-                    total_removed += 1;
-                    count -= 1;
-
-                    if count == 0 {
-                        break;
-                    }
-                }
+                let before = list.len();
+                list.retain(|e| e.value != target);
+                total_removed = before - list.len();
             } else {
-                while let Some(idx) = find_entry(list, &target, ListDirection::Right) {
-                    let mut right = list.split_off(idx);
-                    right.pop_front();
-                    list.append(&mut right);
-                    total_removed += 1;
-                    count += 1;
+                let dir = match count > 0 {
+                    true => ListDirection::Left,
+                    false => ListDirection::Right,
+                };
 
-                    if count == 0 {
+                while total_removed < count.unsigned_abs() {
+                    let Some(idx) = find_entry(list, target, dir) else {
                         break;
-                    }
+                    };
+
+                    list.remove(idx);
+                    total_removed += 1;
                 }
             }
 
-            if list.len() == 0 {
+            if list.is_empty() {
                 self.store.remove(key.as_ref());
             }
         }
 
         return total_removed;
     }
-}
 
-fn find_entry(
-    list: &mut LinkedList<StoreEntry>,
-    target: &String,
-    dir: ListDirection,
-) -> Option<usize> {
-    match dir {
-        ListDirection::Left => {
-            for (pos, entry) in list.iter().enumerate() {
-                if &entry.value == target {
-                    return Some(pos);
-                }
+    /// Drops any expired entries from the list, removing the key if nothing is left.
+    ///
+    /// Returns the number of entries dropped.
+    pub fn purge_expired(&mut self, key: impl AsRef<str>) -> usize {
+        let now = self.clock.now_millis();
+        if let Some(list) = self.store.get_mut(key.as_ref()) {
+            if !list.may_have_expired(now) {
+                return 0;
             }
-        }
-        ListDirection::Right => {
-            let offset = list.len() - 1;
-            for (pos, entry) in list.iter().rev().enumerate() {
-                if &entry.value == target {
-                    return Some(offset - pos);
-                }
+
+            let before = list.len();
+            list.retain(|e| !e.is_expired_at(now));
+
+            let purged = before - list.len();
+            if list.is_empty() {
+                self.store.remove(key.as_ref());
             }
+
+            return purged;
         }
+
+        return 0;
     }
+}
 
-    None
+/// Position of the first entry holding `target`, searching from the `dir` end
+fn find_entry(list: &List, target: &[u8], dir: ListDirection) -> Option<usize> {
+    match dir {
+        ListDirection::Left => return list.iter().position(|e| e.value == target),
+        ListDirection::Right => return list.iter().rposition(|e| e.value == target),
+    }
 }
 
 #[cfg(test)]
 mod list_tests {
     use super::*;
+    use crate::store::clock::MockClock;
+    use crate::store::entry::ExpiryState;
+    use std::sync::Arc;
+    use std::time::Duration;
 
     // L/R Push ✔
     // L/R Pop ✔
     // L/R Index ✔
     // Length ✔
     // Set ✔
+    // Insert ✔
     // Trim ✔
     // Range ✔
     // Remove ✔
 
     fn create_kv_pair(key: impl AsRef<str>, value: impl AsRef<str>) -> KVPair {
-        return (key.as_ref().to_string(), StoreEntry::new(value));
+        return (key.as_ref().to_string(), StoreEntry::new(value.as_ref()));
+    }
+
+    fn create_expired_kv_pair(key: impl AsRef<str>, value: impl AsRef<str>) -> KVPair {
+        let mut entry = StoreEntry::new(value.as_ref());
+        entry.expiry = ExpiryState::Expired;
+
+        return (key.as_ref().to_string(), entry);
     }
 
     fn create_basic_list_store() -> ListStore {
@@ -313,9 +704,9 @@ mod list_tests {
         return list_store;
     }
 
-    fn list_to_vec(list: &LinkedList<StoreEntry>) -> Vec<String> {
+    fn list_to_vec<'a>(list: impl IntoIterator<Item = &'a StoreEntry>) -> Vec<String> {
         return list
-            .iter()
+            .into_iter()
             .map(|e| e.value.to_string())
             .collect::<Vec<String>>();
     }
@@ -424,6 +815,104 @@ mod list_tests {
         assert!(entry.is_none());
     }
 
+    #[test]
+    fn lmove() {
+        let mut list_store = create_list_from_vec(vec!["0", "1", "2"]);
+        let entry = list_store.lmove("test", "other", ListDirection::Right, ListDirection::Left);
+        assert_eq!(entry.unwrap().value, "2".to_string());
+        assert_eq!(list_store.len("test"), 2);
+        assert_eq!(list_store.index("other", 0).unwrap().value, "2".to_string());
+
+        // Moving within the same list rotates it
+        let entry = list_store.lmove("test", "test", ListDirection::Left, ListDirection::Right);
+        assert_eq!(entry.unwrap().value, "0".to_string());
+        assert_eq!(
+            list_to_vec(list_store.store.get("test").unwrap()),
+            vec!["1".to_string(), "0".to_string()]
+        );
+
+        list_store.lmove("other", "test", ListDirection::Left, ListDirection::Left);
+        assert!(!list_store.store.contains_key("other"));
+        assert!(list_store
+            .lmove("missing", "test", ListDirection::Left, ListDirection::Left)
+            .is_none());
+        assert_eq!(list_store.len("test"), 3);
+    }
+
+    #[test]
+    fn push_many() {
+        let mut ls = ListStore::new();
+        let values = |items: &[&str]| {
+            return items
+                .iter()
+                .map(|v| StoreEntry::new(*v))
+                .collect::<Vec<StoreEntry>>();
+        };
+
+        assert_eq!(ls.push_left_many("test", values(&["a", "b", "c"])), 3);
+        assert_eq!(ls.push_right_many("test", values(&["d", "e"])), 5);
+        assert_eq!(
+            list_to_vec(&ls.store["test"]),
+            vec!["c", "b", "a", "d", "e"]
+        );
+        assert_eq!(ls.push_right_many("empty", vec![]), 0);
+        assert!(!ls.store.contains_key("empty"));
+
+        assert_eq!(ls.push_left_if_exists("test", values(&["f"])), 6);
+        assert_eq!(ls.push_right_if_exists("test", values(&["g"])), 7);
+        assert_eq!(ls.store["test"].front().unwrap().value, "f".to_string());
+        assert_eq!(ls.store["test"].back().unwrap().value, "g".to_string());
+
+        assert_eq!(ls.push_left_if_exists("missing", values(&["a"])), 0);
+        assert_eq!(ls.push_right_if_exists("missing", values(&["a"])), 0);
+        assert!(!ls.store.contains_key("missing"));
+
+        ls.push_right(create_expired_kv_pair("expired", "a"));
+        assert_eq!(ls.push_right_if_exists("expired", values(&["b"])), 0);
+        assert!(!ls.store.contains_key("expired"));
+    }
+
+    #[test]
+    fn pop_many() {
+        let mut ls = create_list_from_vec(vec!["a", "b", "c", "d", "e"]);
+        assert_eq!(list_to_vec(&ls.pop_left_n("test", 2)), vec!["a", "b"]);
+        assert_eq!(list_to_vec(&ls.pop_right_n("test", 2)), vec!["e", "d"]);
+        assert!(ls.pop_left_n("test", 0).is_empty());
+        assert_eq!(list_to_vec(&ls.pop_left_n("test", 10)), vec!["c"]);
+        assert!(!ls.store.contains_key("test"));
+        assert!(ls.pop_right_n("test", 10).is_empty());
+
+        ls.push_right(create_kv_pair("test", "a"));
+        ls.push_right(create_expired_kv_pair("test", "b"));
+        ls.push_right(create_kv_pair("test", "c"));
+        assert_eq!(list_to_vec(&ls.pop_left_n("test", 2)), vec!["a", "c"]);
+    }
+
+    #[test]
+    fn position() {
+        let ls = create_list_from_vec(vec!["a", "b", "c", "1", "2", "3", "c", "c"]);
+        assert_eq!(ls.position("test", "c", 1, 1, 0).unwrap(), vec![2]);
+        assert_eq!(ls.position("test", "c", 2, 1, 0).unwrap(), vec![6]);
+        assert_eq!(ls.position("test", "c", -1, 1, 0).unwrap(), vec![7]);
+        assert_eq!(ls.position("test", "c", 1, 0, 0).unwrap(), vec![2, 6, 7]);
+        assert_eq!(ls.position("test", "c", -2, 2, 0).unwrap(), vec![6, 2]);
+        assert_eq!(ls.position("test", "c", 1, 0, 6).unwrap(), vec![2]);
+        assert_eq!(ls.position("test", "c", -1, 0, 2).unwrap(), vec![7, 6]);
+        assert!(ls.position("test", "c", 4, 1, 0).unwrap().is_empty());
+        assert!(ls.position("test", "z", 1, 0, 0).unwrap().is_empty());
+        assert!(ls.position("missing", "c", 1, 0, 0).unwrap().is_empty());
+
+        let err = ls.position("test", "c", 0, 1, 0).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<GranatError>(),
+            Some(&GranatError::ZeroRank)
+        );
+
+        let mut ls = create_list_from_vec(vec!["a", "c"]);
+        ls.push_left(create_expired_kv_pair("test", "c"));
+        assert_eq!(ls.position("test", "c", 1, 0, 0).unwrap(), vec![1]);
+    }
+
     #[test]
     fn index_from_left() {
         let list_store = create_basic_list_store();
@@ -460,17 +949,60 @@ mod list_tests {
     fn set() {
         let mut list_store = create_basic_list_store();
 
-        // Set in the middle
+        // Replaces in place without growing the list
         let mut res = list_store.set(create_kv_pair("test", "new_value"), 1);
         assert!(res.is_ok());
+        assert_eq!(list_store.len("test"), 5);
+        assert_eq!(
+            list_store.index("test", 1).unwrap().value,
+            "new_value".to_string()
+        );
+
+        res = list_store.set(create_kv_pair("test", "last-value"), -1);
+        assert!(res.is_ok());
+        assert_eq!(
+            list_to_vec(list_store.store.get("test").unwrap()),
+            vec![
+                "0".to_string(),
+                "new_value".to_string(),
+                "2".to_string(),
+                "3".to_string(),
+                "last-value".to_string()
+            ]
+        );
+
+        // Out of bounds and missing keys error
+        res = list_store.set(create_kv_pair("test", "new_value"), 5);
+        assert_eq!(
+            res.unwrap_err().downcast_ref::<GranatError>(),
+            Some(&GranatError::OutOfRange)
+        );
+        res = list_store.set(create_kv_pair("test", "new_value"), -6);
+        assert!(res.is_err());
+
+        res = list_store.set(create_kv_pair("missing", "new_value"), 0);
+        assert_eq!(
+            res.unwrap_err().downcast_ref::<GranatError>(),
+            Some(&GranatError::NoSuchKey)
+        );
+        assert!(!list_store.store.contains_key("missing"));
+    }
+
+    #[test]
+    fn insert_at() {
+        let mut list_store = create_basic_list_store();
+
+        // Insert in the middle
+        let mut res = list_store.insert_at(create_kv_pair("test", "new_value"), 1);
+        assert!(res.is_ok());
         assert_eq!(list_store.len("test"), 6);
         let mut entry = list_store.index("test", 1);
         assert!(entry.is_some());
         let mut value = entry.unwrap().value;
         assert_eq!(value, "new_value".to_string());
 
-        // Set at the start
-        res = list_store.set(create_kv_pair("test", "another-new-value"), 0);
+        // Insert at the start
+        res = list_store.insert_at(create_kv_pair("test", "another-new-value"), 0);
         assert!(res.is_ok());
         assert_eq!(list_store.len("test"), 7);
         entry = list_store.index("test", 0);
@@ -478,8 +1010,8 @@ mod list_tests {
         value = entry.unwrap().value;
         assert_eq!(value, "another-new-value".to_string());
 
-        // Set at the end
-        res = list_store.set(create_kv_pair("test", "last-new-value"), -1);
+        // Insert at the end
+        res = list_store.insert_at(create_kv_pair("test", "last-new-value"), -1);
         assert!(res.is_ok());
         assert_eq!(list_store.len("test"), 8);
         entry = list_store.index("test", -1);
@@ -488,11 +1020,47 @@ mod list_tests {
         assert_eq!(value, "last-new-value".to_string());
 
         // Out of bounds errors
-        res = list_store.set(create_kv_pair("test", "new_value"), 500);
+        res = list_store.insert_at(create_kv_pair("test", "new_value"), 500);
         assert!(res.is_err());
 
-        res = list_store.set(create_kv_pair("test", "new_value"), -2354);
+        res = list_store.insert_at(create_kv_pair("test", "new_value"), -2354);
         assert!(res.is_err());
+
+        // A missing key starts out empty
+        res = list_store.insert_at(create_kv_pair("new", "value"), -1);
+        assert_eq!(res.unwrap(), 1);
+        assert!(list_store
+            .insert_at(create_kv_pair("other", "value"), 1)
+            .is_err());
+    }
+
+    #[test]
+    fn linsert() {
+        let mut list_store = create_list_from_vec(vec!["a", "pivot", "b", "pivot"]);
+        let len = list_store.linsert(
+            "test",
+            InsertPosition::Before,
+            "pivot",
+            StoreEntry::new("x"),
+        );
+        assert_eq!(len, 5);
+        let len = list_store.linsert("test", InsertPosition::After, "pivot", StoreEntry::new("y"));
+        assert_eq!(len, 6);
+        assert_eq!(
+            list_to_vec(list_store.store.get("test").unwrap()),
+            vec!["a", "x", "pivot", "y", "b", "pivot"]
+                .into_iter()
+                .map(|v| v.to_string())
+                .collect::<Vec<String>>()
+        );
+
+        let missing_pivot =
+            list_store.linsert("test", InsertPosition::After, "z", StoreEntry::new("y"));
+        assert_eq!(missing_pivot, -1);
+        let missing_key =
+            list_store.linsert("missing", InsertPosition::After, "a", StoreEntry::new("y"));
+        assert_eq!(missing_key, 0);
+        assert!(!list_store.store.contains_key("missing"));
     }
 
     #[test]
@@ -735,4 +1303,155 @@ mod list_tests {
             vec!["0".to_string(), "1".to_string(), "3".to_string()]
         );
     }
+
+    #[test]
+    fn expired_entries_are_skipped() {
+        let mut list_store = ListStore::new();
+        list_store.push_right(create_expired_kv_pair("test", "x"));
+        list_store.push_right(create_kv_pair("test", "0"));
+        list_store.push_right(create_expired_kv_pair("test", "x"));
+        list_store.push_right(create_kv_pair("test", "1"));
+        list_store.push_right(create_expired_kv_pair("test", "x"));
+
+        assert_eq!(list_store.len("test"), 2);
+        assert_eq!(list_store.index("test", 0).unwrap().value, "0".to_string());
+        assert_eq!(list_store.index("test", -1).unwrap().value, "1".to_string());
+        assert!(list_store.index("test", 2).is_none());
+
+        let range = list_store.range("test", 0, -1);
+        assert_eq!(list_to_vec(&range), vec!["0".to_string(), "1".to_string()]);
+
+        assert_eq!(list_store.pop_left("test").unwrap().value, "0".to_string());
+        assert_eq!(list_store.pop_right("test").unwrap().value, "1".to_string());
+        assert!(list_store.pop_left("test").is_none());
+        assert!(!list_store.store.contains_key("test"));
+    }
+
+    #[test]
+    fn purge_expired_entries() {
+        let mut list_store = ListStore::new();
+        list_store.push_right(create_expired_kv_pair("test", "x"));
+        list_store.push_right(create_kv_pair("test", "0"));
+        list_store.push_right(create_expired_kv_pair("test", "x"));
+
+        assert_eq!(list_store.purge_expired("test"), 2);
+        assert_eq!(list_store.store.get("test").unwrap().len(), 1);
+
+        list_store.push_right(create_expired_kv_pair("expired", "x"));
+        assert_eq!(list_store.purge_expired("expired"), 1);
+        assert!(!list_store.store.contains_key("expired"));
+    }
+
+    #[test]
+    fn lists_track_expiring_entries() {
+        let mut list_store = ListStore::new();
+        list_store.push_right(create_kv_pair("test", "0"));
+        list_store.push_right(create_kv_pair("test", "1"));
+        assert!(!list_store.store["test"].has_expiring());
+
+        list_store.push_left(create_expired_kv_pair("test", "x"));
+        assert!(list_store.store["test"].has_expiring());
+        assert_eq!(list_store.index("test", 0).unwrap().value, "0".to_string());
+
+        list_store.purge_expired("test");
+        assert!(!list_store.store["test"].has_expiring());
+
+        // The count isn't persisted, it's rebuilt from the entries
+        list_store.push_right(create_expired_kv_pair("test", "x"));
+        let raw = serde_json::to_string(&list_store.store["test"]).unwrap();
+        let loaded = serde_json::from_str::<List>(&raw).unwrap();
+        assert_eq!(loaded, list_store.store["test"]);
+        assert!(loaded.has_expiring());
+    }
+
+    #[test]
+    fn lists_track_their_earliest_deadline() {
+        let clock = Arc::new(MockClock::new(0));
+        let mut list_store = ListStore::with_clock(clock.clone());
+        list_store.push_right(create_kv_pair("test", "0"));
+        for (value, secs) in [("short", 1), ("long", 10)] {
+            let entry = StoreEntry::new(value).expires_in_with(secs, clock.as_ref());
+            list_store.push_right(("test".to_string(), entry));
+        }
+
+        // Nothing has expired yet, so lookups don't need to skip anything
+        assert!(!list_store.store["test"].may_have_expired(0));
+        assert_eq!(
+            list_store.index("test", 2).unwrap().value,
+            "long".to_string()
+        );
+
+        clock.advance(Duration::from_secs(2));
+        assert!(list_store.store["test"].may_have_expired(2000));
+        assert_eq!(
+            list_store.index("test", 1).unwrap().value,
+            "long".to_string()
+        );
+        assert_eq!(list_store.range("test", 0, -1).len(), 2);
+
+        // Purging moves it on to the next deadline
+        assert_eq!(list_store.purge_expired("test"), 1);
+        assert!(!list_store.store["test"].may_have_expired(2000));
+        assert_eq!(list_store.purge_expired("test"), 0);
+
+        list_store.pop_right("test");
+        assert!(!list_store.store["test"].has_expiring());
+        assert!(!list_store.store["test"].may_have_expired(i64::MAX));
+    }
+
+    #[test]
+    fn expiry_follows_the_store_clock() {
+        let clock = Arc::new(MockClock::new(0));
+        let mut list_store = ListStore::with_clock(clock.clone());
+
+        let entry = StoreEntry::new("short").expires_in_with(1, clock.as_ref());
+        list_store.push_right(("test".to_string(), entry));
+        let entry = StoreEntry::new("long").expires_in_with(10, clock.as_ref());
+        list_store.push_right(("test".to_string(), entry));
+        assert_eq!(list_store.len("test"), 2);
+
+        clock.advance(Duration::from_secs(2));
+        assert_eq!(list_store.len("test"), 1);
+        assert_eq!(
+            list_store.index("test", 0).unwrap().value,
+            "long".to_string()
+        );
+
+        clock.advance(Duration::from_secs(10));
+        assert!(list_store.pop_left("test").is_none());
+        assert!(!list_store.store.contains_key("test"));
+    }
+
+    #[test]
+    fn binary_entries() {
+        let blob: &[u8] = &[0xFF, 0x00, 0xFE];
+        let mut list_store = ListStore::new();
+        list_store.push_right(("test".to_string(), StoreEntry::new(blob)));
+        list_store.push_right(create_kv_pair("test", "text"));
+        list_store.push_right(("test".to_string(), StoreEntry::new(blob)));
+
+        assert_eq!(
+            list_store.position("test", blob, 1, 0, 0).unwrap(),
+            vec![0, 2]
+        );
+        assert_eq!(
+            list_store.position("test", "text", 1, 0, 0).unwrap(),
+            vec![1]
+        );
+        assert_eq!(
+            list_store.linsert("test", InsertPosition::After, blob, StoreEntry::new([0x80])),
+            4
+        );
+        assert_eq!(list_store.index("test", 1).unwrap().value, vec![0x80]);
+
+        let raw = serde_json::to_string(&list_store.store["test"]).unwrap();
+        let loaded = serde_json::from_str::<List>(&raw).unwrap();
+        assert_eq!(loaded, list_store.store["test"]);
+
+        assert_eq!(list_store.remove("test", blob, 0), 2);
+        assert_eq!(
+            list_to_vec(list_store.range("test", 0, -1).iter()),
+            vec!["\u{FFFD}", "text"]
+        );
+    }
 }
@@ -1,13 +1,61 @@
+pub mod aof;
+pub mod bitmap;
+#[cfg(feature = "async")]
+pub mod client;
+pub mod clock;
+pub mod command;
 pub mod entry;
+pub mod error;
 pub mod general;
+pub mod handle;
+pub mod hash;
+pub mod hyperloglog;
+pub mod keyspace;
 pub mod list;
+pub mod queue;
+pub mod set;
+pub mod snapshot;
+pub mod sorted_set;
+pub mod stream;
 
-use entry::StoreEntry;
+use anyhow::Result;
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ops::Bound;
+use std::time::{Duration, Instant};
+
+use aof::AppendOnlyFile;
+use bitmap::{BitFieldOp, BitFieldType, BitOperation, BitRange};
+use clock::{system_clock, SharedClock};
+use command::Command;
+use entry::{ExpiryState, StoreEntry, Value};
+use error::GranatError;
 use general::GeneralStore;
-use list::ListStore;
+use hash::HashStore;
+use hyperloglog::{HyperLogLog, HyperLogLogStore};
+use keyspace::{glob_match, ExpireCondition, KeyType};
+use list::{InsertPosition, ListDirection, ListStore};
+use set::{SetOperation, SetStore};
+use snapshot::{BackgroundSave, SaveConfig};
+use sorted_set::{
+    AddOptions, Aggregate, ScoredMember, SortedSet, SortedSetOperation, SortedSetStore,
+};
+use stream::{
+    ConsumerInfo, GroupInfo, NewId, PendingMessage, Stream, StreamEntry, StreamFields, StreamId,
+    StreamInfo, StreamStore, TrimStrategy,
+};
 
 pub type KVPair = (String, StoreEntry);
 
+/// Keys examined per round of active expiry
+const ACTIVE_EXPIRE_SAMPLE: usize = 20;
+
+/// How often writes trigger an active expiry cycle
+pub(crate) const ACTIVE_EXPIRE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Upper bound on the time a single active expiry cycle can take
+const ACTIVE_EXPIRE_BUDGET: Duration = Duration::from_millis(25);
+
 // The Idea:
 //
 // 1. You can use this sync
@@ -22,13 +70,3139 @@ pub type KVPair = (String, StoreEntry);
 pub struct GranatStore {
     general: GeneralStore,
     list: ListStore,
+    hash: HashStore,
+    set: SetStore,
+    zset: SortedSetStore,
+    stream: StreamStore,
+    hll: HyperLogLogStore,
+
+    /// Index of every key across all stores and the type it holds
+    keyspace: BTreeMap<String, KeyType>,
+
+    /// Key level deadlines (Unix milliseconds) for non-string keys, string
+    /// keys carry theirs on the `StoreEntry`
+    expires: HashMap<String, i64>,
+
+    clock: SharedClock,
+
+    /// Last key sampled by active expiry, the next cycle carries on after it
+    expire_cursor: Option<String>,
+    last_expire_cycle: Instant,
+
+    /// Writes since the last successful save
+    dirty: usize,
+    last_save: Instant,
+    save_failed_at: Option<Instant>,
+    save_config: Option<SaveConfig>,
+    bg_save: Option<BackgroundSave>,
+
+    aof: Option<AppendOnlyFile>,
+}
+
+impl Default for GranatStore {
+    fn default() -> Self {
+        return Self::new();
+    }
 }
 
 impl GranatStore {
     pub fn new() -> Self {
-        Self {
-            general: GeneralStore::new(),
-            list: ListStore::new(),
+        return Self::with_clock(system_clock());
+    }
+
+    /// Creates a store where every expiry check, in all sub-stores, runs against `clock`
+    pub fn with_clock(clock: SharedClock) -> Self {
+        return Self {
+            general: GeneralStore::with_clock(clock.clone()),
+            list: ListStore::with_clock(clock.clone()),
+            hash: HashStore::with_clock(clock.clone()),
+            set: SetStore::new(),
+            zset: SortedSetStore::new(),
+            stream: StreamStore::with_clock(clock.clone()),
+            hll: HyperLogLogStore::new(),
+            keyspace: BTreeMap::new(),
+            expires: HashMap::new(),
+            clock,
+            expire_cursor: None,
+            last_expire_cycle: Instant::now(),
+            dirty: 0,
+            last_save: Instant::now(),
+            save_failed_at: None,
+            save_config: None,
+            bg_save: None,
+            aof: None,
+        };
+    }
+
+    /// The clock used for expiry, use it to create entries with `StoreEntry::expires_in_with`
+    pub fn clock(&self) -> &SharedClock {
+        return &self.clock;
+    }
+
+    /// Whether the key is still indexed but has expired, or everything it holds has
+    fn is_expired_key(&self, key: &str) -> bool {
+        match self.keyspace.get(key) {
+            Some(KeyType::String) => return self.general.is_expired(key),
+            Some(KeyType::List) => {
+                return self.deadline_passed(key) || self.list.len(key) == 0;
+            }
+            Some(KeyType::Hash) => {
+                return self.deadline_passed(key) || self.hash.len(key) == 0;
+            }
+            Some(KeyType::Set | KeyType::SortedSet | KeyType::Stream | KeyType::HyperLogLog) => {
+                return self.deadline_passed(key);
+            }
+            None => return false,
+        }
+    }
+
+    fn deadline_passed(&self, key: &str) -> bool {
+        return self
+            .expires
+            .get(key)
+            .is_some_and(|deadline| *deadline < self.clock.now_millis());
+    }
+
+    /// The key's deadline as a Unix timestamp in milliseconds, `None` if it doesn't expire
+    fn deadline(&self, key: &str) -> Option<i64> {
+        match self.keyspace.get(key) {
+            Some(KeyType::String) => match self.general.store.get(key).map(|e| &e.expiry) {
+                Some(ExpiryState::Active(exp)) => return Some(*exp),
+                Some(ExpiryState::Expired) => return Some(0),
+                _ => return None,
+            },
+            Some(_) => return self.expires.get(key).copied(),
+            None => return None,
+        }
+    }
+
+    fn set_deadline(&mut self, key: &str, deadline: Option<i64>) {
+        match self.keyspace.get(key) {
+            Some(KeyType::String) => {
+                if let Some(entry) = self.general.store.get_mut(key) {
+                    entry.expiry = match deadline {
+                        Some(exp) => ExpiryState::Active(exp),
+                        None => ExpiryState::NoExpiry,
+                    };
+                }
+            }
+            Some(_) => match deadline {
+                Some(exp) => {
+                    self.expires.insert(key.to_string(), exp);
+                }
+                None => {
+                    self.expires.remove(key);
+                }
+            },
+            None => {}
+        }
+    }
+
+    /// The type of the key, treating expired keys as absent
+    fn live_type(&self, key: &str) -> Option<KeyType> {
+        if self.is_expired_key(key) {
+            return None;
+        }
+
+        return self.keyspace.get(key).copied();
+    }
+
+    /// Lazily purges the key if it has expired, returning whether it was purged
+    fn expire_if_needed(&mut self, key: &str) -> bool {
+        let Some(kt) = self.keyspace.get(key).copied() else {
+            return false;
+        };
+
+        if self.deadline_passed(key) {
+            self.remove_key(key);
+            return true;
+        }
+
+        match kt {
+            KeyType::String => {
+                self.general.purge_expired(key);
+            }
+            KeyType::List => {
+                self.list.purge_expired(key);
+            }
+            KeyType::Hash => {
+                self.hash.purge_expired(key);
+            }
+            // Members and entries don't expire on their own, only the whole key does
+            KeyType::Set | KeyType::SortedSet | KeyType::Stream | KeyType::HyperLogLog => {}
+        }
+
+        self.sync_key(key, kt);
+
+        return !self.keyspace.contains_key(key);
+    }
+
+    /// Runs periodic housekeeping, active expiry and any scheduled saves that are due.
+    ///
+    /// This happens on every write, call it from a timer as well if the store
+    /// can sit idle for long stretches.
+    pub fn tick(&mut self) {
+        if self.last_expire_cycle.elapsed() >= ACTIVE_EXPIRE_INTERVAL {
+            self.active_expire();
+        }
+
+        self.save_cron();
+        self.aof_cron();
+    }
+
+    /// Housekeeping run ahead of every write, purges `key` if it has expired
+    /// and runs any periodic tasks that are due.
+    fn before_write(&mut self, key: &str) {
+        self.tick();
+        self.expire_if_needed(key);
+    }
+
+    /// Records a successful write, counting it towards the save rules and
+    /// logging it to the append-only file if one is enabled.
+    ///
+    /// `cmd` is only built when there's a log to write it to. Errors if the
+    /// log couldn't take it, the write has still been applied and the log
+    /// retries it with the next one. Writes that can't return an error leave
+    /// it to `aof_status`.
+    fn propagate(&mut self, cmd: impl FnOnce(&Self) -> Command) -> Result<()> {
+        self.dirty += 1;
+
+        if self.aof.is_some() {
+            let cmd = cmd(self);
+            if let Some(aof) = self.aof.as_mut() {
+                return aof.append(&cmd);
+            }
+        }
+
+        return Ok(());
+    }
+
+    /// Reindexes every key from the sub-stores
+    fn rebuild_keyspace(&mut self) {
+        self.keyspace.clear();
+        for key in self.general.store.keys() {
+            self.keyspace.insert(key.clone(), KeyType::String);
+        }
+
+        for key in self.list.store.keys() {
+            self.keyspace.insert(key.clone(), KeyType::List);
+        }
+
+        for key in self.hash.store.keys() {
+            self.keyspace.insert(key.clone(), KeyType::Hash);
+        }
+
+        for key in self.set.store.keys() {
+            self.keyspace.insert(key.clone(), KeyType::Set);
+        }
+
+        for key in self.zset.store.keys() {
+            self.keyspace.insert(key.clone(), KeyType::SortedSet);
+        }
+
+        for key in self.stream.store.keys() {
+            self.keyspace.insert(key.clone(), KeyType::Stream);
+        }
+
+        for key in self.hll.store.keys() {
+            self.keyspace.insert(key.clone(), KeyType::HyperLogLog);
+        }
+
+        let keyspace = &self.keyspace;
+        self.expires.retain(|key, _| keyspace.contains_key(key));
+    }
+
+    /// Errors with `GranatError::WrongType` if the key exists as anything other than `expected`
+    fn check_type(&self, key: &str, expected: KeyType) -> Result<()> {
+        match self.live_type(key) {
+            Some(kt) if kt != expected => return Err(GranatError::WrongType.into()),
+            _ => return Ok(()),
+        }
+    }
+
+    /// Drops the key if it currently holds anything other than `keep`
+    fn clear_other_types(&mut self, key: &str, keep: KeyType) {
+        match self.keyspace.get(key) {
+            Some(kt) if *kt != keep => {
+                self.remove_key(key);
+            }
+            _ => {}
+        }
+    }
+
+    /// Brings the keyspace entry for `key` back in line with its store after a
+    /// write, sub-stores drop keys themselves once they are emptied.
+    fn sync_key(&mut self, key: &str, kt: KeyType) {
+        let present = match kt {
+            KeyType::String => self.general.store.contains_key(key),
+            KeyType::List => self.list.store.contains_key(key),
+            KeyType::Hash => self.hash.store.contains_key(key),
+            KeyType::Set => self.set.store.contains_key(key),
+            KeyType::SortedSet => self.zset.store.contains_key(key),
+            KeyType::Stream => self.stream.store.contains_key(key),
+            KeyType::HyperLogLog => self.hll.store.contains_key(key),
+        };
+
+        if present {
+            self.keyspace.insert(key.to_string(), kt);
+        } else {
+            self.keyspace.remove(key);
+            self.expires.remove(key);
+        }
+    }
+
+    /// Removes the key from whichever store holds it
+    fn remove_key(&mut self, key: &str) -> bool {
+        let Some(kt) = self.keyspace.remove(key) else {
+            return false;
+        };
+        self.expires.remove(key);
+
+        match kt {
+            KeyType::String => self.general.store.remove(key).is_some(),
+            KeyType::List => self.list.store.remove(key).is_some(),
+            KeyType::Hash => self.hash.store.remove(key).is_some(),
+            KeyType::Set => self.set.store.remove(key).is_some(),
+            KeyType::SortedSet => self.zset.store.remove(key).is_some(),
+            KeyType::Stream => self.stream.store.remove(key).is_some(),
+            KeyType::HyperLogLog => self.hll.store.remove(key).is_some(),
+        };
+
+        return true;
+    }
+
+    // Expiry
+
+    /// Samples keys from the keyspace and purges the expired ones.
+    ///
+    /// Like Redis, another round is sampled straight away while more than a
+    /// quarter of the previous sample had expired, bounded by a time budget.
+    /// This runs periodically on writes, but can be called directly to sweep an
+    /// otherwise idle store. Returns the number of keys purged.
+    pub fn active_expire(&mut self) -> usize {
+        let started = Instant::now();
+        let mut purged = 0;
+
+        loop {
+            let sample = self.next_expire_sample();
+            if sample.is_empty() {
+                break;
+            }
+
+            let expired = sample.iter().filter(|k| self.expire_if_needed(k)).count();
+            purged += expired;
+
+            if expired * 4 <= sample.len() || started.elapsed() >= ACTIVE_EXPIRE_BUDGET {
+                break;
+            }
+        }
+
+        self.last_expire_cycle = Instant::now();
+
+        return purged;
+    }
+
+    /// Takes the next batch of keys after the expiry cursor, wrapping round to the start
+    fn next_expire_sample(&mut self) -> Vec<String> {
+        let start = match &self.expire_cursor {
+            Some(cursor) => Bound::Excluded(cursor.clone()),
+            None => Bound::Unbounded,
+        };
+
+        let mut sample = self
+            .keyspace
+            .range::<String, _>((start, Bound::Unbounded))
+            .take(ACTIVE_EXPIRE_SAMPLE)
+            .map(|(k, _)| k.clone())
+            .collect::<Vec<String>>();
+
+        if sample.is_empty() && self.expire_cursor.is_some() {
+            sample = self
+                .keyspace
+                .keys()
+                .take(ACTIVE_EXPIRE_SAMPLE)
+                .cloned()
+                .collect::<Vec<String>>();
+        }
+
+        self.expire_cursor = sample.last().cloned();
+
+        return sample;
+    }
+
+    /// Sets the key to expire in `seconds`, returns whether the expiry was set
+    pub fn expire(&mut self, key: impl AsRef<str>, seconds: i64, cond: ExpireCondition) -> bool {
+        return self.pexpire(key, seconds.saturating_mul(1000), cond);
+    }
+
+    /// Sets the key to expire in `millis`, returns whether the expiry was set
+    pub fn pexpire(&mut self, key: impl AsRef<str>, millis: i64, cond: ExpireCondition) -> bool {
+        let deadline = self.clock.now_millis().saturating_add(millis);
+        return self.pexpire_at(key, deadline, cond);
+    }
+
+    /// Sets the key to expire at the Unix `timestamp` (seconds), returns whether the expiry was set
+    pub fn expire_at(
+        &mut self,
+        key: impl AsRef<str>,
+        timestamp: i64,
+        cond: ExpireCondition,
+    ) -> bool {
+        return self.pexpire_at(key, timestamp.saturating_mul(1000), cond);
+    }
+
+    /// Sets the key to expire at the Unix `timestamp` (milliseconds), returns whether the expiry was set.
+    ///
+    /// A deadline that has already passed deletes the key straight away.
+    pub fn pexpire_at(
+        &mut self,
+        key: impl AsRef<str>,
+        timestamp: i64,
+        cond: ExpireCondition,
+    ) -> bool {
+        let key = key.as_ref();
+        self.before_write(key);
+        if !self.keyspace.contains_key(key) {
+            return false;
+        }
+
+        let current = self.deadline(key);
+        let allowed = match cond {
+            ExpireCondition::Always => true,
+            ExpireCondition::Nx => current.is_none(),
+            ExpireCondition::Xx => current.is_some(),
+            ExpireCondition::Gt => current.is_some_and(|c| timestamp > c),
+            ExpireCondition::Lt => current.is_none_or(|c| timestamp < c),
+        };
+
+        if !allowed {
+            return false;
+        }
+
+        if timestamp <= self.clock.now_millis() {
+            self.remove_key(key);
+        } else {
+            self.set_deadline(key, Some(timestamp));
+        }
+
+        let _ = self.propagate(|_| Command::PExpireAt {
+            key: key.to_string(),
+            timestamp,
+            cond: ExpireCondition::Always,
+        });
+
+        return true;
+    }
+
+    /// Removes the key's expiry, returns whether it had one to remove
+    pub fn persist(&mut self, key: impl AsRef<str>) -> bool {
+        let key = key.as_ref();
+        self.before_write(key);
+        if self.deadline(key).is_none() {
+            return false;
+        }
+
+        self.set_deadline(key, None);
+        let _ = self.propagate(|_| Command::Persist {
+            key: key.to_string(),
+        });
+
+        return true;
+    }
+
+    /// Remaining time to live in seconds, `-2` if the key doesn't exist and `-1` if it has no expiry
+    pub fn ttl(&self, key: impl AsRef<str>) -> i64 {
+        let pttl = self.pttl(key);
+        if pttl < 0 {
+            return pttl;
+        }
+
+        return (pttl + 500) / 1000;
+    }
+
+    /// Remaining time to live in milliseconds, `-2` if the key doesn't exist and `-1` if it has no expiry
+    pub fn pttl(&self, key: impl AsRef<str>) -> i64 {
+        if self.live_type(key.as_ref()).is_none() {
+            return -2;
+        }
+
+        match self.deadline(key.as_ref()) {
+            Some(deadline) => return (deadline - self.clock.now_millis()).max(0),
+            None => return -1,
+        }
+    }
+
+    // Keyspace
+
+    /// Returns the type of value held at `key`, `None` if it doesn't exist
+    pub fn type_of(&self, key: impl AsRef<str>) -> Option<KeyType> {
+        return self.live_type(key.as_ref());
+    }
+
+    /// Returns how many of the given keys exist, keys are counted each time they're given
+    pub fn exists(&self, keys: Vec<impl AsRef<str>>) -> usize {
+        return keys
+            .iter()
+            .filter(|k| self.live_type(k.as_ref()).is_some())
+            .count();
+    }
+
+    /// Removes the given keys, returning how many were removed
+    pub fn del(&mut self, keys: Vec<impl AsRef<str>>) -> usize {
+        let mut removed = vec![];
+        for key in keys.iter() {
+            self.before_write(key.as_ref());
+            if self.remove_key(key.as_ref()) {
+                removed.push(key.as_ref().to_string());
+            }
+        }
+
+        let count = removed.len();
+        if count > 0 {
+            let _ = self.propagate(|_| Command::Del { keys: removed });
+        }
+
+        return count;
+    }
+
+    /// Renames `src` to `dst`, overwriting `dst` if it already exists
+    pub fn rename(&mut self, src: impl AsRef<str>, dst: impl AsRef<str>) -> Result<()> {
+        let (src, dst) = (src.as_ref(), dst.as_ref());
+        self.before_write(src);
+        self.expire_if_needed(dst);
+
+        let Some(kt) = self.type_of(src) else {
+            return Err(GranatError::NoSuchKey.into());
+        };
+
+        if src == dst {
+            return Ok(());
+        }
+
+        self.remove_key(dst);
+        self.keyspace.remove(src);
+
+        match kt {
+            KeyType::String => {
+                if let Some(value) = self.general.store.remove(src) {
+                    self.general.store.insert(dst.to_string(), value);
+                }
+            }
+            KeyType::List => {
+                if let Some(value) = self.list.store.remove(src) {
+                    self.list.store.insert(dst.to_string(), value);
+                }
+            }
+            KeyType::Hash => {
+                if let Some(value) = self.hash.store.remove(src) {
+                    self.hash.store.insert(dst.to_string(), value);
+                }
+            }
+            KeyType::Set => {
+                if let Some(value) = self.set.store.remove(src) {
+                    self.set.store.insert(dst.to_string(), value);
+                }
+            }
+            KeyType::SortedSet => {
+                if let Some(value) = self.zset.store.remove(src) {
+                    self.zset.store.insert(dst.to_string(), value);
+                }
+            }
+            KeyType::Stream => {
+                if let Some(value) = self.stream.store.remove(src) {
+                    self.stream.store.insert(dst.to_string(), value);
+                }
+            }
+            KeyType::HyperLogLog => {
+                if let Some(value) = self.hll.store.remove(src) {
+                    self.hll.store.insert(dst.to_string(), value);
+                }
+            }
+        }
+
+        if let Some(deadline) = self.expires.remove(src) {
+            self.expires.insert(dst.to_string(), deadline);
+        }
+
+        self.keyspace.insert(dst.to_string(), kt);
+        self.propagate(|_| Command::Rename {
+            src: src.to_string(),
+            dst: dst.to_string(),
+        })?;
+
+        return Ok(());
+    }
+
+    /// Renames `src` to `dst` only if `dst` doesn't exist, returns whether the rename happened
+    pub fn renamenx(&mut self, src: impl AsRef<str>, dst: impl AsRef<str>) -> Result<bool> {
+        if self.type_of(src.as_ref()).is_none() {
+            return Err(GranatError::NoSuchKey.into());
+        }
+
+        if self.type_of(dst.as_ref()).is_some() {
+            return Ok(false);
+        }
+
+        self.rename(src, dst)?;
+
+        return Ok(true);
+    }
+
+    /// Returns every key matching the glob `pattern`
+    pub fn keys(&self, pattern: impl AsRef<str>) -> Vec<String> {
+        return self
+            .keyspace
+            .keys()
+            .filter(|k| glob_match(pattern.as_ref(), k) && !self.is_expired_key(k))
+            .cloned()
+            .collect::<Vec<String>>();
+    }
+
+    /// Incrementally iterates the keyspace.
+    ///
+    /// Start with a cursor of `None` and keep passing back the returned cursor until
+    /// it comes back as `None`. Each call examines up to `count` keys and returns
+    /// those matching `pattern`, so a call can return no keys without being finished.
+    /// The cursor is the last key examined, so keys present for the whole scan are
+    /// always returned, while keys added or removed during it may or may not be.
+    pub fn scan(
+        &self,
+        cursor: Option<&str>,
+        pattern: Option<&str>,
+        count: usize,
+    ) -> (Option<String>, Vec<String>) {
+        let count = count.max(1);
+        let start = match cursor {
+            Some(cursor) => Bound::Excluded(cursor),
+            None => Bound::Unbounded,
+        };
+
+        let examined = self
+            .keyspace
+            .range::<str, _>((start, Bound::Unbounded))
+            .take(count)
+            .map(|(k, _)| k)
+            .collect::<Vec<&String>>();
+
+        let keys = examined
+            .iter()
+            .filter(|k| !self.is_expired_key(k))
+            .filter(|k| match pattern {
+                Some(p) => glob_match(p, k),
+                None => true,
+            })
+            .map(|k| k.to_string())
+            .collect::<Vec<String>>();
+
+        if examined.len() < count {
+            return (None, keys);
+        }
+
+        return (examined.last().map(|k| k.to_string()), keys);
+    }
+
+    // General
+
+    pub fn get(&self, key: impl AsRef<str>) -> Result<Option<StoreEntry>> {
+        self.check_type(key.as_ref(), KeyType::String)?;
+        return Ok(self.general.get(key));
+    }
+
+    /// Keys holding a non-string value are returned as `None` rather than erroring
+    pub fn get_multiple(&self, keys: Vec<impl AsRef<str>>) -> Vec<Option<StoreEntry>> {
+        return self.general.get_multiple(keys);
+    }
+
+    /// Like Redis `SET`, this overwrites the key whatever type it currently holds
+    pub fn set(&mut self, kv: KVPair) -> Result<()> {
+        let key = kv.0.clone();
+        self.before_write(&key);
+        self.clear_other_types(&key, KeyType::String);
+        self.general.set(kv)?;
+        self.keyspace.insert(key.clone(), KeyType::String);
+        self.propagate(|s| Command::Set {
+            entry: s.general.store[&key].clone(),
+            key,
+        })?;
+
+        return Ok(());
+    }
+
+    pub fn set_multiple(&mut self, kvs: Vec<KVPair>) -> Result<()> {
+        let keys = kvs.iter().map(|(k, _)| k.clone()).collect::<Vec<String>>();
+        for key in keys.iter() {
+            self.before_write(key);
+            self.clear_other_types(key, KeyType::String);
+        }
+
+        self.general.set_multiple(kvs)?;
+        for key in keys.iter() {
+            self.keyspace.insert(key.clone(), KeyType::String);
+        }
+
+        self.propagate(|s| Command::SetMultiple {
+            pairs: keys
+                .into_iter()
+                .map(|k| {
+                    let entry = s.general.store[&k].clone();
+                    return (k, entry);
+                })
+                .collect::<Vec<KVPair>>(),
+        })?;
+
+        return Ok(());
+    }
+
+    pub fn increment(&mut self, key: impl AsRef<str>, incr: i64) -> Result<i64> {
+        self.before_write(key.as_ref());
+        self.check_type(key.as_ref(), KeyType::String)?;
+        let value = self.general.increment(key.as_ref(), incr)?;
+        self.sync_key(key.as_ref(), KeyType::String);
+        self.propagate(|_| Command::Increment {
+            key: key.as_ref().to_string(),
+            incr,
+        })?;
+
+        return Ok(value);
+    }
+
+    pub fn increment_float(&mut self, key: impl AsRef<str>, incr: f64) -> Result<f64> {
+        self.before_write(key.as_ref());
+        self.check_type(key.as_ref(), KeyType::String)?;
+        let value = self.general.increment_float(key.as_ref(), incr)?;
+        self.sync_key(key.as_ref(), KeyType::String);
+
+        // Logged as the result so replaying doesn't accumulate float error
+        self.propagate(|s| Command::Set {
+            key: key.as_ref().to_string(),
+            entry: s.general.store[key.as_ref()].clone(),
+        })?;
+
+        return Ok(value);
+    }
+
+    // Bitmap
+
+    /// Sets the bit at `offset` of the string, returning what it was before
+    pub fn setbit(&mut self, key: impl AsRef<str>, offset: u64, bit: bool) -> Result<bool> {
+        self.before_write(key.as_ref());
+        self.check_type(key.as_ref(), KeyType::String)?;
+        let old = self.general.setbit(key.as_ref(), offset, bit)?;
+        self.sync_key(key.as_ref(), KeyType::String);
+        self.propagate(|_| Command::SetBit {
+            key: key.as_ref().to_string(),
+            offset,
+            bit,
+        })?;
+
+        return Ok(old);
+    }
+
+    pub fn getbit(&self, key: impl AsRef<str>, offset: u64) -> Result<bool> {
+        self.check_type(key.as_ref(), KeyType::String)?;
+        return self.general.getbit(key, offset);
+    }
+
+    /// Number of set bits in the string, in `range` if given
+    pub fn bitcount(&self, key: impl AsRef<str>, range: Option<BitRange>) -> Result<usize> {
+        self.check_type(key.as_ref(), KeyType::String)?;
+        return Ok(self.general.bitcount(key, range));
+    }
+
+    /// Offset of the first bit set to `bit`, see `bitmap::position`
+    pub fn bitpos(&self, key: impl AsRef<str>, bit: bool, range: Option<BitRange>) -> Result<i64> {
+        self.check_type(key.as_ref(), KeyType::String)?;
+        return Ok(self.general.bitpos(key, bit, range));
+    }
+
+    /// Stores `op` applied to the strings at `keys` in `dst`, returning its length in bytes.
+    ///
+    /// Missing keys count as empty strings and `dst` is removed if the result is empty.
+    pub fn bitop(
+        &mut self,
+        op: BitOperation,
+        dst: impl AsRef<str>,
+        keys: Vec<impl AsRef<str>>,
+    ) -> Result<usize> {
+        let dst = dst.as_ref();
+        self.before_write(dst);
+        for key in keys.iter() {
+            self.expire_if_needed(key.as_ref());
+        }
+
+        for key in keys.iter() {
+            self.check_type(key.as_ref(), KeyType::String)?;
+        }
+
+        let len = self.general.bitop(op, dst, &keys)?;
+        self.clear_other_types(dst, KeyType::String);
+        self.sync_key(dst, KeyType::String);
+        self.propagate(|_| Command::BitOp {
+            op,
+            dst: dst.to_string(),
+            keys: keys
+                .iter()
+                .map(|k| k.as_ref().to_string())
+                .collect::<Vec<String>>(),
+        })?;
+
+        return Ok(len);
+    }
+
+    /// Runs the bitfield `ops` in order, see `bitmap::bitfield`
+    pub fn bitfield(
+        &mut self,
+        key: impl AsRef<str>,
+        ops: Vec<BitFieldOp>,
+    ) -> Result<Vec<Option<i64>>> {
+        self.before_write(key.as_ref());
+        self.check_type(key.as_ref(), KeyType::String)?;
+        let results = self.general.bitfield(key.as_ref(), &ops)?;
+        self.sync_key(key.as_ref(), KeyType::String);
+
+        // Only reads, nothing to count as a write
+        if !ops.iter().any(|op| op.is_write()) {
+            return Ok(results);
+        }
+
+        self.propagate(|_| Command::BitField {
+            key: key.as_ref().to_string(),
+            ops,
+        })?;
+
+        return Ok(results);
+    }
+
+    /// Reads each field of the given type at its offset without ever writing
+    pub fn bitfield_ro(
+        &self,
+        key: impl AsRef<str>,
+        fields: Vec<(BitFieldType, u64)>,
+    ) -> Result<Vec<i64>> {
+        self.check_type(key.as_ref(), KeyType::String)?;
+        return self.general.bitfield_ro(key, &fields);
+    }
+
+    // List
+
+    /// Returns the length of the list after the push
+    pub fn push_left(&mut self, kv: KVPair) -> Result<usize> {
+        self.before_write(&kv.0);
+        self.check_type(&kv.0, KeyType::List)?;
+        let key = kv.0.clone();
+        self.list.push_left(kv);
+        self.sync_key(&key, KeyType::List);
+        self.propagate(|s| Command::PushLeft {
+            entry: s.list.store[&key].front().cloned().unwrap_or_default(),
+            key: key.clone(),
+        })?;
+
+        return Ok(self.list.len(key));
+    }
+
+    /// Returns the length of the list after the push
+    pub fn push_right(&mut self, kv: KVPair) -> Result<usize> {
+        self.before_write(&kv.0);
+        self.check_type(&kv.0, KeyType::List)?;
+        let key = kv.0.clone();
+        self.list.push_right(kv);
+        self.sync_key(&key, KeyType::List);
+        self.propagate(|s| Command::PushRight {
+            entry: s.list.store[&key].back().cloned().unwrap_or_default(),
+            key: key.clone(),
+        })?;
+
+        return Ok(self.list.len(key));
+    }
+
+    /// Pushes every entry onto the head in turn, returning the length of the list after
+    pub fn push_left_many(
+        &mut self,
+        key: impl AsRef<str>,
+        values: Vec<StoreEntry>,
+    ) -> Result<usize> {
+        return self.push_many(key.as_ref(), values, ListDirection::Left, true);
+    }
+
+    /// Pushes every entry onto the tail in turn, returning the length of the list after
+    pub fn push_right_many(
+        &mut self,
+        key: impl AsRef<str>,
+        values: Vec<StoreEntry>,
+    ) -> Result<usize> {
+        return self.push_many(key.as_ref(), values, ListDirection::Right, true);
+    }
+
+    /// Pushes onto the head only if the list already exists, returning `0` if it doesn't
+    pub fn push_left_if_exists(
+        &mut self,
+        key: impl AsRef<str>,
+        values: Vec<StoreEntry>,
+    ) -> Result<usize> {
+        return self.push_many(key.as_ref(), values, ListDirection::Left, false);
+    }
+
+    /// Pushes onto the tail only if the list already exists, returning `0` if it doesn't
+    pub fn push_right_if_exists(
+        &mut self,
+        key: impl AsRef<str>,
+        values: Vec<StoreEntry>,
+    ) -> Result<usize> {
+        return self.push_many(key.as_ref(), values, ListDirection::Right, false);
+    }
+
+    fn push_many(
+        &mut self,
+        key: &str,
+        values: Vec<StoreEntry>,
+        dir: ListDirection,
+        create: bool,
+    ) -> Result<usize> {
+        self.before_write(key);
+        self.check_type(key, KeyType::List)?;
+        let logged = self.aof.is_some().then(|| values.clone());
+        let before = self.list.len(key);
+        let len = match (dir, create) {
+            (ListDirection::Left, true) => self.list.push_left_many(key, values),
+            (ListDirection::Right, true) => self.list.push_right_many(key, values),
+            (ListDirection::Left, false) => self.list.push_left_if_exists(key, values),
+            (ListDirection::Right, false) => self.list.push_right_if_exists(key, values),
+        };
+        self.sync_key(key, KeyType::List);
+
+        // Only log pushes that happened, replaying them as plain pushes is then equivalent
+        if len > before {
+            self.propagate(|_| Command::PushMany {
+                key: key.to_string(),
+                entries: logged.unwrap_or_default(),
+                dir,
+            })?;
+        }
+
+        return Ok(len);
+    }
+
+    pub fn pop_left(&mut self, key: impl AsRef<str>) -> Result<Option<StoreEntry>> {
+        self.before_write(key.as_ref());
+        self.check_type(key.as_ref(), KeyType::List)?;
+        let entry = self.list.pop_left(key.as_ref());
+        self.sync_key(key.as_ref(), KeyType::List);
+        if entry.is_some() {
+            self.propagate(|_| Command::PopLeft {
+                key: key.as_ref().to_string(),
+            })?;
+        }
+
+        return Ok(entry);
+    }
+
+    pub fn pop_right(&mut self, key: impl AsRef<str>) -> Result<Option<StoreEntry>> {
+        self.before_write(key.as_ref());
+        self.check_type(key.as_ref(), KeyType::List)?;
+        let entry = self.list.pop_right(key.as_ref());
+        self.sync_key(key.as_ref(), KeyType::List);
+        if entry.is_some() {
+            self.propagate(|_| Command::PopRight {
+                key: key.as_ref().to_string(),
+            })?;
         }
+
+        return Ok(entry);
+    }
+
+    /// Pops up to `count` entries from the head of the list in one go
+    pub fn pop_left_n(&mut self, key: impl AsRef<str>, count: usize) -> Result<Vec<StoreEntry>> {
+        self.before_write(key.as_ref());
+        self.check_type(key.as_ref(), KeyType::List)?;
+        let entries = self.list.pop_left_n(key.as_ref(), count);
+        self.sync_key(key.as_ref(), KeyType::List);
+        if !entries.is_empty() {
+            self.propagate(|_| Command::PopLeftCount {
+                key: key.as_ref().to_string(),
+                count: entries.len(),
+            })?;
+        }
+
+        return Ok(entries);
+    }
+
+    /// Pops up to `count` entries from the tail of the list in one go
+    pub fn pop_right_n(&mut self, key: impl AsRef<str>, count: usize) -> Result<Vec<StoreEntry>> {
+        self.before_write(key.as_ref());
+        self.check_type(key.as_ref(), KeyType::List)?;
+        let entries = self.list.pop_right_n(key.as_ref(), count);
+        self.sync_key(key.as_ref(), KeyType::List);
+        if !entries.is_empty() {
+            self.propagate(|_| Command::PopRightCount {
+                key: key.as_ref().to_string(),
+                count: entries.len(),
+            })?;
+        }
+
+        return Ok(entries);
+    }
+
+    /// Atomically pops from the `from` end of `src` and pushes onto the `to` end
+    /// of `dst`, returning the entry moved or `None` if `src` is empty.
+    ///
+    /// Errors without moving anything if either key holds something other than a list.
+    pub fn lmove(
+        &mut self,
+        src: impl AsRef<str>,
+        dst: impl AsRef<str>,
+        from: ListDirection,
+        to: ListDirection,
+    ) -> Result<Option<StoreEntry>> {
+        let (src, dst) = (src.as_ref(), dst.as_ref());
+        self.before_write(src);
+        self.expire_if_needed(dst);
+        self.check_type(src, KeyType::List)?;
+        self.check_type(dst, KeyType::List)?;
+
+        let entry = self.list.lmove(src, dst, from, to);
+        self.sync_key(src, KeyType::List);
+        self.sync_key(dst, KeyType::List);
+        if entry.is_some() {
+            self.propagate(|_| Command::ListMove {
+                src: src.to_string(),
+                dst: dst.to_string(),
+                from,
+                to,
+            })?;
+        }
+
+        return Ok(entry);
+    }
+
+    /// Pops from the right of `src` and pushes onto the left of `dst`
+    pub fn rpoplpush(
+        &mut self,
+        src: impl AsRef<str>,
+        dst: impl AsRef<str>,
+    ) -> Result<Option<StoreEntry>> {
+        return self.lmove(src, dst, ListDirection::Right, ListDirection::Left);
+    }
+
+    pub fn index(&self, key: impl AsRef<str>, idx: isize) -> Result<Option<StoreEntry>> {
+        self.check_type(key.as_ref(), KeyType::List)?;
+        if self.is_expired_key(key.as_ref()) {
+            return Ok(None);
+        }
+
+        return Ok(self.list.index(key, idx));
+    }
+
+    /// Indexes of the entries holding `value`, see `ListStore::position`
+    pub fn position(
+        &self,
+        key: impl AsRef<str>,
+        value: impl AsRef<[u8]>,
+        rank: isize,
+        count: usize,
+        maxlen: usize,
+    ) -> Result<Vec<usize>> {
+        self.check_type(key.as_ref(), KeyType::List)?;
+        if self.is_expired_key(key.as_ref()) {
+            return Ok(vec![]);
+        }
+
+        return self.list.position(key, value, rank, count, maxlen);
+    }
+
+    pub fn list_len(&self, key: impl AsRef<str>) -> Result<usize> {
+        self.check_type(key.as_ref(), KeyType::List)?;
+        if self.is_expired_key(key.as_ref()) {
+            return Ok(0);
+        }
+
+        return Ok(self.list.len(key));
+    }
+
+    pub fn range(&self, key: impl AsRef<str>, start: isize, end: isize) -> Result<Vec<StoreEntry>> {
+        self.check_type(key.as_ref(), KeyType::List)?;
+        if self.is_expired_key(key.as_ref()) {
+            return Ok(vec![]);
+        }
+
+        return Ok(self.list.range(key, start, end));
+    }
+
+    /// Replaces the entry at `idx`, erroring if the list doesn't exist or `idx` is out of range
+    pub fn list_set(&mut self, kv: KVPair, idx: isize) -> Result<()> {
+        self.before_write(&kv.0);
+        self.check_type(&kv.0, KeyType::List)?;
+        let key = kv.0.clone();
+        let logged = self.aof.is_some().then(|| kv.clone());
+        let result = self.list.set(kv, idx);
+        self.sync_key(&key, KeyType::List);
+        result?;
+
+        self.propagate(|_| {
+            let (key, entry) = logged.unwrap_or_default();
+            return Command::ListSet { key, entry, idx };
+        })?;
+
+        return Ok(());
+    }
+
+    /// Inserts the entry so it ends up at `idx`, `0` prepends and `-1` appends.
+    /// Returns the length of the list after the insert.
+    pub fn insert_at(&mut self, kv: KVPair, idx: isize) -> Result<usize> {
+        self.before_write(&kv.0);
+        self.check_type(&kv.0, KeyType::List)?;
+        let key = kv.0.clone();
+        let logged = self.aof.is_some().then(|| kv.1.clone());
+        let result = self.list.insert_at(kv, idx);
+        self.sync_key(&key, KeyType::List);
+        let len = result?;
+
+        self.propagate(|_| Command::ListInsertAt {
+            key,
+            entry: logged.unwrap_or_default(),
+            idx,
+        })?;
+
+        return Ok(len);
+    }
+
+    /// Inserts `value` before or after the first entry holding `pivot`.
+    ///
+    /// Returns the length of the list after the insert, `-1` if `pivot` wasn't
+    /// found and `0` if the list doesn't exist.
+    pub fn linsert(
+        &mut self,
+        key: impl AsRef<str>,
+        pos: InsertPosition,
+        pivot: impl AsRef<[u8]>,
+        value: StoreEntry,
+    ) -> Result<i64> {
+        let key = key.as_ref();
+        self.before_write(key);
+        self.check_type(key, KeyType::List)?;
+        let logged = self.aof.is_some().then(|| value.clone());
+        let len = self.list.linsert(key, pos, pivot.as_ref(), value);
+        self.sync_key(key, KeyType::List);
+        if len > 0 {
+            self.propagate(|_| Command::ListInsert {
+                key: key.to_string(),
+                pos,
+                pivot: pivot.as_ref().into(),
+                entry: logged.unwrap_or_default(),
+            })?;
+        }
+
+        return Ok(len);
+    }
+
+    pub fn trim(&mut self, key: impl AsRef<str>, start: isize, end: isize) -> Result<()> {
+        self.before_write(key.as_ref());
+        self.check_type(key.as_ref(), KeyType::List)?;
+        self.list.trim(key.as_ref(), start, end);
+        self.sync_key(key.as_ref(), KeyType::List);
+        self.propagate(|_| Command::Trim {
+            key: key.as_ref().to_string(),
+            start,
+            end,
+        })?;
+
+        return Ok(());
+    }
+
+    /// Returns the number of removed entries
+    pub fn list_remove(
+        &mut self,
+        key: impl AsRef<str>,
+        value: impl AsRef<[u8]>,
+        count: isize,
+    ) -> Result<usize> {
+        self.before_write(key.as_ref());
+        self.check_type(key.as_ref(), KeyType::List)?;
+        let value = Value::from(value.as_ref());
+        let removed = self.list.remove(key.as_ref(), &value, count);
+        self.sync_key(key.as_ref(), KeyType::List);
+        if removed > 0 {
+            self.propagate(|_| Command::ListRemove {
+                key: key.as_ref().to_string(),
+                value,
+                count,
+            })?;
+        }
+
+        return Ok(removed);
+    }
+
+    // Hash
+
+    /// Sets each field of the hash, returning how many fields were newly added
+    pub fn hset(&mut self, key: impl AsRef<str>, fields: Vec<KVPair>) -> Result<usize> {
+        let key = key.as_ref();
+        self.before_write(key);
+        self.check_type(key, KeyType::Hash)?;
+        if fields.is_empty() {
+            return Ok(0);
+        }
+
+        let logged = self.aof.is_some().then(|| fields.clone());
+        let added = self.hash.set(key, fields);
+        self.sync_key(key, KeyType::Hash);
+        self.propagate(|_| Command::HashSet {
+            key: key.to_string(),
+            fields: logged.unwrap_or_default(),
+        })?;
+
+        return Ok(added);
+    }
+
+    /// Sets the field only if the hash doesn't already hold it, returning whether it was set
+    pub fn hsetnx(&mut self, key: impl AsRef<str>, field: KVPair) -> Result<bool> {
+        let key = key.as_ref();
+        self.before_write(key);
+        self.check_type(key, KeyType::Hash)?;
+        let logged = self.aof.is_some().then(|| field.clone());
+        let set = self.hash.set_nx(key, field);
+        self.sync_key(key, KeyType::Hash);
+        if set {
+            self.propagate(|_| Command::HashSet {
+                key: key.to_string(),
+                fields: logged.into_iter().collect(),
+            })?;
+        }
+
+        return Ok(set);
+    }
+
+    pub fn hget(&self, key: impl AsRef<str>, field: impl AsRef<str>) -> Result<Option<StoreEntry>> {
+        self.check_type(key.as_ref(), KeyType::Hash)?;
+        if self.is_expired_key(key.as_ref()) {
+            return Ok(None);
+        }
+
+        return Ok(self.hash.get(key, field));
+    }
+
+    /// Removes the fields from the hash, returning how many were removed
+    pub fn hdel(&mut self, key: impl AsRef<str>, fields: Vec<impl AsRef<str>>) -> Result<usize> {
+        let key = key.as_ref();
+        self.before_write(key);
+        self.check_type(key, KeyType::Hash)?;
+        let fields = fields
+            .iter()
+            .map(|f| f.as_ref().to_string())
+            .collect::<Vec<String>>();
+
+        let removed = self.hash.delete(key, fields.clone());
+        self.sync_key(key, KeyType::Hash);
+        if removed > 0 {
+            self.propagate(|_| Command::HashDelete {
+                key: key.to_string(),
+                fields,
+            })?;
+        }
+
+        return Ok(removed);
+    }
+
+    pub fn hexists(&self, key: impl AsRef<str>, field: impl AsRef<str>) -> Result<bool> {
+        return Ok(self.hget(key, field)?.is_some());
+    }
+
+    pub fn hlen(&self, key: impl AsRef<str>) -> Result<usize> {
+        self.check_type(key.as_ref(), KeyType::Hash)?;
+        if self.is_expired_key(key.as_ref()) {
+            return Ok(0);
+        }
+
+        return Ok(self.hash.len(key));
+    }
+
+    pub fn hkeys(&self, key: impl AsRef<str>) -> Result<Vec<String>> {
+        self.check_type(key.as_ref(), KeyType::Hash)?;
+        if self.is_expired_key(key.as_ref()) {
+            return Ok(vec![]);
+        }
+
+        return Ok(self.hash.keys(key));
+    }
+
+    pub fn hvals(&self, key: impl AsRef<str>) -> Result<Vec<StoreEntry>> {
+        self.check_type(key.as_ref(), KeyType::Hash)?;
+        if self.is_expired_key(key.as_ref()) {
+            return Ok(vec![]);
+        }
+
+        return Ok(self.hash.values(key));
+    }
+
+    /// Every field in the hash with its entry, in no particular order
+    pub fn hgetall(&self, key: impl AsRef<str>) -> Result<Vec<KVPair>> {
+        self.check_type(key.as_ref(), KeyType::Hash)?;
+        if self.is_expired_key(key.as_ref()) {
+            return Ok(vec![]);
+        }
+
+        return Ok(self.hash.get_all(key));
+    }
+
+    pub fn hincrby(
+        &mut self,
+        key: impl AsRef<str>,
+        field: impl AsRef<str>,
+        incr: i64,
+    ) -> Result<i64> {
+        let (key, field) = (key.as_ref(), field.as_ref());
+        self.before_write(key);
+        self.check_type(key, KeyType::Hash)?;
+        let result = self.hash.increment(key, field, incr);
+        self.sync_key(key, KeyType::Hash);
+        let value = result?;
+
+        self.propagate(|_| Command::HashIncrement {
+            key: key.to_string(),
+            field: field.to_string(),
+            incr,
+        })?;
+
+        return Ok(value);
+    }
+
+    pub fn hincrbyfloat(
+        &mut self,
+        key: impl AsRef<str>,
+        field: impl AsRef<str>,
+        incr: f64,
+    ) -> Result<f64> {
+        let (key, field) = (key.as_ref(), field.as_ref());
+        self.before_write(key);
+        self.check_type(key, KeyType::Hash)?;
+        let result = self.hash.increment_float(key, field, incr);
+        self.sync_key(key, KeyType::Hash);
+        let value = result?;
+
+        // Logged as the result so replaying doesn't accumulate float error
+        self.propagate(|s| Command::HashSet {
+            key: key.to_string(),
+            fields: vec![(field.to_string(), s.hash.store[key][field].clone())],
+        })?;
+
+        return Ok(value);
+    }
+
+    // Set
+
+    /// Adds the members to the set, returning how many weren't already in it
+    pub fn sadd(&mut self, key: impl AsRef<str>, members: Vec<impl AsRef<str>>) -> Result<usize> {
+        let key = key.as_ref();
+        self.before_write(key);
+        self.check_type(key, KeyType::Set)?;
+        let members = members
+            .iter()
+            .map(|m| m.as_ref().to_string())
+            .collect::<Vec<String>>();
+
+        let logged = self.aof.is_some().then(|| members.clone());
+        let added = self.set.add(key, members);
+        self.sync_key(key, KeyType::Set);
+        if added > 0 {
+            self.propagate(|_| Command::SetAdd {
+                key: key.to_string(),
+                members: logged.unwrap_or_default(),
+            })?;
+        }
+
+        return Ok(added);
+    }
+
+    /// Removes the members from the set, returning how many were removed
+    pub fn srem(&mut self, key: impl AsRef<str>, members: Vec<impl AsRef<str>>) -> Result<usize> {
+        let key = key.as_ref();
+        self.before_write(key);
+        self.check_type(key, KeyType::Set)?;
+        let members = members
+            .iter()
+            .map(|m| m.as_ref().to_string())
+            .collect::<Vec<String>>();
+
+        let removed = self.set.remove(key, members.clone());
+        self.sync_key(key, KeyType::Set);
+        if removed > 0 {
+            self.propagate(|_| Command::SetRemove {
+                key: key.to_string(),
+                members,
+            })?;
+        }
+
+        return Ok(removed);
+    }
+
+    pub fn sismember(&self, key: impl AsRef<str>, member: impl AsRef<str>) -> Result<bool> {
+        self.check_type(key.as_ref(), KeyType::Set)?;
+        if self.is_expired_key(key.as_ref()) {
+            return Ok(false);
+        }
+
+        return Ok(self.set.is_member(key, member));
+    }
+
+    /// Every member of the set, in no particular order
+    pub fn smembers(&self, key: impl AsRef<str>) -> Result<Vec<String>> {
+        self.check_type(key.as_ref(), KeyType::Set)?;
+        if self.is_expired_key(key.as_ref()) {
+            return Ok(vec![]);
+        }
+
+        return Ok(self.set.members(key));
+    }
+
+    pub fn scard(&self, key: impl AsRef<str>) -> Result<usize> {
+        self.check_type(key.as_ref(), KeyType::Set)?;
+        if self.is_expired_key(key.as_ref()) {
+            return Ok(0);
+        }
+
+        return Ok(self.set.card(key));
+    }
+
+    /// Removes and returns up to `count` random members
+    pub fn spop(&mut self, key: impl AsRef<str>, count: usize) -> Result<Vec<String>> {
+        let key = key.as_ref();
+        self.before_write(key);
+        self.check_type(key, KeyType::Set)?;
+        let popped = self.set.pop(key, count);
+        self.sync_key(key, KeyType::Set);
+
+        // Logged as the members removed so replaying picks the same ones
+        if !popped.is_empty() {
+            self.propagate(|_| Command::SetRemove {
+                key: key.to_string(),
+                members: popped.clone(),
+            })?;
+        }
+
+        return Ok(popped);
+    }
+
+    /// Random members of the set, see `SetStore::random_members`
+    pub fn srandmember(&self, key: impl AsRef<str>, count: isize) -> Result<Vec<String>> {
+        self.check_type(key.as_ref(), KeyType::Set)?;
+        if self.is_expired_key(key.as_ref()) {
+            return Ok(vec![]);
+        }
+
+        return Ok(self.set.random_members(key, count));
+    }
+
+    pub fn sunion(&self, keys: Vec<impl AsRef<str>>) -> Result<Vec<String>> {
+        return self.set_combine(SetOperation::Union, &keys);
+    }
+
+    pub fn sinter(&self, keys: Vec<impl AsRef<str>>) -> Result<Vec<String>> {
+        return self.set_combine(SetOperation::Intersection, &keys);
+    }
+
+    /// Members of the first set that aren't in any of the others
+    pub fn sdiff(&self, keys: Vec<impl AsRef<str>>) -> Result<Vec<String>> {
+        return self.set_combine(SetOperation::Difference, &keys);
+    }
+
+    /// Stores the union of `keys` in `dst`, returning the size of the new set
+    pub fn sunionstore(
+        &mut self,
+        dst: impl AsRef<str>,
+        keys: Vec<impl AsRef<str>>,
+    ) -> Result<usize> {
+        return self.set_combine_store(SetOperation::Union, dst.as_ref(), &keys);
+    }
+
+    /// Stores the intersection of `keys` in `dst`, returning the size of the new set
+    pub fn sinterstore(
+        &mut self,
+        dst: impl AsRef<str>,
+        keys: Vec<impl AsRef<str>>,
+    ) -> Result<usize> {
+        return self.set_combine_store(SetOperation::Intersection, dst.as_ref(), &keys);
+    }
+
+    /// Stores the difference of `keys` in `dst`, returning the size of the new set
+    pub fn sdiffstore(
+        &mut self,
+        dst: impl AsRef<str>,
+        keys: Vec<impl AsRef<str>>,
+    ) -> Result<usize> {
+        return self.set_combine_store(SetOperation::Difference, dst.as_ref(), &keys);
+    }
+
+    /// Combines the sets at `keys`, missing and expired keys count as empty sets
+    pub(crate) fn set_combine(
+        &self,
+        op: SetOperation,
+        keys: &[impl AsRef<str>],
+    ) -> Result<Vec<String>> {
+        let mut sets = vec![];
+        for key in keys.iter() {
+            self.check_type(key.as_ref(), KeyType::Set)?;
+            match self.is_expired_key(key.as_ref()) {
+                true => sets.push(HashSet::new()),
+                false => sets.push(
+                    self.set
+                        .store
+                        .get(key.as_ref())
+                        .cloned()
+                        .unwrap_or_default(),
+                ),
+            }
+        }
+
+        return Ok(op.combine(sets).into_iter().collect::<Vec<String>>());
+    }
+
+    /// Combines the sets at `keys` into `dst`, overwriting whatever `dst` held
+    pub(crate) fn set_combine_store(
+        &mut self,
+        op: SetOperation,
+        dst: &str,
+        keys: &[impl AsRef<str>],
+    ) -> Result<usize> {
+        self.before_write(dst);
+        for key in keys.iter() {
+            self.expire_if_needed(key.as_ref());
+        }
+
+        for key in keys.iter() {
+            self.check_type(key.as_ref(), KeyType::Set)?;
+        }
+
+        self.clear_other_types(dst, KeyType::Set);
+        self.expires.remove(dst);
+        let size = self.set.combine_store(op, dst, keys);
+        self.sync_key(dst, KeyType::Set);
+        self.propagate(|_| Command::SetCombineStore {
+            op,
+            dst: dst.to_string(),
+            keys: keys
+                .iter()
+                .map(|k| k.as_ref().to_string())
+                .collect::<Vec<String>>(),
+        })?;
+
+        return Ok(size);
+    }
+
+    // Sorted set
+
+    /// Adds or updates members as `opts` allows, like Redis `ZADD`.
+    ///
+    /// Returns the number of members added, or added and changed with `opts.ch`.
+    pub fn zadd(
+        &mut self,
+        key: impl AsRef<str>,
+        members: Vec<ScoredMember>,
+        opts: AddOptions,
+    ) -> Result<usize> {
+        let key = key.as_ref();
+        self.before_write(key);
+        self.check_type(key, KeyType::SortedSet)?;
+
+        let logged = self.aof.is_some().then(|| members.clone());
+        let before = self.zset.card(key);
+        let changed = self
+            .zset
+            .add(key, members, AddOptions { ch: true, ..opts })?;
+        let added = self.zset.card(key) - before;
+        self.sync_key(key, KeyType::SortedSet);
+        if changed > 0 {
+            self.propagate(|_| Command::ZAdd {
+                key: key.to_string(),
+                members: logged.unwrap_or_default(),
+                opts,
+            })?;
+        }
+
+        match opts.ch {
+            true => return Ok(changed),
+            false => return Ok(added),
+        }
+    }
+
+    /// Adds `incr` to the member's score as `opts` allows, like `ZADD INCR`.
+    ///
+    /// Returns the new score, `None` if `opts` stopped the update.
+    pub fn zadd_incr(
+        &mut self,
+        key: impl AsRef<str>,
+        member: impl AsRef<str>,
+        incr: f64,
+        opts: AddOptions,
+    ) -> Result<Option<f64>> {
+        let (key, member) = (key.as_ref(), member.as_ref());
+        self.before_write(key);
+        self.check_type(key, KeyType::SortedSet)?;
+        let score = self.zset.add_incr(key, member, incr, opts)?;
+        self.sync_key(key, KeyType::SortedSet);
+
+        // Logged as the new score to avoid accumulating float error on replay
+        if let Some(score) = score {
+            self.propagate(|_| Command::ZAdd {
+                key: key.to_string(),
+                members: vec![(member.to_string(), score)],
+                opts: AddOptions::default(),
+            })?;
+        }
+
+        return Ok(score);
+    }
+
+    /// Adds `incr` to the member's score, starting from `0` if it isn't in the set
+    pub fn zincrby(
+        &mut self,
+        key: impl AsRef<str>,
+        member: impl AsRef<str>,
+        incr: f64,
+    ) -> Result<f64> {
+        let score = self.zadd_incr(key, member, incr, AddOptions::default())?;
+        return Ok(score.unwrap_or_default());
+    }
+
+    /// Removes the members, returning how many were removed
+    pub fn zrem(&mut self, key: impl AsRef<str>, members: Vec<impl AsRef<str>>) -> Result<usize> {
+        let key = key.as_ref();
+        self.before_write(key);
+        self.check_type(key, KeyType::SortedSet)?;
+        let members = members
+            .iter()
+            .map(|m| m.as_ref().to_string())
+            .collect::<Vec<String>>();
+
+        let removed = self.zset.remove(key, members.clone());
+        self.sync_key(key, KeyType::SortedSet);
+        if removed > 0 {
+            self.propagate(|_| Command::ZRemove {
+                key: key.to_string(),
+                members,
+            })?;
+        }
+
+        return Ok(removed);
+    }
+
+    pub fn zcard(&self, key: impl AsRef<str>) -> Result<usize> {
+        self.check_type(key.as_ref(), KeyType::SortedSet)?;
+        if self.is_expired_key(key.as_ref()) {
+            return Ok(0);
+        }
+
+        return Ok(self.zset.card(key));
+    }
+
+    pub fn zscore(&self, key: impl AsRef<str>, member: impl AsRef<str>) -> Result<Option<f64>> {
+        self.check_type(key.as_ref(), KeyType::SortedSet)?;
+        if self.is_expired_key(key.as_ref()) {
+            return Ok(None);
+        }
+
+        return Ok(self.zset.score(key, member));
+    }
+
+    /// Zero based rank of the member, lowest score first
+    pub fn zrank(&self, key: impl AsRef<str>, member: impl AsRef<str>) -> Result<Option<usize>> {
+        return self.zset_rank(key.as_ref(), member.as_ref(), false);
+    }
+
+    /// Zero based rank of the member, highest score first
+    pub fn zrevrank(&self, key: impl AsRef<str>, member: impl AsRef<str>) -> Result<Option<usize>> {
+        return self.zset_rank(key.as_ref(), member.as_ref(), true);
+    }
+
+    fn zset_rank(&self, key: &str, member: &str, rev: bool) -> Result<Option<usize>> {
+        self.check_type(key, KeyType::SortedSet)?;
+        if self.is_expired_key(key) {
+            return Ok(None);
+        }
+
+        return Ok(self.zset.rank(key, member, rev));
+    }
+
+    /// Members from rank `start` to `stop` inclusive, negative ranks count back
+    /// from the end. With `rev` ranks count from the highest score.
+    pub fn zrange(
+        &self,
+        key: impl AsRef<str>,
+        start: isize,
+        stop: isize,
+        rev: bool,
+    ) -> Result<Vec<ScoredMember>> {
+        self.check_type(key.as_ref(), KeyType::SortedSet)?;
+        if self.is_expired_key(key.as_ref()) {
+            return Ok(vec![]);
+        }
+
+        return Ok(self.zset.range_by_rank(key, start, stop, rev));
+    }
+
+    /// Members with scores between `min` and `max`, highest first if `rev`.
+    ///
+    /// `limit` skips `offset` matches then returns at most `count`.
+    pub fn zrange_by_score(
+        &self,
+        key: impl AsRef<str>,
+        min: Bound<f64>,
+        max: Bound<f64>,
+        rev: bool,
+        limit: Option<(usize, usize)>,
+    ) -> Result<Vec<ScoredMember>> {
+        self.check_type(key.as_ref(), KeyType::SortedSet)?;
+        if self.is_expired_key(key.as_ref()) {
+            return Ok(vec![]);
+        }
+
+        return Ok(self.zset.range_by_score(key, min, max, rev, limit));
+    }
+
+    /// Members between `min` and `max` lexicographically, for sets where every
+    /// member has the same score
+    pub fn zrange_by_lex(
+        &self,
+        key: impl AsRef<str>,
+        min: Bound<String>,
+        max: Bound<String>,
+        rev: bool,
+        limit: Option<(usize, usize)>,
+    ) -> Result<Vec<String>> {
+        self.check_type(key.as_ref(), KeyType::SortedSet)?;
+        if self.is_expired_key(key.as_ref()) {
+            return Ok(vec![]);
+        }
+
+        return Ok(self.zset.range_by_lex(key, min, max, rev, limit));
+    }
+
+    /// Number of members with scores between `min` and `max`
+    pub fn zcount(&self, key: impl AsRef<str>, min: Bound<f64>, max: Bound<f64>) -> Result<usize> {
+        self.check_type(key.as_ref(), KeyType::SortedSet)?;
+        if self.is_expired_key(key.as_ref()) {
+            return Ok(0);
+        }
+
+        return Ok(self.zset.count(key, min, max));
+    }
+
+    /// Removes and returns up to `count` members with the lowest scores
+    pub fn zpopmin(&mut self, key: impl AsRef<str>, count: usize) -> Result<Vec<ScoredMember>> {
+        return self.zset_pop(key.as_ref(), count, false);
+    }
+
+    /// Removes and returns up to `count` members with the highest scores
+    pub fn zpopmax(&mut self, key: impl AsRef<str>, count: usize) -> Result<Vec<ScoredMember>> {
+        return self.zset_pop(key.as_ref(), count, true);
+    }
+
+    fn zset_pop(&mut self, key: &str, count: usize, rev: bool) -> Result<Vec<ScoredMember>> {
+        self.before_write(key);
+        self.check_type(key, KeyType::SortedSet)?;
+        let popped = self.zset.pop(key, count, rev);
+        self.sync_key(key, KeyType::SortedSet);
+        if !popped.is_empty() {
+            self.propagate(|_| Command::ZRemove {
+                key: key.to_string(),
+                members: popped
+                    .iter()
+                    .map(|(member, _)| member.clone())
+                    .collect::<Vec<String>>(),
+            })?;
+        }
+
+        return Ok(popped);
+    }
+
+    /// Union of the sorted sets at `keys`, see `SortedSetOperation::combine`
+    pub fn zunion(
+        &self,
+        keys: Vec<impl AsRef<str>>,
+        weights: Option<Vec<f64>>,
+        aggregate: Aggregate,
+    ) -> Result<Vec<ScoredMember>> {
+        return self.zset_combine(
+            SortedSetOperation::Union,
+            &keys,
+            weights.as_deref(),
+            aggregate,
+        );
+    }
+
+    /// Intersection of the sorted sets at `keys`, see `SortedSetOperation::combine`
+    pub fn zinter(
+        &self,
+        keys: Vec<impl AsRef<str>>,
+        weights: Option<Vec<f64>>,
+        aggregate: Aggregate,
+    ) -> Result<Vec<ScoredMember>> {
+        return self.zset_combine(
+            SortedSetOperation::Intersection,
+            &keys,
+            weights.as_deref(),
+            aggregate,
+        );
+    }
+
+    /// Stores the union of `keys` in `dst`, returning the size of the new set
+    pub fn zunionstore(
+        &mut self,
+        dst: impl AsRef<str>,
+        keys: Vec<impl AsRef<str>>,
+        weights: Option<Vec<f64>>,
+        aggregate: Aggregate,
+    ) -> Result<usize> {
+        return self.zset_combine_store(
+            SortedSetOperation::Union,
+            dst.as_ref(),
+            &keys,
+            weights.as_deref(),
+            aggregate,
+        );
+    }
+
+    /// Stores the intersection of `keys` in `dst`, returning the size of the new set
+    pub fn zinterstore(
+        &mut self,
+        dst: impl AsRef<str>,
+        keys: Vec<impl AsRef<str>>,
+        weights: Option<Vec<f64>>,
+        aggregate: Aggregate,
+    ) -> Result<usize> {
+        return self.zset_combine_store(
+            SortedSetOperation::Intersection,
+            dst.as_ref(),
+            &keys,
+            weights.as_deref(),
+            aggregate,
+        );
+    }
+
+    /// Combines the sorted sets at `keys` in score order, missing and expired
+    /// keys count as empty sets
+    pub(crate) fn zset_combine(
+        &self,
+        op: SortedSetOperation,
+        keys: &[impl AsRef<str>],
+        weights: Option<&[f64]>,
+        aggregate: Aggregate,
+    ) -> Result<Vec<ScoredMember>> {
+        let empty = SortedSet::new();
+        let mut sets = vec![];
+        for key in keys.iter() {
+            self.check_type(key.as_ref(), KeyType::SortedSet)?;
+            match self.is_expired_key(key.as_ref()) {
+                true => sets.push(&empty),
+                false => sets.push(self.zset.store.get(key.as_ref()).unwrap_or(&empty)),
+            }
+        }
+
+        let combined = op.combine(sets, weights, aggregate)?;
+
+        return Ok(combined.iter().collect::<Vec<ScoredMember>>());
+    }
+
+    /// Combines the sorted sets at `keys` into `dst`, overwriting whatever `dst` held
+    pub(crate) fn zset_combine_store(
+        &mut self,
+        op: SortedSetOperation,
+        dst: &str,
+        keys: &[impl AsRef<str>],
+        weights: Option<&[f64]>,
+        aggregate: Aggregate,
+    ) -> Result<usize> {
+        self.before_write(dst);
+        for key in keys.iter() {
+            self.expire_if_needed(key.as_ref());
+        }
+
+        for key in keys.iter() {
+            self.check_type(key.as_ref(), KeyType::SortedSet)?;
+        }
+
+        // Errors before touching `dst` if the weights don't match the keys
+        let size = self.zset.combine_store(op, dst, keys, weights, aggregate)?;
+        self.clear_other_types(dst, KeyType::SortedSet);
+        self.expires.remove(dst);
+        self.sync_key(dst, KeyType::SortedSet);
+        self.propagate(|_| Command::ZCombineStore {
+            op,
+            dst: dst.to_string(),
+            keys: keys
+                .iter()
+                .map(|k| k.as_ref().to_string())
+                .collect::<Vec<String>>(),
+            weights: weights.map(|w| w.to_vec()),
+            aggregate,
+        })?;
+
+        return Ok(size);
+    }
+
+    // Stream
+
+    /// Appends an entry to the stream, creating it if needed, then trims it if asked to.
+    ///
+    /// Returns the ID the entry was added with.
+    pub fn xadd(
+        &mut self,
+        key: impl AsRef<str>,
+        id: NewId,
+        fields: StreamFields,
+        trim: Option<TrimStrategy>,
+    ) -> Result<StreamId> {
+        let key = key.as_ref();
+        self.before_write(key);
+        self.check_type(key, KeyType::Stream)?;
+
+        let logged = self.aof.is_some().then(|| fields.clone());
+        let added = self.stream.add(key, id, fields, trim)?;
+        self.sync_key(key, KeyType::Stream);
+
+        // Logged with the generated ID so replaying doesn't pick a new one
+        self.propagate(|_| Command::StreamAdd {
+            key: key.to_string(),
+            id: NewId::Explicit(added),
+            fields: logged.unwrap_or_default(),
+            trim,
+        })?;
+
+        return Ok(added);
+    }
+
+    /// Trims the stream, returning how many entries were dropped
+    pub fn xtrim(&mut self, key: impl AsRef<str>, strategy: TrimStrategy) -> Result<usize> {
+        let key = key.as_ref();
+        self.before_write(key);
+        self.check_type(key, KeyType::Stream)?;
+        let trimmed = self.stream.trim(key, strategy);
+        if trimmed > 0 {
+            self.propagate(|_| Command::StreamTrim {
+                key: key.to_string(),
+                strategy,
+            })?;
+        }
+
+        return Ok(trimmed);
+    }
+
+    pub fn xlen(&self, key: impl AsRef<str>) -> Result<usize> {
+        self.check_type(key.as_ref(), KeyType::Stream)?;
+        if self.is_expired_key(key.as_ref()) {
+            return Ok(0);
+        }
+
+        return Ok(self.stream.len(key));
+    }
+
+    /// Up to `count` entries with IDs between `start` and `end`, oldest first
+    pub fn xrange(
+        &self,
+        key: impl AsRef<str>,
+        start: Bound<StreamId>,
+        end: Bound<StreamId>,
+        count: Option<usize>,
+    ) -> Result<Vec<StreamEntry>> {
+        return self.stream_range(key.as_ref(), start, end, count, false);
+    }
+
+    /// Up to `count` entries with IDs between `start` and `end`, newest first.
+    ///
+    /// Like Redis `XREVRANGE` the upper bound comes first.
+    pub fn xrevrange(
+        &self,
+        key: impl AsRef<str>,
+        end: Bound<StreamId>,
+        start: Bound<StreamId>,
+        count: Option<usize>,
+    ) -> Result<Vec<StreamEntry>> {
+        return self.stream_range(key.as_ref(), start, end, count, true);
+    }
+
+    fn stream_range(
+        &self,
+        key: &str,
+        start: Bound<StreamId>,
+        end: Bound<StreamId>,
+        count: Option<usize>,
+        rev: bool,
+    ) -> Result<Vec<StreamEntry>> {
+        self.check_type(key, KeyType::Stream)?;
+        if self.is_expired_key(key) {
+            return Ok(vec![]);
+        }
+
+        return Ok(self.stream.range(key, start, end, count, rev));
+    }
+
+    /// Up to `count` entries from each stream with IDs above the one given for
+    /// it, like Redis `XREAD`. Streams with nothing new are left out.
+    pub fn xread(
+        &self,
+        streams: Vec<(impl AsRef<str>, StreamId)>,
+        count: Option<usize>,
+    ) -> Result<Vec<(String, Vec<StreamEntry>)>> {
+        let mut read = vec![];
+        for (key, after) in streams.iter() {
+            self.check_type(key.as_ref(), KeyType::Stream)?;
+            if self.is_expired_key(key.as_ref()) {
+                continue;
+            }
+
+            let entries = self.stream.read(key, *after, count);
+            if !entries.is_empty() {
+                read.push((key.as_ref().to_string(), entries));
+            }
+        }
+
+        return Ok(read);
+    }
+
+    /// Creates a consumer group on the stream, see `StreamStore::create_group`
+    pub fn xgroup_create(
+        &mut self,
+        key: impl AsRef<str>,
+        group: impl AsRef<str>,
+        start: Option<StreamId>,
+        mkstream: bool,
+    ) -> Result<()> {
+        let (key, group) = (key.as_ref(), group.as_ref());
+        self.before_write(key);
+        self.check_type(key, KeyType::Stream)?;
+        let start = self.stream.create_group(key, group, start, mkstream)?;
+        self.sync_key(key, KeyType::Stream);
+        self.propagate(|_| Command::StreamGroupCreate {
+            key: key.to_string(),
+            group: group.to_string(),
+            start: Some(start),
+            mkstream,
+        })?;
+
+        return Ok(());
+    }
+
+    /// Removes the consumer group, returning whether it existed
+    pub fn xgroup_destroy(&mut self, key: impl AsRef<str>, group: impl AsRef<str>) -> Result<bool> {
+        let (key, group) = (key.as_ref(), group.as_ref());
+        self.before_write(key);
+        self.check_type(key, KeyType::Stream)?;
+        let destroyed = self.stream.destroy_group(key, group);
+        if destroyed {
+            self.propagate(|_| Command::StreamGroupDestroy {
+                key: key.to_string(),
+                group: group.to_string(),
+            })?;
+        }
+
+        return Ok(destroyed);
+    }
+
+    /// Reads entries as a consumer of the group, see `StreamStore::read_group`
+    pub fn xreadgroup(
+        &mut self,
+        key: impl AsRef<str>,
+        group: impl AsRef<str>,
+        consumer: impl AsRef<str>,
+        after: Option<StreamId>,
+        count: Option<usize>,
+        noack: bool,
+    ) -> Result<Vec<StreamEntry>> {
+        let (key, group, consumer) = (key.as_ref(), group.as_ref(), consumer.as_ref());
+        self.before_write(key);
+        self.check_type(key, KeyType::Stream)?;
+        let entries = self
+            .stream
+            .read_group(key, group, consumer, after, count, noack)?;
+
+        // Every read marks the consumer as seen, adding it if it's new, but only
+        // reads of new entries change what the group has delivered
+        let (ids, last_delivered) = match (after, entries.last()) {
+            (None, Some((last, _))) => match noack {
+                true => (vec![], *last),
+                false => (entries.iter().map(|(id, _)| *id).collect(), *last),
+            },
+            _ => (vec![], StreamId::MIN),
+        };
+
+        self.propagate(|s| Command::StreamDeliver {
+            key: key.to_string(),
+            group: group.to_string(),
+            consumer: consumer.to_string(),
+            ids,
+            delivered_at: s.stream.seen_at(key, group, consumer).unwrap_or_default(),
+            last_delivered,
+        })?;
+
+        return Ok(entries);
+    }
+
+    /// Acknowledges the entries, returning how many were pending
+    pub fn xack(
+        &mut self,
+        key: impl AsRef<str>,
+        group: impl AsRef<str>,
+        ids: Vec<StreamId>,
+    ) -> Result<usize> {
+        let (key, group) = (key.as_ref(), group.as_ref());
+        self.before_write(key);
+        self.check_type(key, KeyType::Stream)?;
+        let acked = self.stream.ack(key, group, &ids);
+        if acked > 0 {
+            self.propagate(|_| Command::StreamAck {
+                key: key.to_string(),
+                group: group.to_string(),
+                ids,
+            })?;
+        }
+
+        return Ok(acked);
+    }
+
+    /// The group's pending entries, see `StreamStore::pending`
+    pub fn xpending(
+        &self,
+        key: impl AsRef<str>,
+        group: impl AsRef<str>,
+        start: Bound<StreamId>,
+        end: Bound<StreamId>,
+        count: usize,
+        consumer: Option<&str>,
+    ) -> Result<Vec<PendingMessage>> {
+        self.check_type(key.as_ref(), KeyType::Stream)?;
+        if self.is_expired_key(key.as_ref()) {
+            return Err(GranatError::NoSuchGroup.into());
+        }
+
+        return self.stream.pending(key, group, start, end, count, consumer);
+    }
+
+    /// Claims pending entries idle for at least `min_idle` milliseconds, see `StreamStore::claim`
+    pub fn xclaim(
+        &mut self,
+        key: impl AsRef<str>,
+        group: impl AsRef<str>,
+        consumer: impl AsRef<str>,
+        min_idle: u64,
+        ids: Vec<StreamId>,
+    ) -> Result<Vec<StreamEntry>> {
+        let (key, group, consumer) = (key.as_ref(), group.as_ref(), consumer.as_ref());
+        self.before_write(key);
+        self.check_type(key, KeyType::Stream)?;
+        let claimed = self.stream.claim(key, group, consumer, min_idle, &ids)?;
+
+        // Logged even when nothing was claimed, the consumer was still seen
+        self.propagate(|s| Command::StreamDeliver {
+            key: key.to_string(),
+            group: group.to_string(),
+            consumer: consumer.to_string(),
+            ids: claimed.iter().map(|(id, _)| *id).collect::<Vec<StreamId>>(),
+            delivered_at: s.stream.seen_at(key, group, consumer).unwrap_or_default(),
+            last_delivered: StreamId::MIN,
+        })?;
+
+        return Ok(claimed);
+    }
+
+    pub fn xinfo_stream(&self, key: impl AsRef<str>) -> Result<Option<StreamInfo>> {
+        self.check_type(key.as_ref(), KeyType::Stream)?;
+        if self.is_expired_key(key.as_ref()) {
+            return Ok(None);
+        }
+
+        return Ok(self.stream.info(key));
+    }
+
+    pub fn xinfo_groups(&self, key: impl AsRef<str>) -> Result<Vec<GroupInfo>> {
+        self.check_type(key.as_ref(), KeyType::Stream)?;
+        if self.is_expired_key(key.as_ref()) {
+            return Ok(vec![]);
+        }
+
+        return Ok(self.stream.groups(key));
+    }
+
+    pub fn xinfo_consumers(
+        &self,
+        key: impl AsRef<str>,
+        group: impl AsRef<str>,
+    ) -> Result<Vec<ConsumerInfo>> {
+        self.check_type(key.as_ref(), KeyType::Stream)?;
+        if self.is_expired_key(key.as_ref()) {
+            return Err(GranatError::NoSuchGroup.into());
+        }
+
+        return self.stream.consumers(key, group);
+    }
+
+    /// Replays a logged delivery, see `StreamStore::deliver`
+    pub(crate) fn stream_deliver(
+        &mut self,
+        key: &str,
+        group: &str,
+        consumer: &str,
+        ids: Vec<StreamId>,
+        delivered_at: i64,
+        last_delivered: StreamId,
+    ) -> Result<()> {
+        self.before_write(key);
+        self.check_type(key, KeyType::Stream)?;
+        self.stream
+            .deliver(key, group, consumer, &ids, delivered_at, last_delivered)?;
+        self.propagate(|_| Command::StreamDeliver {
+            key: key.to_string(),
+            group: group.to_string(),
+            consumer: consumer.to_string(),
+            ids,
+            delivered_at,
+            last_delivered,
+        })?;
+
+        return Ok(());
+    }
+
+    /// Replaces whatever `key` held with `stream`
+    pub(crate) fn stream_restore(&mut self, key: &str, stream: Stream) -> Result<()> {
+        self.before_write(key);
+        self.clear_other_types(key, KeyType::Stream);
+        let logged = self.aof.is_some().then(|| stream.clone());
+        self.stream.store.insert(key.to_string(), stream);
+        self.sync_key(key, KeyType::Stream);
+        self.propagate(|_| Command::StreamRestore {
+            key: key.to_string(),
+            stream: logged.unwrap_or_default(),
+        })?;
+
+        return Ok(());
+    }
+
+    // HyperLogLog
+
+    /// Adds the elements to the HyperLogLog, returning whether its estimate may have changed.
+    ///
+    /// Creating the key counts as a change, even without any elements.
+    pub fn pfadd(&mut self, key: impl AsRef<str>, elements: Vec<impl AsRef<[u8]>>) -> Result<bool> {
+        let key = key.as_ref();
+        self.before_write(key);
+        self.check_type(key, KeyType::HyperLogLog)?;
+        let changed = self.hll.add(key, &elements);
+        self.sync_key(key, KeyType::HyperLogLog);
+
+        // Adds that changed nothing are left out, replaying them would be a no-op
+        if changed {
+            self.propagate(|_| Command::HllAdd {
+                key: key.to_string(),
+                elements: elements
+                    .iter()
+                    .map(|e| e.as_ref().into())
+                    .collect::<Vec<Value>>(),
+            })?;
+        }
+
+        return Ok(changed);
+    }
+
+    /// Estimated number of distinct elements added across all of `keys`.
+    ///
+    /// Missing and expired keys add nothing.
+    pub fn pfcount(&self, keys: Vec<impl AsRef<str>>) -> Result<u64> {
+        for key in keys.iter() {
+            self.check_type(key.as_ref(), KeyType::HyperLogLog)?;
+        }
+
+        let live = keys
+            .iter()
+            .filter(|k| !self.is_expired_key(k.as_ref()))
+            .collect::<Vec<_>>();
+
+        return Ok(self.hll.count(&live));
+    }
+
+    /// Merges the HyperLogLogs at `keys` into `dst`, keeping anything `dst` already held
+    pub fn pfmerge(&mut self, dst: impl AsRef<str>, keys: Vec<impl AsRef<str>>) -> Result<()> {
+        let dst = dst.as_ref();
+        self.before_write(dst);
+        for key in keys.iter() {
+            self.expire_if_needed(key.as_ref());
+        }
+
+        self.check_type(dst, KeyType::HyperLogLog)?;
+        for key in keys.iter() {
+            self.check_type(key.as_ref(), KeyType::HyperLogLog)?;
+        }
+
+        self.hll.merge(dst, &keys);
+        self.sync_key(dst, KeyType::HyperLogLog);
+        self.propagate(|_| Command::HllMerge {
+            dst: dst.to_string(),
+            keys: keys
+                .iter()
+                .map(|k| k.as_ref().to_string())
+                .collect::<Vec<String>>(),
+        })?;
+
+        return Ok(());
+    }
+
+    /// The HyperLogLog at `key`, used to merge across shards
+    pub(crate) fn hll_get(&self, key: &str) -> Result<Option<HyperLogLog>> {
+        self.check_type(key, KeyType::HyperLogLog)?;
+        if self.is_expired_key(key) {
+            return Ok(None);
+        }
+
+        return Ok(self.hll.store.get(key).cloned());
+    }
+
+    /// Replaces whatever `key` held with `hll`
+    pub(crate) fn hll_restore(&mut self, key: &str, hll: HyperLogLog) -> Result<()> {
+        self.before_write(key);
+        self.clear_other_types(key, KeyType::HyperLogLog);
+        let logged = self.aof.is_some().then(|| hll.clone());
+        self.hll.store.insert(key.to_string(), hll);
+        self.sync_key(key, KeyType::HyperLogLog);
+        self.propagate(|_| Command::HllRestore {
+            key: key.to_string(),
+            hll: logged.unwrap_or_default(),
+        })?;
+
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod granat_store_tests {
+    use super::*;
+    use crate::store::clock::MockClock;
+    use crate::store::entry::ExpiryState;
+    use std::sync::Arc;
+
+    fn create_kv(key: &str, value: &str) -> KVPair {
+        return (key.to_string(), StoreEntry::new(value));
+    }
+
+    fn create_expired_kv(key: &str, value: &str) -> KVPair {
+        let mut entry = StoreEntry::new(value);
+        entry.expiry = ExpiryState::Expired;
+
+        return (key.to_string(), entry);
+    }
+
+    fn is_wrong_type(err: &anyhow::Error) -> bool {
+        return err.downcast_ref::<GranatError>() == Some(&GranatError::WrongType);
+    }
+
+    #[test]
+    fn routes_to_general_store() {
+        let mut gs = GranatStore::new();
+        let _ = gs.set(create_kv("string", "value"));
+
+        let res = gs.get("string");
+        assert!(res.is_ok());
+        assert_eq!(res.unwrap().unwrap().value, "value".to_string());
+
+        let incr = gs.increment("counter", 5);
+        assert_eq!(incr.unwrap(), 5);
+        assert!(gs.get("missing").unwrap().is_none());
+    }
+
+    #[test]
+    fn bitmaps_on_strings() {
+        let clock = Arc::new(MockClock::new(0));
+        let mut gs = GranatStore::with_clock(clock.clone());
+        assert!(!gs.setbit("daily", 3, true).unwrap());
+        assert!(gs.setbit("daily", 3, true).unwrap());
+        assert_eq!(gs.type_of("daily"), Some(KeyType::String));
+        assert!(gs.getbit("daily", 3).unwrap());
+        assert_eq!(gs.bitcount("daily", None).unwrap(), 1);
+        assert_eq!(gs.bitpos("daily", true, None).unwrap(), 3);
+
+        // Reads don't create the key, writes that leave nothing behind don't either
+        let ty = BitFieldType::signed(8).unwrap();
+        let get = BitFieldOp::Get { ty, offset: 0 };
+        assert_eq!(gs.bitfield("missing", vec![get]).unwrap(), vec![Some(0)]);
+        assert_eq!(gs.type_of("missing"), None);
+
+        let ops = vec![
+            BitFieldOp::IncrBy {
+                ty,
+                offset: 8,
+                incr: -2,
+            },
+            get,
+        ];
+        assert_eq!(
+            gs.bitfield("daily", ops).unwrap(),
+            vec![Some(-2), Some(0x10)]
+        );
+        assert_eq!(gs.bitfield_ro("daily", vec![(ty, 8)]).unwrap(), vec![-2]);
+
+        // Writing bits keeps the string's expiry
+        let _ = gs.expire("daily", 10, ExpireCondition::Always);
+        let _ = gs.setbit("daily", 0, true);
+        assert_eq!(gs.ttl("daily"), 10);
+
+        let _ = gs.set(create_kv("other", "\x0f"));
+        let _ = gs.sadd("set", vec!["a"]);
+        assert_eq!(
+            gs.bitop(BitOperation::Xor, "set", vec!["daily", "other"])
+                .unwrap(),
+            2
+        );
+        assert_eq!(gs.type_of("set"), Some(KeyType::String));
+        assert_eq!(*gs.get("set").unwrap().unwrap().value, vec![0x9F, 0xFE]);
+        assert!(gs
+            .bitop(BitOperation::Not, "dst", vec!["daily", "other"])
+            .is_err());
+
+        let _ = gs.push_left(create_kv("list", "a"));
+        assert!(is_wrong_type(&gs.getbit("list", 0).unwrap_err()));
+        assert!(is_wrong_type(
+            &gs.bitop(BitOperation::And, "dst", vec!["set", "list"])
+                .unwrap_err()
+        ));
+        assert_eq!(gs.type_of("dst"), None);
+
+        clock.advance(Duration::from_secs(11));
+        assert_eq!(gs.bitcount("daily", None).unwrap(), 0);
+        assert_eq!(gs.bitpos("daily", false, None).unwrap(), 0);
+    }
+
+    #[test]
+    fn routes_to_list_store() {
+        let mut gs = GranatStore::new();
+        assert_eq!(gs.push_right(create_kv("list", "0")).unwrap(), 1);
+        assert_eq!(gs.push_right(create_kv("list", "1")).unwrap(), 2);
+        assert_eq!(gs.push_left(create_kv("list", "-1")).unwrap(), 3);
+
+        assert_eq!(gs.list_len("list").unwrap(), 3);
+        assert_eq!(
+            gs.index("list", 0).unwrap().unwrap().value,
+            "-1".to_string()
+        );
+
+        let popped = gs.pop_right("list").unwrap();
+        assert_eq!(popped.unwrap().value, "1".to_string());
+        assert_eq!(gs.range("list", 0, -1).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn routes_to_hash_store() {
+        let mut gs = GranatStore::new();
+        let fields = vec![create_kv("name", "granat"), create_kv("visits", "1")];
+        assert_eq!(gs.hset("user", fields).unwrap(), 2);
+        assert_eq!(gs.type_of("user"), Some(KeyType::Hash));
+        assert_eq!(gs.hlen("user").unwrap(), 2);
+        assert_eq!(
+            gs.hget("user", "name").unwrap().unwrap().value,
+            "granat".to_string()
+        );
+
+        assert!(!gs.hsetnx("user", create_kv("name", "other")).unwrap());
+        assert!(gs.hsetnx("user", create_kv("score", "0.5")).unwrap());
+        assert_eq!(gs.hincrby("user", "visits", 2).unwrap(), 3);
+        assert_eq!(gs.hincrbyfloat("user", "score", 0.25).unwrap(), 0.75);
+        assert!(gs.hexists("user", "score").unwrap());
+
+        let mut keys = gs.hkeys("user").unwrap();
+        keys.sort();
+        assert_eq!(keys, vec!["name", "score", "visits"]);
+        assert_eq!(gs.hvals("user").unwrap().len(), 3);
+        assert_eq!(gs.hgetall("user").unwrap().len(), 3);
+
+        assert_eq!(gs.hdel("user", vec!["name", "score", "visits"]).unwrap(), 3);
+        assert_eq!(gs.type_of("user"), None);
+        assert!(gs.hget("user", "name").unwrap().is_none());
+
+        let _ = gs.set(create_kv("string", "value"));
+        assert!(is_wrong_type(&gs.hget("string", "field").unwrap_err()));
+        assert!(is_wrong_type(
+            &gs.hset("string", vec![create_kv("field", "value")])
+                .unwrap_err()
+        ));
+        assert!(is_wrong_type(
+            &gs.hincrby("string", "field", 1).unwrap_err()
+        ));
+
+        let _ = gs.hset("hash", vec![create_kv("field", "value")]);
+        assert!(is_wrong_type(&gs.get("hash").unwrap_err()));
+        assert!(is_wrong_type(&gs.list_len("hash").unwrap_err()));
+    }
+
+    #[test]
+    fn hash_key_expiry() {
+        let clock = Arc::new(MockClock::new(0));
+        let mut gs = GranatStore::with_clock(clock.clone());
+        let _ = gs.hset("hash", vec![create_kv("field", "value")]);
+        assert!(gs.expire("hash", 10, ExpireCondition::Always));
+        assert_eq!(gs.ttl("hash"), 10);
+
+        let _ = gs.rename("hash", "renamed");
+        assert_eq!(gs.ttl("renamed"), 10);
+
+        clock.advance(Duration::from_secs(11));
+        assert_eq!(gs.type_of("renamed"), None);
+        assert!(gs.hget("renamed", "field").unwrap().is_none());
+        assert_eq!(gs.hlen("renamed").unwrap(), 0);
+    }
+
+    #[test]
+    fn routes_to_set_store() {
+        let mut gs = GranatStore::new();
+        assert_eq!(gs.sadd("tags", vec!["rust", "redis", "rust"]).unwrap(), 2);
+        assert_eq!(gs.type_of("tags"), Some(KeyType::Set));
+        assert_eq!(gs.scard("tags").unwrap(), 2);
+        assert!(gs.sismember("tags", "rust").unwrap());
+        assert!(!gs.sismember("missing", "rust").unwrap());
+        assert_eq!(gs.srandmember("tags", -3).unwrap().len(), 3);
+
+        assert_eq!(gs.srem("tags", vec!["redis", "go"]).unwrap(), 1);
+        assert_eq!(gs.smembers("tags").unwrap(), vec!["rust"]);
+        assert_eq!(gs.spop("tags", 2).unwrap(), vec!["rust"]);
+        assert_eq!(gs.type_of("tags"), None);
+
+        let _ = gs.set(create_kv("string", "value"));
+        assert!(is_wrong_type(&gs.sadd("string", vec!["a"]).unwrap_err()));
+        assert!(is_wrong_type(&gs.smembers("string").unwrap_err()));
+        assert!(is_wrong_type(
+            &gs.sunion(vec!["tags", "string"]).unwrap_err()
+        ));
+    }
+
+    #[test]
+    fn set_algebra_across_keys() {
+        let clock = Arc::new(MockClock::new(0));
+        let mut gs = GranatStore::with_clock(clock.clone());
+        let _ = gs.sadd("a", vec!["1", "2", "3"]);
+        let _ = gs.sadd("b", vec!["2", "3", "4"]);
+        let _ = gs.sadd("expiring", vec!["3"]);
+        let _ = gs.expire("expiring", 10, ExpireCondition::Always);
+
+        let mut union = gs.sunion(vec!["a", "b"]).unwrap();
+        union.sort();
+        assert_eq!(union, vec!["1", "2", "3", "4"]);
+        assert_eq!(gs.sdiff(vec!["a", "b", "expiring"]).unwrap(), vec!["1"]);
+
+        clock.advance(Duration::from_secs(11));
+        let mut diff = gs.sdiff(vec!["a", "b", "expiring"]).unwrap();
+        diff.sort();
+        assert_eq!(diff, vec!["1"]);
+        assert!(gs.sinter(vec!["a", "expiring"]).unwrap().is_empty());
+
+        // Storing overwrites any type and clears the old expiry
+        let _ = gs.set(create_kv("dst", "value"));
+        let _ = gs.expire("dst", 100, ExpireCondition::Always);
+        assert_eq!(gs.sinterstore("dst", vec!["a", "b"]).unwrap(), 2);
+        assert_eq!(gs.type_of("dst"), Some(KeyType::Set));
+        assert_eq!(gs.ttl("dst"), -1);
+
+        assert_eq!(gs.sunionstore("dst", vec!["dst", "missing"]).unwrap(), 2);
+        assert_eq!(gs.sdiffstore("dst", vec!["dst", "a"]).unwrap(), 0);
+        assert_eq!(gs.type_of("dst"), None);
+
+        let _ = gs.set(create_kv("string", "value"));
+        assert!(is_wrong_type(
+            &gs.sunionstore("dst", vec!["a", "string"]).unwrap_err()
+        ));
+    }
+
+    fn scored(members: &[(&str, f64)]) -> Vec<ScoredMember> {
+        return members
+            .iter()
+            .map(|(m, s)| (m.to_string(), *s))
+            .collect::<Vec<ScoredMember>>();
+    }
+
+    #[test]
+    fn routes_to_sorted_set_store() {
+        let mut gs = GranatStore::new();
+        let opts = AddOptions::default();
+        let added = gs.zadd("board", scored(&[("a", 1.), ("b", 2.), ("c", 3.)]), opts);
+        assert_eq!(added.unwrap(), 3);
+        assert_eq!(gs.type_of("board"), Some(KeyType::SortedSet));
+
+        // Updates only count towards the reply with `ch`
+        assert_eq!(gs.zadd("board", scored(&[("a", 5.)]), opts).unwrap(), 0);
+        let ch = AddOptions {
+            ch: true,
+            ..Default::default()
+        };
+        assert_eq!(gs.zadd("board", scored(&[("b", 6.)]), ch).unwrap(), 1);
+        assert_eq!(gs.zrank("board", "c").unwrap(), Some(0));
+        assert_eq!(gs.zrevrank("board", "c").unwrap(), Some(2));
+        assert_eq!(gs.zincrby("board", "c", 10.).unwrap(), 13.);
+        assert_eq!(gs.zscore("board", "c").unwrap(), Some(13.));
+        assert_eq!(
+            gs.zrange("board", 0, -1, false).unwrap(),
+            scored(&[("a", 5.), ("b", 6.), ("c", 13.)])
+        );
+        assert_eq!(
+            gs.zcount("board", Bound::Excluded(5.), Bound::Unbounded)
+                .unwrap(),
+            2
+        );
+
+        assert_eq!(gs.zpopmax("board", 1).unwrap(), scored(&[("c", 13.)]));
+        assert_eq!(gs.zrem("board", vec!["a", "missing"]).unwrap(), 1);
+        assert_eq!(gs.zpopmin("board", 5).unwrap(), scored(&[("b", 6.)]));
+        assert_eq!(gs.type_of("board"), None);
+        assert_eq!(gs.zcard("board").unwrap(), 0);
+
+        let _ = gs.set(create_kv("string", "value"));
+        assert!(is_wrong_type(
+            &gs.zadd("string", scored(&[("a", 1.)]), opts).unwrap_err()
+        ));
+        assert!(is_wrong_type(&gs.zscore("string", "a").unwrap_err()));
+    }
+
+    #[test]
+    fn sorted_set_algebra_across_keys() {
+        let clock = Arc::new(MockClock::new(0));
+        let mut gs = GranatStore::with_clock(clock.clone());
+        let opts = AddOptions::default();
+        let _ = gs.zadd("a", scored(&[("x", 1.), ("y", 2.)]), opts);
+        let _ = gs.zadd("b", scored(&[("y", 3.), ("z", 4.)]), opts);
+        let _ = gs.zadd("expiring", scored(&[("x", 10.)]), opts);
+        let _ = gs.expire("expiring", 10, ExpireCondition::Always);
+
+        let union = gs.zunion(vec!["a", "b", "expiring"], None, Aggregate::Sum);
+        assert_eq!(union.unwrap(), scored(&[("z", 4.), ("y", 5.), ("x", 11.)]));
+
+        clock.advance(Duration::from_secs(11));
+        let union = gs.zunion(vec!["a", "b", "expiring"], None, Aggregate::Max);
+        assert_eq!(union.unwrap(), scored(&[("x", 1.), ("y", 3.), ("z", 4.)]));
+        let inter = gs.zinter(vec!["a", "b"], Some(vec![10., 1.]), Aggregate::Sum);
+        assert_eq!(inter.unwrap(), scored(&[("y", 23.)]));
+
+        // Storing overwrites any type and clears the old expiry
+        let _ = gs.set(create_kv("dst", "value"));
+        let _ = gs.expire("dst", 100, ExpireCondition::Always);
+        let stored = gs.zinterstore("dst", vec!["a", "b"], None, Aggregate::Min);
+        assert_eq!(stored.unwrap(), 1);
+        assert_eq!(gs.type_of("dst"), Some(KeyType::SortedSet));
+        assert_eq!(gs.zscore("dst", "y").unwrap(), Some(2.));
+        assert_eq!(gs.ttl("dst"), -1);
+
+        let err = gs
+            .zunionstore("dst", vec!["a", "b"], Some(vec![1.]), Aggregate::Sum)
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<GranatError>(),
+            Some(&GranatError::WeightCount)
+        );
+        assert_eq!(gs.zcard("dst").unwrap(), 1);
+
+        let stored = gs.zunionstore("dst", vec!["dst", "missing"], None, Aggregate::Sum);
+        assert_eq!(stored.unwrap(), 1);
+        let stored = gs.zinterstore("dst", vec!["dst", "missing"], None, Aggregate::Sum);
+        assert_eq!(stored.unwrap(), 0);
+        assert_eq!(gs.type_of("dst"), None);
+    }
+
+    #[test]
+    fn routes_to_stream_store() {
+        let clock = Arc::new(MockClock::new(1000));
+        let mut gs = GranatStore::with_clock(clock.clone());
+        let fields = vec![("event".to_string(), "login".to_string())];
+        let id = gs
+            .xadd("events", NewId::Auto, fields.clone(), None)
+            .unwrap();
+        assert_eq!(id, StreamId::new(1000, 0));
+        assert_eq!(gs.type_of("events"), Some(KeyType::Stream));
+        assert_eq!(gs.xlen("events").unwrap(), 1);
+
+        let _ = gs.xadd("events", NewId::Auto, fields.clone(), None);
+        let all = (Bound::Unbounded, Bound::Unbounded);
+        let newest = gs.xrevrange("events", all.1, all.0, Some(1)).unwrap();
+        assert_eq!(newest[0].0, StreamId::new(1000, 1));
+        let read = gs.xread(vec![("events", id), ("missing", StreamId::MIN)], None);
+        assert_eq!(read.unwrap(), vec![("events".to_string(), newest)]);
+
+        let _ = gs.xgroup_create("events", "workers", Some(StreamId::MIN), false);
+        let read = gs
+            .xreadgroup("events", "workers", "a", None, None, false)
+            .unwrap();
+        assert_eq!(gs.xack("events", "workers", vec![read[0].0]).unwrap(), 1);
+        assert_eq!(
+            gs.xinfo_consumers("events", "workers").unwrap()[0].pending,
+            1
+        );
+
+        // An emptied stream keeps its key
+        assert_eq!(gs.xtrim("events", TrimStrategy::MaxLen(0)).unwrap(), 2);
+        assert_eq!(gs.type_of("events"), Some(KeyType::Stream));
+        assert_eq!(gs.xinfo_stream("events").unwrap().unwrap().length, 0);
+
+        let _ = gs.expire("events", 10, ExpireCondition::Always);
+        clock.advance(Duration::from_secs(11));
+        assert!(gs.xinfo_stream("events").unwrap().is_none());
+        let err = gs.xreadgroup("events", "workers", "a", None, None, false);
+        assert_eq!(
+            err.unwrap_err().downcast_ref::<GranatError>(),
+            Some(&GranatError::NoSuchGroup)
+        );
+
+        let _ = gs.set(create_kv("string", "value"));
+        assert!(is_wrong_type(
+            &gs.xadd("string", NewId::Auto, fields, None).unwrap_err()
+        ));
+        assert!(is_wrong_type(&gs.xlen("string").unwrap_err()));
+    }
+
+    #[test]
+    fn routes_to_hyperloglog_store() {
+        let clock = Arc::new(MockClock::new(1000));
+        let mut gs = GranatStore::with_clock(clock.clone());
+        assert!(gs.pfadd("a", vec!["x", "y", "z"]).unwrap());
+        assert!(!gs.pfadd("a", vec!["x"]).unwrap());
+        assert_eq!(gs.type_of("a"), Some(KeyType::HyperLogLog));
+        assert_eq!(gs.pfcount(vec!["a", "missing"]).unwrap(), 3);
+
+        let _ = gs.pfadd("b", vec!["z", "w"]);
+        assert!(gs.pfmerge("c", vec!["a", "b"]).is_ok());
+        assert_eq!(gs.pfcount(vec!["c"]).unwrap(), 4);
+        assert_eq!(gs.pfcount(vec!["a", "b"]).unwrap(), 4);
+
+        // Merging into an existing key keeps what it counted
+        let _ = gs.pfadd("d", vec!["v"]);
+        let _ = gs.pfmerge("d", vec!["c"]);
+        assert_eq!(gs.pfcount(vec!["d"]).unwrap(), 5);
+
+        let _ = gs.expire("a", 10, ExpireCondition::Always);
+        clock.advance(Duration::from_secs(11));
+        assert_eq!(gs.pfcount(vec!["a"]).unwrap(), 0);
+        assert!(gs.pfadd("a", Vec::<&str>::new()).unwrap());
+        assert_eq!(gs.ttl("a"), -1);
+
+        let _ = gs.set(create_kv("string", "value"));
+        assert!(is_wrong_type(&gs.pfadd("string", vec!["x"]).unwrap_err()));
+        assert!(is_wrong_type(&gs.pfcount(vec!["a", "string"]).unwrap_err()));
+        assert!(is_wrong_type(&gs.pfmerge("string", vec!["a"]).unwrap_err()));
+        assert!(is_wrong_type(&gs.pfmerge("a", vec!["string"]).unwrap_err()));
+    }
+
+    #[test]
+    fn wrong_type_errors() {
+        let mut gs = GranatStore::new();
+        let _ = gs.set(create_kv("string", "value"));
+        let _ = gs.push_right(create_kv("list", "value"));
+
+        let mut err = gs.push_left(create_kv("string", "value")).unwrap_err();
+        assert!(is_wrong_type(&err));
+
+        err = gs.pop_left("string").unwrap_err();
+        assert!(is_wrong_type(&err));
+
+        err = gs.get("list").unwrap_err();
+        assert!(is_wrong_type(&err));
+
+        err = gs.increment("list", 1).unwrap_err();
+        assert!(is_wrong_type(&err));
+
+        let results = gs.get_multiple(vec!["string", "list"]);
+        assert!(results[0].is_some());
+        assert!(results[1].is_none());
+    }
+
+    #[test]
+    fn set_overwrites_other_types() {
+        let mut gs = GranatStore::new();
+        let _ = gs.push_right(create_kv("key", "value"));
+        let _ = gs.set(create_kv("key", "replaced"));
+
+        assert_eq!(
+            gs.get("key").unwrap().unwrap().value,
+            "replaced".to_string()
+        );
+        assert!(gs.list_len("key").is_err());
+    }
+
+    #[test]
+    fn keyspace_tracks_types() {
+        let mut gs = GranatStore::new();
+        let _ = gs.set(create_kv("string", "value"));
+        let _ = gs.push_right(create_kv("list", "value"));
+
+        assert_eq!(gs.type_of("string"), Some(KeyType::String));
+        assert_eq!(gs.type_of("list"), Some(KeyType::List));
+        assert_eq!(gs.type_of("missing"), None);
+        assert_eq!(gs.exists(vec!["string", "list", "missing", "list"]), 3);
+
+        let _ = gs.pop_left("list");
+        assert_eq!(gs.type_of("list"), None);
+    }
+
+    #[test]
+    fn delete_keys() {
+        let mut gs = GranatStore::new();
+        let _ = gs.set(create_kv("string", "value"));
+        let _ = gs.push_right(create_kv("list", "value"));
+
+        assert_eq!(gs.del(vec!["string", "list", "missing"]), 2);
+        assert_eq!(gs.exists(vec!["string", "list"]), 0);
+        assert!(gs.get("string").unwrap().is_none());
+        assert_eq!(gs.list_len("list").unwrap(), 0);
+    }
+
+    #[test]
+    fn rename_keys() {
+        let mut gs = GranatStore::new();
+        let _ = gs.set(create_kv("string", "value"));
+        let _ = gs.push_right(create_kv("list", "value"));
+
+        assert!(gs.rename("list", "moved").is_ok());
+        assert_eq!(gs.type_of("moved"), Some(KeyType::List));
+        assert_eq!(gs.type_of("list"), None);
+
+        // Overwrites the destination whatever its type
+        assert!(gs.rename("string", "moved").is_ok());
+        assert_eq!(gs.type_of("moved"), Some(KeyType::String));
+        assert_eq!(gs.list_len("list").unwrap(), 0);
+
+        let err = gs.rename("missing", "other").unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<GranatError>(),
+            Some(&GranatError::NoSuchKey)
+        );
+
+        let _ = gs.set(create_kv("other", "value"));
+        assert!(!gs.renamenx("moved", "other").unwrap());
+        assert!(gs.renamenx("moved", "fresh").unwrap());
+        assert_eq!(gs.get("fresh").unwrap().unwrap().value, "value".to_string());
+    }
+
+    #[test]
+    fn keys_and_scan() {
+        let mut gs = GranatStore::new();
+        for i in 0..10 {
+            let _ = gs.set(create_kv(&format!("user:{i}"), "value"));
+        }
+        let _ = gs.push_right(create_kv("queue", "value"));
+
+        assert_eq!(gs.keys("user:*").len(), 10);
+        assert_eq!(gs.keys("*").len(), 11);
+        assert_eq!(gs.keys("user:[0-4]").len(), 5);
+
+        let mut found = vec![];
+        let mut cursor = None;
+        loop {
+            let (next, keys) = gs.scan(cursor.as_deref(), Some("user:*"), 3);
+            found.extend(keys);
+            cursor = next;
+            if cursor.is_none() {
+                break;
+            }
+        }
+
+        assert_eq!(found.len(), 10);
+    }
+
+    #[test]
+    fn scan_survives_deletes() {
+        let mut gs = GranatStore::new();
+        for key in ["a", "b", "c", "d"] {
+            let _ = gs.set(create_kv(key, "value"));
+        }
+
+        let (cursor, keys) = gs.scan(None, None, 2);
+        assert_eq!(keys, vec!["a", "b"]);
+
+        // Keys already returned going away doesn't shift the rest
+        gs.del(vec!["a"]);
+        let (cursor, keys) = gs.scan(cursor.as_deref(), None, 2);
+        assert_eq!(keys, vec!["c", "d"]);
+
+        let (cursor, keys) = gs.scan(cursor.as_deref(), None, 2);
+        assert!(keys.is_empty());
+        assert!(cursor.is_none());
+    }
+
+    #[test]
+    fn expired_keys_are_absent() {
+        let mut gs = GranatStore::new();
+        let _ = gs.set(create_expired_kv("string", "value"));
+        let _ = gs.push_right(create_expired_kv("list", "value"));
+
+        assert!(gs.get("string").unwrap().is_none());
+        assert_eq!(gs.list_len("list").unwrap(), 0);
+        assert_eq!(gs.type_of("string"), None);
+        assert_eq!(gs.exists(vec!["string", "list"]), 0);
+        assert!(gs.keys("*").is_empty());
+
+        // An expired key no longer holds its type
+        assert!(gs.push_right(create_kv("string", "value")).is_ok());
+        assert!(gs.increment("list", 1).is_ok());
+        assert_eq!(gs.type_of("string"), Some(KeyType::List));
+        assert_eq!(gs.type_of("list"), Some(KeyType::String));
+    }
+
+    #[test]
+    fn active_expiry_purges_keys() {
+        let mut gs = GranatStore::new();
+        for i in 0..50 {
+            let _ = gs.set(create_expired_kv(&format!("expired:{i}"), "value"));
+        }
+        for i in 0..10 {
+            let _ = gs.set(create_kv(&format!("live:{i}"), "value"));
+        }
+        let _ = gs.push_right(create_expired_kv("list", "value"));
+
+        let mut purged = 0;
+        while gs.keyspace.len() > 10 {
+            purged += gs.active_expire();
+        }
+
+        assert_eq!(purged, 51);
+        assert_eq!(gs.general.store.len(), 10);
+        assert!(gs.list.store.is_empty());
+    }
+
+    #[test]
+    fn key_level_expiry() {
+        let mut gs = GranatStore::new();
+        let _ = gs.set(create_kv("string", "value"));
+        let _ = gs.push_right(create_kv("list", "value"));
+
+        assert_eq!(gs.ttl("string"), -1);
+        assert_eq!(gs.pttl("missing"), -2);
+        assert!(!gs.expire("missing", 10, ExpireCondition::Always));
+
+        assert!(gs.expire("string", 100, ExpireCondition::Always));
+        assert!(gs.pexpire("list", 100_000, ExpireCondition::Always));
+        assert_eq!(gs.ttl("string"), 100);
+        assert_eq!(gs.ttl("list"), 100);
+        assert!(gs.pttl("list") > 99_000);
+
+        // Pushing onto the list keeps its expiry
+        let _ = gs.push_right(create_kv("list", "value"));
+        assert_eq!(gs.ttl("list"), 100);
+
+        assert!(gs.persist("list"));
+        assert!(!gs.persist("list"));
+        assert_eq!(gs.ttl("list"), -1);
+
+        // Setting a string replaces its expiry with the new entry's
+        let _ = gs.set(create_kv("string", "value"));
+        assert_eq!(gs.ttl("string"), -1);
+    }
+
+    #[test]
+    fn expiry_in_the_past_deletes() {
+        let mut gs = GranatStore::new();
+        let _ = gs.set(create_kv("string", "value"));
+        let _ = gs.push_right(create_kv("list", "value"));
+
+        assert!(gs.expire_at("string", 1, ExpireCondition::Always));
+        assert!(gs.pexpire("list", -1, ExpireCondition::Always));
+        assert_eq!(gs.exists(vec!["string", "list"]), 0);
+        assert!(gs.keyspace.is_empty());
+    }
+
+    #[test]
+    fn lapsed_list_deadline_hides_key() {
+        let clock = Arc::new(MockClock::starting_now());
+        let mut gs = GranatStore::with_clock(clock.clone());
+        let _ = gs.push_right(create_kv("list", "value"));
+        let _ = gs.push_right(create_kv("list", "value"));
+        assert!(gs.pexpire("list", 500, ExpireCondition::Always));
+        assert_eq!(gs.pttl("list"), 500);
+
+        clock.advance(Duration::from_millis(501));
+
+        assert_eq!(gs.list_len("list").unwrap(), 0);
+        assert!(gs.range("list", 0, -1).unwrap().is_empty());
+        assert_eq!(gs.pttl("list"), -2);
+
+        // Writes start from an empty list without the old deadline
+        assert_eq!(gs.push_right(create_kv("list", "value")).unwrap(), 1);
+        assert_eq!(gs.ttl("list"), -1);
+    }
+
+    #[test]
+    fn conditional_expiry() {
+        let mut gs = GranatStore::new();
+        let _ = gs.set(create_kv("key", "value"));
+
+        assert!(!gs.expire("key", 100, ExpireCondition::Xx));
+        assert!(!gs.expire("key", 100, ExpireCondition::Gt));
+        assert!(gs.expire("key", 100, ExpireCondition::Nx));
+        assert!(!gs.expire("key", 200, ExpireCondition::Nx));
+
+        assert!(gs.expire("key", 200, ExpireCondition::Gt));
+        assert!(!gs.expire("key", 150, ExpireCondition::Gt));
+        assert_eq!(gs.ttl("key"), 200);
+
+        assert!(gs.expire("key", 50, ExpireCondition::Lt));
+        assert!(!gs.expire("key", 150, ExpireCondition::Lt));
+        assert!(gs.expire("key", 75, ExpireCondition::Xx));
+        assert_eq!(gs.ttl("key"), 75);
+
+        assert!(gs.persist("key"));
+        assert!(gs.expire("key", 10, ExpireCondition::Lt));
+    }
+
+    #[test]
+    fn lmove_between_lists() {
+        let mut gs = GranatStore::new();
+        let _ = gs.push_right(create_kv("pending", "a"));
+        let _ = gs.push_right(create_kv("pending", "b"));
+        let _ = gs.expire("pending", 100, ExpireCondition::Always);
+
+        let moved = gs.rpoplpush("pending", "processing").unwrap();
+        assert_eq!(moved.unwrap().value, "b".to_string());
+        assert_eq!(gs.type_of("processing"), Some(KeyType::List));
+        assert_eq!(gs.ttl("processing"), -1);
+
+        let moved = gs.lmove(
+            "pending",
+            "processing",
+            ListDirection::Left,
+            ListDirection::Right,
+        );
+        assert_eq!(moved.unwrap().unwrap().value, "a".to_string());
+        assert_eq!(gs.type_of("pending"), None);
+        assert_eq!(gs.ttl("pending"), -2);
+        assert!(gs.rpoplpush("pending", "processing").unwrap().is_none());
+
+        let _ = gs.set(create_kv("string", "value"));
+        assert!(is_wrong_type(
+            &gs.rpoplpush("processing", "string").unwrap_err()
+        ));
+        assert!(is_wrong_type(
+            &gs.rpoplpush("string", "processing").unwrap_err()
+        ));
+        assert_eq!(gs.list_len("processing").unwrap(), 2);
+    }
+
+    #[test]
+    fn push_many_and_if_exists() {
+        let mut gs = GranatStore::new();
+        let values = vec![StoreEntry::new("a"), StoreEntry::new("b")];
+
+        assert_eq!(gs.push_right_if_exists("queue", values.clone()).unwrap(), 0);
+        assert_eq!(gs.type_of("queue"), None);
+
+        assert_eq!(gs.push_right_many("queue", values.clone()).unwrap(), 2);
+        assert_eq!(gs.type_of("queue"), Some(KeyType::List));
+        assert_eq!(gs.push_left_if_exists("queue", values.clone()).unwrap(), 4);
+        let values = gs
+            .range("queue", 0, -1)
+            .unwrap()
+            .into_iter()
+            .map(|e| e.value.to_string())
+            .collect::<Vec<String>>();
+        assert_eq!(values, vec!["b", "a", "a", "b"]);
+
+        let _ = gs.del(vec!["queue"]);
+        assert_eq!(
+            gs.push_left_if_exists("queue", vec![StoreEntry::new("c")])
+                .unwrap(),
+            0
+        );
+        assert_eq!(gs.type_of("queue"), None);
+
+        let _ = gs.set(create_kv("string", "value"));
+        assert!(is_wrong_type(
+            &gs.push_left_many("string", vec![StoreEntry::new("x")])
+                .unwrap_err()
+        ));
+        assert!(is_wrong_type(
+            &gs.push_right_if_exists("string", vec![StoreEntry::new("x")])
+                .unwrap_err()
+        ));
+    }
+
+    #[test]
+    fn pop_many_and_position() {
+        let mut gs = GranatStore::new();
+        for value in ["a", "b", "a", "c"] {
+            let _ = gs.push_right(create_kv("list", value));
+        }
+
+        assert_eq!(gs.position("list", "a", 1, 0, 0).unwrap(), vec![0, 2]);
+        assert!(gs.position("missing", "a", 1, 0, 0).unwrap().is_empty());
+
+        let popped = gs.pop_right_n("list", 2).unwrap();
+        assert_eq!(popped, vec![StoreEntry::new("c"), StoreEntry::new("a")]);
+        let popped = gs.pop_left_n("list", 5).unwrap();
+        assert_eq!(popped, vec![StoreEntry::new("a"), StoreEntry::new("b")]);
+        assert_eq!(gs.type_of("list"), None);
+
+        let _ = gs.set(create_kv("string", "value"));
+        assert!(is_wrong_type(&gs.pop_left_n("string", 1).unwrap_err()));
+        assert!(is_wrong_type(
+            &gs.position("string", "value", 1, 0, 0).unwrap_err()
+        ));
+    }
+
+    #[test]
+    fn list_set_and_insert() {
+        let mut gs = GranatStore::new();
+        let err = gs.list_set(create_kv("list", "value"), 0).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<GranatError>(),
+            Some(&GranatError::NoSuchKey)
+        );
+        assert_eq!(gs.type_of("list"), None);
+
+        assert_eq!(gs.insert_at(create_kv("list", "b"), 0).unwrap(), 1);
+        assert_eq!(gs.insert_at(create_kv("list", "d"), -1).unwrap(), 2);
+        assert_eq!(gs.type_of("list"), Some(KeyType::List));
+        assert!(gs.list_set(create_kv("list", "c"), -1).is_ok());
+        assert_eq!(
+            gs.linsert("list", InsertPosition::Before, "b", StoreEntry::new("a"))
+                .unwrap(),
+            3
+        );
+
+        let values = gs
+            .range("list", 0, -1)
+            .unwrap()
+            .into_iter()
+            .map(|e| e.value.to_string())
+            .collect::<Vec<String>>();
+        assert_eq!(values, vec!["a", "b", "c"]);
+
+        let _ = gs.set(create_kv("string", "value"));
+        assert!(is_wrong_type(
+            &gs.insert_at(create_kv("string", "x"), 0).unwrap_err()
+        ));
+        assert!(is_wrong_type(
+            &gs.linsert("string", InsertPosition::After, "x", StoreEntry::new("y"))
+                .unwrap_err()
+        ));
+    }
+
+    #[test]
+    fn rename_keeps_expiry() {
+        let mut gs = GranatStore::new();
+        let _ = gs.push_right(create_kv("list", "value"));
+        let _ = gs.expire("list", 100, ExpireCondition::Always);
+
+        let _ = gs.rename("list", "moved");
+        assert_eq!(gs.ttl("moved"), 100);
+        assert_eq!(gs.ttl("list"), -2);
+    }
+
+    #[test]
+    fn expiry_follows_the_store_clock() {
+        let clock = Arc::new(MockClock::starting_now());
+        let mut gs = GranatStore::with_clock(clock.clone());
+
+        let entry = StoreEntry::new("value").expires_in_with(30, gs.clock().as_ref());
+        let _ = gs.set(("session".to_string(), entry));
+        let _ = gs.push_right(create_kv("queue", "value"));
+        let _ = gs.expire("queue", 60, ExpireCondition::Always);
+
+        clock.advance(Duration::from_secs(20));
+        assert_eq!(gs.ttl("session"), 10);
+        assert_eq!(gs.ttl("queue"), 40);
+
+        clock.advance(Duration::from_secs(11));
+        assert!(gs.get("session").unwrap().is_none());
+        assert_eq!(gs.list_len("queue").unwrap(), 1);
+
+        clock.advance(Duration::from_secs(30));
+        assert_eq!(gs.list_len("queue").unwrap(), 0);
+        assert_eq!(gs.active_expire(), 2);
+        assert!(gs.keyspace.is_empty());
     }
 }
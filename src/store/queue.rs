@@ -0,0 +1,1167 @@
+use anyhow::{anyhow, Result};
+
+use std::future::Future;
+use std::ops::Bound;
+use std::pin::Pin;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Condvar, Mutex, RwLock, RwLockReadGuard};
+use std::task::{Context, Poll, Waker};
+use std::thread::{self, JoinHandle};
+
+use crate::store::bitmap::{BitFieldOp, BitFieldType, BitOperation, BitRange};
+use crate::store::command::{Command, Reply};
+use crate::store::entry::{StoreEntry, Value};
+use crate::store::keyspace::{ExpireCondition, KeyType};
+use crate::store::list::{InsertPosition, ListDirection};
+use crate::store::set::SetOperation;
+use crate::store::sorted_set::{AddOptions, Aggregate, ScoredMember, SortedSetOperation};
+use crate::store::stream::{
+    ConsumerInfo, GroupInfo, NewId, PendingMessage, StreamEntry, StreamFields, StreamId,
+    StreamInfo, TrimStrategy,
+};
+use crate::store::{GranatStore, KVPair, ACTIVE_EXPIRE_INTERVAL};
+
+#[derive(Default)]
+struct TicketState {
+    result: Option<Result<Reply>>,
+    waker: Option<Waker>,
+
+    /// Set once the result has been handed out
+    taken: bool,
+}
+
+#[derive(Default)]
+struct Completion {
+    state: Mutex<TicketState>,
+    ready: Condvar,
+}
+
+impl Completion {
+    fn complete(&self, result: Result<Reply>) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.result = Some(result);
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+
+        self.ready.notify_all();
+    }
+}
+
+/// Completes once a queued write has been applied, with what it returned.
+///
+/// Block on it with `wait`, `.await` it, or drop it if the result doesn't matter,
+/// the write is applied either way.
+pub struct Ticket {
+    completion: Arc<Completion>,
+}
+
+impl Ticket {
+    fn new() -> (Self, Arc<Completion>) {
+        let completion = Arc::new(Completion::default());
+        let ticket = Self {
+            completion: completion.clone(),
+        };
+
+        return (ticket, completion);
+    }
+
+    /// Whether the write has been applied
+    pub fn is_done(&self) -> bool {
+        let state = self
+            .completion
+            .state
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        return state.result.is_some() || state.taken;
+    }
+
+    /// Blocks until the write has been applied
+    pub fn wait(self) -> Result<Reply> {
+        let mut state = self
+            .completion
+            .state
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        loop {
+            if let Some(result) = state.result.take() {
+                state.taken = true;
+                return result;
+            }
+
+            if state.taken {
+                return Err(anyhow!("ticket result already taken"));
+            }
+
+            state = self
+                .completion
+                .ready
+                .wait(state)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+    }
+}
+
+impl Future for Ticket {
+    type Output = Result<Reply>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self
+            .completion
+            .state
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        if let Some(result) = state.result.take() {
+            state.taken = true;
+            return Poll::Ready(result);
+        }
+
+        if state.taken {
+            return Poll::Ready(Err(anyhow!("ticket result already taken")));
+        }
+
+        state.waker = Some(cx.waker().clone());
+
+        return Poll::Pending;
+    }
+}
+
+/// A queued write, `None` is a flush barrier
+struct Job {
+    cmd: Option<Command>,
+    completion: Arc<Completion>,
+}
+
+impl Drop for Job {
+    /// Fails the ticket if the job goes away unapplied, such as when the worker
+    /// panics, so nobody waits on it forever
+    fn drop(&mut self) {
+        let done = {
+            let state = self
+                .completion
+                .state
+                .lock()
+                .unwrap_or_else(|e| e.into_inner());
+            state.result.is_some() || state.taken
+        };
+
+        if !done {
+            self.completion.complete(Err(anyhow!(
+                "write queue worker stopped before applying the write"
+            )));
+        }
+    }
+}
+
+/// A store whose writes are queued and applied in order by a worker thread.
+///
+/// Writes return straight away with a `Ticket` for their result, reads go to the
+/// store directly so they're eventually consistent, a read only sees a write once
+/// its ticket has completed. Use `flush` to wait for everything queued so far.
+/// Between writes the worker runs the store's housekeeping, see `GranatStore::tick`.
+///
+/// Dropping the store waits for the queued writes to be applied.
+pub struct QueuedStore {
+    store: Arc<RwLock<GranatStore>>,
+    sender: Option<Sender<Job>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl Default for QueuedStore {
+    fn default() -> Self {
+        return Self::new(GranatStore::new());
+    }
+}
+
+impl QueuedStore {
+    /// Hands `store` over to a new worker thread
+    pub fn new(store: GranatStore) -> Self {
+        let store = Arc::new(RwLock::new(store));
+        let (sender, receiver) = mpsc::channel::<Job>();
+
+        let worker_store = store.clone();
+        let worker = thread::spawn(move || loop {
+            match receiver.recv_timeout(ACTIVE_EXPIRE_INTERVAL) {
+                Ok(mut job) => {
+                    let result = match job.cmd.take() {
+                        Some(cmd) => {
+                            let mut store = worker_store.write().unwrap_or_else(|e| e.into_inner());
+                            cmd.execute(&mut store)
+                        }
+                        None => Ok(Reply::Ok),
+                    };
+
+                    job.completion.complete(result);
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    worker_store
+                        .write()
+                        .unwrap_or_else(|e| e.into_inner())
+                        .tick();
+                }
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        });
+
+        return Self {
+            store,
+            sender: Some(sender),
+            worker: Some(worker),
+        };
+    }
+
+    fn enqueue(&self, cmd: Option<Command>) -> Ticket {
+        let (ticket, completion) = Ticket::new();
+        let job = Job {
+            cmd,
+            completion: completion.clone(),
+        };
+
+        let sent = self.sender.as_ref().is_some_and(|s| s.send(job).is_ok());
+        if !sent {
+            completion.complete(Err(anyhow!("write queue worker has stopped")));
+        }
+
+        return ticket;
+    }
+
+    /// Queues `cmd` to be applied after everything already queued
+    pub fn submit(&self, cmd: Command) -> Ticket {
+        return self.enqueue(Some(cmd));
+    }
+
+    /// Returns a ticket that completes once every write queued before it has been applied
+    pub fn flush(&self) -> Ticket {
+        return self.enqueue(None);
+    }
+
+    /// Runs `f` against the store as it is now, without waiting for queued writes
+    pub fn read<T>(&self, f: impl FnOnce(&GranatStore) -> T) -> T {
+        let store = self.read_store();
+        return f(&store);
+    }
+
+    fn read_store(&self) -> RwLockReadGuard<'_, GranatStore> {
+        return self.store.read().unwrap_or_else(|e| e.into_inner());
+    }
+
+    // Reads
+
+    pub fn type_of(&self, key: impl AsRef<str>) -> Option<KeyType> {
+        return self.read_store().type_of(key);
+    }
+
+    pub fn exists(&self, keys: Vec<impl AsRef<str>>) -> usize {
+        return self.read_store().exists(keys);
+    }
+
+    pub fn keys(&self, pattern: impl AsRef<str>) -> Vec<String> {
+        return self.read_store().keys(pattern);
+    }
+
+    pub fn ttl(&self, key: impl AsRef<str>) -> i64 {
+        return self.read_store().ttl(key);
+    }
+
+    pub fn get(&self, key: impl AsRef<str>) -> Result<Option<StoreEntry>> {
+        return self.read_store().get(key);
+    }
+
+    pub fn get_multiple(&self, keys: Vec<impl AsRef<str>>) -> Vec<Option<StoreEntry>> {
+        return self.read_store().get_multiple(keys);
+    }
+
+    pub fn getbit(&self, key: impl AsRef<str>, offset: u64) -> Result<bool> {
+        return self.read_store().getbit(key, offset);
+    }
+
+    pub fn bitcount(&self, key: impl AsRef<str>, range: Option<BitRange>) -> Result<usize> {
+        return self.read_store().bitcount(key, range);
+    }
+
+    pub fn bitpos(&self, key: impl AsRef<str>, bit: bool, range: Option<BitRange>) -> Result<i64> {
+        return self.read_store().bitpos(key, bit, range);
+    }
+
+    pub fn bitfield_ro(
+        &self,
+        key: impl AsRef<str>,
+        fields: Vec<(BitFieldType, u64)>,
+    ) -> Result<Vec<i64>> {
+        return self.read_store().bitfield_ro(key, fields);
+    }
+
+    pub fn index(&self, key: impl AsRef<str>, idx: isize) -> Result<Option<StoreEntry>> {
+        return self.read_store().index(key, idx);
+    }
+
+    pub fn position(
+        &self,
+        key: impl AsRef<str>,
+        value: impl AsRef<[u8]>,
+        rank: isize,
+        count: usize,
+        maxlen: usize,
+    ) -> Result<Vec<usize>> {
+        return self.read_store().position(key, value, rank, count, maxlen);
+    }
+
+    pub fn list_len(&self, key: impl AsRef<str>) -> Result<usize> {
+        return self.read_store().list_len(key);
+    }
+
+    pub fn range(&self, key: impl AsRef<str>, start: isize, end: isize) -> Result<Vec<StoreEntry>> {
+        return self.read_store().range(key, start, end);
+    }
+
+    pub fn hget(&self, key: impl AsRef<str>, field: impl AsRef<str>) -> Result<Option<StoreEntry>> {
+        return self.read_store().hget(key, field);
+    }
+
+    pub fn hexists(&self, key: impl AsRef<str>, field: impl AsRef<str>) -> Result<bool> {
+        return self.read_store().hexists(key, field);
+    }
+
+    pub fn hlen(&self, key: impl AsRef<str>) -> Result<usize> {
+        return self.read_store().hlen(key);
+    }
+
+    pub fn hkeys(&self, key: impl AsRef<str>) -> Result<Vec<String>> {
+        return self.read_store().hkeys(key);
+    }
+
+    pub fn hvals(&self, key: impl AsRef<str>) -> Result<Vec<StoreEntry>> {
+        return self.read_store().hvals(key);
+    }
+
+    pub fn hgetall(&self, key: impl AsRef<str>) -> Result<Vec<KVPair>> {
+        return self.read_store().hgetall(key);
+    }
+
+    pub fn sismember(&self, key: impl AsRef<str>, member: impl AsRef<str>) -> Result<bool> {
+        return self.read_store().sismember(key, member);
+    }
+
+    pub fn smembers(&self, key: impl AsRef<str>) -> Result<Vec<String>> {
+        return self.read_store().smembers(key);
+    }
+
+    pub fn scard(&self, key: impl AsRef<str>) -> Result<usize> {
+        return self.read_store().scard(key);
+    }
+
+    pub fn srandmember(&self, key: impl AsRef<str>, count: isize) -> Result<Vec<String>> {
+        return self.read_store().srandmember(key, count);
+    }
+
+    pub fn sunion(&self, keys: Vec<impl AsRef<str>>) -> Result<Vec<String>> {
+        return self.read_store().sunion(keys);
+    }
+
+    pub fn sinter(&self, keys: Vec<impl AsRef<str>>) -> Result<Vec<String>> {
+        return self.read_store().sinter(keys);
+    }
+
+    pub fn sdiff(&self, keys: Vec<impl AsRef<str>>) -> Result<Vec<String>> {
+        return self.read_store().sdiff(keys);
+    }
+
+    pub fn zcard(&self, key: impl AsRef<str>) -> Result<usize> {
+        return self.read_store().zcard(key);
+    }
+
+    pub fn zscore(&self, key: impl AsRef<str>, member: impl AsRef<str>) -> Result<Option<f64>> {
+        return self.read_store().zscore(key, member);
+    }
+
+    pub fn zrank(&self, key: impl AsRef<str>, member: impl AsRef<str>) -> Result<Option<usize>> {
+        return self.read_store().zrank(key, member);
+    }
+
+    pub fn zrevrank(&self, key: impl AsRef<str>, member: impl AsRef<str>) -> Result<Option<usize>> {
+        return self.read_store().zrevrank(key, member);
+    }
+
+    pub fn zrange(
+        &self,
+        key: impl AsRef<str>,
+        start: isize,
+        stop: isize,
+        rev: bool,
+    ) -> Result<Vec<ScoredMember>> {
+        return self.read_store().zrange(key, start, stop, rev);
+    }
+
+    pub fn zrange_by_score(
+        &self,
+        key: impl AsRef<str>,
+        min: Bound<f64>,
+        max: Bound<f64>,
+        rev: bool,
+        limit: Option<(usize, usize)>,
+    ) -> Result<Vec<ScoredMember>> {
+        return self.read_store().zrange_by_score(key, min, max, rev, limit);
+    }
+
+    pub fn zrange_by_lex(
+        &self,
+        key: impl AsRef<str>,
+        min: Bound<String>,
+        max: Bound<String>,
+        rev: bool,
+        limit: Option<(usize, usize)>,
+    ) -> Result<Vec<String>> {
+        return self.read_store().zrange_by_lex(key, min, max, rev, limit);
+    }
+
+    pub fn zcount(&self, key: impl AsRef<str>, min: Bound<f64>, max: Bound<f64>) -> Result<usize> {
+        return self.read_store().zcount(key, min, max);
+    }
+
+    pub fn zunion(
+        &self,
+        keys: Vec<impl AsRef<str>>,
+        weights: Option<Vec<f64>>,
+        aggregate: Aggregate,
+    ) -> Result<Vec<ScoredMember>> {
+        return self.read_store().zunion(keys, weights, aggregate);
+    }
+
+    pub fn zinter(
+        &self,
+        keys: Vec<impl AsRef<str>>,
+        weights: Option<Vec<f64>>,
+        aggregate: Aggregate,
+    ) -> Result<Vec<ScoredMember>> {
+        return self.read_store().zinter(keys, weights, aggregate);
+    }
+
+    pub fn xlen(&self, key: impl AsRef<str>) -> Result<usize> {
+        return self.read_store().xlen(key);
+    }
+
+    pub fn xrange(
+        &self,
+        key: impl AsRef<str>,
+        start: Bound<StreamId>,
+        end: Bound<StreamId>,
+        count: Option<usize>,
+    ) -> Result<Vec<StreamEntry>> {
+        return self.read_store().xrange(key, start, end, count);
+    }
+
+    pub fn xrevrange(
+        &self,
+        key: impl AsRef<str>,
+        end: Bound<StreamId>,
+        start: Bound<StreamId>,
+        count: Option<usize>,
+    ) -> Result<Vec<StreamEntry>> {
+        return self.read_store().xrevrange(key, end, start, count);
+    }
+
+    pub fn xread(
+        &self,
+        streams: Vec<(impl AsRef<str>, StreamId)>,
+        count: Option<usize>,
+    ) -> Result<Vec<(String, Vec<StreamEntry>)>> {
+        return self.read_store().xread(streams, count);
+    }
+
+    pub fn xpending(
+        &self,
+        key: impl AsRef<str>,
+        group: impl AsRef<str>,
+        start: Bound<StreamId>,
+        end: Bound<StreamId>,
+        count: usize,
+        consumer: Option<&str>,
+    ) -> Result<Vec<PendingMessage>> {
+        return self
+            .read_store()
+            .xpending(key, group, start, end, count, consumer);
+    }
+
+    pub fn xinfo_stream(&self, key: impl AsRef<str>) -> Result<Option<StreamInfo>> {
+        return self.read_store().xinfo_stream(key);
+    }
+
+    pub fn xinfo_groups(&self, key: impl AsRef<str>) -> Result<Vec<GroupInfo>> {
+        return self.read_store().xinfo_groups(key);
+    }
+
+    pub fn xinfo_consumers(
+        &self,
+        key: impl AsRef<str>,
+        group: impl AsRef<str>,
+    ) -> Result<Vec<ConsumerInfo>> {
+        return self.read_store().xinfo_consumers(key, group);
+    }
+
+    pub fn pfcount(&self, keys: Vec<impl AsRef<str>>) -> Result<u64> {
+        return self.read_store().pfcount(keys);
+    }
+
+    // Writes
+
+    pub fn set(&self, kv: KVPair) -> Ticket {
+        return self.submit(Command::Set {
+            key: kv.0,
+            entry: kv.1,
+        });
+    }
+
+    pub fn set_multiple(&self, kvs: Vec<KVPair>) -> Ticket {
+        return self.submit(Command::SetMultiple { pairs: kvs });
+    }
+
+    pub fn increment(&self, key: impl AsRef<str>, incr: i64) -> Ticket {
+        return self.submit(Command::Increment {
+            key: key.as_ref().to_string(),
+            incr,
+        });
+    }
+
+    pub fn increment_float(&self, key: impl AsRef<str>, incr: f64) -> Ticket {
+        return self.submit(Command::IncrementFloat {
+            key: key.as_ref().to_string(),
+            incr,
+        });
+    }
+
+    pub fn setbit(&self, key: impl AsRef<str>, offset: u64, bit: bool) -> Ticket {
+        return self.submit(Command::SetBit {
+            key: key.as_ref().to_string(),
+            offset,
+            bit,
+        });
+    }
+
+    pub fn bitop(
+        &self,
+        op: BitOperation,
+        dst: impl AsRef<str>,
+        keys: Vec<impl AsRef<str>>,
+    ) -> Ticket {
+        return self.submit(Command::BitOp {
+            op,
+            dst: dst.as_ref().to_string(),
+            keys: keys
+                .iter()
+                .map(|k| k.as_ref().to_string())
+                .collect::<Vec<String>>(),
+        });
+    }
+
+    pub fn bitfield(&self, key: impl AsRef<str>, ops: Vec<BitFieldOp>) -> Ticket {
+        return self.submit(Command::BitField {
+            key: key.as_ref().to_string(),
+            ops,
+        });
+    }
+
+    pub fn push_left(&self, kv: KVPair) -> Ticket {
+        return self.submit(Command::PushLeft {
+            key: kv.0,
+            entry: kv.1,
+        });
+    }
+
+    pub fn push_right(&self, kv: KVPair) -> Ticket {
+        return self.submit(Command::PushRight {
+            key: kv.0,
+            entry: kv.1,
+        });
+    }
+
+    pub fn pop_left(&self, key: impl AsRef<str>) -> Ticket {
+        return self.submit(Command::PopLeft {
+            key: key.as_ref().to_string(),
+        });
+    }
+
+    pub fn pop_right(&self, key: impl AsRef<str>) -> Ticket {
+        return self.submit(Command::PopRight {
+            key: key.as_ref().to_string(),
+        });
+    }
+
+    pub fn push_left_many(&self, key: impl AsRef<str>, values: Vec<StoreEntry>) -> Ticket {
+        return self.submit(Command::PushMany {
+            key: key.as_ref().to_string(),
+            entries: values,
+            dir: ListDirection::Left,
+        });
+    }
+
+    pub fn push_right_many(&self, key: impl AsRef<str>, values: Vec<StoreEntry>) -> Ticket {
+        return self.submit(Command::PushMany {
+            key: key.as_ref().to_string(),
+            entries: values,
+            dir: ListDirection::Right,
+        });
+    }
+
+    pub fn push_left_if_exists(&self, key: impl AsRef<str>, values: Vec<StoreEntry>) -> Ticket {
+        return self.submit(Command::PushExisting {
+            key: key.as_ref().to_string(),
+            entries: values,
+            dir: ListDirection::Left,
+        });
+    }
+
+    pub fn push_right_if_exists(&self, key: impl AsRef<str>, values: Vec<StoreEntry>) -> Ticket {
+        return self.submit(Command::PushExisting {
+            key: key.as_ref().to_string(),
+            entries: values,
+            dir: ListDirection::Right,
+        });
+    }
+
+    pub fn pop_left_n(&self, key: impl AsRef<str>, count: usize) -> Ticket {
+        return self.submit(Command::PopLeftCount {
+            key: key.as_ref().to_string(),
+            count,
+        });
+    }
+
+    pub fn pop_right_n(&self, key: impl AsRef<str>, count: usize) -> Ticket {
+        return self.submit(Command::PopRightCount {
+            key: key.as_ref().to_string(),
+            count,
+        });
+    }
+
+    pub fn list_set(&self, kv: KVPair, idx: isize) -> Ticket {
+        return self.submit(Command::ListSet {
+            key: kv.0,
+            entry: kv.1,
+            idx,
+        });
+    }
+
+    pub fn insert_at(&self, kv: KVPair, idx: isize) -> Ticket {
+        return self.submit(Command::ListInsertAt {
+            key: kv.0,
+            entry: kv.1,
+            idx,
+        });
+    }
+
+    pub fn linsert(
+        &self,
+        key: impl AsRef<str>,
+        pos: InsertPosition,
+        pivot: impl AsRef<[u8]>,
+        value: StoreEntry,
+    ) -> Ticket {
+        return self.submit(Command::ListInsert {
+            key: key.as_ref().to_string(),
+            pos,
+            pivot: pivot.as_ref().into(),
+            entry: value,
+        });
+    }
+
+    pub fn trim(&self, key: impl AsRef<str>, start: isize, end: isize) -> Ticket {
+        return self.submit(Command::Trim {
+            key: key.as_ref().to_string(),
+            start,
+            end,
+        });
+    }
+
+    pub fn list_remove(
+        &self,
+        key: impl AsRef<str>,
+        value: impl AsRef<[u8]>,
+        count: isize,
+    ) -> Ticket {
+        return self.submit(Command::ListRemove {
+            key: key.as_ref().to_string(),
+            value: value.as_ref().into(),
+            count,
+        });
+    }
+
+    pub fn lmove(
+        &self,
+        src: impl AsRef<str>,
+        dst: impl AsRef<str>,
+        from: ListDirection,
+        to: ListDirection,
+    ) -> Ticket {
+        return self.submit(Command::ListMove {
+            src: src.as_ref().to_string(),
+            dst: dst.as_ref().to_string(),
+            from,
+            to,
+        });
+    }
+
+    pub fn hset(&self, key: impl AsRef<str>, fields: Vec<KVPair>) -> Ticket {
+        return self.submit(Command::HashSet {
+            key: key.as_ref().to_string(),
+            fields,
+        });
+    }
+
+    pub fn hsetnx(&self, key: impl AsRef<str>, field: KVPair) -> Ticket {
+        return self.submit(Command::HashSetNx {
+            key: key.as_ref().to_string(),
+            field,
+        });
+    }
+
+    pub fn hdel(&self, key: impl AsRef<str>, fields: Vec<impl AsRef<str>>) -> Ticket {
+        return self.submit(Command::HashDelete {
+            key: key.as_ref().to_string(),
+            fields: fields
+                .iter()
+                .map(|f| f.as_ref().to_string())
+                .collect::<Vec<String>>(),
+        });
+    }
+
+    pub fn hincrby(&self, key: impl AsRef<str>, field: impl AsRef<str>, incr: i64) -> Ticket {
+        return self.submit(Command::HashIncrement {
+            key: key.as_ref().to_string(),
+            field: field.as_ref().to_string(),
+            incr,
+        });
+    }
+
+    pub fn hincrbyfloat(&self, key: impl AsRef<str>, field: impl AsRef<str>, incr: f64) -> Ticket {
+        return self.submit(Command::HashIncrementFloat {
+            key: key.as_ref().to_string(),
+            field: field.as_ref().to_string(),
+            incr,
+        });
+    }
+
+    pub fn sadd(&self, key: impl AsRef<str>, members: Vec<impl AsRef<str>>) -> Ticket {
+        return self.submit(Command::SetAdd {
+            key: key.as_ref().to_string(),
+            members: members
+                .iter()
+                .map(|m| m.as_ref().to_string())
+                .collect::<Vec<String>>(),
+        });
+    }
+
+    pub fn srem(&self, key: impl AsRef<str>, members: Vec<impl AsRef<str>>) -> Ticket {
+        return self.submit(Command::SetRemove {
+            key: key.as_ref().to_string(),
+            members: members
+                .iter()
+                .map(|m| m.as_ref().to_string())
+                .collect::<Vec<String>>(),
+        });
+    }
+
+    pub fn spop(&self, key: impl AsRef<str>, count: usize) -> Ticket {
+        return self.submit(Command::SetPop {
+            key: key.as_ref().to_string(),
+            count,
+        });
+    }
+
+    pub fn sunionstore(&self, dst: impl AsRef<str>, keys: Vec<impl AsRef<str>>) -> Ticket {
+        return self.set_combine_store(SetOperation::Union, dst, keys);
+    }
+
+    pub fn sinterstore(&self, dst: impl AsRef<str>, keys: Vec<impl AsRef<str>>) -> Ticket {
+        return self.set_combine_store(SetOperation::Intersection, dst, keys);
+    }
+
+    pub fn sdiffstore(&self, dst: impl AsRef<str>, keys: Vec<impl AsRef<str>>) -> Ticket {
+        return self.set_combine_store(SetOperation::Difference, dst, keys);
+    }
+
+    fn set_combine_store(
+        &self,
+        op: SetOperation,
+        dst: impl AsRef<str>,
+        keys: Vec<impl AsRef<str>>,
+    ) -> Ticket {
+        return self.submit(Command::SetCombineStore {
+            op,
+            dst: dst.as_ref().to_string(),
+            keys: keys
+                .iter()
+                .map(|k| k.as_ref().to_string())
+                .collect::<Vec<String>>(),
+        });
+    }
+
+    pub fn zadd(
+        &self,
+        key: impl AsRef<str>,
+        members: Vec<ScoredMember>,
+        opts: AddOptions,
+    ) -> Ticket {
+        return self.submit(Command::ZAdd {
+            key: key.as_ref().to_string(),
+            members,
+            opts,
+        });
+    }
+
+    pub fn zadd_incr(
+        &self,
+        key: impl AsRef<str>,
+        member: impl AsRef<str>,
+        incr: f64,
+        opts: AddOptions,
+    ) -> Ticket {
+        return self.submit(Command::ZAddIncr {
+            key: key.as_ref().to_string(),
+            member: member.as_ref().to_string(),
+            incr,
+            opts,
+        });
+    }
+
+    pub fn zincrby(&self, key: impl AsRef<str>, member: impl AsRef<str>, incr: f64) -> Ticket {
+        return self.zadd_incr(key, member, incr, AddOptions::default());
+    }
+
+    pub fn zrem(&self, key: impl AsRef<str>, members: Vec<impl AsRef<str>>) -> Ticket {
+        return self.submit(Command::ZRemove {
+            key: key.as_ref().to_string(),
+            members: members
+                .iter()
+                .map(|m| m.as_ref().to_string())
+                .collect::<Vec<String>>(),
+        });
+    }
+
+    pub fn zpopmin(&self, key: impl AsRef<str>, count: usize) -> Ticket {
+        return self.submit(Command::ZPop {
+            key: key.as_ref().to_string(),
+            count,
+            rev: false,
+        });
+    }
+
+    pub fn zpopmax(&self, key: impl AsRef<str>, count: usize) -> Ticket {
+        return self.submit(Command::ZPop {
+            key: key.as_ref().to_string(),
+            count,
+            rev: true,
+        });
+    }
+
+    pub fn zunionstore(
+        &self,
+        dst: impl AsRef<str>,
+        keys: Vec<impl AsRef<str>>,
+        weights: Option<Vec<f64>>,
+        aggregate: Aggregate,
+    ) -> Ticket {
+        return self.zset_combine_store(SortedSetOperation::Union, dst, keys, weights, aggregate);
+    }
+
+    pub fn zinterstore(
+        &self,
+        dst: impl AsRef<str>,
+        keys: Vec<impl AsRef<str>>,
+        weights: Option<Vec<f64>>,
+        aggregate: Aggregate,
+    ) -> Ticket {
+        return self.zset_combine_store(
+            SortedSetOperation::Intersection,
+            dst,
+            keys,
+            weights,
+            aggregate,
+        );
+    }
+
+    fn zset_combine_store(
+        &self,
+        op: SortedSetOperation,
+        dst: impl AsRef<str>,
+        keys: Vec<impl AsRef<str>>,
+        weights: Option<Vec<f64>>,
+        aggregate: Aggregate,
+    ) -> Ticket {
+        return self.submit(Command::ZCombineStore {
+            op,
+            dst: dst.as_ref().to_string(),
+            keys: keys
+                .iter()
+                .map(|k| k.as_ref().to_string())
+                .collect::<Vec<String>>(),
+            weights,
+            aggregate,
+        });
+    }
+
+    pub fn xadd(
+        &self,
+        key: impl AsRef<str>,
+        id: NewId,
+        fields: StreamFields,
+        trim: Option<TrimStrategy>,
+    ) -> Ticket {
+        return self.submit(Command::StreamAdd {
+            key: key.as_ref().to_string(),
+            id,
+            fields,
+            trim,
+        });
+    }
+
+    pub fn xtrim(&self, key: impl AsRef<str>, strategy: TrimStrategy) -> Ticket {
+        return self.submit(Command::StreamTrim {
+            key: key.as_ref().to_string(),
+            strategy,
+        });
+    }
+
+    pub fn xgroup_create(
+        &self,
+        key: impl AsRef<str>,
+        group: impl AsRef<str>,
+        start: Option<StreamId>,
+        mkstream: bool,
+    ) -> Ticket {
+        return self.submit(Command::StreamGroupCreate {
+            key: key.as_ref().to_string(),
+            group: group.as_ref().to_string(),
+            start,
+            mkstream,
+        });
+    }
+
+    pub fn xgroup_destroy(&self, key: impl AsRef<str>, group: impl AsRef<str>) -> Ticket {
+        return self.submit(Command::StreamGroupDestroy {
+            key: key.as_ref().to_string(),
+            group: group.as_ref().to_string(),
+        });
+    }
+
+    pub fn xreadgroup(
+        &self,
+        key: impl AsRef<str>,
+        group: impl AsRef<str>,
+        consumer: impl AsRef<str>,
+        after: Option<StreamId>,
+        count: Option<usize>,
+        noack: bool,
+    ) -> Ticket {
+        return self.submit(Command::StreamReadGroup {
+            key: key.as_ref().to_string(),
+            group: group.as_ref().to_string(),
+            consumer: consumer.as_ref().to_string(),
+            after,
+            count,
+            noack,
+        });
+    }
+
+    pub fn xack(&self, key: impl AsRef<str>, group: impl AsRef<str>, ids: Vec<StreamId>) -> Ticket {
+        return self.submit(Command::StreamAck {
+            key: key.as_ref().to_string(),
+            group: group.as_ref().to_string(),
+            ids,
+        });
+    }
+
+    pub fn xclaim(
+        &self,
+        key: impl AsRef<str>,
+        group: impl AsRef<str>,
+        consumer: impl AsRef<str>,
+        min_idle: u64,
+        ids: Vec<StreamId>,
+    ) -> Ticket {
+        return self.submit(Command::StreamClaim {
+            key: key.as_ref().to_string(),
+            group: group.as_ref().to_string(),
+            consumer: consumer.as_ref().to_string(),
+            min_idle,
+            ids,
+        });
+    }
+
+    pub fn pfadd(&self, key: impl AsRef<str>, elements: Vec<impl AsRef<[u8]>>) -> Ticket {
+        return self.submit(Command::HllAdd {
+            key: key.as_ref().to_string(),
+            elements: elements
+                .iter()
+                .map(|e| e.as_ref().into())
+                .collect::<Vec<Value>>(),
+        });
+    }
+
+    pub fn pfmerge(&self, dst: impl AsRef<str>, keys: Vec<impl AsRef<str>>) -> Ticket {
+        return self.submit(Command::HllMerge {
+            dst: dst.as_ref().to_string(),
+            keys: keys
+                .iter()
+                .map(|k| k.as_ref().to_string())
+                .collect::<Vec<String>>(),
+        });
+    }
+
+    pub fn del(&self, keys: Vec<impl AsRef<str>>) -> Ticket {
+        return self.submit(Command::Del {
+            keys: keys.iter().map(|k| k.as_ref().to_string()).collect(),
+        });
+    }
+
+    pub fn rename(&self, src: impl AsRef<str>, dst: impl AsRef<str>) -> Ticket {
+        return self.submit(Command::Rename {
+            src: src.as_ref().to_string(),
+            dst: dst.as_ref().to_string(),
+        });
+    }
+
+    /// The deadline is worked out when the write is queued, not when it's applied
+    pub fn expire(&self, key: impl AsRef<str>, seconds: i64, cond: ExpireCondition) -> Ticket {
+        return self.pexpire(key, seconds.saturating_mul(1000), cond);
+    }
+
+    /// The deadline is worked out when the write is queued, not when it's applied
+    pub fn pexpire(&self, key: impl AsRef<str>, millis: i64, cond: ExpireCondition) -> Ticket {
+        let now = self.read_store().clock().now_millis();
+        return self.pexpire_at(key, now.saturating_add(millis), cond);
+    }
+
+    pub fn pexpire_at(
+        &self,
+        key: impl AsRef<str>,
+        timestamp: i64,
+        cond: ExpireCondition,
+    ) -> Ticket {
+        return self.submit(Command::PExpireAt {
+            key: key.as_ref().to_string(),
+            timestamp,
+            cond,
+        });
+    }
+
+    pub fn persist(&self, key: impl AsRef<str>) -> Ticket {
+        return self.submit(Command::Persist {
+            key: key.as_ref().to_string(),
+        });
+    }
+}
+
+impl Drop for QueuedStore {
+    fn drop(&mut self) {
+        // Closing the channel lets the worker drain the queue and stop
+        self.sender.take();
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod queue_tests {
+    use super::*;
+    use crate::store::error::GranatError;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::task::Wake;
+
+    fn create_kv(key: &str, value: &str) -> KVPair {
+        return (key.to_string(), StoreEntry::new(value));
+    }
+
+    struct FlagWaker(AtomicBool);
+
+    impl Wake for FlagWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn writes_apply_in_order() {
+        let queue = QueuedStore::default();
+        for i in 0..100 {
+            queue.push_right(create_kv("list", &i.to_string()));
+        }
+        let popped = queue.pop_left("list");
+        queue.increment("counter", 5);
+        let incremented = queue.increment("counter", 5);
+
+        assert_eq!(
+            popped.wait().unwrap(),
+            Reply::Entry(Some(StoreEntry::new("0")))
+        );
+        assert_eq!(incremented.wait().unwrap(), Reply::Integer(10));
+
+        assert!(queue.flush().wait().is_ok());
+        assert_eq!(queue.list_len("list").unwrap(), 99);
+        assert_eq!(
+            queue.index("list", 0).unwrap().unwrap().value,
+            "1".to_string()
+        );
+    }
+
+    #[test]
+    fn errors_come_back_on_the_ticket() {
+        let queue = QueuedStore::default();
+        queue.set(create_kv("string", "value"));
+        let err = queue
+            .push_left(create_kv("string", "value"))
+            .wait()
+            .unwrap_err();
+
+        assert_eq!(
+            err.downcast_ref::<GranatError>(),
+            Some(&GranatError::WrongType)
+        );
+
+        // An overflowing increment errors rather than taking the worker down
+        queue.set(create_kv("counter", &i64::MAX.to_string()));
+        assert!(queue.increment("counter", 1).wait().is_err());
+        assert_eq!(
+            queue.increment("counter", -1).wait().unwrap(),
+            Reply::Integer(i64::MAX - 1)
+        );
+    }
+
+    #[test]
+    fn tickets_are_futures() {
+        let queue = QueuedStore::default();
+        let flag = Arc::new(FlagWaker(AtomicBool::new(false)));
+        let waker = Waker::from(flag.clone());
+        let mut cx = Context::from_waker(&waker);
+
+        let mut ticket = queue.set(create_kv("key", "value"));
+        loop {
+            match Pin::new(&mut ticket).poll(&mut cx) {
+                Poll::Ready(result) => {
+                    assert_eq!(result.unwrap(), Reply::Ok);
+                    break;
+                }
+                Poll::Pending => {
+                    while !flag.0.swap(false, Ordering::SeqCst) {
+                        thread::yield_now();
+                    }
+                }
+            }
+        }
+
+        assert!(ticket.is_done());
+        assert!(queue.get("key").unwrap().is_some());
+    }
+
+    #[test]
+    fn dropping_drains_the_queue() {
+        let queue = QueuedStore::default();
+        let tickets = (0..50)
+            .map(|i| queue.set(create_kv(&format!("key:{i}"), "value")))
+            .collect::<Vec<Ticket>>();
+        let store = queue.store.clone();
+        drop(queue);
+
+        assert!(tickets.iter().all(|t| t.is_done()));
+        assert_eq!(store.read().unwrap().keys("*").len(), 50);
+    }
+}
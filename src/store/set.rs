@@ -0,0 +1,317 @@
+use serde::{Deserialize, Serialize};
+
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, HashSet};
+use std::hash::{BuildHasher, Hasher};
+
+/// Ways of combining several sets into one, the Redis `SUNION`, `SINTER` and `SDIFF`
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+pub enum SetOperation {
+    Union,
+    Intersection,
+    /// Members of the first set that aren't in any of the others
+    Difference,
+}
+
+impl SetOperation {
+    /// Combines `sets` in the order given, missing keys should be passed as empty sets
+    pub fn combine(&self, sets: Vec<HashSet<String>>) -> HashSet<String> {
+        let mut sets = sets.into_iter();
+        let Some(mut result) = sets.next() else {
+            return HashSet::new();
+        };
+
+        for set in sets {
+            match self {
+                Self::Union => result.extend(set),
+                Self::Intersection => result.retain(|m| set.contains(m)),
+                Self::Difference => result.retain(|m| !set.contains(m)),
+            }
+        }
+
+        return result;
+    }
+}
+
+/// A random index below `len`, each `RandomState` is seeded differently
+fn random_index(len: usize) -> usize {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_usize(len);
+
+    return (hasher.finish() % len as u64) as usize;
+}
+
+/// Unordered sets of unique members.
+///
+/// Members are plain strings rather than `StoreEntry`s, a set only expires as
+/// a whole through its key.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SetStore {
+    pub store: HashMap<String, HashSet<String>>,
+}
+
+impl Default for SetStore {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+impl SetStore {
+    pub fn new() -> Self {
+        return Self {
+            store: HashMap::new(),
+        };
+    }
+
+    /// Adds the members, returning how many weren't already in the set
+    pub fn add(&mut self, key: impl AsRef<str>, members: Vec<impl AsRef<str>>) -> usize {
+        if members.is_empty() {
+            return 0;
+        }
+
+        let set = self.store.entry(key.as_ref().to_string()).or_default();
+        return members
+            .iter()
+            .filter(|m| set.insert(m.as_ref().to_string()))
+            .count();
+    }
+
+    /// Removes the members, dropping the set once it's empty.
+    ///
+    /// Returns the number of members removed.
+    pub fn remove(&mut self, key: impl AsRef<str>, members: Vec<impl AsRef<str>>) -> usize {
+        let Some(set) = self.store.get_mut(key.as_ref()) else {
+            return 0;
+        };
+
+        let removed = members.iter().filter(|m| set.remove(m.as_ref())).count();
+        if set.is_empty() {
+            self.store.remove(key.as_ref());
+        }
+
+        return removed;
+    }
+
+    pub fn is_member(&self, key: impl AsRef<str>, member: impl AsRef<str>) -> bool {
+        return self
+            .store
+            .get(key.as_ref())
+            .is_some_and(|set| set.contains(member.as_ref()));
+    }
+
+    /// Every member of the set, in no particular order
+    pub fn members(&self, key: impl AsRef<str>) -> Vec<String> {
+        return self
+            .store
+            .get(key.as_ref())
+            .into_iter()
+            .flatten()
+            .cloned()
+            .collect::<Vec<String>>();
+    }
+
+    pub fn card(&self, key: impl AsRef<str>) -> usize {
+        return self.store.get(key.as_ref()).map_or(0, |set| set.len());
+    }
+
+    /// Removes and returns up to `count` random members
+    pub fn pop(&mut self, key: impl AsRef<str>, count: usize) -> Vec<String> {
+        let Some(set) = self.store.get_mut(key.as_ref()) else {
+            return vec![];
+        };
+
+        let mut members = set.iter().cloned().collect::<Vec<String>>();
+        let mut popped = vec![];
+        while popped.len() < count && !members.is_empty() {
+            let member = members.swap_remove(random_index(members.len()));
+            set.remove(&member);
+            popped.push(member);
+        }
+
+        if set.is_empty() {
+            self.store.remove(key.as_ref());
+        }
+
+        return popped;
+    }
+
+    /// Random members without removing them, like Redis `SRANDMEMBER`.
+    ///
+    /// A positive `count` returns up to that many distinct members, a negative
+    /// one returns exactly that many with members possibly repeated.
+    pub fn random_members(&self, key: impl AsRef<str>, count: isize) -> Vec<String> {
+        let mut members = self.members(key);
+        if members.is_empty() {
+            return vec![];
+        }
+
+        if count < 0 {
+            return (0..count.unsigned_abs())
+                .map(|_| members[random_index(members.len())].clone())
+                .collect::<Vec<String>>();
+        }
+
+        let mut picked = vec![];
+        while picked.len() < count as usize && !members.is_empty() {
+            picked.push(members.swap_remove(random_index(members.len())));
+        }
+
+        return picked;
+    }
+
+    /// Combines the sets at `keys`, missing keys count as empty sets
+    pub fn combine(&self, op: SetOperation, keys: &[impl AsRef<str>]) -> HashSet<String> {
+        let sets = keys
+            .iter()
+            .map(|k| self.store.get(k.as_ref()).cloned().unwrap_or_default())
+            .collect::<Vec<HashSet<String>>>();
+
+        return op.combine(sets);
+    }
+
+    /// Combines the sets at `keys` into `dst`, replacing whatever set it held.
+    ///
+    /// Returns the size of the new set, `dst` is removed if it's empty.
+    pub fn combine_store(
+        &mut self,
+        op: SetOperation,
+        dst: impl AsRef<str>,
+        keys: &[impl AsRef<str>],
+    ) -> usize {
+        let result = self.combine(op, keys);
+        let size = result.len();
+        if result.is_empty() {
+            self.store.remove(dst.as_ref());
+        } else {
+            self.store.insert(dst.as_ref().to_string(), result);
+        }
+
+        return size;
+    }
+}
+
+#[cfg(test)]
+mod set_store_tests {
+    use super::*;
+
+    fn create_basic_set_store() -> SetStore {
+        let mut ss = SetStore::new();
+        ss.add("a", vec!["1", "2", "3", "4"]);
+        ss.add("b", vec!["3", "4", "5"]);
+        ss.add("c", vec!["4", "6"]);
+
+        return ss;
+    }
+
+    fn sorted(members: impl IntoIterator<Item = String>) -> Vec<String> {
+        let mut members = members.into_iter().collect::<Vec<String>>();
+        members.sort();
+
+        return members;
+    }
+
+    #[test]
+    fn add_and_remove() {
+        let mut ss = SetStore::new();
+        assert_eq!(ss.add("set", vec!["a", "b", "a"]), 2);
+        assert_eq!(ss.add("set", vec!["b", "c"]), 1);
+        assert_eq!(ss.card("set"), 3);
+        assert!(ss.is_member("set", "a"));
+        assert!(!ss.is_member("set", "z"));
+        assert!(!ss.is_member("missing", "a"));
+        assert_eq!(sorted(ss.members("set")), vec!["a", "b", "c"]);
+
+        assert_eq!(ss.remove("set", vec!["a", "z"]), 1);
+        assert_eq!(ss.remove("set", vec!["b", "c"]), 2);
+        assert!(!ss.store.contains_key("set"));
+        assert_eq!(ss.remove("set", vec!["a"]), 0);
+
+        let empty: Vec<&str> = vec![];
+        assert_eq!(ss.add("empty", empty), 0);
+        assert!(!ss.store.contains_key("empty"));
+    }
+
+    #[test]
+    fn pop_members() {
+        let mut ss = create_basic_set_store();
+        let popped = ss.pop("a", 3);
+        assert_eq!(popped.len(), 3);
+        assert_eq!(ss.card("a"), 1);
+        assert!(popped.iter().all(|m| !ss.is_member("a", m)));
+
+        assert_eq!(ss.pop("a", 5).len(), 1);
+        assert!(!ss.store.contains_key("a"));
+        assert!(ss.pop("a", 1).is_empty());
+    }
+
+    #[test]
+    fn random_members() {
+        let ss = create_basic_set_store();
+        let picked = ss.random_members("a", 3);
+        assert_eq!(picked.iter().collect::<HashSet<&String>>().len(), 3);
+
+        assert_eq!(ss.random_members("a", 10).len(), 4);
+        assert_eq!(ss.random_members("c", -5).len(), 5);
+        assert!(ss
+            .random_members("c", -5)
+            .iter()
+            .all(|m| ss.is_member("c", m)));
+        assert!(ss.random_members("missing", 3).is_empty());
+        assert_eq!(ss.card("a"), 4);
+    }
+
+    #[test]
+    fn set_algebra() {
+        let ss = create_basic_set_store();
+        assert_eq!(
+            sorted(ss.combine(SetOperation::Union, &["a", "b", "c"])),
+            vec!["1", "2", "3", "4", "5", "6"]
+        );
+        assert_eq!(
+            sorted(ss.combine(SetOperation::Intersection, &["a", "b", "c"])),
+            vec!["4"]
+        );
+        assert_eq!(
+            sorted(ss.combine(SetOperation::Difference, &["a", "b", "c"])),
+            vec!["1", "2"]
+        );
+
+        // Missing keys are empty sets
+        assert!(ss
+            .combine(SetOperation::Intersection, &["a", "missing"])
+            .is_empty());
+        assert_eq!(
+            ss.combine(SetOperation::Difference, &["a", "missing"])
+                .len(),
+            4
+        );
+        assert!(ss.combine(SetOperation::Union, &["missing"]).is_empty());
+    }
+
+    #[test]
+    fn set_algebra_into_destination() {
+        let mut ss = create_basic_set_store();
+        assert_eq!(
+            ss.combine_store(SetOperation::Intersection, "dst", &["a", "b"]),
+            2
+        );
+        assert_eq!(sorted(ss.members("dst")), vec!["3", "4"]);
+
+        // The destination can be one of the sources
+        assert_eq!(
+            ss.combine_store(SetOperation::Union, "dst", &["dst", "c"]),
+            3
+        );
+        assert_eq!(sorted(ss.members("dst")), vec!["3", "4", "6"]);
+
+        assert_eq!(
+            ss.combine_store(SetOperation::Difference, "dst", &["c", "a"]),
+            1
+        );
+        assert_eq!(
+            ss.combine_store(SetOperation::Intersection, "dst", &["a", "missing"]),
+            0
+        );
+        assert!(!ss.store.contains_key("dst"));
+    }
+}
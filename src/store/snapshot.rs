@@ -0,0 +1,553 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::store::clock::{system_clock, SharedClock};
+use crate::store::general::GeneralStore;
+use crate::store::hash::HashStore;
+use crate::store::hyperloglog::HyperLogLogStore;
+use crate::store::list::ListStore;
+use crate::store::set::SetStore;
+use crate::store::sorted_set::SortedSetStore;
+use crate::store::stream::StreamStore;
+use crate::store::GranatStore;
+
+/// Bumped whenever the snapshot layout grows, older builds refuse anything newer
+/// rather than silently dropping what they don't know about.
+///
+/// Version 1 only had strings and lists, 2 added hashes, sets, sorted sets,
+/// streams, HyperLogLogs and binary string values. Version 1 still loads.
+pub const SNAPSHOT_VERSION: u32 = 2;
+
+/// How long to wait before retrying a scheduled save that failed
+const SAVE_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Keeps the temporary files of writes running at the same time apart
+static TMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Serialize)]
+struct SnapshotRef<'a> {
+    version: u32,
+    general: &'a GeneralStore,
+    list: &'a ListStore,
+    hash: &'a HashStore,
+    set: &'a SetStore,
+    zset: &'a SortedSetStore,
+    stream: &'a StreamStore,
+    hll: &'a HyperLogLogStore,
+    expires: &'a HashMap<String, i64>,
+}
+
+/// Just enough of a snapshot to check its version before reading the rest
+#[derive(Deserialize)]
+struct SnapshotHeader {
+    version: u32,
+}
+
+/// An owned copy of every store, for serialising off the store's thread
+#[derive(Deserialize, Serialize)]
+struct Snapshot {
+    version: u32,
+    general: GeneralStore,
+    list: ListStore,
+
+    // Missing from snapshots written before these types existed
+    #[serde(default)]
+    hash: HashStore,
+    #[serde(default)]
+    set: SetStore,
+    #[serde(default)]
+    zset: SortedSetStore,
+    #[serde(default)]
+    stream: StreamStore,
+    #[serde(default)]
+    hll: HyperLogLogStore,
+    expires: HashMap<String, i64>,
+}
+
+/// Save the store if at least `changes` writes have happened in the last `after`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SaveRule {
+    pub after: Duration,
+    pub changes: usize,
+}
+
+impl SaveRule {
+    pub fn new(after: Duration, changes: usize) -> Self {
+        return Self { after, changes };
+    }
+}
+
+/// Where and when the store saves itself in the background
+#[derive(Debug, Clone)]
+pub struct SaveConfig {
+    pub path: PathBuf,
+    pub rules: Vec<SaveRule>,
+}
+
+impl SaveConfig {
+    pub fn new(path: impl AsRef<Path>, rules: Vec<SaveRule>) -> Self {
+        return Self {
+            path: path.as_ref().to_path_buf(),
+            rules,
+        };
+    }
+}
+
+/// A snapshot being written out on another thread
+pub(crate) struct BackgroundSave {
+    handle: JoinHandle<Result<()>>,
+
+    /// Writes that had happened when the snapshot was taken
+    dirty_at_start: usize,
+}
+
+/// Writes `bytes` to a temporary file beside `path`, syncs it, then renames it
+/// over `path` so readers never see a partially written file.
+pub(crate) fn write_atomically(path: &Path, bytes: &[u8]) -> Result<()> {
+    let seq = TMP_COUNTER.fetch_add(1, Ordering::Relaxed);
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(format!(".{}.{seq}.tmp", std::process::id()));
+    let tmp_path = path.with_file_name(tmp_name);
+
+    let written = write_synced(&tmp_path, bytes);
+    if written.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+        return written;
+    }
+
+    fs::rename(&tmp_path, path)?;
+
+    return Ok(());
+}
+
+fn write_synced(path: &Path, bytes: &[u8]) -> Result<()> {
+    let mut file = File::create(path)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+
+    return Ok(());
+}
+
+impl GranatStore {
+    /// Serialises every store, with expiry metadata, into a versioned snapshot
+    fn snapshot_bytes(&self) -> Result<Vec<u8>> {
+        let snapshot = SnapshotRef {
+            version: SNAPSHOT_VERSION,
+            general: &self.general,
+            list: &self.list,
+            hash: &self.hash,
+            set: &self.set,
+            zset: &self.zset,
+            stream: &self.stream,
+            hll: &self.hll,
+            expires: &self.expires,
+        };
+
+        match serde_json::to_vec(&snapshot) {
+            Ok(bytes) => return Ok(bytes),
+            Err(e) => return Err(anyhow!("unable to serialize snapshot: {e}")),
+        }
+    }
+
+    /// Writes a snapshot of the whole store to `path`, replacing it atomically.
+    ///
+    /// Waits for any background save first, so its older snapshot can't be
+    /// renamed over this one once it's done.
+    pub fn save(&mut self, path: impl AsRef<Path>) -> Result<()> {
+        // This snapshot supersedes the background one however that went
+        let _ = self.wait_for_save();
+
+        let bytes = self.snapshot_bytes()?;
+        write_atomically(path.as_ref(), &bytes)?;
+
+        self.dirty = 0;
+        self.last_save = Instant::now();
+        self.save_failed_at = None;
+
+        return Ok(());
+    }
+
+    /// Snapshots the store and writes it to `path` on a background thread.
+    ///
+    /// Only copying the store happens on this thread, serialising and writing
+    /// it happen in the background. The store can carry on taking writes
+    /// meanwhile, they'll be picked up by the next save. Errors if a background
+    /// save is already running.
+    pub fn background_save(&mut self, path: impl AsRef<Path>) -> Result<()> {
+        if self.bg_save.is_some() {
+            return Err(anyhow!("background save already in progress"));
+        }
+
+        let snapshot = Snapshot {
+            version: SNAPSHOT_VERSION,
+            general: self.general.clone(),
+            list: self.list.clone(),
+            hash: self.hash.clone(),
+            set: self.set.clone(),
+            zset: self.zset.clone(),
+            stream: self.stream.clone(),
+            hll: self.hll.clone(),
+            expires: self.expires.clone(),
+        };
+
+        let path = path.as_ref().to_path_buf();
+        let handle = thread::spawn(move || {
+            let bytes = match serde_json::to_vec(&snapshot) {
+                Ok(bytes) => bytes,
+                Err(e) => return Err(anyhow!("unable to serialize snapshot: {e}")),
+            };
+
+            return write_atomically(&path, &bytes);
+        });
+
+        self.bg_save = Some(BackgroundSave {
+            handle,
+            dirty_at_start: self.dirty,
+        });
+
+        return Ok(());
+    }
+
+    /// Whether a background save is still being written
+    pub fn is_saving(&self) -> bool {
+        return self
+            .bg_save
+            .as_ref()
+            .is_some_and(|save| !save.handle.is_finished());
+    }
+
+    /// Blocks until any running background save finishes, returning its result
+    pub fn wait_for_save(&mut self) -> Result<()> {
+        let Some(save) = self.bg_save.take() else {
+            return Ok(());
+        };
+
+        return self.finish_save(save);
+    }
+
+    /// Number of writes since the last successful save
+    pub fn changes_since_save(&self) -> usize {
+        return self.dirty;
+    }
+
+    /// Sets up, or with `None` turns off, periodic background saves
+    pub fn set_save_config(&mut self, config: Option<SaveConfig>) {
+        self.save_config = config;
+    }
+
+    fn finish_save(&mut self, save: BackgroundSave) -> Result<()> {
+        let result = match save.handle.join() {
+            Ok(result) => result,
+            Err(_) => Err(anyhow!("background save thread panicked")),
+        };
+
+        if result.is_ok() {
+            self.dirty = self.dirty.saturating_sub(save.dirty_at_start);
+            self.last_save = Instant::now();
+            self.save_failed_at = None;
+        } else {
+            self.save_failed_at = Some(Instant::now());
+        }
+
+        return result;
+    }
+
+    /// Reaps a finished background save and starts a new one if a save rule is due
+    pub(crate) fn save_cron(&mut self) {
+        if self
+            .bg_save
+            .as_ref()
+            .is_some_and(|s| s.handle.is_finished())
+        {
+            if let Some(save) = self.bg_save.take() {
+                // A failed save leaves the store dirty so a later cycle retries it
+                let _ = self.finish_save(save);
+            }
+        }
+
+        let retry_pending = self
+            .save_failed_at
+            .is_some_and(|failed| failed.elapsed() < SAVE_RETRY_DELAY);
+
+        if self.bg_save.is_some() || retry_pending {
+            return;
+        }
+
+        let Some(config) = &self.save_config else {
+            return;
+        };
+
+        let since_save = self.last_save.elapsed();
+        let due = config
+            .rules
+            .iter()
+            .any(|rule| self.dirty >= rule.changes && since_save >= rule.after);
+
+        if due && self.dirty > 0 {
+            let path = config.path.clone();
+            let _ = self.background_save(path);
+        }
+    }
+
+    /// Loads a store from a snapshot written by `save`
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        return Self::load_with_clock(path, system_clock());
+    }
+
+    /// Loads a store from a snapshot, dropping anything that has expired
+    /// according to `clock` since it was written.
+    pub fn load_with_clock(path: impl AsRef<Path>, clock: SharedClock) -> Result<Self> {
+        let bytes = fs::read(path.as_ref())?;
+        let version = match serde_json::from_slice::<SnapshotHeader>(&bytes) {
+            Ok(header) => header.version,
+            Err(e) => return Err(anyhow!("unable to deserialize snapshot: {e}")),
+        };
+
+        if !(1..=SNAPSHOT_VERSION).contains(&version) {
+            return Err(anyhow!(
+                "unsupported snapshot version {version}, expected at most {SNAPSHOT_VERSION}"
+            ));
+        }
+
+        let snapshot = match serde_json::from_slice::<Snapshot>(&bytes) {
+            Ok(snapshot) => snapshot,
+            Err(e) => return Err(anyhow!("unable to deserialize snapshot: {e}")),
+        };
+
+        let mut store = Self::with_clock(clock);
+        store.general.store = snapshot.general.store;
+        store.list.store = snapshot.list.store;
+        store.hash.store = snapshot.hash.store;
+        store.set.store = snapshot.set.store;
+        store.zset.store = snapshot.zset.store;
+        store.stream.store = snapshot.stream.store;
+        store.hll.store = snapshot.hll.store;
+        store.expires = snapshot.expires;
+        store.rebuild_keyspace();
+
+        let keys = store.keyspace.keys().cloned().collect::<Vec<String>>();
+        for key in keys.iter() {
+            store.expire_if_needed(key);
+        }
+
+        return Ok(store);
+    }
+}
+
+#[cfg(test)]
+mod snapshot_tests {
+    use super::*;
+    use crate::store::clock::MockClock;
+    use crate::store::entry::StoreEntry;
+    use crate::store::keyspace::{ExpireCondition, KeyType};
+    use crate::store::sorted_set::AddOptions;
+    use crate::store::stream::{NewId, StreamId};
+    use crate::store::KVPair;
+    use std::sync::Arc;
+
+    fn create_kv(key: &str, value: &str) -> KVPair {
+        return (key.to_string(), StoreEntry::new(value));
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+
+        return std::env::temp_dir()
+            .join(format!("granat-{name}-{}-{nanos}.json", std::process::id()));
+    }
+
+    #[test]
+    fn save_and_load() {
+        let path = temp_path("save-and-load");
+        let mut gs = GranatStore::new();
+        let _ = gs.set(create_kv("string", "value"));
+        let _ = gs.set(("binary".to_string(), StoreEntry::new([0xFF, 0x00])));
+        let _ = gs.increment("counter", 41);
+        let _ = gs.push_right(create_kv("list", "0"));
+        let _ = gs.push_right(create_kv("list", "1"));
+        let _ = gs.expire("list", 100, ExpireCondition::Always);
+        let _ = gs.hset("hash", vec![create_kv("field", "value")]);
+        let _ = gs.sadd("set", vec!["a", "b"]);
+        let _ = gs.zadd(
+            "zset",
+            vec![("a".to_string(), 1.), ("b".to_string(), f64::INFINITY)],
+            AddOptions::default(),
+        );
+        let fields = vec![("field".to_string(), "value".to_string())];
+        let _ = gs.xadd("stream", NewId::Auto, fields, None);
+        let _ = gs.xgroup_create("stream", "group", Some(StreamId::MIN), false);
+        let _ = gs.xreadgroup("stream", "group", "consumer", None, None, false);
+        let _ = gs.pfadd("hll", vec!["a", "b", "c"]);
+
+        assert!(gs.save(&path).is_ok());
+        assert_eq!(gs.changes_since_save(), 0);
+
+        let mut loaded = GranatStore::load(&path).unwrap();
+        assert_eq!(
+            loaded.get("string").unwrap().unwrap().value,
+            "value".to_string()
+        );
+        assert_eq!(
+            loaded.get("binary").unwrap().unwrap().value,
+            vec![0xFF, 0x00]
+        );
+        assert_eq!(loaded.increment("counter", 1).unwrap(), 42);
+        assert_eq!(loaded.list_len("list").unwrap(), 2);
+        assert_eq!(loaded.type_of("list"), Some(KeyType::List));
+        assert_eq!(loaded.ttl("list"), 100);
+        assert_eq!(
+            loaded.hget("hash", "field").unwrap().unwrap().value,
+            "value".to_string()
+        );
+        assert_eq!(loaded.scard("set").unwrap(), 2);
+        assert_eq!(loaded.zscore("zset", "b").unwrap(), Some(f64::INFINITY));
+        assert_eq!(loaded.zrank("zset", "a").unwrap(), Some(0));
+        assert_eq!(loaded.xlen("stream").unwrap(), 1);
+        assert_eq!(loaded.xinfo_groups("stream").unwrap()[0].pending, 1);
+        assert_eq!(loaded.pfcount(vec!["hll"]).unwrap(), 3);
+        assert!(!loaded.pfadd("hll", vec!["a"]).unwrap());
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn load_drops_expired_entries() {
+        let path = temp_path("expired");
+        let clock = Arc::new(MockClock::starting_now());
+        let mut gs = GranatStore::with_clock(clock.clone());
+
+        let entry = StoreEntry::new("value").expires_in_with(10, clock.as_ref());
+        let _ = gs.set(("short".to_string(), entry));
+        let _ = gs.set(create_kv("forever", "value"));
+        let _ = gs.push_right(create_kv("list", "value"));
+        let _ = gs.expire("list", 10, ExpireCondition::Always);
+        let _ = gs.save(&path);
+
+        clock.advance(Duration::from_secs(11));
+        let loaded = GranatStore::load_with_clock(&path, clock.clone()).unwrap();
+        assert_eq!(loaded.keys("*"), vec!["forever".to_string()]);
+        assert!(!loaded.general.store.contains_key("short"));
+        assert!(loaded.list.store.is_empty());
+        assert!(loaded.expires.is_empty());
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn rejects_unknown_versions() {
+        let path = temp_path("version");
+        let mut gs = GranatStore::new();
+        let _ = gs.save(&path);
+
+        let raw = fs::read_to_string(&path).unwrap();
+        let bumped = raw.replace(
+            &format!("\"version\":{SNAPSHOT_VERSION}"),
+            &format!("\"version\":{}", SNAPSHOT_VERSION + 1),
+        );
+        let _ = fs::write(&path, bumped);
+
+        assert!(GranatStore::load(&path).is_err());
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn loads_older_versions() {
+        let path = temp_path("old-version");
+        let mut gs = GranatStore::new();
+        let _ = gs.set(create_kv("string", "value"));
+        let _ = gs.push_right(create_kv("list", "0"));
+        let _ = gs.save(&path);
+
+        // A first version snapshot only had strings and lists
+        let mut raw =
+            serde_json::from_slice::<serde_json::Value>(&fs::read(&path).unwrap()).unwrap();
+        let fields = raw.as_object_mut().unwrap();
+        for newer in ["hash", "set", "zset", "stream", "hll"] {
+            fields.remove(newer);
+        }
+        fields.insert("version".to_string(), 1.into());
+        let _ = fs::write(&path, serde_json::to_vec(&raw).unwrap());
+
+        let loaded = GranatStore::load(&path).unwrap();
+        assert_eq!(loaded.type_of("string"), Some(KeyType::String));
+        assert_eq!(loaded.list_len("list").unwrap(), 1);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn background_save() {
+        let path = temp_path("background");
+        let mut gs = GranatStore::new();
+        let _ = gs.set(create_kv("string", "value"));
+        assert_eq!(gs.changes_since_save(), 1);
+
+        assert!(gs.background_save(&path).is_ok());
+        let _ = gs.set(create_kv("during", "value"));
+        assert!(gs.wait_for_save().is_ok());
+
+        // The write made during the save still needs saving
+        assert_eq!(gs.changes_since_save(), 1);
+
+        let loaded = GranatStore::load(&path).unwrap();
+        assert!(loaded.get("string").unwrap().is_some());
+        assert!(loaded.get("during").unwrap().is_none());
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn save_waits_for_a_background_save() {
+        let path = temp_path("save-during-background");
+        let mut gs = GranatStore::new();
+        let _ = gs.set(create_kv("old", "value"));
+        assert!(gs.background_save(&path).is_ok());
+
+        let _ = gs.set(create_kv("new", "value"));
+        assert!(gs.save(&path).is_ok());
+        assert!(!gs.is_saving());
+
+        // Nothing left to reap that could take back writes made since
+        let _ = gs.set(create_kv("after", "value"));
+        gs.tick();
+        assert_eq!(gs.changes_since_save(), 1);
+
+        let loaded = GranatStore::load(&path).unwrap();
+        assert!(loaded.get("new").unwrap().is_some());
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn scheduled_save() {
+        let path = temp_path("scheduled");
+        let mut gs = GranatStore::new();
+        gs.set_save_config(Some(SaveConfig::new(
+            &path,
+            vec![SaveRule::new(Duration::ZERO, 3)],
+        )));
+
+        let _ = gs.set(create_kv("one", "value"));
+        let _ = gs.set(create_kv("two", "value"));
+        gs.tick();
+        assert!(!gs.is_saving() && !path.exists());
+
+        let _ = gs.set(create_kv("three", "value"));
+        gs.tick();
+        assert!(gs.wait_for_save().is_ok());
+        assert_eq!(gs.changes_since_save(), 0);
+        assert_eq!(GranatStore::load(&path).unwrap().keys("*").len(), 3);
+
+        let _ = fs::remove_file(&path);
+    }
+}
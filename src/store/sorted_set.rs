@@ -0,0 +1,1324 @@
+use anyhow::Result;
+use serde::de::Deserializer;
+use serde::ser::Serializer;
+use serde::{Deserialize, Serialize};
+
+use std::cmp::Ordering;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher};
+use std::ops::Bound;
+
+use crate::store::error::GranatError;
+
+/// A member of a sorted set along with its score
+pub type ScoredMember = (String, f64);
+
+/// Most levels a skiplist node can have, plenty for any set that fits in memory
+const MAX_LEVEL: usize = 32;
+
+/// Flags for `zadd`, mirroring the Redis `ZADD` options
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct AddOptions {
+    /// Only add new members, never update existing ones
+    pub nx: bool,
+    /// Only update existing members, never add new ones
+    pub xx: bool,
+    /// Only update a member if its new score is greater than the current one
+    pub gt: bool,
+    /// Only update a member if its new score is less than the current one
+    pub lt: bool,
+    /// Count members whose score changed as well as those added
+    pub ch: bool,
+}
+
+impl AddOptions {
+    /// Errors with `GranatError::IncompatibleOptions` for `NX` with `XX`, or
+    /// more than one of `GT`, `LT` and `NX`
+    fn validate(&self) -> Result<()> {
+        let exclusive = [self.gt, self.lt, self.nx].iter().filter(|f| **f).count();
+        if (self.nx && self.xx) || exclusive > 1 {
+            return Err(GranatError::IncompatibleOptions.into());
+        }
+
+        return Ok(());
+    }
+
+    /// Whether a member currently at `current` (`None` if new) may be set to `score`
+    fn allows(&self, current: Option<f64>, score: f64) -> bool {
+        match current {
+            None => return !self.xx,
+            Some(_) if self.nx => return false,
+            Some(current) if self.gt => return score > current,
+            Some(current) if self.lt => return score < current,
+            Some(_) => return true,
+        }
+    }
+}
+
+/// How scores are merged when combining sorted sets
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Aggregate {
+    #[default]
+    Sum,
+    Min,
+    Max,
+}
+
+impl Aggregate {
+    fn apply(&self, a: f64, b: f64) -> f64 {
+        match self {
+            Self::Sum => return a + b,
+            Self::Min => return a.min(b),
+            Self::Max => return a.max(b),
+        }
+    }
+}
+
+/// Ways of combining several sorted sets into one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum SortedSetOperation {
+    Union,
+    Intersection,
+}
+
+impl SortedSetOperation {
+    /// Combines `sets` with each one's scores multiplied by its weight, missing
+    /// keys should be passed as empty sets. Without `weights` every set counts once.
+    pub fn combine(
+        &self,
+        sets: Vec<&SortedSet>,
+        weights: Option<&[f64]>,
+        aggregate: Aggregate,
+    ) -> Result<SortedSet> {
+        if weights.is_some_and(|w| w.len() != sets.len()) {
+            return Err(GranatError::WeightCount.into());
+        }
+
+        let weight = |i: usize| weights.map_or(1., |w| w[i]);
+        let mut scores: HashMap<String, f64> = HashMap::new();
+        let mut sets = sets.into_iter().enumerate();
+        if let Some((i, first)) = sets.next() {
+            for (member, score) in first.scores.iter() {
+                scores.insert(member.clone(), weighted(*score, weight(i)));
+            }
+        }
+
+        for (i, set) in sets {
+            if *self == Self::Intersection {
+                scores.retain(|member, _| set.scores.contains_key(member));
+            }
+
+            for (member, score) in set.scores.iter() {
+                let score = weighted(*score, weight(i));
+                match scores.get_mut(member) {
+                    Some(current) => *current = aggregate.apply(*current, score),
+                    None if *self == Self::Union => {
+                        scores.insert(member.clone(), score);
+                    }
+                    None => {}
+                }
+            }
+        }
+
+        // Infinities cancelling out become zero, as in Redis
+        return Ok(scores
+            .into_iter()
+            .map(|(member, score)| (member, if score.is_nan() { 0. } else { score }))
+            .collect::<SortedSet>());
+    }
+}
+
+/// JSON has no infinities, so infinite scores are written the way Redis prints them
+#[derive(Deserialize, Serialize)]
+#[serde(untagged)]
+enum JsonScore {
+    Finite(f64),
+    Named(String),
+}
+
+impl From<f64> for JsonScore {
+    fn from(score: f64) -> Self {
+        match score {
+            f64::INFINITY => return Self::Named("inf".to_string()),
+            f64::NEG_INFINITY => return Self::Named("-inf".to_string()),
+            score => return Self::Finite(score),
+        }
+    }
+}
+
+impl JsonScore {
+    fn into_score<E: serde::de::Error>(self) -> Result<f64, E> {
+        match self {
+            Self::Finite(score) => return Ok(score),
+            Self::Named(name) => match name.parse::<f64>() {
+                Ok(score) if !score.is_nan() => return Ok(score),
+                _ => return Err(E::custom(format!("invalid score {name}"))),
+            },
+        }
+    }
+}
+
+/// Serde helpers for scored members that keep infinite scores intact
+pub(crate) mod scored_members {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        members: &[ScoredMember],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        return serializer.collect_seq(members.iter().map(|(m, s)| (m, JsonScore::from(*s))));
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<ScoredMember>, D::Error> {
+        return Vec::<(String, JsonScore)>::deserialize(deserializer)?
+            .into_iter()
+            .map(|(member, score)| Ok((member, score.into_score()?)))
+            .collect::<Result<Vec<ScoredMember>, D::Error>>();
+    }
+}
+
+/// Serde helpers for optional weights that keep infinite weights intact
+pub(crate) mod weights {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        weights: &Option<Vec<f64>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match weights {
+            Some(weights) => {
+                let weights = weights.iter().map(|w| JsonScore::from(*w));
+                return serializer.serialize_some(&weights.collect::<Vec<JsonScore>>());
+            }
+            None => return serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Vec<f64>>, D::Error> {
+        let Some(weights) = Option::<Vec<JsonScore>>::deserialize(deserializer)? else {
+            return Ok(None);
+        };
+
+        return weights
+            .into_iter()
+            .map(|w| w.into_score())
+            .collect::<Result<Vec<f64>, D::Error>>()
+            .map(Some);
+    }
+}
+
+fn compare(a: (f64, &str), b: (f64, &str)) -> Ordering {
+    return a.0.total_cmp(&b.0).then_with(|| a.1.cmp(b.1));
+}
+
+/// Whether `value` sits on the inside of `min`
+fn above<T: PartialOrd + ?Sized>(value: &T, min: Bound<&T>) -> bool {
+    match min {
+        Bound::Included(min) => return value >= min,
+        Bound::Excluded(min) => return value > min,
+        Bound::Unbounded => return true,
+    }
+}
+
+/// Whether `value` sits on the inside of `max`
+fn below<T: PartialOrd + ?Sized>(value: &T, max: Bound<&T>) -> bool {
+    match max {
+        Bound::Included(max) => return value <= max,
+        Bound::Excluded(max) => return value < max,
+        Bound::Unbounded => return true,
+    }
+}
+
+/// Forward pointer from a node, `span` is how many nodes it skips over, the
+/// node it lands on included. A link to nothing spans to the end of the list.
+#[derive(Debug, Clone, Copy, Default)]
+struct Link {
+    next: Option<usize>,
+    span: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    member: String,
+    score: f64,
+    levels: Vec<Link>,
+    prev: Option<usize>,
+}
+
+/// An indexable skiplist ordered by score then member, as Redis uses for
+/// sorted sets. Inserts, removals and rank lookups are O(log n).
+///
+/// Nodes live in a slab and point at each other by index, `None` standing
+/// in for the head on the way back and the end of the list on the way forward.
+#[derive(Debug, Clone)]
+struct SkipList {
+    nodes: Vec<Option<Node>>,
+    free: Vec<usize>,
+    head: [Link; MAX_LEVEL],
+    tail: Option<usize>,
+    level: usize,
+    len: usize,
+    seed: u64,
+}
+
+/// Last node passed on each level by a search, with the rank it was at
+type SearchPath = ([Option<usize>; MAX_LEVEL], [usize; MAX_LEVEL]);
+
+impl SkipList {
+    fn new() -> Self {
+        return Self {
+            nodes: vec![],
+            free: vec![],
+            head: [Link::default(); MAX_LEVEL],
+            tail: None,
+            level: 1,
+            len: 0,
+            // Xorshift can't start from zero
+            seed: RandomState::new().build_hasher().finish() | 1,
+        };
+    }
+
+    fn node(&self, idx: usize) -> &Node {
+        return self.nodes[idx]
+            .as_ref()
+            .expect("skiplist links only point at live nodes");
+    }
+
+    fn node_mut(&mut self, idx: usize) -> &mut Node {
+        return self.nodes[idx]
+            .as_mut()
+            .expect("skiplist links only point at live nodes");
+    }
+
+    fn link(&self, at: Option<usize>, level: usize) -> Link {
+        match at {
+            Some(idx) => return self.node(idx).levels[level],
+            None => return self.head[level],
+        }
+    }
+
+    fn link_mut(&mut self, at: Option<usize>, level: usize) -> &mut Link {
+        match at {
+            Some(idx) => return &mut self.node_mut(idx).levels[level],
+            None => return &mut self.head[level],
+        }
+    }
+
+    /// Each extra level is kept with a 1 in 4 chance
+    fn random_level(&mut self) -> usize {
+        let mut level = 1;
+        while level < MAX_LEVEL {
+            self.seed ^= self.seed << 13;
+            self.seed ^= self.seed >> 7;
+            self.seed ^= self.seed << 17;
+            if self.seed & 3 != 0 {
+                break;
+            }
+
+            level += 1;
+        }
+
+        return level;
+    }
+
+    /// Walks down from the top level, moving forward while `before` holds for
+    /// the next node, so the walk ends on the last node `before` holds for
+    fn search(&self, before: impl Fn(&Node) -> bool) -> SearchPath {
+        let mut path = [None; MAX_LEVEL];
+        let mut ranks = [0; MAX_LEVEL];
+        let (mut at, mut traversed) = (None, 0);
+
+        for level in (0..self.level).rev() {
+            loop {
+                let link = self.link(at, level);
+                match link.next {
+                    Some(next) if before(self.node(next)) => {
+                        traversed += link.span;
+                        at = Some(next);
+                    }
+                    _ => break,
+                }
+            }
+
+            path[level] = at;
+            ranks[level] = traversed;
+        }
+
+        return (path, ranks);
+    }
+
+    /// Inserts a member that isn't already in the list
+    fn insert(&mut self, score: f64, member: String) {
+        let (mut path, mut ranks) =
+            self.search(|n| compare((n.score, &n.member), (score, &member)) == Ordering::Less);
+
+        let level = self.random_level();
+        if level > self.level {
+            for lvl in self.level..level {
+                path[lvl] = None;
+                ranks[lvl] = 0;
+                self.head[lvl] = Link {
+                    next: None,
+                    span: self.len,
+                };
+            }
+
+            self.level = level;
+        }
+
+        let mut levels = vec![Link::default(); level];
+        for (lvl, link) in levels.iter_mut().enumerate() {
+            let before = self.link(path[lvl], lvl);
+            link.next = before.next;
+            link.span = before.span - (ranks[0] - ranks[lvl]);
+        }
+
+        let next = levels[0].next;
+        let node = Node {
+            member,
+            score,
+            levels,
+            prev: path[0],
+        };
+
+        let idx = match self.free.pop() {
+            Some(idx) => {
+                self.nodes[idx] = Some(node);
+                idx
+            }
+            None => {
+                self.nodes.push(Some(node));
+                self.nodes.len() - 1
+            }
+        };
+
+        for lvl in 0..level {
+            let link = self.link_mut(path[lvl], lvl);
+            link.next = Some(idx);
+            link.span = ranks[0] - ranks[lvl] + 1;
+        }
+
+        for (lvl, at) in path.iter().enumerate().take(self.level).skip(level) {
+            self.link_mut(*at, lvl).span += 1;
+        }
+
+        match next {
+            Some(next) => self.node_mut(next).prev = Some(idx),
+            None => self.tail = Some(idx),
+        }
+
+        self.len += 1;
+    }
+
+    /// Removes the member, returning whether it was there
+    fn remove(&mut self, score: f64, member: &str) -> bool {
+        let (path, _) =
+            self.search(|n| compare((n.score, &n.member), (score, member)) == Ordering::Less);
+
+        let Some(idx) = self.link(path[0], 0).next else {
+            return false;
+        };
+
+        if compare(
+            (self.node(idx).score, &self.node(idx).member),
+            (score, member),
+        ) != Ordering::Equal
+        {
+            return false;
+        }
+
+        for (lvl, at) in path.iter().enumerate().take(self.level) {
+            if self.link(*at, lvl).next == Some(idx) {
+                let removed = self.node(idx).levels[lvl];
+                let link = self.link_mut(*at, lvl);
+                link.span = link.span + removed.span - 1;
+                link.next = removed.next;
+            } else {
+                self.link_mut(*at, lvl).span -= 1;
+            }
+        }
+
+        let (next, prev) = (self.node(idx).levels[0].next, self.node(idx).prev);
+        match next {
+            Some(next) => self.node_mut(next).prev = prev,
+            None => self.tail = prev,
+        }
+
+        while self.level > 1 && self.head[self.level - 1].next.is_none() {
+            self.head[self.level - 1] = Link::default();
+            self.level -= 1;
+        }
+
+        self.nodes[idx] = None;
+        self.free.push(idx);
+        self.len -= 1;
+
+        return true;
+    }
+
+    /// Zero based rank of a member known to be in the list
+    fn rank(&self, score: f64, member: &str) -> usize {
+        let (_, ranks) =
+            self.search(|n| compare((n.score, &n.member), (score, member)) == Ordering::Less);
+
+        return ranks[0];
+    }
+
+    /// The node at the zero based `rank`
+    fn at_rank(&self, rank: usize) -> Option<usize> {
+        let target = rank + 1;
+        let (mut at, mut traversed) = (None, 0);
+        for level in (0..self.level).rev() {
+            loop {
+                let link = self.link(at, level);
+                match link.next {
+                    Some(next) if traversed + link.span <= target => {
+                        traversed += link.span;
+                        at = Some(next);
+                    }
+                    _ => break,
+                }
+            }
+
+            if traversed == target {
+                return at;
+            }
+        }
+
+        return None;
+    }
+
+    fn first(&self) -> Option<usize> {
+        return self.head[0].next;
+    }
+
+    fn next(&self, idx: usize) -> Option<usize> {
+        return self.node(idx).levels[0].next;
+    }
+
+    fn prev(&self, idx: usize) -> Option<usize> {
+        return self.node(idx).prev;
+    }
+
+    /// Walks the list from `start`, forwards or backwards, while `inside` holds
+    fn walk<'a>(
+        &'a self,
+        start: Option<usize>,
+        rev: bool,
+        inside: impl Fn(&Node) -> bool + 'a,
+    ) -> impl Iterator<Item = &'a Node> + 'a {
+        let mut at = start;
+        return std::iter::from_fn(move || {
+            let node = self.node(at?);
+            if !inside(node) {
+                return None;
+            }
+
+            let idx = at?;
+            at = match rev {
+                true => self.prev(idx),
+                false => self.next(idx),
+            };
+
+            return Some(node);
+        });
+    }
+}
+
+/// A set of unique members ordered by score, members with equal scores are
+/// ordered lexicographically.
+#[derive(Debug, Clone)]
+pub struct SortedSet {
+    scores: HashMap<String, f64>,
+    list: SkipList,
+}
+
+impl Default for SortedSet {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+impl SortedSet {
+    pub fn new() -> Self {
+        return Self {
+            scores: HashMap::new(),
+            list: SkipList::new(),
+        };
+    }
+
+    pub fn len(&self) -> usize {
+        return self.scores.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.scores.is_empty();
+    }
+
+    pub fn score(&self, member: impl AsRef<str>) -> Option<f64> {
+        return self.scores.get(member.as_ref()).copied();
+    }
+
+    /// Sets the member's score, returning its old one
+    pub fn insert(&mut self, member: impl AsRef<str>, score: f64) -> Option<f64> {
+        // Keep `-0.0` and `0.0` the same score
+        let score = score + 0.0;
+        let member = member.as_ref();
+        let old = self.scores.insert(member.to_string(), score);
+        if let Some(old) = old {
+            if old == score {
+                return Some(old);
+            }
+
+            self.list.remove(old, member);
+        }
+
+        self.list.insert(score, member.to_string());
+
+        return old;
+    }
+
+    /// Removes the member, returning its score
+    pub fn remove(&mut self, member: impl AsRef<str>) -> Option<f64> {
+        let score = self.scores.remove(member.as_ref())?;
+        self.list.remove(score, member.as_ref());
+
+        return Some(score);
+    }
+
+    /// Zero based rank of the member, counting from the highest score if `rev`
+    pub fn rank(&self, member: impl AsRef<str>, rev: bool) -> Option<usize> {
+        let score = self.score(member.as_ref())?;
+        let rank = self.list.rank(score, member.as_ref());
+        match rev {
+            true => return Some(self.len() - 1 - rank),
+            false => return Some(rank),
+        }
+    }
+
+    /// Every member in score order
+    pub fn iter(&self) -> impl Iterator<Item = ScoredMember> + '_ {
+        return self
+            .list
+            .walk(self.list.first(), false, |_| true)
+            .map(|n| (n.member.clone(), n.score));
+    }
+
+    /// Members from rank `start` to `stop` inclusive, negative ranks count back
+    /// from the end. With `rev` ranks count from the highest score.
+    pub fn range_by_rank(&self, start: isize, stop: isize, rev: bool) -> Vec<ScoredMember> {
+        let len = self.len() as isize;
+        let start = if start < 0 { len + start } else { start }.max(0);
+        let stop = if stop < 0 { len + stop } else { stop }.min(len - 1);
+        if start > stop || start >= len {
+            return vec![];
+        }
+
+        let first = match rev {
+            true => self.list.at_rank((len - 1 - start) as usize),
+            false => self.list.at_rank(start as usize),
+        };
+
+        return self
+            .list
+            .walk(first, rev, |_| true)
+            .take((stop - start + 1) as usize)
+            .map(|n| (n.member.clone(), n.score))
+            .collect::<Vec<ScoredMember>>();
+    }
+
+    /// First node inside `min`, or the last inside `max` for a reverse walk
+    fn score_start(&self, min: Bound<&f64>, max: Bound<&f64>, rev: bool) -> Option<usize> {
+        if rev {
+            let (path, _) = self.list.search(|n| below(&n.score, max));
+            return path[0];
+        }
+
+        let (path, _) = self.list.search(|n| !above(&n.score, min));
+        return self.list.link(path[0], 0).next;
+    }
+
+    /// Members with scores between `min` and `max`, highest first if `rev`.
+    ///
+    /// `limit` skips `offset` matches then returns at most `count`.
+    pub fn range_by_score(
+        &self,
+        min: Bound<f64>,
+        max: Bound<f64>,
+        rev: bool,
+        limit: Option<(usize, usize)>,
+    ) -> Vec<ScoredMember> {
+        let (min, max) = (min.as_ref(), max.as_ref());
+        let (offset, count) = limit.unwrap_or((0, usize::MAX));
+
+        return self
+            .list
+            .walk(self.score_start(min, max, rev), rev, move |n| {
+                return above(&n.score, min) && below(&n.score, max);
+            })
+            .skip(offset)
+            .take(count)
+            .map(|n| (n.member.clone(), n.score))
+            .collect::<Vec<ScoredMember>>();
+    }
+
+    /// Members between `min` and `max` compared lexicographically, like Redis
+    /// `ZRANGEBYLEX` this assumes every member has the same score.
+    pub fn range_by_lex(
+        &self,
+        min: Bound<String>,
+        max: Bound<String>,
+        rev: bool,
+        limit: Option<(usize, usize)>,
+    ) -> Vec<String> {
+        let min = min.as_ref().map(|m| m.as_str());
+        let max = max.as_ref().map(|m| m.as_str());
+        let (offset, count) = limit.unwrap_or((0, usize::MAX));
+
+        let start = match rev {
+            true => self.list.search(|n| below(n.member.as_str(), max)).0[0],
+            false => {
+                let (path, _) = self.list.search(|n| !above(n.member.as_str(), min));
+                self.list.link(path[0], 0).next
+            }
+        };
+
+        return self
+            .list
+            .walk(start, rev, move |n| {
+                return above(n.member.as_str(), min) && below(n.member.as_str(), max);
+            })
+            .skip(offset)
+            .take(count)
+            .map(|n| n.member.clone())
+            .collect::<Vec<String>>();
+    }
+
+    /// Number of members with scores between `min` and `max`
+    pub fn count(&self, min: Bound<f64>, max: Bound<f64>) -> usize {
+        let (min, max) = (min.as_ref(), max.as_ref());
+        let (_, before_min) = self.list.search(|n| !above(&n.score, min));
+        let (_, up_to_max) = self.list.search(|n| below(&n.score, max));
+
+        return up_to_max[0].saturating_sub(before_min[0]);
+    }
+
+    /// Removes up to `count` members from the lowest scores, or the highest if `rev`
+    pub fn pop(&mut self, count: usize, rev: bool) -> Vec<ScoredMember> {
+        let mut popped = vec![];
+        while popped.len() < count {
+            let at = match rev {
+                true => self.list.tail,
+                false => self.list.first(),
+            };
+
+            let Some(idx) = at else {
+                break;
+            };
+
+            let member = self.list.node(idx).member.clone();
+            if let Some(score) = self.remove(&member) {
+                popped.push((member, score));
+            }
+        }
+
+        return popped;
+    }
+}
+
+impl FromIterator<ScoredMember> for SortedSet {
+    fn from_iter<I: IntoIterator<Item = ScoredMember>>(iter: I) -> Self {
+        let mut set = SortedSet::new();
+        for (member, score) in iter {
+            set.insert(member, score);
+        }
+
+        return set;
+    }
+}
+
+// Written as plain `[member, score]` pairs in score order, the skiplist is rebuilt on load
+
+impl Serialize for SortedSet {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        return scored_members::serialize(&self.iter().collect::<Vec<ScoredMember>>(), serializer);
+    }
+}
+
+impl<'de> Deserialize<'de> for SortedSet {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let members = scored_members::deserialize(deserializer)?;
+        return Ok(members.into_iter().collect::<SortedSet>());
+    }
+}
+
+/// Sorted sets of unique members, each with a score.
+///
+/// Like sets, members are plain strings that only expire with their key.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SortedSetStore {
+    pub store: HashMap<String, SortedSet>,
+}
+
+impl Default for SortedSetStore {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+impl SortedSetStore {
+    pub fn new() -> Self {
+        return Self {
+            store: HashMap::new(),
+        };
+    }
+
+    /// Adds or updates members as `opts` allows, like Redis `ZADD`.
+    ///
+    /// Returns the number of members added, or added and changed with `opts.ch`.
+    pub fn add(
+        &mut self,
+        key: impl AsRef<str>,
+        members: Vec<ScoredMember>,
+        opts: AddOptions,
+    ) -> Result<usize> {
+        opts.validate()?;
+        if members.iter().any(|(_, score)| score.is_nan()) {
+            return Err(GranatError::NotANumber.into());
+        }
+
+        let set = self.store.entry(key.as_ref().to_string()).or_default();
+        let mut changed = 0;
+        for (member, score) in members.into_iter() {
+            let current = set.score(&member);
+            if !opts.allows(current, score) {
+                continue;
+            }
+
+            match set.insert(member, score) {
+                None => changed += 1,
+                Some(old) if opts.ch && old != score => changed += 1,
+                Some(_) => {}
+            }
+        }
+
+        if set.is_empty() {
+            self.store.remove(key.as_ref());
+        }
+
+        return Ok(changed);
+    }
+
+    /// Adds `incr` to the member's score as `opts` allows, like `ZADD INCR`.
+    ///
+    /// Returns the new score, `None` if `opts` stopped the update.
+    pub fn add_incr(
+        &mut self,
+        key: impl AsRef<str>,
+        member: impl AsRef<str>,
+        incr: f64,
+        opts: AddOptions,
+    ) -> Result<Option<f64>> {
+        opts.validate()?;
+        let current = self
+            .store
+            .get(key.as_ref())
+            .and_then(|set| set.score(member.as_ref()));
+
+        let score = current.unwrap_or(0.) + incr;
+        if score.is_nan() {
+            return Err(GranatError::NotANumber.into());
+        }
+
+        if !opts.allows(current, score) {
+            return Ok(None);
+        }
+
+        self.store
+            .entry(key.as_ref().to_string())
+            .or_default()
+            .insert(member, score);
+
+        return Ok(Some(score));
+    }
+
+    /// Removes the members, dropping the set once it's empty.
+    ///
+    /// Returns the number of members removed.
+    pub fn remove(&mut self, key: impl AsRef<str>, members: Vec<impl AsRef<str>>) -> usize {
+        let Some(set) = self.store.get_mut(key.as_ref()) else {
+            return 0;
+        };
+
+        let removed = members
+            .iter()
+            .filter(|m| set.remove(m.as_ref()).is_some())
+            .count();
+
+        if set.is_empty() {
+            self.store.remove(key.as_ref());
+        }
+
+        return removed;
+    }
+
+    pub fn card(&self, key: impl AsRef<str>) -> usize {
+        return self.store.get(key.as_ref()).map_or(0, |set| set.len());
+    }
+
+    pub fn score(&self, key: impl AsRef<str>, member: impl AsRef<str>) -> Option<f64> {
+        return self.store.get(key.as_ref())?.score(member);
+    }
+
+    /// Zero based rank of the member, counting from the highest score if `rev`
+    pub fn rank(&self, key: impl AsRef<str>, member: impl AsRef<str>, rev: bool) -> Option<usize> {
+        return self.store.get(key.as_ref())?.rank(member, rev);
+    }
+
+    pub fn range_by_rank(
+        &self,
+        key: impl AsRef<str>,
+        start: isize,
+        stop: isize,
+        rev: bool,
+    ) -> Vec<ScoredMember> {
+        match self.store.get(key.as_ref()) {
+            Some(set) => return set.range_by_rank(start, stop, rev),
+            None => return vec![],
+        }
+    }
+
+    pub fn range_by_score(
+        &self,
+        key: impl AsRef<str>,
+        min: Bound<f64>,
+        max: Bound<f64>,
+        rev: bool,
+        limit: Option<(usize, usize)>,
+    ) -> Vec<ScoredMember> {
+        match self.store.get(key.as_ref()) {
+            Some(set) => return set.range_by_score(min, max, rev, limit),
+            None => return vec![],
+        }
+    }
+
+    pub fn range_by_lex(
+        &self,
+        key: impl AsRef<str>,
+        min: Bound<String>,
+        max: Bound<String>,
+        rev: bool,
+        limit: Option<(usize, usize)>,
+    ) -> Vec<String> {
+        match self.store.get(key.as_ref()) {
+            Some(set) => return set.range_by_lex(min, max, rev, limit),
+            None => return vec![],
+        }
+    }
+
+    pub fn count(&self, key: impl AsRef<str>, min: Bound<f64>, max: Bound<f64>) -> usize {
+        return self
+            .store
+            .get(key.as_ref())
+            .map_or(0, |set| set.count(min, max));
+    }
+
+    /// Pops up to `count` members with the lowest scores, or the highest if `rev`
+    pub fn pop(&mut self, key: impl AsRef<str>, count: usize, rev: bool) -> Vec<ScoredMember> {
+        let Some(set) = self.store.get_mut(key.as_ref()) else {
+            return vec![];
+        };
+
+        let popped = set.pop(count, rev);
+        if set.is_empty() {
+            self.store.remove(key.as_ref());
+        }
+
+        return popped;
+    }
+
+    /// Combines the sorted sets at `keys`, missing keys count as empty sets
+    pub fn combine(
+        &self,
+        op: SortedSetOperation,
+        keys: &[impl AsRef<str>],
+        weights: Option<&[f64]>,
+        aggregate: Aggregate,
+    ) -> Result<SortedSet> {
+        let empty = SortedSet::new();
+        let sets = keys
+            .iter()
+            .map(|k| self.store.get(k.as_ref()).unwrap_or(&empty))
+            .collect::<Vec<&SortedSet>>();
+
+        return op.combine(sets, weights, aggregate);
+    }
+
+    /// Combines the sorted sets at `keys` into `dst`, replacing whatever it held.
+    ///
+    /// Returns the size of the new set, `dst` is removed if it's empty.
+    pub fn combine_store(
+        &mut self,
+        op: SortedSetOperation,
+        dst: impl AsRef<str>,
+        keys: &[impl AsRef<str>],
+        weights: Option<&[f64]>,
+        aggregate: Aggregate,
+    ) -> Result<usize> {
+        let result = self.combine(op, keys, weights, aggregate)?;
+        let size = result.len();
+        if result.is_empty() {
+            self.store.remove(dst.as_ref());
+        } else {
+            self.store.insert(dst.as_ref().to_string(), result);
+        }
+
+        return Ok(size);
+    }
+}
+
+/// A weight of zero zeroes the score, even an infinite one
+fn weighted(score: f64, weight: f64) -> f64 {
+    if weight == 0. {
+        return 0.;
+    }
+
+    return score * weight;
+}
+
+#[cfg(test)]
+mod sorted_set_store_tests {
+    use super::*;
+
+    fn scored(members: &[(&str, f64)]) -> Vec<ScoredMember> {
+        return members
+            .iter()
+            .map(|(m, s)| (m.to_string(), *s))
+            .collect::<Vec<ScoredMember>>();
+    }
+
+    fn members(scored: Vec<ScoredMember>) -> Vec<String> {
+        return scored.into_iter().map(|(m, _)| m).collect::<Vec<String>>();
+    }
+
+    fn create_basic_sorted_set_store() -> SortedSetStore {
+        let mut zs = SortedSetStore::new();
+        let _ = zs.add(
+            "board",
+            scored(&[("a", 1.), ("b", 2.), ("c", 3.), ("d", 3.), ("e", 5.)]),
+            AddOptions::default(),
+        );
+
+        return zs;
+    }
+
+    #[test]
+    fn skiplist_stays_ordered() {
+        let mut set = SortedSet::new();
+        for i in 0..1000 {
+            set.insert(format!("member-{i}"), ((i * 7919) % 1000) as f64);
+        }
+
+        for i in (0..1000).step_by(3) {
+            set.remove(format!("member-{i}"));
+        }
+
+        let all = set.iter().collect::<Vec<ScoredMember>>();
+        assert_eq!(all.len(), set.len());
+        assert!(all
+            .windows(2)
+            .all(|w| compare((w[0].1, &w[0].0), (w[1].1, &w[1].0)) == Ordering::Less));
+
+        for (rank, (member, _)) in all.iter().enumerate() {
+            assert_eq!(set.rank(member, false), Some(rank));
+            assert_eq!(
+                set.range_by_rank(rank as isize, rank as isize, false)[0].0,
+                *member
+            );
+        }
+    }
+
+    #[test]
+    fn add_with_options() {
+        let mut zs = create_basic_sorted_set_store();
+        assert_eq!(zs.card("board"), 5);
+
+        let nx = AddOptions {
+            nx: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            zs.add("board", scored(&[("a", 10.), ("f", 6.)]), nx)
+                .unwrap(),
+            1
+        );
+        assert_eq!(zs.score("board", "a"), Some(1.));
+
+        let xx = AddOptions {
+            xx: true,
+            ch: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            zs.add("board", scored(&[("a", 10.), ("g", 7.)]), xx)
+                .unwrap(),
+            1
+        );
+        assert_eq!(zs.score("board", "a"), Some(10.));
+        assert_eq!(zs.score("board", "g"), None);
+
+        let gt = AddOptions {
+            gt: true,
+            ..Default::default()
+        };
+        let _ = zs.add("board", scored(&[("a", 4.), ("b", 4.)]), gt);
+        assert_eq!(zs.score("board", "a"), Some(10.));
+        assert_eq!(zs.score("board", "b"), Some(4.));
+
+        let lt = AddOptions {
+            lt: true,
+            ..Default::default()
+        };
+        let _ = zs.add("board", scored(&[("a", 0.), ("e", 9.)]), lt);
+        assert_eq!(zs.score("board", "a"), Some(0.));
+        assert_eq!(zs.score("board", "e"), Some(5.));
+
+        let bad = AddOptions {
+            nx: true,
+            xx: true,
+            ..Default::default()
+        };
+        let err = zs.add("board", scored(&[("a", 1.)]), bad).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<GranatError>(),
+            Some(&GranatError::IncompatibleOptions)
+        );
+        let err = zs
+            .add("board", scored(&[("a", f64::NAN)]), AddOptions::default())
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<GranatError>(),
+            Some(&GranatError::NotANumber)
+        );
+    }
+
+    #[test]
+    fn add_incr() {
+        let mut zs = create_basic_sorted_set_store();
+        let opts = AddOptions::default();
+        assert_eq!(zs.add_incr("board", "a", 2.5, opts).unwrap(), Some(3.5));
+        assert_eq!(zs.add_incr("board", "new", 1., opts).unwrap(), Some(1.));
+
+        let gt = AddOptions {
+            gt: true,
+            ..Default::default()
+        };
+        assert_eq!(zs.add_incr("board", "a", -1., gt).unwrap(), None);
+        assert_eq!(zs.score("board", "a"), Some(3.5));
+
+        let _ = zs.add_incr("board", "inf", f64::INFINITY, opts);
+        let err = zs
+            .add_incr("board", "inf", f64::NEG_INFINITY, opts)
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<GranatError>(),
+            Some(&GranatError::NotANumber)
+        );
+    }
+
+    #[test]
+    fn remove_and_rank() {
+        let mut zs = create_basic_sorted_set_store();
+        assert_eq!(zs.rank("board", "a", false), Some(0));
+        assert_eq!(zs.rank("board", "d", false), Some(3));
+        assert_eq!(zs.rank("board", "e", true), Some(0));
+        assert_eq!(zs.rank("board", "missing", false), None);
+
+        assert_eq!(zs.remove("board", vec!["a", "missing"]), 1);
+        assert_eq!(zs.rank("board", "d", false), Some(2));
+        assert_eq!(zs.remove("board", vec!["b", "c", "d", "e"]), 4);
+        assert!(!zs.store.contains_key("board"));
+    }
+
+    #[test]
+    fn range_by_rank() {
+        let zs = create_basic_sorted_set_store();
+        assert_eq!(
+            members(zs.range_by_rank("board", 0, -1, false)),
+            vec!["a", "b", "c", "d", "e"]
+        );
+        assert_eq!(
+            members(zs.range_by_rank("board", 1, 2, false)),
+            vec!["b", "c"]
+        );
+        assert_eq!(
+            members(zs.range_by_rank("board", 0, 1, true)),
+            vec!["e", "d"]
+        );
+        assert_eq!(
+            members(zs.range_by_rank("board", -2, 10, false)),
+            vec!["d", "e"]
+        );
+        assert!(zs.range_by_rank("board", 3, 1, false).is_empty());
+        assert!(zs.range_by_rank("missing", 0, -1, false).is_empty());
+    }
+
+    #[test]
+    fn range_by_score() {
+        let zs = create_basic_sorted_set_store();
+        let range = zs.range_by_score(
+            "board",
+            Bound::Included(2.),
+            Bound::Included(3.),
+            false,
+            None,
+        );
+        assert_eq!(range, scored(&[("b", 2.), ("c", 3.), ("d", 3.)]));
+
+        let range = zs.range_by_score("board", Bound::Excluded(2.), Bound::Unbounded, false, None);
+        assert_eq!(members(range), vec!["c", "d", "e"]);
+
+        let range = zs.range_by_score("board", Bound::Unbounded, Bound::Excluded(3.), true, None);
+        assert_eq!(members(range), vec!["b", "a"]);
+
+        let range = zs.range_by_score(
+            "board",
+            Bound::Unbounded,
+            Bound::Unbounded,
+            true,
+            Some((1, 2)),
+        );
+        assert_eq!(members(range), vec!["d", "c"]);
+
+        assert!(zs
+            .range_by_score(
+                "board",
+                Bound::Included(3.5),
+                Bound::Included(4.),
+                false,
+                None
+            )
+            .is_empty());
+        assert_eq!(
+            zs.count("board", Bound::Included(2.), Bound::Included(3.)),
+            3
+        );
+        assert_eq!(zs.count("board", Bound::Excluded(3.), Bound::Unbounded), 1);
+        assert_eq!(
+            zs.count("board", Bound::Included(6.), Bound::Included(1.)),
+            0
+        );
+    }
+
+    #[test]
+    fn range_by_lex() {
+        let mut zs = SortedSetStore::new();
+        let _ = zs.add(
+            "names",
+            scored(&[("alpha", 0.), ("bravo", 0.), ("charlie", 0.), ("delta", 0.)]),
+            AddOptions::default(),
+        );
+
+        let range = zs.range_by_lex(
+            "names",
+            Bound::Included("b".to_string()),
+            Bound::Excluded("d".to_string()),
+            false,
+            None,
+        );
+        assert_eq!(range, vec!["bravo", "charlie"]);
+
+        let range = zs.range_by_lex(
+            "names",
+            Bound::Unbounded,
+            Bound::Unbounded,
+            true,
+            Some((1, 2)),
+        );
+        assert_eq!(range, vec!["charlie", "bravo"]);
+
+        let range = zs.range_by_lex(
+            "names",
+            Bound::Excluded("alpha".to_string()),
+            Bound::Included("bravo".to_string()),
+            false,
+            None,
+        );
+        assert_eq!(range, vec!["bravo"]);
+    }
+
+    #[test]
+    fn pop_min_and_max() {
+        let mut zs = create_basic_sorted_set_store();
+        assert_eq!(zs.pop("board", 2, false), scored(&[("a", 1.), ("b", 2.)]));
+        assert_eq!(zs.pop("board", 1, true), scored(&[("e", 5.)]));
+        assert_eq!(zs.pop("board", 5, false).len(), 2);
+        assert!(!zs.store.contains_key("board"));
+        assert!(zs.pop("board", 1, false).is_empty());
+    }
+
+    #[test]
+    fn union_and_intersection() {
+        let a = scored(&[("x", 1.), ("y", 2.)])
+            .into_iter()
+            .collect::<SortedSet>();
+        let b = scored(&[("y", 3.), ("z", 4.)])
+            .into_iter()
+            .collect::<SortedSet>();
+
+        let union = SortedSetOperation::Union
+            .combine(vec![&a, &b], None, Aggregate::Sum)
+            .unwrap();
+        assert_eq!(
+            union.iter().collect::<Vec<ScoredMember>>(),
+            scored(&[("x", 1.), ("z", 4.), ("y", 5.)])
+        );
+
+        let inter = SortedSetOperation::Intersection
+            .combine(vec![&a, &b], Some(&[2., 1.]), Aggregate::Max)
+            .unwrap();
+        assert_eq!(
+            inter.iter().collect::<Vec<ScoredMember>>(),
+            scored(&[("y", 4.)])
+        );
+
+        let min = SortedSetOperation::Union
+            .combine(vec![&a, &b], None, Aggregate::Min)
+            .unwrap();
+        assert_eq!(min.score("y"), Some(2.));
+
+        let empty = SortedSet::new();
+        let inter =
+            SortedSetOperation::Intersection.combine(vec![&a, &empty], None, Aggregate::Sum);
+        assert!(inter.unwrap().is_empty());
+
+        let err = SortedSetOperation::Union.combine(vec![&a, &b], Some(&[1.]), Aggregate::Sum);
+        assert_eq!(
+            err.unwrap_err().downcast_ref::<GranatError>(),
+            Some(&GranatError::WeightCount)
+        );
+    }
+
+    #[test]
+    fn infinite_scores_survive_serialisation() {
+        let set = scored(&[
+            ("low", f64::NEG_INFINITY),
+            ("mid", 1.5),
+            ("high", f64::INFINITY),
+        ])
+        .into_iter()
+        .collect::<SortedSet>();
+
+        let json = serde_json::to_string(&set).unwrap();
+        let loaded = serde_json::from_str::<SortedSet>(&json).unwrap();
+        assert_eq!(
+            loaded.iter().collect::<Vec<ScoredMember>>(),
+            set.iter().collect::<Vec<ScoredMember>>()
+        );
+    }
+}
@@ -0,0 +1,886 @@
+use anyhow::Result;
+use serde::de::Deserializer;
+use serde::ser::Serializer;
+use serde::{Deserialize, Serialize};
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::ops::Bound;
+use std::str::FromStr;
+
+use crate::store::clock::{system_clock, SharedClock};
+use crate::store::error::GranatError;
+
+/// Field value pairs of a stream entry, in the order they were added
+pub type StreamFields = Vec<(String, String)>;
+
+/// An entry of a stream with its ID
+pub type StreamEntry = (StreamId, StreamFields);
+
+/// ID of a stream entry, the millisecond it was added at and a sequence
+/// number for entries added within the same millisecond.
+///
+/// Written as `<ms>-<seq>`, the same as Redis.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl StreamId {
+    pub const MIN: StreamId = StreamId { ms: 0, seq: 0 };
+    pub const MAX: StreamId = StreamId {
+        ms: u64::MAX,
+        seq: u64::MAX,
+    };
+
+    pub fn new(ms: u64, seq: u64) -> Self {
+        return Self { ms, seq };
+    }
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+impl FromStr for StreamId {
+    type Err = GranatError;
+
+    /// Parses `<ms>-<seq>`, a bare `<ms>` has a sequence number of `0`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (ms, seq) = s.split_once('-').unwrap_or((s, "0"));
+        match (ms.parse::<u64>(), seq.parse::<u64>()) {
+            (Ok(ms), Ok(seq)) => return Ok(Self::new(ms, seq)),
+            _ => return Err(GranatError::InvalidStreamId),
+        }
+    }
+}
+
+// Written as strings so IDs can key JSON maps
+
+impl Serialize for StreamId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        return serializer.collect_str(self);
+    }
+}
+
+impl<'de> Deserialize<'de> for StreamId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let id = String::deserialize(deserializer)?;
+        return id.parse::<StreamId>().map_err(serde::de::Error::custom);
+    }
+}
+
+/// How the ID of a new entry is picked, like the ID argument to Redis `XADD`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum NewId {
+    /// `*`, the current time with the next free sequence number
+    Auto,
+    /// `<ms>-*`, the given millisecond with the next free sequence number
+    AutoSeq(u64),
+    /// Exactly this ID, which has to be greater than the stream's last ID
+    Explicit(StreamId),
+}
+
+/// How a stream is trimmed, like the `MAXLEN` and `MINID` options of Redis `XTRIM`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum TrimStrategy {
+    /// Keep at most this many of the newest entries
+    MaxLen(usize),
+    /// Drop every entry with an ID lower than this one
+    MinId(StreamId),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+struct PendingEntry {
+    consumer: String,
+    delivered_at: i64,
+    deliveries: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+struct ConsumerGroup {
+    last_delivered: StreamId,
+    pending: BTreeMap<StreamId, PendingEntry>,
+    /// Each consumer with when it was last active
+    consumers: BTreeMap<String, i64>,
+}
+
+/// An append-only log of entries ordered by ID, with the consumer groups reading it
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Stream {
+    entries: BTreeMap<StreamId, StreamFields>,
+    /// The highest ID ever added, new IDs have to be above it even once it's trimmed
+    last_id: StreamId,
+    groups: BTreeMap<String, ConsumerGroup>,
+}
+
+impl Stream {
+    /// Drops entries as `strategy` says, returning how many were dropped.
+    ///
+    /// Pending entries for dropped messages are dropped with them, there's
+    /// nothing left for a consumer to be given.
+    fn trim(&mut self, strategy: TrimStrategy) -> usize {
+        let keep_from = match strategy {
+            TrimStrategy::MaxLen(len) => {
+                let excess = self.entries.len().saturating_sub(len);
+                match self.entries.keys().nth(excess) {
+                    Some(id) => *id,
+                    None => StreamId::MAX,
+                }
+            }
+            TrimStrategy::MinId(id) => id,
+        };
+
+        let before = self.entries.len();
+        self.entries = self.entries.split_off(&keep_from);
+        for group in self.groups.values_mut() {
+            group.pending = group.pending.split_off(&keep_from);
+        }
+
+        return before - self.entries.len();
+    }
+}
+
+/// `BTreeMap::range` panics on inverted ranges, they're just empty here
+fn is_empty_range(start: Bound<&StreamId>, end: Bound<&StreamId>) -> bool {
+    match (start, end) {
+        (Bound::Included(s), Bound::Included(e)) => return s > e,
+        (Bound::Included(s) | Bound::Excluded(s), Bound::Included(e) | Bound::Excluded(e)) => {
+            return s >= e;
+        }
+        _ => return false,
+    }
+}
+
+/// What `StreamStore::info` reports about a stream
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamInfo {
+    pub length: usize,
+    pub last_id: StreamId,
+    pub groups: usize,
+    pub first: Option<StreamEntry>,
+    pub last: Option<StreamEntry>,
+}
+
+/// What `StreamStore::groups` reports about a consumer group
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupInfo {
+    pub name: String,
+    pub consumers: usize,
+    pub pending: usize,
+    pub last_delivered: StreamId,
+}
+
+/// What `StreamStore::consumers` reports about a consumer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConsumerInfo {
+    pub name: String,
+    pub pending: usize,
+    /// Milliseconds since the consumer last read or claimed anything
+    pub idle: u64,
+}
+
+/// A delivered entry that hasn't been acknowledged yet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingMessage {
+    pub id: StreamId,
+    pub consumer: String,
+    /// Milliseconds since the entry was last delivered
+    pub idle: u64,
+    pub deliveries: u64,
+}
+
+/// Streams of entries with auto-generated time based IDs, each read by any
+/// number of consumer groups that track what they've been given.
+///
+/// Unlike lists and sets an emptied stream keeps its key, along with its last
+/// ID and groups, until it's deleted.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct StreamStore {
+    pub store: HashMap<String, Stream>,
+
+    #[serde(skip, default = "system_clock")]
+    clock: SharedClock,
+}
+
+impl Default for StreamStore {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+impl StreamStore {
+    pub fn new() -> Self {
+        return Self::with_clock(system_clock());
+    }
+
+    /// Creates a store that generates IDs and idle times from `clock`
+    pub fn with_clock(clock: SharedClock) -> Self {
+        return Self {
+            store: HashMap::new(),
+            clock,
+        };
+    }
+
+    /// Appends an entry, creating the stream if needed, then trims it if asked to.
+    ///
+    /// Errors with `GranatError::StreamIdTooSmall` if the ID isn't above the
+    /// stream's last ID. Returns the ID the entry was added with.
+    pub fn add(
+        &mut self,
+        key: impl AsRef<str>,
+        id: NewId,
+        fields: StreamFields,
+        trim: Option<TrimStrategy>,
+    ) -> Result<StreamId> {
+        let last = self.last_id(key.as_ref()).unwrap_or_default();
+        let id = match id {
+            NewId::Explicit(id) => id,
+            NewId::AutoSeq(ms) if ms == last.ms => StreamId::new(ms, last.seq.saturating_add(1)),
+            // `0-0` is never a valid ID
+            NewId::AutoSeq(ms) => StreamId::new(ms, (ms == 0) as u64),
+            NewId::Auto => {
+                let now = self.clock.now_millis().max(0) as u64;
+                match now > last.ms {
+                    true => StreamId::new(now, 0),
+                    false => StreamId::new(last.ms, last.seq.saturating_add(1)),
+                }
+            }
+        };
+
+        if id <= last || id == StreamId::MAX {
+            return Err(GranatError::StreamIdTooSmall.into());
+        }
+
+        let stream = self.store.entry(key.as_ref().to_string()).or_default();
+        stream.entries.insert(id, fields);
+        stream.last_id = id;
+        if let Some(strategy) = trim {
+            stream.trim(strategy);
+        }
+
+        return Ok(id);
+    }
+
+    /// Trims the stream, returning how many entries were dropped
+    pub fn trim(&mut self, key: impl AsRef<str>, strategy: TrimStrategy) -> usize {
+        match self.store.get_mut(key.as_ref()) {
+            Some(stream) => return stream.trim(strategy),
+            None => return 0,
+        }
+    }
+
+    pub fn len(&self, key: impl AsRef<str>) -> usize {
+        return self
+            .store
+            .get(key.as_ref())
+            .map_or(0, |stream| stream.entries.len());
+    }
+
+    /// The highest ID ever added to the stream, `None` if it doesn't exist
+    pub fn last_id(&self, key: impl AsRef<str>) -> Option<StreamId> {
+        return self.store.get(key.as_ref()).map(|stream| stream.last_id);
+    }
+
+    /// Entries with IDs between `start` and `end`, newest first if `rev`
+    pub fn range(
+        &self,
+        key: impl AsRef<str>,
+        start: Bound<StreamId>,
+        end: Bound<StreamId>,
+        count: Option<usize>,
+        rev: bool,
+    ) -> Vec<StreamEntry> {
+        let Some(stream) = self.store.get(key.as_ref()) else {
+            return vec![];
+        };
+
+        if is_empty_range(start.as_ref(), end.as_ref()) {
+            return vec![];
+        }
+
+        let range = stream.entries.range((start, end));
+        let entries: Box<dyn Iterator<Item = (&StreamId, &StreamFields)>> = match rev {
+            true => Box::new(range.rev()),
+            false => Box::new(range),
+        };
+
+        return entries
+            .take(count.unwrap_or(usize::MAX))
+            .map(|(id, fields)| (*id, fields.clone()))
+            .collect::<Vec<StreamEntry>>();
+    }
+
+    /// Up to `count` entries with IDs above `after`, like Redis `XREAD`
+    pub fn read(
+        &self,
+        key: impl AsRef<str>,
+        after: StreamId,
+        count: Option<usize>,
+    ) -> Vec<StreamEntry> {
+        return self.range(key, Bound::Excluded(after), Bound::Unbounded, count, false);
+    }
+
+    /// Creates a consumer group that'll be given entries after `start`, or
+    /// after the stream's last ID with `None`. An empty stream is created for
+    /// it with `mkstream`, otherwise the stream has to exist.
+    ///
+    /// Returns the ID the group starts from.
+    pub fn create_group(
+        &mut self,
+        key: impl AsRef<str>,
+        group: impl AsRef<str>,
+        start: Option<StreamId>,
+        mkstream: bool,
+    ) -> Result<StreamId> {
+        if mkstream {
+            self.store.entry(key.as_ref().to_string()).or_default();
+        }
+
+        let Some(stream) = self.store.get_mut(key.as_ref()) else {
+            return Err(GranatError::NoSuchKey.into());
+        };
+
+        if stream.groups.contains_key(group.as_ref()) {
+            return Err(GranatError::GroupExists.into());
+        }
+
+        let start = start.unwrap_or(stream.last_id);
+        stream.groups.insert(
+            group.as_ref().to_string(),
+            ConsumerGroup {
+                last_delivered: start,
+                ..Default::default()
+            },
+        );
+
+        return Ok(start);
+    }
+
+    /// Removes the consumer group with its pending entries, returning whether it existed
+    pub fn destroy_group(&mut self, key: impl AsRef<str>, group: impl AsRef<str>) -> bool {
+        return self
+            .store
+            .get_mut(key.as_ref())
+            .is_some_and(|stream| stream.groups.remove(group.as_ref()).is_some());
+    }
+
+    fn group(&self, key: &str, group: &str) -> Result<&ConsumerGroup> {
+        match self
+            .store
+            .get(key)
+            .and_then(|stream| stream.groups.get(group))
+        {
+            Some(group) => return Ok(group),
+            None => return Err(GranatError::NoSuchGroup.into()),
+        }
+    }
+
+    /// Reads entries as `consumer` of the group, like Redis `XREADGROUP`.
+    ///
+    /// With `after` as `None` this hands out entries no one in the group has
+    /// been given yet, adding them to the consumer's pending entries unless
+    /// `noack`. Otherwise it re-reads the consumer's own pending entries above `after`.
+    pub fn read_group(
+        &mut self,
+        key: impl AsRef<str>,
+        group: impl AsRef<str>,
+        consumer: impl AsRef<str>,
+        after: Option<StreamId>,
+        count: Option<usize>,
+        noack: bool,
+    ) -> Result<Vec<StreamEntry>> {
+        let (key, consumer) = (key.as_ref(), consumer.as_ref());
+        let cg = self.group(key, group.as_ref())?;
+        let now = self.clock.now_millis();
+
+        let Some(after) = after else {
+            let entries = self.read(key, cg.last_delivered, count);
+            let last = entries.last().map_or(cg.last_delivered, |(id, _)| *id);
+            let ids = match noack {
+                true => vec![],
+                false => entries.iter().map(|(id, _)| *id).collect::<Vec<StreamId>>(),
+            };
+
+            self.deliver(key, group, consumer, &ids, now, last)?;
+            return Ok(entries);
+        };
+
+        let stream = &self.store[key];
+        let entries = cg
+            .pending
+            .range((Bound::Excluded(after), Bound::Unbounded))
+            .filter(|(_, pending)| pending.consumer == consumer)
+            .filter_map(|(id, _)| Some((*id, stream.entries.get(id)?.clone())))
+            .take(count.unwrap_or(usize::MAX))
+            .collect::<Vec<StreamEntry>>();
+
+        self.deliver(key, group, consumer, &[], now, StreamId::MIN)?;
+
+        return Ok(entries);
+    }
+
+    /// Records `ids` as delivered to `consumer` at `delivered_at`, bumping their
+    /// delivery counts, and moves the group's last delivered ID up to `last_delivered`.
+    ///
+    /// This is what group reads and claims come down to, and how they're logged.
+    pub fn deliver(
+        &mut self,
+        key: impl AsRef<str>,
+        group: impl AsRef<str>,
+        consumer: impl AsRef<str>,
+        ids: &[StreamId],
+        delivered_at: i64,
+        last_delivered: StreamId,
+    ) -> Result<()> {
+        let Some(cg) = self
+            .store
+            .get_mut(key.as_ref())
+            .and_then(|stream| stream.groups.get_mut(group.as_ref()))
+        else {
+            return Err(GranatError::NoSuchGroup.into());
+        };
+
+        let consumer = consumer.as_ref();
+        cg.consumers.insert(consumer.to_string(), delivered_at);
+        cg.last_delivered = cg.last_delivered.max(last_delivered);
+        for id in ids.iter() {
+            let pending = cg.pending.entry(*id).or_insert_with(|| PendingEntry {
+                consumer: consumer.to_string(),
+                delivered_at,
+                deliveries: 0,
+            });
+
+            pending.consumer = consumer.to_string();
+            pending.delivered_at = delivered_at;
+            pending.deliveries += 1;
+        }
+
+        return Ok(());
+    }
+
+    /// Acknowledges the entries, removing them from the group's pending entries.
+    ///
+    /// Returns the number that were pending.
+    pub fn ack(&mut self, key: impl AsRef<str>, group: impl AsRef<str>, ids: &[StreamId]) -> usize {
+        let Some(cg) = self
+            .store
+            .get_mut(key.as_ref())
+            .and_then(|stream| stream.groups.get_mut(group.as_ref()))
+        else {
+            return 0;
+        };
+
+        return ids
+            .iter()
+            .filter(|id| cg.pending.remove(id).is_some())
+            .count();
+    }
+
+    /// Up to `count` of the group's pending entries with IDs between `start`
+    /// and `end`, only those of `consumer` if given
+    pub fn pending(
+        &self,
+        key: impl AsRef<str>,
+        group: impl AsRef<str>,
+        start: Bound<StreamId>,
+        end: Bound<StreamId>,
+        count: usize,
+        consumer: Option<&str>,
+    ) -> Result<Vec<PendingMessage>> {
+        let cg = self.group(key.as_ref(), group.as_ref())?;
+        if is_empty_range(start.as_ref(), end.as_ref()) {
+            return Ok(vec![]);
+        }
+
+        let now = self.clock.now_millis();
+        return Ok(cg
+            .pending
+            .range((start, end))
+            .filter(|(_, pending)| consumer.is_none_or(|c| pending.consumer == c))
+            .take(count)
+            .map(|(id, pending)| PendingMessage {
+                id: *id,
+                consumer: pending.consumer.clone(),
+                idle: now.saturating_sub(pending.delivered_at).max(0) as u64,
+                deliveries: pending.deliveries,
+            })
+            .collect::<Vec<PendingMessage>>());
+    }
+
+    /// Hands the pending entries that have sat idle for at least `min_idle`
+    /// milliseconds over to `consumer`, like Redis `XCLAIM`.
+    ///
+    /// Returns the entries claimed, IDs that aren't pending are skipped.
+    pub fn claim(
+        &mut self,
+        key: impl AsRef<str>,
+        group: impl AsRef<str>,
+        consumer: impl AsRef<str>,
+        min_idle: u64,
+        ids: &[StreamId],
+    ) -> Result<Vec<StreamEntry>> {
+        let (key, group) = (key.as_ref(), group.as_ref());
+        let cg = self.group(key, group)?;
+        let now = self.clock.now_millis();
+
+        let stream = &self.store[key];
+        let claimed = ids
+            .iter()
+            .filter(|id| {
+                return cg.pending.get(id).is_some_and(|pending| {
+                    return now.saturating_sub(pending.delivered_at) >= min_idle as i64;
+                });
+            })
+            .filter_map(|id| Some((*id, stream.entries.get(id)?.clone())))
+            .collect::<Vec<StreamEntry>>();
+
+        let ids = claimed.iter().map(|(id, _)| *id).collect::<Vec<StreamId>>();
+        self.deliver(key, group, consumer, &ids, now, StreamId::MIN)?;
+
+        return Ok(claimed);
+    }
+
+    pub fn info(&self, key: impl AsRef<str>) -> Option<StreamInfo> {
+        let stream = self.store.get(key.as_ref())?;
+        let entry = |(id, fields): (&StreamId, &StreamFields)| (*id, fields.clone());
+
+        return Some(StreamInfo {
+            length: stream.entries.len(),
+            last_id: stream.last_id,
+            groups: stream.groups.len(),
+            first: stream.entries.first_key_value().map(entry),
+            last: stream.entries.last_key_value().map(entry),
+        });
+    }
+
+    /// Every consumer group of the stream, in name order
+    pub fn groups(&self, key: impl AsRef<str>) -> Vec<GroupInfo> {
+        let Some(stream) = self.store.get(key.as_ref()) else {
+            return vec![];
+        };
+
+        return stream
+            .groups
+            .iter()
+            .map(|(name, group)| GroupInfo {
+                name: name.clone(),
+                consumers: group.consumers.len(),
+                pending: group.pending.len(),
+                last_delivered: group.last_delivered,
+            })
+            .collect::<Vec<GroupInfo>>();
+    }
+
+    /// Every consumer of the group, in name order
+    pub fn consumers(
+        &self,
+        key: impl AsRef<str>,
+        group: impl AsRef<str>,
+    ) -> Result<Vec<ConsumerInfo>> {
+        let cg = self.group(key.as_ref(), group.as_ref())?;
+        let now = self.clock.now_millis();
+
+        return Ok(cg
+            .consumers
+            .iter()
+            .map(|(name, seen_at)| ConsumerInfo {
+                name: name.clone(),
+                pending: cg.pending.values().filter(|p| p.consumer == *name).count(),
+                idle: now.saturating_sub(*seen_at).max(0) as u64,
+            })
+            .collect::<Vec<ConsumerInfo>>());
+    }
+
+    /// When the group last saw `consumer`, the time its last read or claim ran at
+    pub fn seen_at(
+        &self,
+        key: impl AsRef<str>,
+        group: impl AsRef<str>,
+        consumer: impl AsRef<str>,
+    ) -> Option<i64> {
+        let cg = self.group(key.as_ref(), group.as_ref()).ok()?;
+        return cg.consumers.get(consumer.as_ref()).copied();
+    }
+}
+
+#[cfg(test)]
+mod stream_store_tests {
+    use super::*;
+    use crate::store::clock::MockClock;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn fields(pairs: &[(&str, &str)]) -> StreamFields {
+        return pairs
+            .iter()
+            .map(|(f, v)| (f.to_string(), v.to_string()))
+            .collect::<StreamFields>();
+    }
+
+    fn ids(entries: &[StreamEntry]) -> Vec<StreamId> {
+        return entries.iter().map(|(id, _)| *id).collect::<Vec<StreamId>>();
+    }
+
+    fn create_basic_stream_store() -> (StreamStore, Arc<MockClock>) {
+        let clock = Arc::new(MockClock::new(1000));
+        let mut ss = StreamStore::with_clock(clock.clone());
+        for i in 1..=5 {
+            let id = NewId::Explicit(StreamId::new(i, 0));
+            let _ = ss.add("events", id, fields(&[("n", &i.to_string())]), None);
+        }
+
+        return (ss, clock);
+    }
+
+    fn is_error(err: &anyhow::Error, expected: GranatError) -> bool {
+        return err.downcast_ref::<GranatError>() == Some(&expected);
+    }
+
+    #[test]
+    fn parse_and_print_ids() {
+        assert_eq!("5-3".parse::<StreamId>().unwrap(), StreamId::new(5, 3));
+        assert_eq!("5".parse::<StreamId>().unwrap(), StreamId::new(5, 0));
+        assert!("5-x".parse::<StreamId>().is_err());
+        assert!("-1".parse::<StreamId>().is_err());
+        assert_eq!(StreamId::new(7, 1).to_string(), "7-1");
+    }
+
+    #[test]
+    fn generated_ids() {
+        let clock = Arc::new(MockClock::new(1000));
+        let mut ss = StreamStore::with_clock(clock.clone());
+        let add = |ss: &mut StreamStore, id: NewId| ss.add("s", id, fields(&[("a", "1")]), None);
+
+        assert_eq!(add(&mut ss, NewId::Auto).unwrap(), StreamId::new(1000, 0));
+        assert_eq!(add(&mut ss, NewId::Auto).unwrap(), StreamId::new(1000, 1));
+        assert_eq!(
+            add(&mut ss, NewId::AutoSeq(1000)).unwrap(),
+            StreamId::new(1000, 2)
+        );
+        assert_eq!(
+            add(&mut ss, NewId::AutoSeq(1500)).unwrap(),
+            StreamId::new(1500, 0)
+        );
+
+        // A clock behind the last ID carries on from it
+        assert_eq!(add(&mut ss, NewId::Auto).unwrap(), StreamId::new(1500, 1));
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(add(&mut ss, NewId::Auto).unwrap(), StreamId::new(2000, 0));
+
+        let err = add(&mut ss, NewId::Explicit(StreamId::new(2000, 0))).unwrap_err();
+        assert!(is_error(&err, GranatError::StreamIdTooSmall));
+        assert!(add(&mut ss, NewId::AutoSeq(10)).is_err());
+        assert!(add(&mut StreamStore::new(), NewId::Explicit(StreamId::MIN)).is_err());
+        assert_eq!(
+            add(&mut StreamStore::new(), NewId::AutoSeq(0)).unwrap(),
+            StreamId::new(0, 1)
+        );
+    }
+
+    #[test]
+    fn trimming() {
+        let (mut ss, _) = create_basic_stream_store();
+        assert_eq!(ss.trim("events", TrimStrategy::MaxLen(3)), 2);
+        assert_eq!(ss.len("events"), 3);
+        assert_eq!(
+            ss.trim("events", TrimStrategy::MinId(StreamId::new(5, 0))),
+            2
+        );
+        assert_eq!(ss.len("events"), 1);
+
+        let trim = Some(TrimStrategy::MaxLen(0));
+        let _ = ss.add("events", NewId::Auto, fields(&[("a", "1")]), trim);
+        assert_eq!(ss.len("events"), 0);
+
+        // The key and last ID outlive the entries
+        assert_eq!(ss.last_id("events"), Some(StreamId::new(1000, 0)));
+        assert_eq!(ss.trim("missing", TrimStrategy::MaxLen(0)), 0);
+    }
+
+    #[test]
+    fn ranges_and_reads() {
+        let (ss, _) = create_basic_stream_store();
+        let all = ss.range("events", Bound::Unbounded, Bound::Unbounded, None, false);
+        assert_eq!(all.len(), 5);
+        assert_eq!(all[0], (StreamId::new(1, 0), fields(&[("n", "1")])));
+
+        let (two, four) = (StreamId::new(2, 0), StreamId::new(4, 0));
+        let range = ss.range(
+            "events",
+            Bound::Included(two),
+            Bound::Excluded(four),
+            None,
+            false,
+        );
+        assert_eq!(ids(&range), vec![two, StreamId::new(3, 0)]);
+
+        let range = ss.range("events", Bound::Unbounded, Bound::Unbounded, Some(2), true);
+        assert_eq!(ids(&range), vec![StreamId::new(5, 0), four]);
+
+        assert!(ss
+            .range(
+                "events",
+                Bound::Included(four),
+                Bound::Included(two),
+                None,
+                false
+            )
+            .is_empty());
+        assert!(ss
+            .range(
+                "events",
+                Bound::Excluded(two),
+                Bound::Excluded(two),
+                None,
+                false
+            )
+            .is_empty());
+
+        assert_eq!(
+            ids(&ss.read("events", four, None)),
+            vec![StreamId::new(5, 0)]
+        );
+        assert_eq!(ss.read("events", StreamId::MIN, Some(3)).len(), 3);
+        assert!(ss.read("missing", StreamId::MIN, None).is_empty());
+    }
+
+    #[test]
+    fn consumer_groups() {
+        let (mut ss, clock) = create_basic_stream_store();
+        assert_eq!(
+            ss.create_group("events", "workers", Some(StreamId::MIN), false)
+                .unwrap(),
+            StreamId::MIN
+        );
+        let err = ss
+            .create_group("events", "workers", None, false)
+            .unwrap_err();
+        assert!(is_error(&err, GranatError::GroupExists));
+        let err = ss
+            .create_group("missing", "workers", None, false)
+            .unwrap_err();
+        assert!(is_error(&err, GranatError::NoSuchKey));
+
+        // Each consumer gets entries no one else in the group has had
+        let first = ss.read_group("events", "workers", "a", None, Some(2), false);
+        assert_eq!(
+            ids(&first.unwrap()),
+            vec![StreamId::new(1, 0), StreamId::new(2, 0)]
+        );
+        let second = ss.read_group("events", "workers", "b", None, None, false);
+        assert_eq!(second.unwrap().len(), 3);
+        assert!(ss
+            .read_group("events", "workers", "a", None, None, false)
+            .unwrap()
+            .is_empty());
+
+        // Re-reading history only returns the consumer's own pending entries
+        let history = ss.read_group("events", "workers", "a", Some(StreamId::MIN), None, false);
+        assert_eq!(history.unwrap().len(), 2);
+
+        clock.advance(Duration::from_secs(5));
+        let all = (Bound::Unbounded, Bound::Unbounded);
+        let pending = ss
+            .pending("events", "workers", all.0, all.1, 10, None)
+            .unwrap();
+        assert_eq!(pending.len(), 5);
+        assert_eq!(pending[0].consumer, "a");
+        assert_eq!(pending[0].deliveries, 1);
+        assert_eq!(pending[0].idle, 5000);
+        let only_b = ss.pending("events", "workers", all.0, all.1, 10, Some("b"));
+        assert_eq!(only_b.unwrap().len(), 3);
+
+        let acked = ss.ack(
+            "events",
+            "workers",
+            &[StreamId::new(1, 0), StreamId::new(9, 0)],
+        );
+        assert_eq!(acked, 1);
+        assert_eq!(ss.ack("events", "missing", &[StreamId::new(2, 0)]), 0);
+
+        let err = ss.read_group("events", "missing", "a", None, None, false);
+        assert!(is_error(&err.unwrap_err(), GranatError::NoSuchGroup));
+
+        assert!(ss.destroy_group("events", "workers"));
+        assert!(!ss.destroy_group("events", "workers"));
+    }
+
+    #[test]
+    fn noack_reads_skip_pending() {
+        let (mut ss, _) = create_basic_stream_store();
+        let _ = ss.create_group("events", "fire-and-forget", Some(StreamId::MIN), false);
+        let read = ss.read_group("events", "fire-and-forget", "a", None, None, true);
+        assert_eq!(read.unwrap().len(), 5);
+
+        let groups = ss.groups("events");
+        assert_eq!(groups[0].pending, 0);
+        assert_eq!(groups[0].last_delivered, StreamId::new(5, 0));
+    }
+
+    #[test]
+    fn claiming_idle_entries() {
+        let (mut ss, clock) = create_basic_stream_store();
+        let _ = ss.create_group("events", "workers", Some(StreamId::MIN), false);
+        let _ = ss.read_group("events", "workers", "a", None, Some(2), false);
+
+        let wanted = [
+            StreamId::new(1, 0),
+            StreamId::new(2, 0),
+            StreamId::new(3, 0),
+        ];
+        assert!(ss
+            .claim("events", "workers", "b", 1000, &wanted)
+            .unwrap()
+            .is_empty());
+
+        clock.advance(Duration::from_secs(2));
+        let claimed = ss.claim("events", "workers", "b", 1000, &wanted).unwrap();
+        assert_eq!(ids(&claimed), vec![wanted[0], wanted[1]]);
+
+        let all = (Bound::Unbounded, Bound::Unbounded);
+        let pending = ss
+            .pending("events", "workers", all.0, all.1, 10, None)
+            .unwrap();
+        assert!(pending
+            .iter()
+            .all(|p| p.consumer == "b" && p.deliveries == 2 && p.idle == 0));
+
+        // Trimmed entries can't be claimed and stop being pending
+        let _ = ss.trim("events", TrimStrategy::MinId(wanted[1]));
+        let claimed = ss.claim("events", "workers", "c", 0, &wanted).unwrap();
+        assert_eq!(ids(&claimed), vec![wanted[1]]);
+    }
+
+    #[test]
+    fn introspection() {
+        let (mut ss, clock) = create_basic_stream_store();
+        let _ = ss.create_group("events", "workers", None, false);
+        let _ = ss.create_group("events", "audit", Some(StreamId::MIN), false);
+        let _ = ss.read_group("events", "audit", "reader", None, Some(1), false);
+
+        let info = ss.info("events").unwrap();
+        assert_eq!(info.length, 5);
+        assert_eq!(info.last_id, StreamId::new(5, 0));
+        assert_eq!(info.groups, 2);
+        assert_eq!(info.first.unwrap().0, StreamId::new(1, 0));
+        assert_eq!(info.last.unwrap().0, StreamId::new(5, 0));
+        assert!(ss.info("missing").is_none());
+
+        let groups = ss.groups("events");
+        assert_eq!(groups[0].name, "audit");
+        assert_eq!(groups[0].consumers, 1);
+        assert_eq!(groups[0].pending, 1);
+        assert_eq!(groups[1].last_delivered, StreamId::new(5, 0));
+
+        clock.advance(Duration::from_millis(250));
+        let consumers = ss.consumers("events", "audit").unwrap();
+        assert_eq!(consumers[0].name, "reader");
+        assert_eq!(consumers[0].pending, 1);
+        assert_eq!(consumers[0].idle, 250);
+        assert!(ss.consumers("events", "missing").is_err());
+    }
+}